@@ -420,6 +420,9 @@ impl MultibootParsed
 			let kernel_start = unsafe { &::arch::imp::v_kernel_end as *const _ as u64 - IDENT_START as u64 };
 			mapbuilder.set_range( 0x100000, kernel_start - 0x10000,
 				::memory::MemoryState::Used, 0 ).ok().unwrap();
+			// - AP startup trampoline
+			mapbuilder.set_range( super::smp::AP_TRAMPOLINE_ADDR as u64, ::PAGE_SIZE as u64,
+				::memory::MemoryState::Used, 0 ).ok().unwrap();
 			// - Command line string
 			mapbuilder.set_range( self.cmdline.as_ptr() as u64 - IDENT_START as u64, self.cmdline.len() as u64,
 				::memory::MemoryState::Used, 0 ).ok().unwrap();
@@ -1,5 +1,8 @@
 
-%define MAX_CPUS	1
+%ifndef MAX_CPUS	; Passed by the Makefile (from ::threads::MAX_CPUS)
+%error "MAX_CPUS not defined"
+%endif
+%define AP_TRAMPOLINE_ADDR	0x8000	; NOTE: Must match smp.rs
 %define KSTACK_BASE	0xFFFFA00000000000
 %define INITIAL_KSTACK_SIZE	16
 %define KERNEL_BASE	0xFFFFFFFF80000000
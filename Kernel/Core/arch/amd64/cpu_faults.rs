@@ -60,6 +60,13 @@ pub extern "C" fn error_handler(regs: &InterruptRegs)
 			todo!("What should be done if #NM is generated but SSE was already enababled");
 		}
 		},
+	8 => {
+		puts("#DF\n");
+		// - Double faults are run on a separate stack, so a kernel stack overflow ends up here
+		if ::memory::virt::is_stack_guard( (regs.rsp as usize).wrapping_sub(8) ) {
+			::threads::log_stack_overflow(regs.rsp as usize);
+		}
+		},
 	13 => { puts("GPF ("); puth(regs.errorcode); puts(")\n"); },
 	14 => {
 		let cr2 = get_cr2();
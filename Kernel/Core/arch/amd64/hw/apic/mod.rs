@@ -108,6 +108,56 @@ fn get_lapic() -> &'static raw::LAPIC
 	&*s_lapic
 }
 
+/// Returns the APIC IDs of all usable processors (from the MADT)
+pub fn processor_ids() -> Vec<u8>
+{
+	if ! s_lapic.ls_is_valid() {
+		return Vec::new();
+	}
+	let madt = match ::arch::acpi::find::<init::ACPI_MADT>("APIC", 0)
+		{
+		None => return Vec::new(),
+		Some(v) => v,
+		};
+	madt.data().records(madt.data_len()).filter_map(
+			|r| match r {
+				init::MADTDevRecord::DevLAPIC(a) if a.flags & 1 != 0 => Some(a.apic_id),
+				_ => None
+				}
+			).collect()
+}
+/// Returns the local APIC ID of the current processor
+pub fn local_apic_id() -> u32
+{
+	get_lapic().get_id()
+}
+/// Initialise the local APIC of an application processor
+pub fn init_ap()
+{
+	get_lapic().init();
+}
+
+pub fn send_init(apic_id: u32)
+{
+	get_lapic().send_init(apic_id);
+}
+pub fn send_startup(apic_id: u32, page: u8)
+{
+	get_lapic().send_startup(apic_id, page);
+}
+/// Interrupt the specified processor so it re-checks its run queue
+#[is_safe(irq)]
+pub fn send_reschedule(apic_id: u32)
+{
+	get_lapic().send_reschedule(apic_id);
+}
+/// Interrupt the specified processor to handle a TLB shootdown
+#[is_safe(irq)]
+pub fn send_tlb_shootdown(apic_id: u32)
+{
+	get_lapic().send_tlb_shootdown(apic_id);
+}
+
 ///// Registers a message-signalled interrupt handler.
 //pub fn register_msi(callback: fn (*const()), info: *const ()) -> Result<(uint,::arch::interrupts::ISRHandle),()>
 //{
@@ -6,12 +6,16 @@
 use prelude::*;
 
 static TIMER_VEC: u8 = 0x7E;
+static TLB_SHOOTDOWN_VEC: u8 = 0x7C;
+static RESCHEDULE_VEC: u8 = 0x7D;
 
 pub struct LAPIC
 {
 	paddr: u64,
 	mapping: ::memory::virt::AllocHandle,
 	timer_isr: ::arch::imp::interrupts::ISRHandle,
+	tlb_shootdown_isr: ::arch::imp::interrupts::ISRHandle,
+	reschedule_isr: ::arch::imp::interrupts::ISRHandle,
 }
 
 pub struct IOAPIC
@@ -59,6 +63,7 @@ enum ApicReg
 	ErrStatus = 0x28,	// Error Status
 	LVTCMCI   = 0x2F,	// LVT CMCI Registers (?)
 	ICR       = 0x30,	// Interrupt Command Register (1/2)
+	ICRHi     = 0x31,	// Interrupt Command Register (2/2)
 	LVTTimer  = 0x32,
 	LVTThermalSensor = 0x33,
 	LVTPermCounters  = 0x34,
@@ -86,6 +91,8 @@ impl LAPIC
 			// Assume SAFE: Shouldn't be aliasing
 			mapping: unsafe { ::memory::virt::map_hw_rw(paddr, 1, "APIC").unwrap() },
 			timer_isr: Default::default(),
+			tlb_shootdown_isr: Default::default(),
+			reschedule_isr: Default::default(),
 			};
 		
 		log_debug!("LAPIC {{ IDReg={:x}, Ver={:x}, SIR={:#x} }}",
@@ -104,6 +111,16 @@ impl LAPIC
 			Ok(v) => v,
 			Err(e) => panic!("Unable to bind LAPIC timer: {:?}", e),
 			};
+		self.tlb_shootdown_isr = match ::arch::imp::interrupts::bind_isr(TLB_SHOOTDOWN_VEC, lapic_tlb_shootdown, self as *mut _ as *const (), 0)
+			{
+			Ok(v) => v,
+			Err(e) => panic!("Unable to bind TLB shootdown IPI: {:?}", e),
+			};
+		self.reschedule_isr = match ::arch::imp::interrupts::bind_isr(RESCHEDULE_VEC, lapic_reschedule, self as *mut _ as *const (), 0)
+			{
+			Ok(v) => v,
+			Err(e) => panic!("Unable to bind reschedule IPI: {:?}", e),
+			};
 	}
 	/// Initialise the LAPIC (for this CPU)
 	pub fn init(&self)
@@ -130,9 +147,10 @@ impl LAPIC
 		
 		//self.write_reg(ApicReg::SIR as usize, self.read_reg(ApicReg_SIR as usize) | (1 << 8));
 		self.write_reg(ApicReg::SIR, 0x7F | (1 << 8));	// Enable LAPIC (and set Spurious to 127)
-		self.write_reg(ApicReg::InitCount, 0x100000);
+		// TODO: Calibrate against the HPET (this is ~1ms with QEMU's 1GHz bus clock)
+		self.write_reg(ApicReg::InitCount, 0x10000);
 		self.write_reg(ApicReg::TmrDivide, 3);	// Timer Divide = 16
-		self.write_reg(ApicReg::LVTTimer, TIMER_VEC as u32);	// Enable Timer
+		self.write_reg(ApicReg::LVTTimer, TIMER_VEC as u32 | (1 << 17));	// Enable Timer (periodic)
 		self.write_reg(ApicReg::LVTThermalSensor, 0);	// "Disable" Thermal Sensor
 		self.write_reg(ApicReg::LVTPermCounters, 0);	// "Disable" ? Counters
 		self.write_reg(ApicReg::LVT_LINT0, 0);	// "Disable" LINT0
@@ -159,6 +177,46 @@ impl LAPIC
 		self.write_reg(ApicReg::EOI, num as u32);
 	}
 	
+	/// Local APIC ID of the current CPU
+	pub fn get_id(&self) -> u32
+	{
+		self.read_reg(ApicReg::LAPIC_ID) >> 24
+	}
+	/// Send a fixed interrupt to another CPU
+	#[is_safe(irq)]	// Holds interrupts
+	pub fn send_ipi(&self, dest: u32, vector: u8)
+	{
+		self.send_icr(dest, (1 << 14) | vector as u32);	// Assert, Fixed
+	}
+	/// Send an INIT IPI (resets the target CPU to wait for a startup IPI)
+	pub fn send_init(&self, dest: u32)
+	{
+		self.send_icr(dest, (1 << 14) | (5 << 8));	// Assert, INIT
+	}
+	/// Send a startup IPI, starting the target CPU in real mode at `page` * 0x1000
+	pub fn send_startup(&self, dest: u32, page: u8)
+	{
+		self.send_icr(dest, (1 << 14) | (6 << 8) | page as u32);	// Assert, Start-up
+	}
+	pub fn send_tlb_shootdown(&self, dest: u32)
+	{
+		self.send_ipi(dest, TLB_SHOOTDOWN_VEC);
+	}
+	pub fn send_reschedule(&self, dest: u32)
+	{
+		self.send_ipi(dest, RESCHEDULE_VEC);
+	}
+	fn send_icr(&self, dest: u32, value: u32)
+	{
+		// Writing the low word sends the IPI, so the pair must not be split by an interrupt
+		let _irql = ::sync::hold_interrupts();
+		self.write_reg(ApicReg::ICRHi, dest << 24);
+		self.write_reg(ApicReg::ICR, value);
+		// Wait for delivery
+		while self.read_reg(ApicReg::ICR) & (1 << 12) != 0 {
+		}
+	}
+	
 	fn read_reg(&self, reg: ApicReg) -> u32
 	{
 		// SAFE: Aligned memory accesses to hardware are atomic on x86
@@ -196,8 +254,9 @@ impl LAPIC
 		assert!( !sp.is_null() );
 		// SAFE: 'sp' is the bound pointer, and should be valid
 		let s: &LAPIC = unsafe { &*(sp as *const LAPIC) };
-		log_trace!("LAPIC Timer");
 		s.eoi(isr);
+		// Drive the scheduler's time slices (each CPU has its own timer, and this is the only tick source)
+		::threads::timer_tick();
 	}
 }
 impl ApicReg
@@ -226,6 +285,20 @@ extern "C" fn lapic_timer(isr: usize, sp: *const (), _idx: usize)
 {
 	LAPIC::local_timer(isr, sp, _idx);	
 }
+extern "C" fn lapic_tlb_shootdown(isr: usize, sp: *const (), _idx: usize)
+{
+	// SAFE: 'sp' is the bound pointer, and should be valid
+	let s: &LAPIC = unsafe { &*(sp as *const LAPIC) };
+	::arch::imp::smp::handle_tlb_shootdown();
+	s.eoi(isr);
+}
+extern "C" fn lapic_reschedule(isr: usize, sp: *const (), _idx: usize)
+{
+	// SAFE: 'sp' is the bound pointer, and should be valid
+	let s: &LAPIC = unsafe { &*(sp as *const LAPIC) };
+	s.eoi(isr);
+	::threads::reschedule_ipi();
+}
 
 impl IOAPIC
 {
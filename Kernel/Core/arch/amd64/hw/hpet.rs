@@ -50,6 +50,12 @@ pub fn get_timestamp() -> u64
 	}
 }
 
+/// Returns true if a HPET was found (and hence `get_timestamp` is advancing)
+pub fn is_present() -> bool
+{
+	S_INSTANCE.ls_is_valid()
+}
+
 fn init()
 {
 	log_trace!("init()");
@@ -121,6 +127,8 @@ impl HPET
 		s.write_reg(HPETReg::ISR as usize, s.read_reg(HPETReg::ISR as usize));
 		
 		s.oneshot(0, s.current() + 100*1000 );
+		// NOTE: This doesn't tick the scheduler, that's done by each CPU's LAPIC timer (a tick here would double the
+		// BSP's rate)
 	}
 	
 	fn read_reg(&self, reg: usize) -> u64 {
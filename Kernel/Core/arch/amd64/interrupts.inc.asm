@@ -167,10 +167,24 @@ BLANKINT i
 %assign i i+1
 %endrep
 [extern irq_handler]
+[extern irq_return_to_user]
 IRQCommon:
 	API_SAVE
+	; If the interrupt came from userland, switch to the kernel's GS base
+	mov rax, [rsp+API_SAVE_SIZE+8+8]	; CS (above saved RBX and RIP)
+	cmp rax, 0x2B
+	jnz .kernel_entry
+	swapgs
+.kernel_entry:
 	mov rdi, rbx
 	call irq_handler
+	mov rax, [rsp+API_SAVE_SIZE+8+8]
+	cmp rax, 0x2B
+	jnz .kernel_exit
+	; Returning to userland - a safe point to preempt the current thread
+	call irq_return_to_user
+	swapgs
+.kernel_exit:
 	API_RESTORE
 	pop rbx
 	iretq
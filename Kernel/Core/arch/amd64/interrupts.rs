@@ -38,13 +38,25 @@ static S_IRQ_HANDLERS_LOCK: ::sync::Spinlock<[IRQHandlersEnt; 256]> = ::sync::Sp
 /// ISR handler called by assembly
 pub extern "C" fn irq_handler(index: usize)
 {
-	let lh = S_IRQ_HANDLERS_LOCK.lock_irqsafe();
-	let ent = (*lh)[index];
+	// NOTE: Lock released before calling, so other CPUs can handle interrupts concurrently
+	let ent = S_IRQ_HANDLERS_LOCK.lock_irqsafe()[index];
 	if let Some(h) = ent.handler {
 		(h)(index, ent.info, ent.idx);
 	}
 }
 
+#[no_mangle]
+#[doc(hidden)]
+/// Called by assembly when an interrupt is about to return to userland (after EOI)
+pub extern "C" fn irq_return_to_user()
+{
+	// SAFE: The interrupt has been acknowledged, so it's safe to take more here
+	unsafe { ::arch::sync::start_interrupts(); }
+	::threads::preempt_check();
+	// SAFE: Disabled until the `iretq` restores the user's flags
+	unsafe { ::arch::sync::stop_interrupts(); }
+}
+
 #[derive(Debug,Copy,Clone)]
 /// Error code for bind_isr
 pub enum BindISRError
@@ -16,7 +16,8 @@ use memory::page_array::{PageArray};
 
 /// Multiref count array
 static S_REFCOUNT_ARRAY: RwLock<PageArray<AtomicU32>> = RwLock::new( PageArray::new(PMEMREF_BASE, PMEMREF_END) );
-static S_USED_BITMAP: RwLock<PageArray<AtomicU32>> = RwLock::new( PageArray::new(PMEMBM_BASE, PMEMBM_END) );
+/// Allocated frame bitmap (not locked, as pages are only ever added and updates are atomic)
+static S_USED_BITMAP: PageArray<AtomicU32> = PageArray::new(PMEMBM_BASE, PMEMBM_END);
 
 /// Calls the provided closure with a borrow of the reference count for the specified frame
 fn with_ref<U, F: FnOnce(&AtomicU32)->U>(frame_idx: u64, fcn: F) -> Option<U>
@@ -29,16 +30,10 @@ fn with_ref_alloc<U, F: FnOnce(&AtomicU32)->U>(frame_idx: u64, fcn: F) -> U
 	let mut lh = S_REFCOUNT_ARRAY.write();
 	fcn( lh.get_alloc(frame_idx as usize) )
 }
-/// Calls the provided closure with a borrow of the reference count for the specified frame
+/// Calls the provided closure with a borrow of the specified word of the allocation bitmap
 fn with_bm<U, F: FnOnce(&AtomicU32)->U>(ofs: usize, fcn: F) -> Option<U>
 {
-	S_USED_BITMAP.read().get(ofs).map(fcn)
-}
-/// Calls the provided closure with a reference to the specified frame's reference count (allocating if required)
-fn with_bm_alloc<U, F: FnOnce(&AtomicU32)->U>(ofs: usize, fcn: F) -> U
-{
-	let mut lh = S_USED_BITMAP.write();
-	fcn( lh.get_alloc(ofs) )
+	S_USED_BITMAP.get(ofs).map(fcn)
 }
 
 
@@ -85,12 +80,38 @@ pub fn mark_free(frame_idx: u64) -> bool {
 }
 pub fn mark_used(frame_idx: u64) {
 	let mask = 1 << ((frame_idx % 32) as usize);
-	with_bm_alloc( (frame_idx / 32) as usize, |c| {
-		// Should always succeed due to write lock in `with_bm_alloc`
-		let old = c.load(Ordering::Relaxed);
-		let new_old = c.compare_and_swap(old, old | mask, Ordering::Relaxed);
-		assert_eq!(new_old, old);
-		})
+	with_bm( (frame_idx / 32) as usize, |c| {
+		let mut old = c.load(Ordering::Relaxed);
+		// Loop until a compare+swap succeeds (the bitmap isn't locked)
+		loop
+		{
+			let new_old = c.compare_and_swap(old, old | mask, Ordering::Relaxed);
+			if old == new_old {
+				break ;
+			}
+			old = new_old;
+		}
+		});
+}
+/// Returns true if the frame is allocated (frames outside the bitmap are treated as allocated)
+pub fn is_used(frame_idx: u64) -> bool {
+	let mask = 1 << ((frame_idx % 32) as usize);
+	with_bm( (frame_idx / 32) as usize, |c| c.load(Ordering::Relaxed) & mask != 0 ).unwrap_or(true)
+}
+
+/// Allocate the allocation bitmap for all frames below `max_frame_idx`
+///
+/// Done up-front, as allocating on demand would recurse into the frame allocator
+pub fn init_bitmap(max_frame_idx: u64) -> bool {
+	let words_per_page = ::PAGE_SIZE / 4;
+	let n_words = (max_frame_idx as usize + 31) / 32;
+	for ofs in (0 .. n_words).step_by(words_per_page) {
+		if ! S_USED_BITMAP.ensure_page(ofs) {
+			log_error!("init_bitmap - Unable to allocate bitmap for frames from {:#x}", ofs * 32);
+			return false;
+		}
+	}
+	true
 }
 
 
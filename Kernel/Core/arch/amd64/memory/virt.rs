@@ -17,6 +17,7 @@ const FLAG_W:   u64 = 2;
 const FLAG_U:   u64 = 4;
 const FLAG_G:   u64 = 0x100;
 const FLAG_COW: u64 = 0x200;	// free bit, overloaded as COW
+const FLAG_LAZY: u64 = 0x400;	// free bit, marks a non-present entry to be filled on demand
 const FLAG_NX:  u64 = (1<<63);
 
 const FAULT_LOCKED: u32 = 1;
@@ -256,6 +257,11 @@ fn invlpg(addr: *mut ()) {
 		asm!("invlpg ($0)" : : "r" (addr) : "memory" : "volatile");
 	}
 }
+/// Invalidate a page on all CPUs (needed when a mapping is removed or restricted)
+fn invlpg_all(addr: *mut ()) {
+	invlpg(addr);
+	super::super::smp::tlb_shootdown(addr as usize);
+}
 
 pub fn can_map_without_alloc(addr: *mut ()) -> bool {
 	// The following only returns PTE::null() if an intermediate step was unallocated
@@ -285,10 +291,44 @@ pub unsafe fn unmap(addr: *mut ()) -> Option<PAddr>
 		};
 	pte.set( 0, ::memory::virt::ProtectionMode::Unmapped );
 	
-	invlpg(addr);
+	invlpg_all(addr);
 	
 	rv
 }
+/// Mark a page as reserved for demand paging (filled by `::memory::vma` on first access)
+pub unsafe fn map_lazy(addr: *mut (), prot: ::memory::virt::ProtectionMode) -> bool
+{
+	let mut pte = get_page_ent(addr as usize, true, LargeOk::No);
+	assert!( !pte.is_null(), "Failed to obtain ent for {:p}", addr );
+	if pte.set_lazy_if_unset( prot ).is_err() {
+		panic!("Attempting to lazy-map over existing allocation addr={:p}", addr);
+	}
+	true
+}
+/// Returns the protection mode of a page awaiting demand paging (`None` if not lazily mapped)
+pub fn get_lazy<T>(addr: *const T) -> Option<ProtectionMode>
+{
+	let pte = get_page_ent(addr as usize, false, LargeOk::No);
+	if pte.is_lazy() {
+		Some( pte.get_lazy_perms() )
+	}
+	else {
+		None
+	}
+}
+/// Replace a lazy entry with a mapping of the provided frame (returns false if the entry was no longer lazy)
+pub unsafe fn fill_lazy(addr: *mut (), phys: PAddr, prot: ::memory::virt::ProtectionMode) -> bool
+{
+	let mut pte = get_page_ent(addr as usize, false, LargeOk::No);
+	if pte.is_null() {
+		return false;
+	}
+	if pte.replace_lazy( phys, prot ).is_err() {
+		return false;
+	}
+	invlpg(addr);
+	true
+}
 /// Change protections mode
 pub unsafe fn reprotect(addr: *mut (), prot: ::memory::virt::ProtectionMode)
 {
@@ -298,7 +338,7 @@ pub unsafe fn reprotect(addr: *mut (), prot: ::memory::virt::ProtectionMode)
 	assert!( pte.is_present(), "Reprotecting unmapped page {:p}", addr );
 	let phys = pte.addr();
 	pte.set( phys, prot );
-	invlpg(addr);
+	invlpg_all(addr);
 }
 
 static PF_PRESENT : u64 = 0x001;
@@ -335,6 +375,12 @@ impl PTE
 			self.is_present() && *self.data & (PF_PRESENT | PF_LARGE) == PF_LARGE|PF_PRESENT
 		}
 	}
+	pub fn is_lazy(&self) -> bool {
+		// SAFE: Construction should ensure this pointer is valid
+		unsafe {
+			!self.is_null() && *self.data & (FLAG_P|FLAG_LAZY) == FLAG_LAZY
+		}
+	}
 	pub fn is_cow(&self) -> bool {
 		// SAFE: Construction should ensure this pointer is valid
 		unsafe {
@@ -388,10 +434,50 @@ impl PTE
 		}
 	}
 	
+	/// Set this (unused) entry to a lazy (non-present) entry with the specified final protection
+	pub fn set_lazy_if_unset(&mut self, prot: ::memory::virt::ProtectionMode) -> Result<(),()> {
+		assert!(!self.is_null());
+		let v = (Self::mode_to_flags(prot) & !FLAG_P) | FLAG_LAZY;
+		// SAFE: Atomic 64-bit and valid pointer
+		if unsafe { ::core::intrinsics::atomic_cxchg_relaxed(self.data, 0, v).0 } == 0 {
+			Ok( () )
+		}
+		else {
+			Err( () )
+		}
+	}
+	/// Atomically replace a lazy entry with a present mapping
+	pub fn replace_lazy(&mut self, paddr: PAddr, prot: ::memory::virt::ProtectionMode) -> Result<(),()> {
+		assert!(!self.is_null());
+		// SAFE: Pointer should be valid
+		let old = unsafe { *self.data };
+		if old & (FLAG_P|FLAG_LAZY) != FLAG_LAZY {
+			return Err( () );
+		}
+		let v = (paddr & 0x7FFFFFFF_FFFFF000) | Self::mode_to_flags(prot);
+		// SAFE: Atomic 64-bit and valid pointer
+		if unsafe { ::core::intrinsics::atomic_cxchg(self.data, old, v).0 } == old {
+			Ok( () )
+		}
+		else {
+			Err( () )
+		}
+	}
+	/// Protection mode that a lazy entry will be given once filled
+	pub fn get_lazy_perms(&self) -> ::memory::virt::ProtectionMode {
+		assert!(self.is_lazy());
+		// SAFE: Pointer should be valid
+		let val = unsafe { *self.data };
+		Self::flags_to_mode(val | FLAG_P)
+	}
+	
 	pub fn get_perms(&self) -> ::memory::virt::ProtectionMode {
 		assert!(!self.is_null());
 		// SAFE: Pointer should be valid
 		let val = unsafe { *self.data };
+		Self::flags_to_mode(val)
+	}
+	fn flags_to_mode(val: u64) -> ::memory::virt::ProtectionMode {
 		if val & FLAG_P == 0 {
 			ProtectionMode::Unmapped
 		}
@@ -452,22 +538,49 @@ pub fn handle_page_fault(accessed_address: usize, error_code: u32) -> bool
 		//::memory::virt::cow_write(accessed_address);
 
 		// 1. Lock (relevant) address space
+		let mut copied = false;
 		// SAFE: Changes to address space are transparent
 		::memory::virt::with_lock(accessed_address, || unsafe {
 			let frame = pte.addr();
 			let pgaddr = (accessed_address as usize) & !PAGE_MASK;
 			// 2. Get the PMM to provide us with a unique copy of that frame (can return the same addr)
-			let newframe = ::memory::phys::make_unique( frame, &*(pgaddr as *const [u8; 4096]) );
-			// 3. Remap to this page as UserRW (because COW is user-only atm)
-			pte.set(newframe, ProtectionMode::UserRW);
-			invlpg( (accessed_address & !0xFFF) as *mut () );
+			if let Ok(newframe) = ::memory::phys::make_unique( frame, &*(pgaddr as *const [u8; 4096]) ) {
+				// 3. Remap to this page as UserRW (because COW is user-only atm)
+				pte.set(newframe, ProtectionMode::UserRW);
+				invlpg_all( (accessed_address & !0xFFF) as *mut () );
+				copied = true;
+			}
 			});
+		if copied {
+			return true;
+		}
+		log_error!("Out of memory copying COW page {:#x}", accessed_address);
+		return false;
+	}
+	//  > Writes to clean pages of writeback file mappings
+	if error_code & (FAULT_WRITE|FAULT_LOCKED) == (FAULT_WRITE|FAULT_LOCKED) && ::memory::vma::handle_write_fault(accessed_address) {
 		return true;
 	}
+	//  > Demand-paged (lazy) pages
+	if error_code & FAULT_LOCKED == 0 && pte.is_lazy() {
+		if ::memory::vma::handle_fault(accessed_address) {
+			return true;
+		}
+	}
 	//  > Paged-out pages
-	if error_code & FAULT_LOCKED == 0 && pte.is_reserved() {
+	else if error_code & FAULT_LOCKED == 0 && pte.is_reserved() {
 		todo!("Paged - {:#x} pte = {:?}", accessed_address, pte);
 	}
+	//  > Stack overflow (kernel guard page), or growth of a user stack
+	else if error_code & FAULT_LOCKED == 0 {
+		if ::memory::virt::is_stack_guard(accessed_address) {
+			::threads::log_stack_overflow(accessed_address);
+			return false;
+		}
+		if ::memory::vma::handle_stack_fault(accessed_address) {
+			return true;
+		}
+	}
 	
 	
 	// Check if the user is buggy
@@ -573,7 +686,13 @@ impl AddressSpace
 			//log_trace!("opt_clone_page(idx={:#x})", idx);
 			
 			// SAFE: Only called when parent table is present
-			let ent = unsafe { get_entry(0, idx, false) };
+			let mut ent = unsafe { get_entry(0, idx, false) };
+			if ent.is_lazy() {
+				// Demand-paged regions aren't known to the new address space, so populate before copying
+				::memory::vma::populate( (idx << 12) as *const () );
+				// SAFE: Only called when parent table is present
+				ent = unsafe { get_entry(0, idx, false) };
+			}
 			if ! ent.is_reserved()
 			{
 				Ok(0)
@@ -680,6 +799,40 @@ impl AddressSpace
 	pub fn get_cr3(&self) -> u64 {
 		self.0
 	}
+
+	/// Count the number of resident (present) pages in the user half of this address space
+	pub fn resident_pages(&self) -> usize {
+		fn count_table_ent(table_ent: &mut u64, level: u8) -> usize {
+			// SAFE: Pointer is valid for the lifetime of the PTE, and it's only read
+			let pte = unsafe { PTE::new(PTEPos::from_level(level), table_ent) };
+			if ! pte.is_present() {
+				0
+			}
+			else if level == 1 {
+				1
+			}
+			else if pte.is_large() {
+				1 << (9 * (level as usize - 1))
+			}
+			else {
+				// SAFE: Table is owned by this address space (which is borrowed), and is only read
+				unsafe {
+					::memory::virt::with_temp(pte.addr(), |tab_pg| {
+						let tab: &mut [u64; 512] = ::core::mem::transmute(tab_pg);
+						tab.iter_mut().map(|e| count_table_ent(e, level-1)).sum()
+						})
+				}
+			}
+		}
+
+		// SAFE: Root table is owned by this address space (which is borrowed), and is only read
+		unsafe {
+			::memory::virt::with_temp(self.0, |pml4_pg| {
+				let pml4: &mut [u64; 512] = ::core::mem::transmute(pml4_pg);
+				pml4[..256].iter_mut().map(|e| count_table_ent(e, 4)).sum()
+				})
+		}
+	}
 }
 impl ::core::ops::Drop for AddressSpace {
 	fn drop(&mut self) {
@@ -688,7 +841,10 @@ impl ::core::ops::Drop for AddressSpace {
 			assert!(1 <= level && level <= 4, "AddressSpace::drop::drop_table_ent - level invalid, {}", level);
 			// SAFE: We have &mut
 			let pte = unsafe { PTE::new(PTEPos::from_level(level), table_ent) };
-			if ! pte.is_reserved() {
+			if pte.is_lazy() && level == 1 {
+				// Demand-paged entry that was never touched, no frame to release
+			}
+			else if ! pte.is_reserved() {
 				assert!( *table_ent == 0, "TODO: Handle non-zero non-present table entry" );
 			}
 			else {
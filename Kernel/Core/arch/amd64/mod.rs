@@ -7,7 +7,7 @@ use core::option::Option;
 
 pub use self::log::{puts, puth};
 
-module_define!{arch, [APIC, HPET], init}
+module_define!{arch, [APIC, HPET, TSS], init}
 
 pub mod interrupts;
 #[doc(hidden)]
@@ -18,6 +18,7 @@ pub mod boot;
 pub mod sync;
 
 mod tss;
+pub mod smp;
 
 mod log;
 pub mod x86_io;
@@ -32,7 +33,8 @@ extern "C"
 
 fn init()
 {
-	// None needed, just dependencies
+	// Bring up the other CPUs once interrupts and timers are available
+	smp::init();
 }
 
 #[inline(always)]
@@ -57,6 +59,15 @@ pub fn cur_timestamp() -> u64
 	hw::hpet::get_timestamp()
 }
 
+/// Return a free-running cycle counter (used as an entropy source)
+pub fn cycle_count() -> u64
+{
+	let (lo, hi): (u32, u32);
+	// SAFE: RDTSC has no side-effects
+	unsafe { asm!("rdtsc" : "={eax}" (lo), "={edx}" (hi) : : : "volatile"); }
+	(hi as u64) << 32 | lo as u64
+}
+
 /// Print a backtrace, starting at the current location.
 pub fn print_backtrace()
 {
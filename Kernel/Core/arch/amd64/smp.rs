@@ -0,0 +1,314 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/arch/amd64/smp.rs
+//! Multi-processor support (AP startup, per-CPU data, and inter-processor interrupts)
+#[allow(unused_imports)]
+use prelude::*;
+use core::sync::atomic::{AtomicBool,AtomicUsize,Ordering};
+use core::sync::atomic::{ATOMIC_BOOL_INIT,ATOMIC_USIZE_INIT};
+use super::memory::addresses::IDENT_START;
+
+pub use threads::MAX_CPUS;
+// NOTE: MUST match the value in common.inc.asm
+/// Physical address the AP startup trampoline is copied to (must be page aligned and below 1MiB)
+pub const AP_TRAMPOLINE_ADDR: usize = 0x8000;
+
+/// Passed to `tlb_shootdown` to flush all TLB entries (including global pages)
+pub const FLUSH_ALL: usize = !0;
+
+/// Per-CPU data
+pub struct CpuInfo
+{
+	/// Local APIC ID
+	apic_id: AtomicUsize,
+	/// Set once the CPU has completed initialisation
+	online: AtomicBool,
+	/// This CPU's idle thread (a leaked `ThreadPtr`)
+	idle_thread: AtomicUsize,
+	/// Count of outstanding `disable_task_switch` calls
+	pub task_switch_disable: AtomicUsize,
+	/// `is_running` flag of the thread this CPU is switching away from
+	prev_running: AtomicUsize,
+	/// Page table root (CR3) of the address space this CPU is running
+	cur_cr3: AtomicUsize,
+	/// TLB shootdown requests from each CPU (address with bit 0 set, cleared once this CPU has invalidated)
+	tlb_requests: [AtomicUsize; MAX_CPUS],
+}
+const CPUINFO_INIT: CpuInfo = CpuInfo {
+	apic_id: ATOMIC_USIZE_INIT,
+	online: ATOMIC_BOOL_INIT,
+	idle_thread: ATOMIC_USIZE_INIT,
+	task_switch_disable: ATOMIC_USIZE_INIT,
+	prev_running: ATOMIC_USIZE_INIT,
+	cur_cr3: ATOMIC_USIZE_INIT,
+	tlb_requests: per_cpu_init!(ATOMIC_USIZE_INIT),
+	};
+
+static S_CPUS: [CpuInfo; MAX_CPUS] = per_cpu_init!(CPUINFO_INIT);
+/// Number of CPUs online (indexes in `S_CPUS` are allocated sequentially)
+static S_NUM_CPUS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+extern "C" {
+	static ap_trampoline: u8;
+	static ap_trampoline_end: u8;
+	static mut s_ap_boot_rsp: u64;
+	static mut s_ap_boot_tls: u64;
+	static mut InitialPML4: [u64; 512];
+	static InitialPDP: [u64; 512];
+}
+
+/// Start all application processors listed in the MADT
+pub fn init()
+{
+	let ids = super::hw::apic::processor_ids();
+	if ids.len() == 0 {
+		// No APIC, so not even the BSP's ID is known
+		S_NUM_CPUS.store(1, Ordering::Relaxed);
+		return ;
+	}
+
+	let bsp_id = super::hw::apic::local_apic_id();
+	S_CPUS[0].apic_id.store(bsp_id as usize, Ordering::Relaxed);
+	S_CPUS[0].cur_cr3.store(get_cr3(), Ordering::Relaxed);
+	S_CPUS[0].online.store(true, Ordering::Relaxed);
+	S_NUM_CPUS.store(1, Ordering::Release);
+
+	if ids.len() == 1 {
+		return ;
+	}
+	if ! super::hw::hpet::is_present() {
+		log_warning!("No HPET, can't time AP startup. Only using the BSP");
+		return ;
+	}
+	log_log!("Starting {} application processors", ids.len() - 1);
+
+	// SAFE: Single-threaded (other CPUs are halted), the trampoline page is reserved in the memory map
+	unsafe {
+		// Copy the real-mode trampoline to low memory
+		let src = &ap_trampoline as *const u8;
+		let len = &ap_trampoline_end as *const u8 as usize - src as usize;
+		assert!(len <= ::PAGE_SIZE);
+		::core::ptr::copy_nonoverlapping( (src as usize + IDENT_START) as *const u8, (AP_TRAMPOLINE_ADDR + IDENT_START) as *mut u8, len );
+		// Restore the low identity mapping, used while the APs enable paging
+		InitialPML4[0] = (&InitialPDP as *const _ as u64 - IDENT_START as u64) | 3;
+	}
+
+	for &id in ids.iter().filter(|&&id| id as u32 != bsp_id)
+	{
+		let idx = S_NUM_CPUS.load(Ordering::Relaxed);
+		if idx == MAX_CPUS {
+			log_warning!("Too many CPUs, only using {}", MAX_CPUS);
+			break;
+		}
+		if start_ap(idx, id) {
+			S_NUM_CPUS.store(idx + 1, Ordering::Release);
+		}
+	}
+
+	// SAFE: Nothing uses the identity mapping once the APs are in the higher half
+	unsafe {
+		InitialPML4[0] = 0;
+	}
+	// The identity mapping is global, so flush it everywhere
+	tlb_shootdown(FLUSH_ALL);
+	invalidate(FLUSH_ALL);
+
+	log_notice!("{} CPUs online", num_cpus());
+}
+
+/// Start a single AP, returning `true` once it's online
+fn start_ap(idx: usize, apic_id: u8) -> bool
+{
+	log_debug!("start_ap(idx={}, apic_id={})", idx, apic_id);
+	let mut idle = ::threads::new_idle_thread(idx);
+	let (rsp, tls) = super::threads::prep_ap_thread(&mut idle, idx);
+	S_CPUS[idx].apic_id.store(apic_id as usize, Ordering::Relaxed);
+	set_idle_thread(idx, idle);
+
+	// SAFE: APs are started one at a time, and the previous one has finished with these
+	unsafe {
+		s_ap_boot_rsp = rsp;
+		s_ap_boot_tls = tls;
+	}
+
+	// INIT-SIPI-SIPI sequence
+	let vector = (AP_TRAMPOLINE_ADDR / ::PAGE_SIZE) as u8;
+	super::hw::apic::send_init(apic_id as u32);
+	delay_ms(10);
+	super::hw::apic::send_startup(apic_id as u32, vector);
+	if ! wait_online(idx, 1) {
+		super::hw::apic::send_startup(apic_id as u32, vector);
+		if ! wait_online(idx, 1000) {
+			log_error!("CPU{} (APIC {}) failed to start", idx, apic_id);
+			return false;
+		}
+	}
+	true
+}
+
+fn wait_online(idx: usize, ms: u64) -> bool
+{
+	let end = ::arch::cur_timestamp() + ms;
+	while ::arch::cur_timestamp() <= end
+	{
+		if S_CPUS[idx].online.load(Ordering::Acquire) {
+			return true;
+		}
+	}
+	S_CPUS[idx].online.load(Ordering::Acquire)
+}
+fn delay_ms(ms: u64)
+{
+	let end = ::arch::cur_timestamp() + ms;
+	while ::arch::cur_timestamp() <= end {
+	}
+}
+
+#[no_mangle]
+#[doc(hidden)]
+/// Called by `ap_start64_higher` once the AP is running on its idle thread's stack
+pub extern "C" fn ap_entry()
+{
+	let idx = super::threads::cpu_num();
+	super::tss::init_ap(idx);
+	// NOTE: Enables interrupts
+	super::hw::apic::init_ap();
+	log_notice!("CPU{} online (APIC {})", idx, S_CPUS[idx].apic_id.load(Ordering::Relaxed));
+	S_CPUS[idx].cur_cr3.store(get_cr3(), Ordering::SeqCst);
+	S_CPUS[idx].online.store(true, Ordering::Release);
+}
+
+/// Number of CPUs online
+pub fn num_cpus() -> usize
+{
+	::core::cmp::max(1, S_NUM_CPUS.load(Ordering::Acquire))
+}
+/// Data for the current CPU
+pub fn cur_cpu() -> &'static CpuInfo
+{
+	&S_CPUS[super::threads::cpu_num()]
+}
+
+pub fn set_idle_thread(cpu: usize, thread: ::threads::ThreadPtr)
+{
+	let prev = S_CPUS[cpu].idle_thread.swap(thread.unwrap() as usize, Ordering::Relaxed);
+	assert!(prev == 0, "Idle thread for CPU{} set twice", cpu);
+}
+pub fn get_idle_thread(cpu: usize) -> ::threads::ThreadPtr
+{
+	let p = S_CPUS[cpu].idle_thread.load(Ordering::Relaxed);
+	assert!(p != 0, "No idle thread for CPU{}", cpu);
+	// SAFE: Pointer came from `set_idle_thread`, and idle threads are never freed
+	unsafe {
+		::threads::ThreadPtr::new_static( &mut *(p as *mut ::threads::Thread) )
+	}
+}
+
+/// Record the running flag of the thread being switched away from
+pub fn set_prev_running(cpu: usize, flag: &AtomicBool)
+{
+	S_CPUS[cpu].prev_running.store(flag as *const _ as usize, Ordering::Relaxed);
+}
+/// Obtain (and clear) the flag set by `set_prev_running`
+pub fn take_prev_running(cpu: usize) -> Option<&'static AtomicBool>
+{
+	match S_CPUS[cpu].prev_running.swap(0, Ordering::Relaxed)
+	{
+	0 => None,
+	// SAFE: The flag is part of a thread that can't be freed until it's cleared
+	p => Some(unsafe { &*(p as *const AtomicBool) }),
+	}
+}
+
+/// Interrupt the specified CPU so it re-checks its run queue
+pub fn send_reschedule(cpu: usize)
+{
+	assert!(cpu < num_cpus());
+	super::hw::apic::send_reschedule( S_CPUS[cpu].apic_id.load(Ordering::Relaxed) as u32 );
+}
+
+/// Record the address space that the specified CPU is about to switch to (called before CR3 is loaded)
+pub fn set_cur_cr3(cpu: usize, cr3: u64)
+{
+	S_CPUS[cpu].cur_cr3.store(cr3 as usize, Ordering::SeqCst);
+}
+
+/// Invalidate a page (or with `FLUSH_ALL`, the entire TLB) on all other CPUs that could have it cached
+///
+/// Global (kernel) pages are invalidated everywhere, user pages only on CPUs running the current address space.
+pub fn tlb_shootdown(addr: usize)
+{
+	if num_cpus() <= 1 {
+		return ;
+	}
+	let _irq_lock = ::sync::hold_interrupts();
+	let cur = super::threads::cpu_num();
+	let everywhere = addr == FLUSH_ALL || super::memory::addresses::is_global(addr);
+	let cr3 = get_cr3();
+
+	// Post a request to each affected CPU (each initiator has its own slot, so no lock is needed)
+	let mut sent = false;
+	for (i,c) in S_CPUS.iter().enumerate()
+	{
+		if i == cur || !c.online.load(Ordering::Relaxed) {
+			continue ;
+		}
+		// NOTE: A CPU that switches to this address space after this check loads the updated tables
+		if !everywhere && c.cur_cr3.load(Ordering::SeqCst) != cr3 {
+			continue ;
+		}
+		c.tlb_requests[cur].store(addr | 1, Ordering::SeqCst);
+		super::hw::apic::send_tlb_shootdown( c.apic_id.load(Ordering::Relaxed) as u32 );
+		sent = true;
+	}
+	if !sent {
+		return ;
+	}
+	// Wait for the targets to acknowledge, handling incoming requests (the other initiators are waiting on this CPU)
+	for c in S_CPUS.iter()
+	{
+		while c.tlb_requests[cur].load(Ordering::Acquire) != 0 {
+			handle_tlb_shootdown();
+		}
+	}
+}
+
+/// TLB shootdown IPI handler
+#[is_safe(irq)]
+pub fn handle_tlb_shootdown()
+{
+	let c = cur_cpu();
+	for req in c.tlb_requests.iter()
+	{
+		let v = req.load(Ordering::Acquire);
+		if v != 0 {
+			invalidate( if v == FLUSH_ALL { FLUSH_ALL } else { v & !1 } );
+			req.store(0, Ordering::Release);
+		}
+	}
+}
+
+fn get_cr3() -> usize
+{
+	// SAFE: Reading CR3 has no side-effects
+	unsafe { let v: usize; asm!("mov %cr3, $0" : "=r" (v)); v }
+}
+
+fn invalidate(addr: usize)
+{
+	if addr == FLUSH_ALL {
+		// SAFE: Toggling CR4.PGE flushes the TLB (including global pages), and has no other effect
+		unsafe {
+			asm!("mov %cr4, %rax; xor $$0x80, %rax; mov %rax, %cr4; xor $$0x80, %rax; mov %rax, %cr4" : : : "rax" : "volatile");
+		}
+	}
+	else {
+		// SAFE: Cannot cause memory unsafety
+		unsafe {
+			asm!("invlpg ($0)" : : "r" (addr) : "memory" : "volatile");
+		}
+	}
+}
+
+// vim: ft=rust
@@ -196,6 +196,66 @@ start64:
 strNot64BitCapable:
 	db "ERROR: CPU doesn't support 64-bit operation",0
 
+;;
+;; Application processor startup
+;;
+; Real-mode trampoline, copied to AP_TRAMPOLINE_ADDR by smp.rs before the startup IPI is sent
+; - Entered with CS=AP_TRAMPOLINE_ADDR/16, IP=0
+[BITS 16]
+EXPORT ap_trampoline
+	cli
+	mov ax, cs
+	mov ds, ax
+	; Load a GDT with 32-bit segments (the kernel GDT only has 64-bit ones)
+	lgdt [ap_trampoline_gdtptr - ap_trampoline]
+	mov eax, cr0
+	or al, 1	; PE
+	mov cr0, eax
+	jmp dword 0x08:ap_start32
+ap_trampoline_gdt:
+	dd 0, 0
+	dd 0x0000FFFF, 0x00CF9A00	; 0x08: 32-bit Code
+	dd 0x0000FFFF, 0x00CF9200	; 0x10: 32-bit Data
+ap_trampoline_gdtptr:
+	dw	ap_trampoline_gdtptr - ap_trampoline_gdt - 1
+	dd	AP_TRAMPOLINE_ADDR + ap_trampoline_gdt - ap_trampoline
+EXPORT ap_trampoline_end
+
+[section .inittext]
+[BITS 32]
+; Same mode switch as the BSP (see `start`), the low identity mapping is restored by smp.rs while APs start
+ap_start32:
+	mov ax, 0x10
+	mov ds, ax
+	mov es, ax
+	mov ss, ax
+	
+	; PGE, PAE, PSE, OSFXSR, OSXMMEXCPT
+	mov eax, cr4
+	or eax, 0x80|0x20|0x10
+	or ax, (1 << 9)|(1 << 10)
+	mov cr4, eax
+	
+	mov eax, low_InitialPML4
+	mov cr3, eax
+	
+	mov ecx, 0xC0000080
+	rdmsr
+	or eax, (1 << 11)|(1 << 8)|(1 << 0)	; NXE, LME, SCE
+	wrmsr
+	
+	; PG, WP, TS, MP, ~EM
+	mov eax, cr0
+	or eax, 0x80010000|(1 << 3)|(1 << 1)
+	and ax, ~(1 << 2)
+	mov cr0, eax
+	lgdt [GDTPtr - KERNEL_BASE]
+	jmp 0x08:ap_start64
+[BITS 64]
+ap_start64:
+	mov rax, ap_start64_higher
+	jmp rax
+
 [section .text]
 [extern kmain]
 start64_higher:
@@ -240,7 +300,20 @@ start64_higher:
 	mov al, 10
 	out dx, al
 	
-	; Bind the 'SYSCALL' handler (and set flags for it)
+	call syscall_init
+	
+	mov rax, InitialPML4
+	mov QWORD [rax], 0
+	; 7. Call rust kmain
+	call kmain
+.dead_loop:
+	cli
+	hlt
+	jmp .dead_loop
+
+; Bind the 'SYSCALL' handler (and set flags for it)
+; - Per-CPU MSRs, so called by both the BSP and APs
+syscall_init:
 	; LSTAR = 0xC000_0082
 	mov rax, syscall_handler
 	mov rdx, rax
@@ -257,15 +330,42 @@ start64_higher:
 	mov edx, 0
 	mov ecx, 0xC0000084
 	wrmsr
+	ret
+
+[extern ap_entry]
+ap_start64_higher:
+	lgdt [a32 DWORD GDTPtr2 - KERNEL_BASE]
+	mov ax, 0x10
+	mov ds, ax
+	mov ss, ax
+	mov es, ax
+	mov fs, ax
+	mov gs, ax
 	
-	mov rax, InitialPML4
-	mov QWORD [rax], 0
-	; 7. Call rust kmain
-	call kmain
-.dead_loop:
-	cli
-	hlt
-	jmp .dead_loop
+	; Switch to the idle thread's stack and TLS block (prepared by smp.rs)
+	mov rsp, [rel s_ap_boot_rsp]
+	mov rax, [rel s_ap_boot_tls]
+	mov rdx, rax
+	shr rdx, 32
+	mov ecx, 0xC0000100	; FS Base
+	wrmsr
+	mov ecx, 0xC0000101	; GS Base
+	wrmsr
+	
+	mov rax, IDTPtr
+	lidt [rax]
+	call syscall_init
+	
+	; Per-CPU initialisation (in rust)
+	mov rbp, rsp
+	and rsp, ~0xF
+	call ap_entry
+	mov rsp, rbp
+	
+	; Start the idle thread (same as the tail of task_switch)
+	RESTORE rbx, r12, r13, r14, r15
+	pop rbp
+	ret
 
 %include "Core/arch/amd64/interrupts.inc.asm"
 
@@ -285,10 +385,8 @@ EXPORT task_switch
 	mov cr3, rcx	; New CR3
 	invlpg [rsp]
 	
-	; Update stack top (RSP0) and TLS base (GS)
-	; TLS base and stack top are the same address.
-	; TODO: This assumes uniprocessor
-	mov [rel TSSes+tss.rsp0], rdx
+	; Update TLS base (GS)
+	; - The stack top (RSP0) is updated by the caller, as it's per-CPU
 	mov rax, rdx
 	shr rdx, 32	; EDX = High
 	mov ecx, 0xC0000101	; GS Base
@@ -324,6 +422,7 @@ EXPORT drop_to_user
 	mov gs, ax
 	mov rsp, rsi	; User's stack
 	mov rax, rdx	; Argument passed in RAX
+	mov rdi, rdx	; - and in RDI (first SysV argument, used by thread entrypoints)
 	db 0x48
 	sysret
 
@@ -548,6 +647,11 @@ IDTPtr:
 	dq	IDT
 EXPORT s_tid0_tls_base
 	dq	0
+; Stack and TLS base for the next AP to start (see smp.rs)
+EXPORT s_ap_boot_rsp
+	dq	0
+EXPORT s_ap_boot_tls
+	dq	0
 
 [section .bss]
 EXPORT TSSes
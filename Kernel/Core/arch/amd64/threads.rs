@@ -4,6 +4,7 @@
 // Core/arch/amd64/threads.rs
 //! Architecture-level thread handling (helpers for ::threads).
 use prelude::*;
+use core::sync::atomic::{AtomicBool,Ordering};
 
 #[derive(Default)]//,Copy,Clone)]
 /// Low-level thread state
@@ -12,6 +13,8 @@ pub struct State
 	cr3: u64,
 	rsp: u64,
 	tlsbase: u64,
+	/// Set while the thread is executing on a CPU (or that CPU is still saving its state)
+	is_running: AtomicBool,
 	// Not strictly part of the CPU state, but it prevents this thread's stack from disappearing
 	stack_handle: Option< ::memory::virt::ArrayHandle<u8> >,
 	// TODO: SSE state 
@@ -31,7 +34,6 @@ extern "C" {
 }
 
 pub static S_IRQS_ENABLED: ::core::sync::atomic::AtomicBool = ::core::sync::atomic::ATOMIC_BOOL_INIT;
-static mut S_IDLE_THREAD: *mut ::threads::Thread = 0 as *mut _;
 
 #[repr(C)]
 /// Thread-local-storage block
@@ -48,6 +50,8 @@ struct TLSData {
 	// Free to reorder these
 	thread_ptr: *mut ::threads::Thread,
 	thread_ptr_lent: bool,
+	/// Index of the CPU this thread is running on (updated by `switch_to`)
+	cpu_idx: usize,
 	
 	sse_registers: Option<Box<SSERegisters>>,
 }
@@ -56,10 +60,7 @@ struct TLSData {
 /// Returns the thread state for TID0 (aka the kernel's core thread)
 pub fn init_tid0_state() -> State
 {
-	// SAFE: Called in single-threaded context... hopefully (TODO)
-	unsafe {
-		S_IDLE_THREAD = ::core::mem::transmute( ::threads::new_idle_thread(0) );
-	}
+	super::smp::set_idle_thread(0, ::threads::new_idle_thread(0));
 	// SAFE: Just taking the address
 	let cr3 = unsafe { &InitialPML4 as *const _ as u64 - super::memory::addresses::IDENT_START as u64 };
 	log_debug!("init_tid0_state - cr3 = {:#x}", cr3);
@@ -68,10 +69,24 @@ pub fn init_tid0_state() -> State
 		rsp: 0,
 		// SAFE: Doesn't change outside rust control
 		tlsbase: unsafe { s_tid0_tls_base },
+		is_running: AtomicBool::new(true),
 		stack_handle: None,
 		}
 }
 
+/// Prepare an AP's idle thread to be started by that AP, returning the initial stack pointer and TLS base
+pub fn prep_ap_thread(thread: &mut ::threads::Thread, cpu: usize) -> (u64, u64)
+{
+	let state = &mut thread.cpu_state;
+	// The AP starts executing as this thread (see `ap_start64_higher`)
+	state.is_running.store(true, Ordering::Relaxed);
+	// SAFE: The TLS block is valid (created by `start_thread`), and the thread isn't running yet
+	unsafe {
+		(*(state.tlsbase as *mut TLSData)).cpu_idx = cpu;
+	}
+	(state.rsp, state.tlsbase)
+}
+
 impl State
 {
 	/// Construct a new empty CPU state using the provided address space
@@ -82,6 +97,14 @@ impl State
 		rv
 	}
 }
+impl Drop for State
+{
+	fn drop(&mut self) {
+		// A terminated thread can be reaped by another CPU before its own CPU has finished switching away from it
+		while self.is_running.load(Ordering::Acquire) {
+		}
+	}
+}
 
 /// Idle for a short period, called when the CPU has nothing else to do
 pub fn idle()
@@ -115,6 +138,7 @@ pub unsafe extern "C" fn prep_tls(top: usize, _bottom: usize, thread_ptr: *mut :
 		
 		thread_ptr: thread_ptr,
 		thread_ptr_lent: false,
+		cpu_idx: 0,
 		sse_registers: None,
 		});
 	
@@ -175,6 +199,8 @@ pub fn start_thread<F: FnOnce()+Send>(thread: &mut ::threads::Thread, code: F)
 		fn thread_trampoline();
 	}
 	fn thread_root<F: FnOnce()+Send>(code_ptr: *const F) -> ! {
+		// A new thread doesn't return through `switch_to`, so complete the switch here
+		finish_switch();
 		// Copy the closure locally
 		// - TODO: Find a way that avoids needing to make this unnessesary copy. By-value FnOnce is kinda undefined, sadly
 		// SAFE: Functionally owns that pointer
@@ -182,19 +208,33 @@ pub fn start_thread<F: FnOnce()+Send>(thread: &mut ::threads::Thread, code: F)
 		// 1. Run closure
 		code();
 		// 2. terminate thread
-		panic!("TODO: Terminate thread at end of thread_root");
+		::threads::terminate_thread();
 	}
 }
 
 pub fn get_idle_thread() -> ::threads::ThreadPtr
 {
-	// TODO: Shared mutability shouldn't be an issue (this thread pointer should not be created twice)
-	// SAFE: Passes a static pointer. `static mut` should be initialised
+	super::smp::get_idle_thread(cpu_num())
+}
+
+/// Index of the current CPU
+pub fn cpu_num() -> usize
+{
+	// SAFE: Valid pointer access
 	unsafe {
-		assert!(S_IDLE_THREAD != 0 as *mut _);
-		::threads::ThreadPtr::new_static( &mut *S_IDLE_THREAD )
+		(*get_tls_ptr()).cpu_idx
 	}
 }
+/// Number of CPUs online
+pub fn num_cpus() -> usize
+{
+	super::smp::num_cpus()
+}
+/// Send a reschedule IPI to the specified CPU
+pub fn send_reschedule(cpu: usize)
+{
+	super::smp::send_reschedule(cpu)
+}
 
 /// Switch to the passed thread (suspending the current thread until it is rescheduled)
 pub fn switch_to(newthread: ::threads::ThreadPtr)
@@ -232,6 +272,7 @@ pub fn switch_to(newthread: ::threads::ThreadPtr)
 		// SAFE: Valid pointer accesses, task_switch trusted
 		unsafe
 		{
+			let cpu = cpu_num();
 			let outstate = &mut (*(*get_tls_ptr()).thread_ptr).cpu_state;
 			let state = &newthread.cpu_state;
 			// Don't assert RSP, could be switching to self
@@ -242,8 +283,21 @@ pub fn switch_to(newthread: ::threads::ThreadPtr)
 			
 			assert!( *(outstate.tlsbase as *const usize) != 0, "outstate TLS Base clobbered before switch" );
 			assert!( *(state.tlsbase as *const usize) != 0, "TLS Base clobbered before switch" );
+			if state as *const _ != outstate as *const _
+			{
+				// Wait until the thread's previous CPU has saved its state
+				while state.is_running.compare_and_swap(false, true, Ordering::Acquire) {
+				}
+				// - The flag on the outgoing thread is released once it's saved (by `finish_switch`)
+				super::smp::set_prev_running(cpu, &outstate.is_running);
+			}
+			(*(state.tlsbase as *mut TLSData)).cpu_idx = cpu;
+			super::tss::set_kernel_stack(cpu, state.tlsbase);
+			// - Published before CR3 is loaded, so TLB shootdowns for the new address space reach this CPU
+			super::smp::set_cur_cr3(cpu, state.cr3);
 			task_switch(&mut outstate.rsp, &state.rsp, state.tlsbase, state.cr3);
 		}
+		finish_switch();
 		
 		if EAGER_SSE_ENABLE {
 			// If the task is using SSE, enable SSE here
@@ -268,6 +322,14 @@ pub fn switch_to(newthread: ::threads::ThreadPtr)
 	}
 }
 
+/// Complete a task switch, releasing the thread that this CPU switched away from
+fn finish_switch()
+{
+	if let Some(flag) = super::smp::take_prev_running(cpu_num()) {
+		flag.store(false, Ordering::Release);
+	}
+}
+
 fn get_tls_ptr() -> *mut TLSData {
 	let ret;
 	// SAFE: Just obtains the pointer from %gs
@@ -320,17 +382,18 @@ pub fn set_thread_ptr(ptr: ::threads::ThreadPtr)
 /// Disable task switching until corresponding `enable_task_switch` call
 pub fn disable_task_switch()
 {
-	// TODO: increment CPU-local counter representing task switch state
+	super::smp::cur_cpu().task_switch_disable.fetch_add(1, Ordering::Relaxed);
 }
 /// Re-enable task switching
 pub fn enable_task_switch()
 {
+	let prev = super::smp::cur_cpu().task_switch_disable.fetch_sub(1, Ordering::Relaxed);
+	assert!(prev > 0, "enable_task_switch called without matching disable");
 }
 /// Returns true is task switching is enabled
 pub fn is_task_switching_disabled() -> bool
 {
-	// Return (s_cpu_local.
-	false
+	super::smp::cur_cpu().task_switch_disable.load(Ordering::Relaxed) > 0
 }
 
 /// Enable SSE for this thread
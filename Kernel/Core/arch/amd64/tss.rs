@@ -7,8 +7,7 @@
 // Just a run-of-the-mill module, as it's not needed until the switch to usermode
 module_define!(TSS, [], init);
 
-// NOTE: MUST match the value in common.inc.asm
-const MAX_CPUS: usize = 1;
+use super::smp::MAX_CPUS;
 
 #[repr(C,packed)]
 struct TSS
@@ -26,9 +25,13 @@ struct TSS
 #[repr(C,packed)]
 struct GDTEnt(u32,u32);
 
+/// Interrupt stack table slot used for double faults (so a kernel stack overflow can be reported)
+const IST_DOUBLE_FAULT: usize = 1;
+
 extern "C" {
 	static mut GDT: [GDTEnt; 7+MAX_CPUS*2];
-	static mut TSSes: [TSS; 4];
+	static mut TSSes: [TSS; MAX_CPUS];
+	static mut IDT: [[u32; 4]; 256];
 	
 	static s_tid0_tls_base: u64;
 }
@@ -44,10 +47,50 @@ fn init()
 		}
 		TSSes[0].rsp0 = s_tid0_tls_base as u64;
 	}
+	set_fault_stack(0);
+	// SAFE: Single-threaded, and the IST entry has just been populated
+	unsafe {
+		IDT[8][1] |= IST_DOUBLE_FAULT as u32;
+	}
 	
-	// SAFE: Just setting the task register
+	load_task_register(0);
+}
+
+/// Load the task register for an application processor
+pub fn init_ap(cpu: usize)
+{
+	assert!(cpu < MAX_CPUS);
+	set_fault_stack(cpu);
+	load_task_register(cpu);
+}
+
+/// Allocate the stack used for double faults on this CPU (the faulting stack may have overflowed)
+fn set_fault_stack(cpu: usize)
+{
+	let stack = ::memory::virt::alloc_stack().into_array::<u8>();
+	let top = (&stack[stack.len()-1] as *const _ as usize + 1) as u64;
+	::core::mem::forget(stack);
+	// SAFE: Each CPU only updates its own TSS
+	unsafe {
+		TSSes[cpu].ists[IST_DOUBLE_FAULT-1] = top;
+	}
+}
+
+fn load_task_register(cpu: usize)
+{
+	// SAFE: Just setting the task register (to a descriptor populated by `init`)
+	unsafe {
+		asm!("ltr %cx" : : "{ecx}" ((7 + cpu*2)*8) );
+	}
+}
+
+/// Set the stack used when a CPU enters the kernel from userland
+pub fn set_kernel_stack(cpu: usize, top: u64)
+{
+	assert!(cpu < MAX_CPUS);
+	// SAFE: Each CPU only updates its own TSS
 	unsafe {
-		asm!("ltr %cx" : : "{ecx}" (7*8) );
+		TSSes[cpu].rsp0 = top;
 	}
 }
 
@@ -34,6 +34,8 @@ pub mod addresses {
 	const MAX_RAM_BYTES: usize = 2*1024*1024*1024;
 	pub const PMEMREF_BASE: usize = 0xB00_00000;
 	pub const PMEMREF_END : usize = PMEMREF_BASE + MAX_RAM_BYTES / ::PAGE_SIZE * 4;	// 4 bytes / 8KB frame = 1MB?
+	pub const PMEMBM_BASE: usize = PMEMREF_END;
+	pub const PMEMBM_END : usize = PMEMBM_BASE + MAX_RAM_BYTES / ::PAGE_SIZE / 8;	// 1 bit / 8KB frame = 32KB
 	
 
 	pub const TEMP_BASE: usize = 0xEFF_00000;
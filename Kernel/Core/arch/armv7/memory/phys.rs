@@ -6,12 +6,14 @@
 //!
 //! Handles reference counting and allocation bitmaps
 //use prelude::*;
-use arch::imp::memory::addresses::{PMEMREF_BASE,PMEMREF_END/*,PMEMBM_BASE,PMEMBM_END*/};
+use arch::imp::memory::addresses::{PMEMREF_BASE,PMEMREF_END,PMEMBM_BASE,PMEMBM_END};
 use sync::{RwLock,AtomicU32};
 use core::sync::atomic::Ordering;
 use memory::page_array::PageArray;
 
 static S_REFCOUNT_ARRAY: RwLock<PageArray<AtomicU32>> = RwLock::new( PageArray::new(PMEMREF_BASE, PMEMREF_END) );
+/// Allocated frame bitmap (not locked, as pages are only ever added and updates are atomic)
+static S_USED_BITMAP: PageArray<AtomicU32> = PageArray::new(PMEMBM_BASE, PMEMBM_END);
 
 pub fn ref_frame(frame_idx: u64) {
 	with_ref_alloc( frame_idx, |r| r.fetch_add(1, Ordering::Acquire) );
@@ -30,13 +32,58 @@ pub fn get_multiref_count(frame_idx: u64) -> u32 {
 	with_ref( frame_idx, |r| r.load(Ordering::Relaxed) ).unwrap_or(0)
 }
 
+/// Returns true if the frame was marked as allocated
 pub fn mark_free(frame_idx: u64) -> bool {
-	log_warning!("TODO: mark_free - frame_idx={:#x} ({:#x})", frame_idx, frame_idx*::PAGE_SIZE as u64);
-	// HACK: Assume it was used
-	true
+	let mask = 1 << ((frame_idx % 32) as usize);
+	with_bm( (frame_idx / 32) as usize, |c| {
+		let mut old = c.load(Ordering::Relaxed);
+		loop
+		{
+			if old & mask == 0 {
+				// Bit was clear, frame was already free?
+				return false;
+			}
+			let new_old = c.compare_and_swap(old, old & !mask, Ordering::Relaxed);
+			if old == new_old {
+				return true;
+			}
+			old = new_old;
+		}
+		}).unwrap_or(false)
 }
 pub fn mark_used(frame_idx: u64) {
-	log_warning!("TODO: mark_used - frame_idx={:#x} ({:#x})", frame_idx, frame_idx*::PAGE_SIZE as u64);
+	let mask = 1 << ((frame_idx % 32) as usize);
+	with_bm( (frame_idx / 32) as usize, |c| {
+		let mut old = c.load(Ordering::Relaxed);
+		loop
+		{
+			let new_old = c.compare_and_swap(old, old | mask, Ordering::Relaxed);
+			if old == new_old {
+				break ;
+			}
+			old = new_old;
+		}
+		});
+}
+/// Returns true if the frame is allocated (frames outside the bitmap are treated as allocated)
+pub fn is_used(frame_idx: u64) -> bool {
+	let mask = 1 << ((frame_idx % 32) as usize);
+	with_bm( (frame_idx / 32) as usize, |c| c.load(Ordering::Relaxed) & mask != 0 ).unwrap_or(true)
+}
+
+/// Allocate the allocation bitmap for all frames below `max_frame_idx`
+///
+/// Done up-front, as allocating on demand would recurse into the frame allocator
+pub fn init_bitmap(max_frame_idx: u64) -> bool {
+	let words_per_page = ::PAGE_SIZE / 4;
+	let n_words = (max_frame_idx as usize + 31) / 32;
+	for ofs in (0 .. n_words).step_by(words_per_page) {
+		if ! S_USED_BITMAP.ensure_page(ofs) {
+			log_error!("init_bitmap - Unable to allocate bitmap for frames from {:#x}", ofs * 32);
+			return false;
+		}
+	}
+	true
 }
 
 
@@ -49,3 +96,7 @@ fn with_ref_alloc<U, F: FnOnce(&AtomicU32)->U>(frame_idx: u64, fcn: F) -> U
 	let mut lh = S_REFCOUNT_ARRAY.write();
 	fcn( lh.get_alloc(frame_idx as usize) )
 }
+fn with_bm<U, F: FnOnce(&AtomicU32)->U>(ofs: usize, fcn: F) -> Option<U>
+{
+	S_USED_BITMAP.get(ofs).map(fcn)
+}
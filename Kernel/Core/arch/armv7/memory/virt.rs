@@ -594,6 +594,16 @@ pub unsafe fn reprotect(a: *mut (), mode: ProtectionMode) {
 		tlbimva( (a as usize + 0x1000) as *mut () );
 	}
 }
+// TODO: Support lazy (demand-paged) entries, the VMM fills pages immediately when these fail
+pub unsafe fn map_lazy(_a: *mut (), _mode: ProtectionMode) -> bool {
+	false
+}
+pub fn get_lazy<T>(_a: *const T) -> Option<ProtectionMode> {
+	None
+}
+pub unsafe fn fill_lazy(_a: *mut (), _p: PAddr, _mode: ProtectionMode) -> bool {
+	false
+}
 pub unsafe fn unmap(a: *mut ()) -> Option<PAddr> {
 	log_debug!("unmap({:p})", a);
 	return unmap_int(a);
@@ -679,6 +689,12 @@ impl AddressSpace
 	}
 
 	pub fn get_ttbr0(&self) -> u32 { self.0 }
+
+	/// Count the number of resident pages in the user portion of this address space
+	pub fn resident_pages(&self) -> usize {
+		// TODO: Walk the user tables (not yet tracked, so report nothing)
+		0
+	}
 }
 
 
@@ -705,17 +721,34 @@ pub fn data_abort_handler(pc: u32, reg_state: &AbortRegs, dfar: u32, dfsr: u32)
 	
 	let mut ent = PageEntry::get(dfar as usize as *const ());
 	if ent.mode() == ProtectionMode::UserCOW {
+		let mut copied = false;
 		// 1. Lock (relevant) address space
 		// SAFE: Changes to address space are transparent
 		::memory::virt::with_lock(dfar as usize, || unsafe {
 			let frame = ent.phys_addr();
 			// 2. Get the PMM to provide us with a unique copy of that frame (can return the same addr)
-			let newframe = ::memory::phys::make_unique( frame, &*(((dfar as usize) & !PAGE_MASK) as *const [u8; PAGE_SIZE]) );
-			// 3. Remap to this page as UserRW (because COW is user-only atm)
-			ent.set(newframe, ProtectionMode::UserRW);
-			log_debug!("- COW frame copied");
+			if let Ok(newframe) = ::memory::phys::make_unique( frame, &*(((dfar as usize) & !PAGE_MASK) as *const [u8; PAGE_SIZE]) ) {
+				// 3. Remap to this page as UserRW (because COW is user-only atm)
+				ent.set(newframe, ProtectionMode::UserRW);
+				log_debug!("- COW frame copied");
+				copied = true;
+			}
 			});
 
+		if copied {
+			return ;
+		}
+		log_error!("Out of memory copying COW page {:#x}", dfar);
+	}
+	// Write to a clean page of a writeback file mapping
+	if ::memory::vma::handle_write_fault(dfar as usize) {
+		return ;
+	}
+	// Stack overflow (kernel guard page), or growth of a user stack
+	if ::memory::virt::is_stack_guard(dfar as usize) {
+		::threads::log_stack_overflow(dfar as usize);
+	}
+	else if ::memory::vma::handle_stack_fault(dfar as usize) {
 		return ;
 	}
 	
@@ -109,6 +109,10 @@ pub fn puth(v: u64) {
 pub fn cur_timestamp() -> u64 {
 	0
 }
+pub fn cycle_count() -> u64 {
+	// TODO: Read the cycle counter (PMCCNTR)
+	0
+}
 
 pub fn print_backtrace() {
 	let rs = aeabi_unwind::UnwindState::new_cur();
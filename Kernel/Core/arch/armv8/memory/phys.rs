@@ -35,8 +35,15 @@ pub fn mark_free(frame_idx: u64) -> bool {
 	// HACK: Assume it was used
 	true
 }
-pub fn mark_used(frame_idx: u64) {
-	log_warning!("TODO: mark_used - frame_idx={:#x} ({:#x})", frame_idx, frame_idx*::PAGE_SIZE as u64);
+pub fn mark_used(_frame_idx: u64) {
+	// TODO: Allocation bitmap (`mark_free` assumes that all frames are used)
+}
+pub fn is_used(_frame_idx: u64) -> bool {
+	true
+}
+pub fn init_bitmap(_max_frame_idx: u64) -> bool {
+	// No bitmap yet, frames are only allocated from the memory map
+	false
 }
 
 
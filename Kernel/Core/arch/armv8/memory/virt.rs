@@ -182,6 +182,18 @@ pub unsafe fn reprotect(addr: *const (), prot: ProtectionMode)
 {
 	todo!("reprotect");
 }
+pub unsafe fn map_lazy(addr: *const (), prot: ProtectionMode) -> bool
+{
+	false
+}
+pub fn get_lazy<T>(addr: *const T) -> Option<ProtectionMode>
+{
+	None
+}
+pub unsafe fn fill_lazy(addr: *const (), phys: u64, prot: ProtectionMode) -> bool
+{
+	false
+}
 pub unsafe fn unmap(addr: *const ()) -> Option<u64>
 {
 	None
@@ -226,5 +238,11 @@ impl AddressSpace
 	pub fn as_phys(&self) -> u64 {
 		self.0
 	}
+
+	/// Count the number of resident pages in the user portion of this address space
+	pub fn resident_pages(&self) -> usize {
+		// TODO: Walk the user tables (not yet tracked, so report nothing)
+		0
+	}
 }
 
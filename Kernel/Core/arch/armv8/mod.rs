@@ -48,6 +48,10 @@ pub fn print_backtrace() {
 pub fn cur_timestamp() -> u64 {
 	0
 }
+pub fn cycle_count() -> u64 {
+	// TODO: Read the cycle counter (PMCCNTR)
+	0
+}
 
 extern "C" {
 	pub fn drop_to_user(entry: usize, stack: usize, args_len: usize) -> !;
@@ -83,6 +83,15 @@ pub fn switch_to(thread: ::threads::ThreadPtr) {
 		task_switch(&mut outstate.sp, new_sp, new_ttbr0, thread.into_usize());
 	}
 }
+// TODO: SMP support (only the boot CPU is used)
+pub fn cpu_num() -> usize {
+	0
+}
+pub fn num_cpus() -> usize {
+	1
+}
+pub fn send_reschedule(_cpu: usize) {
+}
 pub fn idle() {
 	log_trace!("idle");
 	// SAFE: Calls 'wait for interrupt'
@@ -131,7 +140,7 @@ pub fn start_thread<F: FnOnce()+Send+'static>(thread: &mut ::threads::Thread, co
 		// SAFE: Functionally owns that pointer
 		(unsafe { ::core::ptr::read(code_ptr) })();
 		// 2. terminate thread
-		panic!("TODO: Terminate thread at end of thread_root");
+		::threads::terminate_thread();
 	}
 }
 
@@ -35,6 +35,9 @@ pub mod memory {
 			pub fn new(_cstart: usize, _cend: usize) -> Result<AddressSpace,()> {
 				todo!("AddressSpace::new");
 			}
+			pub fn resident_pages(&self) -> usize {
+				0
+			}
 		}
 
 		pub fn post_init() {
@@ -69,6 +72,15 @@ pub mod memory {
 
 		pub unsafe fn map(_a: *mut (), _p: ::memory::PAddr, _mode: ::memory::virt::ProtectionMode) {
 		}
+		pub unsafe fn map_lazy(_a: *mut (), _mode: ::memory::virt::ProtectionMode) -> bool {
+			false
+		}
+		pub fn get_lazy<T>(_p: *const T) -> Option<::memory::virt::ProtectionMode> {
+			None
+		}
+		pub unsafe fn fill_lazy(_a: *mut (), _p: ::memory::PAddr, _mode: ::memory::virt::ProtectionMode) -> bool {
+			false
+		}
 		pub unsafe fn reprotect(_a: *mut (), _mode: ::memory::virt::ProtectionMode) {
 		}
 		pub unsafe fn unmap(_a: *mut ()) -> Option<::memory::PAddr> {
@@ -90,6 +102,12 @@ pub mod memory {
 		}
 		pub fn mark_used(_frame_idx: u64) {
 		}
+		pub fn is_used(_frame_idx: u64) -> bool {
+			true
+		}
+		pub fn init_bitmap(_max_frame_idx: u64) -> bool {
+			false
+		}
 	}
 }
 pub mod sync {
@@ -185,6 +203,15 @@ pub mod threads {
 		::core::ptr::null()
 	}
 
+	pub fn cpu_num() -> usize {
+		0
+	}
+	pub fn num_cpus() -> usize {
+		1
+	}
+	pub fn send_reschedule(_cpu: usize) {
+	}
+
 	pub fn idle() {
 	}
 	pub fn get_idle_thread() -> ::threads::ThreadPtr {
@@ -215,6 +242,9 @@ pub fn puth(_v: u64) {
 pub fn cur_timestamp() -> u64 {
 	0
 }
+pub fn cycle_count() -> u64 {
+	0
+}
 pub fn print_backtrace() {
 }
 
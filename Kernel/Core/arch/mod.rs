@@ -183,6 +183,16 @@ pub mod memory {
 		pub fn mark_used(frame_idx: u64) {
 			imp::mark_used(frame_idx)
 		}
+		#[inline]
+		/// Returns true if the frame is marked as allocated (or isn't tracked by the bitmap)
+		pub fn is_used(frame_idx: u64) -> bool {
+			imp::is_used(frame_idx)
+		}
+		#[inline]
+		/// Prepare the allocation bitmap for all frames below `max_frame_idx`, returning false if it's unavailable
+		pub fn init_bitmap(max_frame_idx: u64) -> bool {
+			imp::init_bitmap(max_frame_idx)
+		}
 	}
 }
 
@@ -274,6 +284,22 @@ pub mod threads {
 		imp::borrow_thread()
 	}
 
+	#[inline]
+	/// Index of the current CPU (between zero and `num_cpus`)
+	pub fn cpu_num() -> usize {
+		imp::cpu_num()
+	}
+	#[inline]
+	/// Number of CPUs currently online
+	pub fn num_cpus() -> usize {
+		imp::num_cpus()
+	}
+	#[inline]
+	/// Interrupt the specified CPU so it re-checks its run queue
+	pub fn send_reschedule(cpu: usize) {
+		imp::send_reschedule(cpu)
+	}
+
 	#[inline]
 	pub fn idle() {
 		imp::idle()
@@ -326,6 +352,10 @@ pub fn cur_timestamp() -> u64 {
 	imp::cur_timestamp()
 }
 #[inline]
+pub fn cycle_count() -> u64 {
+	imp::cycle_count()
+}
+#[inline]
 pub fn print_backtrace() {
 	imp::print_backtrace()
 }
@@ -88,6 +88,8 @@ def_config_set! {
 		Loader @ "LOADER" = "/sysroot/bin/loader",
 //		/// Startup - Init executable (first userland process)
 		Init @ "INIT" = "/sysroot/bin/init",
+//		/// Memory - Maximum size of a growable user stack (in KiB)
+		UserStackLimit @ "USER_STACK_LIMIT" = "1024",
 	}
 }
 
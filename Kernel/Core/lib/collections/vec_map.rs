@@ -83,6 +83,11 @@ impl<K: Ord, V> VecMap<K,V>
 		}
 	}
 	
+	/// Remove all items for which the predicate returns `false`
+	pub fn retain<F: FnMut(&K, &V)->bool>(&mut self, mut f: F) {
+		self.ents.retain(|e| f(&e.0, &e.1))
+	}
+	
 	/// Return an 'entry' in the map, allowing cheap handling of insertion/lookup
 	pub fn entry(&mut self, key: K) -> Entry<K, V>
 	{
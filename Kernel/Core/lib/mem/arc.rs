@@ -25,6 +25,19 @@ impl<T> Arc<T>
 	{
 		Arc { _inner: Grc::new(value) }
 	}
+
+	/// Obtain a non-owning pointer to the allocation, for use with `try_from_raw`
+	pub fn as_raw(this: &Arc<T>) -> *const () {
+		this._inner.as_raw()
+	}
+	/// Obtain a new reference from a pointer returned by `as_raw`
+	///
+	/// Returns `None` if the last reference has already been dropped.
+	///
+	/// UNSAFE: The allocation must not have been freed (i.e. the caller must synchronise with `T`'s destructor)
+	pub unsafe fn try_from_raw(ptr: *const ()) -> Option<Arc<T>> {
+		Grc::try_from_raw(ptr).map(|v| Arc { _inner: v })
+	}
 }
 
 
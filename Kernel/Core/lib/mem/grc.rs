@@ -17,6 +17,8 @@ pub trait Counter {
 	fn is_zero(&self) -> bool;
 	fn is_one(&self) -> bool;
 	fn inc(&self);
+	/// Increment only if the count is non-zero, returning `false` if it was zero
+	fn inc_nonzero(&self) -> bool;
 	fn dec(&self) -> bool;
 }
 
@@ -47,6 +49,7 @@ impl Counter for ::core::cell::Cell<usize> {
 	fn is_zero(&self) -> bool { self.get() == 0 }
 	fn is_one(&self) -> bool { self.get() == 1 }
 	fn inc(&self) { self.set( self.get() + 1 ) }
+	fn inc_nonzero(&self) -> bool { if self.get() == 0 { false } else { self.inc(); true } }
 	fn dec(&self) -> bool { self.set( self.get() - 1 ); self.is_zero() }
 }
 /// Atomic unsigned integer
@@ -57,6 +60,17 @@ impl Counter for AtomicUsize {
 	fn is_zero(&self) -> bool { self.load(Ordering::SeqCst) == 0 }
 	fn is_one(&self) -> bool { self.load(Ordering::SeqCst) == 1 }
 	fn inc(&self) { self.fetch_add(1, Ordering::Acquire); }
+	fn inc_nonzero(&self) -> bool {
+		let mut cur = self.load(Ordering::Relaxed);
+		while cur != 0 {
+			match self.compare_exchange_weak(cur, cur+1, Ordering::Acquire, Ordering::Relaxed)
+			{
+			Ok(_) => return true,
+			Err(v) => cur = v,
+			}
+		}
+		false
+	}
 	fn dec(&self) -> bool { self.fetch_sub(1, Ordering::Acquire) == 1 }
 }
 
@@ -94,6 +108,26 @@ impl<C: Counter, T: ?Sized> Grc<C, T>
 		}
 	}
 }
+impl<C: Counter, T> Grc<C, T>
+{
+	/// Obtain a non-owning pointer to the allocation (see `try_from_raw`)
+	pub fn as_raw(&self) -> *const () {
+		self.ptr.as_ptr() as *const ()
+	}
+	/// Obtain a new reference from a pointer returned by `as_raw`, fails if the last reference has been dropped
+	///
+	/// UNSAFE: The caller must ensure that the allocation has not been freed (e.g. by synchronising with the
+	/// value's destructor)
+	pub unsafe fn try_from_raw(ptr: *const ()) -> Option<Self> {
+		let ptr = ptr as *mut GrcInner<C, T>;
+		if (*ptr).strong.inc_nonzero() {
+			Some(Grc { ptr: NonNull::new_unchecked(ptr) })
+		}
+		else {
+			None
+		}
+	}
+}
 /// Create an allocation using the interior's default
 impl<C: Counter, T: Default> Default for Grc<C, T> {
 	fn default() -> Grc<C, T> {
@@ -52,12 +52,15 @@ impl<T> SparseVec<T>
 		self.count += 1;
 		self.data.len() - 1
 	}
-	/// Remove the item at the specified location
-	pub fn remove(&mut self, idx: usize) {
+	/// Remove the item at the specified location (returning it)
+	pub fn remove(&mut self, idx: usize) -> Option<T> {
 		if idx < self.data.len() && self.data[idx].is_some()
 		{
-			self.data[idx] = None;
 			self.count -= 1;
+			self.data[idx].take()
+		}
+		else {
+			None
 		}
 	}
 	
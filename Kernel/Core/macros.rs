@@ -169,4 +169,10 @@ macro_rules! mutex_init{ ($val:expr) => ($crate::sync::mutex::Mutex::new($val))
 #[macro_export]
 macro_rules! lazymutex_init{ () => ($crate::sync::mutex::LazyMutex::new())}
 
+/// Initialise a per-CPU array (`[T; threads::MAX_CPUS]`), with one copy of `$val` for each CPU
+///
+/// NOTE: The entry count must match `threads::MAX_CPUS` (checked at compile time by `threads::PER_CPU_INIT_CHECK`)
+#[macro_export]
+macro_rules! per_cpu_init{ ($val:expr) => ([$val, $val, $val, $val, $val, $val, $val, $val]) }
+
 // vim: ft=rust
@@ -76,6 +76,8 @@ pub mod memory;
 pub mod threads;
 /// Timekeeping (timers and wall time)
 pub mod time;
+/// Random number source
+pub mod rand;
 
 /// Module management (loading and initialisation of kernel modules)
 pub mod modules;
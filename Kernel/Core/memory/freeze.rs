@@ -4,13 +4,31 @@
 // Core/memory/freeze.rs
 //! Borrow checking/enforcement for the user-kernel boundary
 //!
-//! Works by marking the referenced pages as being frozen, and either kernel-only (for mut) or read-only (for non-mut)
-//! A separate record is stored that contains finer-grained information, handling the case when frozen regions may overlap
-//! or at least share a page.
-//!
-//! The page-fault handler should handle the case of a user PF on a frozen page by sleeping that thread until the page is unfrozen.
-#[allow(unused_imports)]
+//! Frozen byte ranges are recorded in a per-process list. Shared freezes may overlap each other, but a mutable
+//! freeze can't overlap any other freeze. The pages containing a frozen range can't be unmapped or reprotected
+//! until the freeze is released (see `with_unfrozen`), so the kernel's borrow stays valid.
 use prelude::*;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use memory::virt::ProtectionMode;
+
+/// Source of freeze identifiers (zero is used for a freeze with no record)
+static S_NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// A frozen range of user memory
+struct Region
+{
+	id: usize,
+	start: usize,
+	end: usize,
+	mutable: bool,
+}
+
+/// List of frozen regions in the current process (stored as process-local data)
+#[derive(Default)]
+pub struct FreezeList
+{
+	regions: ::sync::Mutex<Vec<Region>>,
+}
 
 
 #[derive(Debug)]
@@ -19,23 +37,26 @@ pub enum FreezeError {
 	Unmapped,
 	/// The pased pointer was inaccessible (read-only)
 	Inaccessible,
-	/// Collides with an existing freeze owned by this thread
-	///
-	/// TODO: What happens if two threads collide? Should the kernel let them hold each other?
+	/// Collides with an existing freeze (by any thread in the process)
 	Locked,
 }
 
 /// Type that holds an object in memory, ensuring that it's unmodified and kept valid
-pub struct Freeze<T:?Sized>(*const T);
+pub struct Freeze<T:?Sized>(*const T, usize);
 
 /// Type that holds an object in memory, ensuring that nothing attempts to mutate it
-pub struct FreezeMut<T:?Sized>(*mut T);
+pub struct FreezeMut<T:?Sized>(*mut T, usize);
 
 impl<T: ?Sized> Freeze<T> {
 	// UNSAFE: Requires the passed pointer to never alias pointers not protected via the API
 	pub unsafe fn new(ptr: *const T) -> Result<Freeze<T>,FreezeError> {
-		// TODO: Freeze page as immutable (using a per-process freeze list to handle overlaps)
-		Ok( Freeze(ptr) )
+		let id = try!( freeze(ptr as *const () as usize, ::core::mem::size_of_val(&*ptr), false) );
+		Ok( Freeze(ptr, id) )
+	}
+}
+impl<T: ?Sized> ::core::ops::Drop for Freeze<T> {
+	fn drop(&mut self) {
+		release(self.1);
 	}
 }
 impl<T: ?Sized> ::core::convert::AsRef<T> for Freeze<T> {
@@ -54,8 +75,13 @@ impl<T: ?Sized> ::core::ops::Deref for Freeze<T> {
 impl<T: ?Sized> FreezeMut<T> {
 	// UNSAFE: Requires the passed pointer to never alias pointers not protected via the API
 	pub unsafe fn new(ptr: *mut T) -> Result<FreezeMut<T>,FreezeError> {
-		// TODO: Freeze page as mutable (using a per-process freeze list to handle overlaps)
-		Ok( FreezeMut(ptr) )
+		let id = try!( freeze(ptr as *const () as usize, ::core::mem::size_of_val(&*ptr), true) );
+		Ok( FreezeMut(ptr, id) )
+	}
+}
+impl<T: ?Sized> ::core::ops::Drop for FreezeMut<T> {
+	fn drop(&mut self) {
+		release(self.1);
 	}
 }
 impl<T: ?Sized> ::core::convert::AsRef<T> for FreezeMut<T> {
@@ -81,3 +107,92 @@ impl<T: ?Sized> ::core::ops::DerefMut for FreezeMut<T> {
 		unsafe {&mut *self.0 }
 	}
 }
+
+/// Run `fcn` with the guarantee that no page in the range is frozen (and won't be until it returns)
+///
+/// Used by operations that unmap or reprotect user pages, returns `FreezeError::Locked` if any page is frozen.
+pub fn with_unfrozen<R, F: FnOnce()->R>(addr: *const (), page_count: usize, fcn: F) -> Result<R, FreezeError>
+{
+	let start = addr as usize & !(::PAGE_SIZE - 1);
+	let end = start + page_count * ::PAGE_SIZE;
+	let list = ::threads::get_process_local::<FreezeList>();
+	let lh = list.regions.lock();
+	// - Compare at page granularity, the page containing a frozen byte can't be changed
+	if let Some(r) = lh.iter().find(|r| r.start & !(::PAGE_SIZE - 1) < end && start < r.end) {
+		log_debug!("with_unfrozen: {:#x}-{:#x} overlaps frozen {:#x}-{:#x}", start, end, r.start, r.end);
+		return Err( FreezeError::Locked );
+	}
+	Ok( fcn() )
+}
+
+/// Check and record a freeze of user memory, returning the identifier of the record
+fn freeze(start: usize, len: usize, mutable: bool) -> Result<usize, FreezeError>
+{
+	// Empty borrows don't need tracking
+	if len == 0 {
+		return Ok( 0 );
+	}
+	// Only user memory can be frozen (userland can't pass kernel pointers)
+	if ::arch::memory::addresses::is_global(start) {
+		return Err( FreezeError::Inaccessible );
+	}
+	let end = match start.checked_add(len)
+		{
+		Some(v) => v,
+		None => return Err( FreezeError::Unmapped ),
+		};
+	if ::arch::memory::addresses::is_global(end - 1) {
+		return Err( FreezeError::Unmapped );
+	}
+
+	let list = ::threads::get_process_local::<FreezeList>();
+	let mut lh = list.regions.lock();
+	// 1. Reject overlaps with existing freezes (only shared freezes can overlap)
+	if lh.iter().any(|r| r.start < end && start < r.end && (mutable || r.mutable)) {
+		log_debug!("Freeze {:#x}+{} ({}) collides with an existing freeze", start, len, if mutable { "mut" } else { "shared" });
+		return Err( FreezeError::Locked );
+	}
+	// 2. Ensure that the pages are present and accessible (holding the lock prevents them being unmapped)
+	for page in (start & !(::PAGE_SIZE - 1) .. end).step_by(::PAGE_SIZE)
+	{
+		::memory::vma::populate(page as *const ());
+		let prot = match ::arch::memory::virt::get_info(page as *const ())
+			{
+			Some((_, p)) => p,
+			None => return Err( FreezeError::Unmapped ),
+			};
+		let ok = match prot
+			{
+			ProtectionMode::UserRW | ProtectionMode::UserRWX | ProtectionMode::UserCOW => true,
+			ProtectionMode::UserRO | ProtectionMode::UserRX => !mutable || ::memory::vma::handle_write_fault(page),
+			_ => false,
+			};
+		if !ok {
+			return Err( FreezeError::Inaccessible );
+		}
+	}
+	// 3. Record the freeze
+	let id = S_NEXT_ID.fetch_add(1, Ordering::Relaxed);
+	lh.push(Region {
+		id: id,
+		start: start,
+		end: end,
+		mutable: mutable,
+		});
+	Ok( id )
+}
+
+/// Release a freeze recorded by `freeze`
+fn release(id: usize)
+{
+	if id == 0 {
+		return ;
+	}
+	let list = ::threads::get_process_local::<FreezeList>();
+	let mut lh = list.regions.lock();
+	match lh.iter().position(|r| r.id == id)
+	{
+	Some(i) => { lh.swap_remove(i); },
+	None => log_error!("BUG: Freeze #{} not in the freeze list", id),
+	}
+}
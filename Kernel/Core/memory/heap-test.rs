@@ -1,3 +1,22 @@
+//! Heap shim for host-side tests (allocations go to the host's allocator)
+//!
+//! The kernel's allocator implementations are also built here, so they can be exercised on the host
+//! using memory from the host allocator.
+#[cfg(test)]
+#[path="heap/heapdef.rs"]
+mod heapdef;
+#[cfg(test)]
+#[path="heap/slab.rs"]
+mod slab;
+
+#[derive(Debug)]
+pub enum Error
+{
+	Corrupted,
+	OutOfReservation,
+	OutOfMemory,
+}
+
 
 pub unsafe fn alloc<T>(_v: T) -> *mut T {
 	todo!("heap::alloc");
@@ -92,3 +111,173 @@ impl<T> Drop for ArrayAlloc<T>
 		}
 	}
 }
+
+/// Page-aligned host memory standing in for a heap's address range
+#[cfg(test)]
+struct TestRegion
+{
+	_buf: ::std::vec::Vec<u8>,
+	base: usize,
+	size: usize,
+}
+#[cfg(test)]
+impl TestRegion
+{
+	fn new(pages: usize) -> TestRegion {
+		let buf = vec![0u8; (pages + 1) * ::PAGE_SIZE];
+		let base = (buf.as_ptr() as usize + ::PAGE_SIZE - 1) & !(::PAGE_SIZE - 1);
+		TestRegion { _buf: buf, base: base, size: pages * ::PAGE_SIZE }
+	}
+}
+/// The region is already allocated, so mapping does nothing
+#[cfg(test)]
+struct TestBacking;
+#[cfg(test)]
+impl heapdef::Backing for TestBacking {
+	unsafe fn map(_addr: *mut (), _count: usize) -> Result<(), Error> {
+		Ok( () )
+	}
+}
+/// Simple deterministic PRNG (xorshift) for fuzzing
+#[cfg(test)]
+struct Rng(u32);
+#[cfg(test)]
+impl Rng {
+	fn next(&mut self) -> u32 {
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 17;
+		self.0 ^= self.0 << 5;
+		self.0
+	}
+}
+#[cfg(test)]
+unsafe fn fill(ptr: *mut u8, len: usize, tag: u8) {
+	for i in 0 .. len {
+		*ptr.offset(i as isize) = tag ^ i as u8;
+	}
+}
+#[cfg(test)]
+unsafe fn check(ptr: *mut u8, len: usize, tag: u8) {
+	for i in 0 .. len {
+		assert_eq!(*ptr.offset(i as isize), tag ^ i as u8, "Allocation {:p} corrupted at +{}", ptr, i);
+	}
+}
+
+#[test]
+fn heapdef_fuzz()
+{
+	let region = TestRegion::new(1024);
+	let mut heap = heapdef::HeapDef::<TestBacking>::new(region.base, region.base + region.size);
+	let mut rng = Rng(0x1234_5678);
+	let mut live: ::std::vec::Vec<(*mut u8, usize, u8)> = ::std::vec::Vec::new();
+	// SAFE: All pointers come from the heap, and are used within their sizes
+	unsafe {
+		for i in 0 .. 20000
+		{
+			match rng.next() % 8
+			{
+			0 ... 2 => {
+				let size = 1 + (rng.next() % 8192) as usize;
+				let align = 1 << (rng.next() % 10);
+				match heap.allocate(size, align, 1)
+				{
+				Ok(p) => {
+					assert_eq!(p as usize % align, 0, "Allocation {:p} not aligned to {}", p, align);
+					let tag = rng.next() as u8;
+					fill(p as *mut u8, size, tag);
+					live.push( (p as *mut u8, size, tag) );
+					},
+				Err(Error::OutOfReservation) => {},
+				Err(e) => panic!("Allocation failed: {:?}", e),
+				}
+				},
+			3 ... 5 if live.len() > 0 => {
+				let (p, size, tag) = live.swap_remove(rng.next() as usize % live.len());
+				check(p, size, tag);
+				assert_eq!(heap.deallocate(p as *mut (), size), (size, 1));
+				},
+			6 if live.len() > 0 => {
+				let idx = rng.next() as usize % live.len();
+				let (p, size, tag) = live[idx];
+				let new_size = 1 + (rng.next() % 8192) as usize;
+				if new_size > size {
+					if heap.expand_alloc(p as *mut (), new_size).is_some() {
+						check(p, size, tag);
+						fill(p, new_size, tag);
+						live[idx].1 = new_size;
+					}
+				}
+				else {
+					heap.shrink_alloc(p as *mut (), new_size);
+					check(p, new_size, tag);
+					live[idx].1 = new_size;
+				}
+				},
+			_ => {},
+			}
+			if i % 256 == 0 {
+				heap.validate().expect("validate");
+			}
+		}
+		for (p, size, tag) in live.drain(..)
+		{
+			check(p, size, tag);
+			heap.deallocate(p as *mut (), size);
+		}
+	}
+	heap.validate().expect("validate");
+	// Everything should have merged back into a single free block
+	let (mapped, free) = heap.usage();
+	assert_eq!(mapped, free);
+}
+
+#[test]
+fn slab_fuzz()
+{
+	use self::slab::PageSource;
+	let region = TestRegion::new(1024);
+	let mut pages = slab::PagePool::<TestBacking>::new(region.base, region.base + region.size);
+	let mut caches = [slab::SlabCache::new(), slab::SlabCache::new()];
+	let mut rng = Rng(0x8765_4321);
+	let mut live: ::std::vec::Vec<(*mut u8, usize, u8, usize)> = ::std::vec::Vec::new();
+	// SAFE: All pointers come from the caches, and are used within their sizes
+	unsafe {
+		for _ in 0 .. 20000
+		{
+			if rng.next() % 2 != 0 || live.len() == 0
+			{
+				let size = 1 + (rng.next() % 1024) as usize;
+				let align = 1 << (rng.next() % 6);
+				let class = slab::class_for(size, align).expect("class_for");
+				let owner = (rng.next() % 2) as usize;
+				let p = caches[owner].allocate(class, owner, &mut pages).expect("allocate") as *mut u8;
+				assert_eq!(p as usize % align, 0);
+				assert_eq!(slab::object_info(p as *mut ()).expect("object_info"), (class, owner));
+				let tag = rng.next() as u8;
+				fill(p, size, tag);
+				live.push( (p, size, tag, owner) );
+			}
+			else
+			{
+				let (p, size, tag, owner) = live.swap_remove(rng.next() as usize % live.len());
+				check(p, size, tag);
+				caches[owner].deallocate(p as *mut (), &mut pages);
+			}
+		}
+		for (p, size, tag, owner) in live.drain(..)
+		{
+			check(p, size, tag);
+			caches[owner].deallocate(p as *mut (), &mut pages);
+		}
+	}
+	// Only the cached empty slab for each class should remain
+	for c in caches.iter() {
+		assert!(c.page_count() <= slab::NUM_CLASSES, "{} pages left in cache", c.page_count());
+	}
+	// - And released pages are re-used
+	let before = pages.free_count();
+	if before > 0 {
+		pages.get_page().expect("get_page");
+		assert_eq!(pages.free_count(), before - 1);
+	}
+}
@@ -1,457 +1,401 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
 //
-//
-//
-//! 
-use super::{ZERO_ALLOC, Error};
-use arch::memory::addresses;
+// Core/memory/heap/heapdef.rs
+//! Coalescing allocator for large (and highly aligned) allocations
+//!
+//! Blocks are tagged at both ends (a header, and a footer pointing back to the header), so freed blocks
+//! can be merged with both neighbours. Free blocks are kept on doubly-linked lists binned by size.
+use super::Error;
+use core::marker::PhantomData;
 
-// Curse no CTFE
-//const HEADERS_SIZE: usize = ::core::mem::size_of::<HeapHead>() + ::core::mem::size_of::<HeapFoot>();
-const MAGIC: u32 = 0x71ff11A1;
+const MAGIC_USED: u32 = 0x71ff11A1;
+const MAGIC_FREE: u32 = 0x71ff11F0;
+/// Allocation granularity (and minimum alignment of all returned pointers)
+pub const GRANULE: usize = 16;
+/// Number of free list bins (bin `n` holds blocks of `2^(n+MIN_SHIFT)` up to `2^(n+MIN_SHIFT+1)` bytes)
+const NUM_BINS: usize = 24;
+const MIN_SHIFT: usize = 5;
 
-// TODO: Store the limits in the definition
-pub struct HeapDef
+/// Source of memory for a heap region
+pub trait Backing
 {
-	start: *mut HeapHead,
-	last_foot: *mut HeapFoot,
-	first_free: *mut HeapHead,
+	/// Make `count` pages starting at `addr` accessible
+	unsafe fn map(addr: *mut (), count: usize) -> Result<(), Error>;
 }
-unsafe impl ::core::marker::Send for HeapDef {}
 
-#[derive(Debug,PartialEq)]	// RawPtr Debug is the address
-enum HeapState
+pub struct HeapDef<B>
 {
-	Free(*mut HeapHead),
-	Used(usize),
+	start: usize,
+	limit: usize,
+	/// End of the mapped (and block-covered) region
+	top: usize,
+	bins: [*mut Head; NUM_BINS],
+	free_bytes: usize,
+	_backing: PhantomData<B>,
 }
+unsafe impl<B> Send for HeapDef<B> {}
 
-struct HeapHead
+#[repr(C)]
+struct Head
 {
 	magic: u32,
+	/// Total size of the block (including the header and footer)
 	size: usize,
-	state: HeapState,
+	/// Requested size (used blocks only)
+	req: usize,
+	/// Opaque tag for the owner of the allocation (used blocks only)
+	owner: usize,
 }
-impl_fmt! {
-	Debug(self, f) for HeapHead {
-		write!(f, "HeapHead {{ magic: {:#x}, size: {:#x}, state: {:?} }}", self.magic, self.size, self.state)
-	}
+/// Free-list links, stored in the data area of free blocks
+struct Links
+{
+	next: *mut Head,
+	prev: *mut Head,
 }
-struct HeapFoot
+struct Foot
 {
-	head: *mut HeapHead,
+	head: *mut Head,
 }
 
+fn head_size() -> usize {
+	::lib::num::round_up(::core::mem::size_of::<Head>(), GRANULE)
+}
+fn min_block() -> usize {
+	::lib::num::round_up(head_size() + ::core::mem::size_of::<Links>() + ::core::mem::size_of::<Foot>(), GRANULE)
+}
+/// Size of a block able to hold `size` bytes of data
+fn block_size_for(size: usize) -> usize {
+	let data = ::core::cmp::max(size, ::core::mem::size_of::<Links>());
+	::lib::num::round_up(head_size() + data + ::core::mem::size_of::<Foot>(), GRANULE)
+}
+fn bin_for(size: usize) -> usize {
+	let log2 = 8 * ::core::mem::size_of::<usize>() - 1 - size.leading_zeros() as usize;
+	::core::cmp::min(log2.saturating_sub(MIN_SHIFT), NUM_BINS-1)
+}
 
-
-impl HeapDef
+impl<B> HeapDef<B>
 {
-	/// Construct a new heap instance
-	pub const fn new() -> HeapDef {
+	/// Construct a new heap instance covering `start .. limit` (nothing is mapped until the first allocation)
+	pub const fn new(start: usize, limit: usize) -> HeapDef<B> {
 		HeapDef {
-			start: 0 as *mut _,
-			last_foot: 0 as *mut _,
-			first_free: 0 as *mut _,
+			start: start,
+			limit: limit,
+			top: start,
+			bins: [0 as *mut Head; NUM_BINS],
+			free_bytes: 0,
+			_backing: PhantomData,
 			}
 	}
 
-	/// Allocate arbitary bytes from the heap
-	/// 
-	// TODO: Is this actually unsafe?
-	pub unsafe fn allocate(&mut self, size: usize, align: usize) -> Result<*mut (), Error>
+	/// Returns true if the pointer is within this heap's range
+	pub fn contains(&self, ptr: *const ()) -> bool {
+		self.start <= ptr as usize && (ptr as usize) < self.limit
+	}
+	/// Returns the mapped and free byte counts
+	pub fn usage(&self) -> (usize, usize) {
+		(self.top - self.start, self.free_bytes)
+	}
+}
+
+impl<B: Backing> HeapDef<B>
+{
+	/// Allocate `size` bytes aligned to `align`, tagged with `owner`
+	pub unsafe fn allocate(&mut self, size: usize, align: usize, owner: usize) -> Result<*mut (), Error>
 	{
-		// TODO: Have different pools for different alignments
-		
-		// SHORT CCT: Zero size allocation
-		if size == 0 {
-			return Ok(ZERO_ALLOC);
-		}
-		
-		// This would be static, if CTFE was avalible
-		let headers_size = ::core::mem::size_of::<HeapHead>() + ::core::mem::size_of::<HeapFoot>();
-		
-		// 1. Round size up to closest heap block size
-		let blocksize = ::lib::num::round_up(size + headers_size, 32);
-		log_debug!("allocate(size={},align={}) blocksize={}", size, align, blocksize);
+		assert!(size > 0);
+		assert!(align.is_power_of_two(), "Alignment {} isn't a power of two", align);
+		let align = ::core::cmp::max(align, GRANULE);
+		let need = block_size_for(size);
 
-		// 2. Locate a free location
-		// Check all free blocks for one that would fit this allocation
-		let mut prev = ::core::ptr::null_mut();
-		let mut opt_fb = self.first_free;
-		while !opt_fb.is_null()
-		{
-			let fb = &*opt_fb;
-			assert!( fb.magic == MAGIC );
-			let next = match fb.state
-				{
-				HeapState::Free(n) => n,
-				_ => {
-					self.dump();
-					log_error!("Non-free block ({:p}) in free list", fb);
-					return Err(Error::Corrupted)
-					}
-				};
-			if fb.size() >= blocksize
-			{
-				break;
-			}
-			prev = opt_fb;
-			assert!(opt_fb != next);
-			opt_fb = next;
-		}
-		if !opt_fb.is_null()
+		// 1. Search the free lists (starting at the bin that could hold this size)
+		for bin in bin_for(need) .. NUM_BINS
 		{
-			let fb = &mut *opt_fb;
-			let next = match fb.state { HeapState::Free(n) => n, _ => unreachable!() };
-			// Split block (if needed)
-			if fb.size() > blocksize + headers_size
+			let mut b = self.bins[bin];
+			while !b.is_null()
 			{
-				let far_foot = fb.foot() as *mut HeapFoot;
-				let far_size = fb.size() - blocksize;
-				fb.resize(blocksize);
-				
-				let far_head = fb.next();
-				assert!(far_head != prev);
-				(*far_head).initialise( far_size, HeapState::Free(next) );
-				assert_eq!( far_foot, (*far_head).foot() as *mut _ );
-
-				log_debug!("Split block, new block {:?}", *far_head);
-				if prev.is_null() {
-					self.first_free = far_head;
-				}
-				else {
-					(*prev).state = HeapState::Free(far_head);
-					log_debug!("Chained atop {:p} {:?}", prev, *prev);
+				if let Some(pad) = fit(b, need, align) {
+					return Ok( self.take(b, pad, need, size, owner) );
 				}
+				b = (*links(b)).next;
 			}
-			else if prev.is_null()
-			{
-				self.first_free = next;
-				log_debug!("Set first free to {:p}", next);
-			}
-			else
-			{
-				(*prev).state = HeapState::Free(next);
-				log_debug!("Chain with next with {:p} {:?}", prev, *prev);
-			}
-			// Return newly allocated block
-			fb.state = HeapState::Used(size);
-			log_debug!("Returning block {:p} (Freelist)", fb);
-			return Ok( fb.data() );
 		}
-		assert!(opt_fb.is_null());
 
-		// Fall through: No free blocks would fit the allocation
-		
-		// 3. If none, allocate more space
-		let block_ptr = try!( self.expand(blocksize) );
-		let block = &mut *block_ptr;
-		// > Split returned block into a block of required size and a free block
-		if block.size() > blocksize
-		{
-			// Create a new block header at end of block
-			let tailsize = block.size() - blocksize;
-			block.resize(blocksize);
-
-			(*block.next()).initialise( tailsize, HeapState::Free(self.first_free) );
-
-			self.first_free = block.next();
-		}
-		
-		block.state = HeapState::Used(size);	
-	
-		log_trace!("Returning block {:p} (new)", block);
-		Ok( block.data() )
+		// 2. Expand the heap so a suitable block exists at the end
+		let b = try!( self.grow(need + align + min_block()) );
+		let pad = fit(b, need, align).expect("HeapDef::allocate - Block from grow doesn't fit");
+		Ok( self.take(b, pad, need, size, owner) )
 	}
 
-	/// Attempt to expand the specified block without reallocating
-	pub unsafe fn expand_alloc(&mut self, ptr: *mut (), size: usize) -> bool
+	/// Release an allocation, returning the requested size and owner tag
+	///
+	/// A `size` of zero skips the size check (for C's `free`)
+	pub unsafe fn deallocate(&mut self, ptr: *mut (), size: usize) -> (usize, usize)
 	{
-		let headers_size = ::core::mem::size_of::<HeapHead>() + ::core::mem::size_of::<HeapFoot>();
-		
-		// Quick check: Zero allocation can't grow
-		if ptr == ZERO_ALLOC {
-			return false;
-		}
-		
-		let headptr = {
-			let hp = (ptr as *mut HeapHead).offset(-1);
-			assert!( (hp as usize) >= self.start as usize );
-			assert!( (hp as usize) < self.last_foot as usize );
-			&mut *hp
-			};
+		let mut b = self.used_block(ptr);
+		let rv = ((*b).req, (*b).owner);
+		assert!(size == 0 || size == (*b).req, "HeapDef::deallocate({:p}) - Size mismatch {} != {}", ptr, size, (*b).req);
 
-		// If the new size fits within the old block, update the cached size and return true
-		if size + headers_size <= headptr.size()
-		{
-			headptr.state = HeapState::Used(size);
-			true
+		let mut bsize = (*b).size;
+		// Merge right
+		let next = (b as usize + bsize) as *mut Head;
+		if (next as usize) < self.top && (*next).magic == MAGIC_FREE {
+			self.unlink(next);
+			bsize += (*next).size;
 		}
-		// TODO: Can this expand into the next block?
-		else
-		{
-			false
+		// Merge left
+		if b as usize != self.start {
+			let prev = (*((b as usize - ::core::mem::size_of::<Foot>()) as *const Foot)).head;
+			if (*prev).magic == MAGIC_FREE {
+				self.unlink(prev);
+				bsize += (*prev).size;
+				b = prev;
+			}
 		}
+		init_block(b, bsize, MAGIC_FREE);
+		self.insert(b);
+		rv
 	}
-	pub unsafe fn shrink_alloc(&mut self, ptr: *mut (), new_size: usize)
-	{
-		//let headers_size = ::core::mem::size_of::<HeapHead>() + ::core::mem::size_of::<HeapFoot>();
-		
-		// Quick check: Zero allocation can't grow or shrink
-		if ptr == ZERO_ALLOC {
-			assert!(new_size == 0, "Calling shrink_alloc on ZERO_ALLOC with new_size!=0");
-			return ;
-		}
-		
-		let headptr = {
-			let hp = (ptr as *mut HeapHead).offset(-1);
-			assert!( (hp as usize) >= self.start as usize );
-			assert!( (hp as usize) < self.last_foot as usize );
-			&mut *hp
-			};
 
-		match headptr.state
-		{
-		HeapState::Used(ref mut sz) => {
-			// TODO: Split block if possible
-			assert!(*sz >= new_size, "Calling shrink_alloc with a larger size");
-			*sz = new_size;
-			},
-		HeapState::Free(..) => panic!("Calling shrink_alloc on a free block ({:p})", ptr),
-		}
-	}
-	
-	pub unsafe fn deallocate(&mut self, ptr: *mut (), size: usize, _align: usize)
+	/// Attempt to expand an allocation without moving it, returning the previous size and owner tag
+	pub unsafe fn expand_alloc(&mut self, ptr: *mut (), new_size: usize) -> Option<(usize, usize)>
 	{
-		log_debug!("deallocate(ptr={:p},size={:#x})", ptr, size);
-		if ptr == ZERO_ALLOC {
-			assert!(size == 0, "ZERO_ALLOC but size({}) != 0", size);
-			log_trace!("Free zero alloc");
-			return ;
-		}
-
-		let mut no_add = false;
-		let headptr = (ptr as *mut HeapHead).offset(-1);
-		assert!(headptr as usize >= addresses::HEAP_START);
-		
+		let b = self.used_block(ptr);
+		let rv = ((*b).req, (*b).owner);
+		let need = block_size_for(new_size);
+		if need > (*b).size
 		{
-			let headref = &mut *headptr;
-			headref.validate().unwrap();
-			if size == 0 {
-				// Special case for use as C free() (as part of ACPICA shim)
-				assert!( is!(headref.state, HeapState::Used(_)), "Header {:p} state invalid {:?} not Used(_)",
-					headref, headref.state );
+			let next = (b as usize + (*b).size) as *mut Head;
+			if next as usize == self.top {
+				// Last block, map more memory after it
+				if self.grow(need - (*b).size).is_err() {
+					return None;
+				}
 			}
-			else {
-				assert_eq!( headref.state, HeapState::Used(size), "Header {:p} state invalid {:?} not Used({})",
-					headref, headref.state, size );
+			if (next as usize) < self.top && (*next).magic == MAGIC_FREE && (*b).size + (*next).size >= need {
+				self.unlink(next);
+				(*b).size += (*next).size;
+				init_block(b, (*b).size, MAGIC_USED);
+				self.split_tail(b, need);
 			}
-			
-			// Merge left and right
-			// 1. Left:
-			if headptr as usize != addresses::HEAP_START
-			{
-				if let HeapState::Free(_) = (*headref.prev()).state
-				{
-					log_trace!("Merged left with {:p}", headref.prev());
-					// increase size of previous block to cover this block
-					let prev_block = &mut *headref.prev();
-					let new_size = prev_block.size() + headref.size();
-					prev_block.resize( new_size );
-					no_add = true;
-				}
+			else {
+				return None;
 			}
-			
-			// 2. Right
-			//if_let!( HeapState::Free(_) => 
-			// TODO: Merging right requires being able to arbitarily remove items from the free list
-		}
-		
-		if !no_add
-		{
-			(*headptr).state = HeapState::Free(self.first_free);
-			self.first_free = headptr;
 		}
+		(*b).req = new_size;
+		Some(rv)
 	}
-	
-	/// Expand the heap to create a block at least `min_size` bytes long at the end
-	/// \return New block, pre-allocated
-	#[inline(never)]
-	unsafe fn expand(&mut self, min_size: usize) -> Result<*mut HeapHead, Error>
+	/// Shrink an allocation in-place (releasing the tail if possible), returning the previous size and owner tag
+	pub unsafe fn shrink_alloc(&mut self, ptr: *mut (), new_size: usize) -> (usize, usize)
 	{
-		log_trace!("HeapDef::expand(min_size={:#x})", min_size);
-		//log_debug!("self.{{start = {:p}, last_foot = {:?}}}", self.start, self.last_foot);
-		let use_prev =
-			if self.start.is_null() {
-				let base = addresses::HEAP_START;
-				self.start = base as *mut HeapHead;
-				// note: Evil hack, set last_foot to invalid memory (it's only used for .next_head())
-				self.last_foot = (base as *mut HeapFoot).offset(-1);
-				false
+		let b = self.used_block(ptr);
+		let rv = ((*b).req, (*b).owner);
+		assert!(new_size <= (*b).req, "Calling shrink_alloc with a larger size ({} > {})", new_size, (*b).req);
+		(*b).req = new_size;
+		let need = block_size_for(new_size);
+		self.split_tail(b, need);
+		// - Merge the released tail with a following free block
+		let tail = (b as usize + (*b).size) as *mut Head;
+		if (tail as usize) < self.top && (*tail).magic == MAGIC_FREE {
+			let next = (tail as usize + (*tail).size) as *mut Head;
+			if (next as usize) < self.top && (*next).magic == MAGIC_FREE {
+				self.unlink(tail);
+				self.unlink(next);
+				init_block(tail, (*tail).size + (*next).size, MAGIC_FREE);
+				self.insert(tail);
 			}
-			else {
-				false
-				// DISABLED: To use the final block, the previous block on the freelist must be edited
-				// - Not easy to do
-			};
-		//log_debug!("(2) self.{{start = {:#x}, last_foot = {:?}}}, use_prev={}", self.start as usize, self.last_foot, use_prev);
-		assert!( !self.last_foot.is_null() );
-		let last_foot = &mut *self.last_foot;
-		let alloc_size = min_size - (if use_prev { last_foot.head().size() } else { 0 });
-		
-		
-		// 1. Allocate at least one page at the end of the heap
-		let n_pages = ::lib::num::round_up(alloc_size, ::PAGE_SIZE) / ::PAGE_SIZE;
-		//log_debug!("HeapDef.expand(min_size={}), alloc_size={}, n_pages={}", min_size, alloc_size, n_pages);
-		//log_trace!("last_foot = {:p}", self.last_foot);
-		assert!(n_pages > 0);
-
-		if last_foot.next_head() as usize == addresses::HEAP_END {
-			return Err( Error::OutOfReservation );
 		}
-		if last_foot.next_head() as usize + n_pages * ::PAGE_SIZE > addresses::HEAP_END {
-			return Err( Error::OutOfReservation );
-		}
-
-		// Allocate memory into the new region
-		match ::memory::virt::allocate(last_foot.next_head() as *mut(), n_pages)
-		{
-		Ok(_) => {},
-		Err(::memory::virt::MapError::OutOfMemory) => return Err(Error::OutOfMemory),
-		Err(e @ _) => panic!("Unknown error from VMM: {:?}", e),
-		}
-
-		// 2. If the final block is a free block, expand it to cover the new area
-		let block = if use_prev
-			{
-				let block = &mut *last_foot.head;
-				log_debug!("HeapDef.expand: (prev) &block={:p}", block);
-				let newsize = block.size() + n_pages * ::PAGE_SIZE;
-				block.resize(newsize);
-				
-				block
-			}
-			else
-			{
-				let block = &mut *last_foot.next_head();
-
-				log_debug!("HeapDef.expand: (new) &block={:p}", block);
-				(*block).initialise( n_pages * ::PAGE_SIZE, HeapState::Free(0 as *mut _) );
-				
-				block
-			};
-		self.last_foot = block.foot() as *mut HeapFoot;
-		//log_debug!("HeapDef.expand: &block={:p}, self.last_foot={:p}", block, self.last_foot);
-		//block.state = HeapState::Used(0);
-		// 3. Return final block
-		Ok( block )
+		rv
 	}
-	
-	fn dump(&self)
+
+	/// Check the consistency of the heap (block chain and free lists)
+	pub fn validate(&self) -> Result<(), Error>
 	{
-		log_log!("Dumping Heap");
-		// SAFE: Does an immutable heap walk
+		// SAFE: Immutable walk of the heap, all blocks are within the mapped region
 		unsafe {
-			let mut block_head = self.start;
-			loop
+			let mut free_blocks = 0;
+			let mut free_bytes = 0;
+			let mut prev_free = false;
+			let mut b = self.start;
+			while b < self.top
 			{
-				let head_ref = &*block_head;
-				log_log!("{:p} {:?}", block_head, head_ref);
-				if head_ref.foot_im() as *const HeapFoot == self.last_foot {
-					break;
+				let h = &*(b as *const Head);
+				if h.magic != MAGIC_USED && h.magic != MAGIC_FREE {
+					log_error!("Block {:#x} magic invalid {:#x}", b, h.magic);
+					return Err( Error::Corrupted );
+				}
+				if h.size < min_block() || h.size % GRANULE != 0 || b + h.size > self.top {
+					log_error!("Block {:#x} size invalid {:#x}", b, h.size);
+					return Err( Error::Corrupted );
+				}
+				if (*foot(b as *mut Head)).head as usize != b {
+					log_error!("Block {:#x} foot backlink invalid", b);
+					return Err( Error::Corrupted );
+				}
+				let is_free = h.magic == MAGIC_FREE;
+				if is_free && prev_free {
+					log_error!("Block {:#x} is free, but wasn't merged with the previous block", b);
+					return Err( Error::Corrupted );
+				}
+				if is_free {
+					free_blocks += 1;
+					free_bytes += h.size;
+				}
+				prev_free = is_free;
+				b += h.size;
+			}
+			if free_bytes != self.free_bytes {
+				log_error!("Free byte count mismatch {:#x} != {:#x}", free_bytes, self.free_bytes);
+				return Err( Error::Corrupted );
+			}
+			let mut listed = 0;
+			for (i,&first) in self.bins.iter().enumerate()
+			{
+				let mut prev = ::core::ptr::null_mut();
+				let mut p = first;
+				while !p.is_null()
+				{
+					if (*p).magic != MAGIC_FREE || bin_for((*p).size) != i || (*links(p)).prev != prev {
+						log_error!("Free list {} corrupted at {:p}", i, p);
+						return Err( Error::Corrupted );
+					}
+					listed += 1;
+					prev = p;
+					p = (*links(p)).next;
 				}
-				block_head = head_ref.next();
+			}
+			if listed != free_blocks {
+				log_error!("{} free blocks, but {} on the free lists", free_blocks, listed);
+				return Err( Error::Corrupted );
 			}
 		}
+		Ok( () )
 	}
-}
 
-impl HeapHead
-{
-	fn size(&self) -> usize { self.size }
-	
-	fn ptr(&self) -> *mut HeapHead { self as *const _ as *mut _ }
-	pub unsafe fn prev(&self) -> *mut HeapHead
+	/// Obtain (and check) the header of a used block from its data pointer
+	unsafe fn used_block(&self, ptr: *mut ()) -> *mut Head
 	{
-		(*(self.ptr() as *mut HeapFoot).offset(-1)).head()
+		let b = (ptr as usize - head_size()) as *mut Head;
+		assert!( self.start <= b as usize && (b as usize) < self.top, "Pointer {:p} not within heap", ptr );
+		assert!( (*b).magic == MAGIC_USED, "Block for {:p} not in use (magic={:#x})", ptr, (*b).magic );
+		assert!( (*foot(b)).head == b, "Block for {:p} has invalid foot", ptr );
+		b
 	}
 
-	/// Obtain a raw pointer to the next block linearly
-	pub fn next(&self) -> *mut HeapHead
+	/// Allocate from the free block `b`, splitting off `pad` bytes before and any excess after
+	unsafe fn take(&mut self, b: *mut Head, pad: usize, need: usize, req: usize, owner: usize) -> *mut ()
 	{
-		// SAFE: This block should control its entire allocation, thus the offsetting is valid
-		unsafe { 
-			(self.ptr() as *mut u8).offset( self.size as isize ) as *mut HeapHead
+		self.unlink(b);
+		let mut b = b;
+		if pad > 0 {
+			let rem = (*b).size - pad;
+			init_block(b, pad, MAGIC_FREE);
+			self.insert(b);
+			b = (b as usize + pad) as *mut Head;
+			init_block(b, rem, MAGIC_USED);
 		}
-	}
-
-	pub unsafe fn data(&mut self) -> *mut ()
-	{
-		self.ptr().offset( 1 ) as *mut ()
-	}
-
-	pub fn foot(&mut self) -> &mut HeapFoot
-	{
-		// SAFE: Block foot is owned by the block
-		unsafe {
-			&mut *(self.next() as *mut HeapFoot).offset( -1 )
+		else {
+			init_block(b, (*b).size, MAGIC_USED);
 		}
+		self.split_tail(b, need);
+		(*b).req = req;
+		(*b).owner = owner;
+		(b as usize + head_size()) as *mut ()
 	}
-	pub fn foot_im(&self) -> &HeapFoot
+	/// Release the portion of a used block after `need` bytes as a new free block (if large enough)
+	unsafe fn split_tail(&mut self, b: *mut Head, need: usize)
 	{
-		// SAFE: Block foot is owned by the block
-		unsafe {
-			& *(self.next() as *const HeapFoot).offset( -1 )
+		let size = (*b).size;
+		if size - need >= min_block() {
+			init_block(b, need, MAGIC_USED);
+			let tail = (b as usize + need) as *mut Head;
+			init_block(tail, size - need, MAGIC_FREE);
+			self.insert(tail);
 		}
 	}
 
-	pub fn validate(&mut self) -> Result<(), Error>
+	/// Map at least `min_size` bytes onto the end of the heap, returning the final (free) block
+	unsafe fn grow(&mut self, min_size: usize) -> Result<*mut Head, Error>
 	{
-		let selfptr: *mut _ = self;
-		if self.magic != MAGIC {
-			log_error!("Header {:p} magic invalid {:#x} instead of {:#x}",  self, self.magic, MAGIC );
-			return Err( Error::Corrupted );
+		let n_pages = ::lib::num::round_up(min_size, ::PAGE_SIZE) / ::PAGE_SIZE;
+		if n_pages * ::PAGE_SIZE > self.limit - self.top {
+			return Err( Error::OutOfReservation );
 		}
-		let foot_headptr: *mut _ = self.foot().head();
-		if foot_headptr != selfptr {
-			log_error!("Header {:p} foot backlink invalid, {:p} points to {:p}",  selfptr, self.foot_im(), foot_headptr );
-			return Err( Error::Corrupted );
+		try!( B::map(self.top as *mut (), n_pages) );
+		let new_top = self.top + n_pages * ::PAGE_SIZE;
+
+		// If the current last block is free, extend it to cover the new area
+		let mut b = self.top as *mut Head;
+		let mut size = new_top - self.top;
+		if self.top != self.start {
+			let last = (*((self.top - ::core::mem::size_of::<Foot>()) as *const Foot)).head;
+			if (*last).magic == MAGIC_FREE {
+				self.unlink(last);
+				size += (*last).size;
+				b = last;
+			}
 		}
-		Ok( () )
+		self.top = new_top;
+		init_block(b, size, MAGIC_FREE);
+		self.insert(b);
+		Ok( b )
 	}
 
-	pub unsafe fn resize(&mut self, new_size: usize)
+	unsafe fn insert(&mut self, b: *mut Head)
 	{
-		assert!( is!(self.state, HeapState::Free(_)) );
-		self.size = new_size;
-		*self.foot() = HeapFoot {
-				head: self as *mut _,
-				};
+		let bin = bin_for((*b).size);
+		let next = self.bins[bin];
+		*links(b) = Links { next: next, prev: ::core::ptr::null_mut() };
+		if !next.is_null() {
+			(*links(next)).prev = b;
+		}
+		self.bins[bin] = b;
+		self.free_bytes += (*b).size;
 	}
-
-	/// UNSAFE: Assumes that the passed size is valid
-	pub unsafe fn initialise(&mut self, size: usize, state: HeapState)
+	unsafe fn unlink(&mut self, b: *mut Head)
 	{
-		*self = HeapHead {
-			magic: MAGIC,
-			state: state,
-			size: size,
-			};
-		self.foot().head = self;
+		let Links { next, prev } = ::core::ptr::read(links(b));
+		if prev.is_null() {
+			self.bins[bin_for((*b).size)] = next;
+		}
+		else {
+			(*links(prev)).next = next;
+		}
+		if !next.is_null() {
+			(*links(next)).prev = prev;
+		}
+		self.free_bytes -= (*b).size;
 	}
 }
 
-impl HeapFoot
+/// Determine the padding needed to fit an allocation with the given alignment in a free block
+unsafe fn fit(b: *mut Head, need: usize, align: usize) -> Option<usize>
 {
-	pub fn head(&mut self) -> &mut HeapHead
-	{
-		// SAFE: It's your own dumb fault if this is somewhere invalid
-		unsafe {
-			&mut *self.head
-		}
+	let data = b as usize + head_size();
+	let mut pad = ::lib::num::round_up(data, align) - data;
+	if pad != 0 && pad < min_block() {
+		// Padding must be large enough to be its own free block
+		pad = ::lib::num::round_up(data + min_block(), align) - data;
 	}
-	pub unsafe fn next_head(&mut self) -> *mut HeapHead
-	{
-		let self_ptr: *mut HeapFoot = self;
-		self_ptr.offset(1) as *mut HeapHead
+	if pad + need <= (*b).size {
+		Some(pad)
+	}
+	else {
+		None
 	}
 }
+/// Set a block's header and footer
+unsafe fn init_block(b: *mut Head, size: usize, magic: u32)
+{
+	(*b).magic = magic;
+	(*b).size = size;
+	(*foot(b)).head = b;
+}
+unsafe fn links(b: *mut Head) -> *mut Links
+{
+	(b as usize + head_size()) as *mut Links
+}
+unsafe fn foot(b: *mut Head) -> *mut Foot
+{
+	(b as usize + (*b).size - ::core::mem::size_of::<Foot>()) as *mut Foot
+}
@@ -3,21 +3,56 @@
 //
 // Core/memory/heap.rs
 //! Dynamic memory manager
+//!
+//! Small allocations are served from per-heap slabs (one page per slab, one size class per slab), while
+//! large or highly aligned allocations use a shared coalescing allocator. The heap region is split in
+//! half between the two, so a pointer's allocator is known from its address.
+//!
+//! Subsystems can define their own named `Heap` to keep their small objects apart from the rest of the
+//! kernel, and to get separate allocation statistics.
 use core::ptr::NonNull;
-
-// TODO: Rewrite this to correctly use the size information avaliable
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use arch::memory::addresses::{HEAP_START, HEAP_END};
 
 use self::heapdef::HeapDef;
+use self::slab::{SlabCache, PagePool};
 
 mod heapdef;
+mod slab;
 
 // --------------------------------------------------------
 // Types
-#[derive(Copy,Clone)]
-pub enum HeapId
+/// A named heap
+pub struct Heap
 {
-	Local,	// Inaccessible outside of process
-	Global,	// Global allocations
+	name: &'static str,
+	slabs: ::sync::Mutex<SlabCache>,
+	allocations: AtomicUsize,
+	bytes: AtomicUsize,
+	peak_bytes: AtomicUsize,
+	total_allocations: AtomicUsize,
+	failures: AtomicUsize,
+	/// Next heap in the global list (see `for_each_heap`)
+	next: AtomicPtr<Heap>,
+	registered: AtomicBool,
+}
+
+/// Allocation statistics for a heap
+#[derive(Debug,Default,Copy,Clone)]
+pub struct Stats
+{
+	/// Number of live allocations
+	pub allocations: usize,
+	/// Bytes used by live allocations (small allocations count their full size class)
+	pub bytes: usize,
+	/// Maximum value of `bytes`
+	pub peak_bytes: usize,
+	/// Number of allocations made
+	pub total_allocations: usize,
+	/// Number of allocations that failed
+	pub failures: usize,
+	/// Pages used by this heap's slabs
+	pub slab_pages: usize,
 }
 
 #[derive(Debug)]
@@ -51,13 +86,134 @@ unsafe impl<T: Send> Send for ArrayAlloc<T> {
 
 pub const ZERO_ALLOC: *mut () = 1 as *mut _;
 
-//static S_LOCAL_HEAP: ::sync::Mutex<HeapDef> = mutex_init!(HeapDef{head:None});
-static S_GLOBAL_HEAP: ::sync::Mutex<HeapDef> = ::sync::Mutex::new(HeapDef::new());
+/// Split point between slab pages (below) and the large allocation heap (above)
+const LARGE_START: usize = HEAP_START + (HEAP_END - HEAP_START) / 2;
+
+static S_GLOBAL_HEAP: Heap = Heap::new("global");
+/// Head of the list of heaps that have been used
+static S_HEAPS: AtomicPtr<Heap> = AtomicPtr::new(0 as *mut _);
+static S_SLAB_PAGES: ::sync::Mutex<PagePool<VirtBacking>> = ::sync::Mutex::new(PagePool::new(HEAP_START, LARGE_START));
+static S_LARGE_HEAP: ::sync::Mutex<HeapDef<VirtBacking>> = ::sync::Mutex::new(HeapDef::new(LARGE_START, HEAP_END));
+
+/// Heap memory is mapped using the VMM
+struct VirtBacking;
+impl heapdef::Backing for VirtBacking
+{
+	unsafe fn map(addr: *mut (), count: usize) -> Result<(), Error>
+	{
+		// Freeing memory in a reclaimer requires the heap lock, which is held here
+		let _h = ::memory::phys::hold_reclaim();
+		match ::memory::virt::allocate(addr, count)
+		{
+		Ok(_) => Ok( () ),
+		Err(::memory::virt::MapError::OutOfMemory) => Err(Error::OutOfMemory),
+		Err(e @ _) => panic!("Unknown error from VMM: {:?}", e),
+		}
+	}
+}
+/// Slab pages from the shared pool
+struct GlobalPages;
+impl slab::PageSource for GlobalPages
+{
+	fn get_page(&mut self) -> Result<*mut (), Error> {
+		S_SLAB_PAGES.lock().get_page()
+	}
+	fn release_page(&mut self, page: *mut ()) {
+		S_SLAB_PAGES.lock().release_page(page)
+	}
+}
 
 // --------------------------------------------------------
 // Code
 pub fn init()
 {
+	S_GLOBAL_HEAP.register();
+}
+
+impl Heap
+{
+	pub const fn new(name: &'static str) -> Heap
+	{
+		Heap {
+			name: name,
+			slabs: ::sync::Mutex::new(SlabCache::new()),
+			allocations: AtomicUsize::new(0),
+			bytes: AtomicUsize::new(0),
+			peak_bytes: AtomicUsize::new(0),
+			total_allocations: AtomicUsize::new(0),
+			failures: AtomicUsize::new(0),
+			next: AtomicPtr::new(0 as *mut _),
+			registered: AtomicBool::new(false),
+		}
+	}
+
+	pub fn name(&self) -> &str {
+		self.name
+	}
+	pub fn stats(&self) -> Stats {
+		Stats {
+			allocations: self.allocations.load(Ordering::Relaxed),
+			bytes: self.bytes.load(Ordering::Relaxed),
+			peak_bytes: self.peak_bytes.load(Ordering::Relaxed),
+			total_allocations: self.total_allocations.load(Ordering::Relaxed),
+			failures: self.failures.load(Ordering::Relaxed),
+			slab_pages: self.slabs.lock().page_count(),
+			}
+	}
+
+	/// Add this heap to the global list (on first use)
+	fn register(&'static self)
+	{
+		if self.registered.swap(true, Ordering::Relaxed) == false
+		{
+			let ptr = self as *const Heap as *mut Heap;
+			loop
+			{
+				let head = S_HEAPS.load(Ordering::Acquire);
+				self.next.store(head, Ordering::Relaxed);
+				if S_HEAPS.compare_and_swap(head, ptr, Ordering::AcqRel) == head {
+					break;
+				}
+			}
+		}
+	}
+
+	fn add_stats(&self, bytes: usize)
+	{
+		self.allocations.fetch_add(1, Ordering::Relaxed);
+		self.total_allocations.fetch_add(1, Ordering::Relaxed);
+		let cur = self.bytes.fetch_add(bytes, Ordering::Relaxed) + bytes;
+		if cur > self.peak_bytes.load(Ordering::Relaxed) {
+			self.peak_bytes.store(cur, Ordering::Relaxed);
+		}
+	}
+	fn sub_stats(&self, bytes: usize)
+	{
+		self.allocations.fetch_sub(1, Ordering::Relaxed);
+		self.bytes.fetch_sub(bytes, Ordering::Relaxed);
+	}
+}
+
+/// Call the provided closure for each heap that has been used
+pub fn for_each_heap<F: FnMut(&'static Heap)>(mut f: F)
+{
+	let mut p = S_HEAPS.load(Ordering::Acquire);
+	while !p.is_null()
+	{
+		// SAFE: Only `'static` heaps are added to the list
+		let h: &'static Heap = unsafe { &*p };
+		f(h);
+		p = h.next.load(Ordering::Relaxed);
+	}
+}
+
+/// Log the statistics of every heap
+pub fn dump_stats()
+{
+	for_each_heap(|h| log_log!("Heap '{}': {:?}", h.name(), h.stats()));
+	let (mapped, free) = S_LARGE_HEAP.lock().usage();
+	log_log!("Large allocations: {}KB mapped, {}KB free", mapped / 1024, free / 1024);
+	log_log!("Slab pages: {} unused", S_SLAB_PAGES.lock().free_count());
 }
 
 // Used by Box<T>
@@ -66,7 +222,7 @@ pub fn init()
 #[inline]
 unsafe fn exchange_malloc(size: usize, align: usize) -> *mut u8
 {
-	match allocate(HeapId::Global, size, align)
+	match allocate(&S_GLOBAL_HEAP, size, align)
 	{
 	Some(x) => x as *mut u8,
 	None => panic!("exchange_malloc({}, {}) out of memory", size, align),
@@ -79,14 +235,14 @@ unsafe fn box_free<T: ?Sized>(ptr: *mut T) {
 	let size = ::core::mem::size_of_val(&*ptr);
 	let align = ::core::mem::align_of_val(&*ptr);
 	if size != 0 {
-		S_GLOBAL_HEAP.lock().deallocate(ptr as *mut (), size, align);
+		deallocate(ptr as *mut (), size, align);
 	}
 }
 
 // Used by libgcc and ACPICA
 #[cfg(all(not(test),not(test_shim)))]
 #[no_mangle] pub unsafe extern "C" fn malloc(size: usize) -> *mut () {
-	allocate(HeapId::Global, size, 16).unwrap()
+	allocate(&S_GLOBAL_HEAP, size, 16).unwrap()
 } 
 #[cfg(all(not(test),not(test_shim)))]
 #[no_mangle] pub unsafe extern "C" fn free(ptr: *mut ()) {
@@ -98,7 +254,15 @@ unsafe fn box_free<T: ?Sized>(ptr: *mut T) {
 // Used by kernel internals
 pub unsafe fn alloc<T>(value: T) -> *mut T
 {
-	let ret = match allocate(HeapId::Global, ::core::mem::size_of::<T>(), ::core::mem::align_of::<T>())
+	alloc_in(&S_GLOBAL_HEAP, value)
+}
+pub unsafe fn alloc_raw(size: usize, align: usize) -> *mut () {
+	alloc_raw_in(&S_GLOBAL_HEAP, size, align)
+}
+/// Allocate a value from a named heap (release with `dealloc`)
+pub unsafe fn alloc_in<T>(heap: &'static Heap, value: T) -> *mut T
+{
+	let ret = match allocate(heap, ::core::mem::size_of::<T>(), ::core::mem::align_of::<T>())
 		{
 		Some(v) => v as *mut T,
 		None => panic!("Out of memory")
@@ -106,8 +270,9 @@ pub unsafe fn alloc<T>(value: T) -> *mut T
 	::core::ptr::write(ret, value);
 	ret
 }
-pub unsafe fn alloc_raw(size: usize, align: usize) -> *mut () {
-	match allocate(HeapId::Global, size, align)
+/// Allocate raw memory from a named heap (release with `dealloc_raw`)
+pub unsafe fn alloc_raw_in(heap: &'static Heap, size: usize, align: usize) -> *mut () {
+	match allocate(heap, size, align)
 	{
 	Some(v) => v,
 	None => panic!("Out of memory")
@@ -147,7 +312,7 @@ impl<T> ArrayAlloc<T>
 			}
 			else
 			{
-				let ptr = match allocate(HeapId::Global, ::core::mem::size_of::<T>() * count, ::core::mem::align_of::<T>())
+				let ptr = match allocate(&S_GLOBAL_HEAP, ::core::mem::size_of::<T>() * count, ::core::mem::align_of::<T>())
 					{
 					Some(v) => v as *mut T,
 					None => panic!("Out of memory when allocating array of {} elements", count)
@@ -251,37 +416,93 @@ impl<T> ::core::ops::Drop for ArrayAlloc<T>
 	}
 }
 
+
 // Main entrypoints
 /// Allocate memory from the specified heap
-unsafe fn allocate(heap: HeapId, size: usize, align: usize) -> Option<*mut ()>
+unsafe fn allocate(heap: &'static Heap, size: usize, align: usize) -> Option<*mut ()>
 {
-	match heap
-	{
-	HeapId::Global => match S_GLOBAL_HEAP.lock().allocate(size, align)
+	if size == 0 {
+		return Some(ZERO_ALLOC);
+	}
+	heap.register();
+	let owner = heap as *const Heap as usize;
+	let (rv, bytes) = match slab::class_for(size, align)
 		{
-		Ok(v) => Some(v),
-		Err(e) => {
-			log_error!("Unable to allocate: {:?}", e);
-			None
-			},
+		Some(class) => (heap.slabs.lock().allocate(class, owner, &mut GlobalPages), slab::class_size(class)),
+		None => (S_LARGE_HEAP.lock().allocate(size, align, owner), size),
+		};
+	match rv
+	{
+	Ok(v) => {
+		heap.add_stats(bytes);
+		Some(v)
+		},
+	Err(e) => {
+		heap.failures.fetch_add(1, Ordering::Relaxed);
+		log_error!("Unable to allocate {} bytes (align {}) from heap '{}': {:?}", size, align, heap.name, e);
+		None
 		},
-	_ => panic!("TODO: Non-global heaps"),
+	}
+}
+
+/// Obtain the heap that made a slab allocation
+unsafe fn slab_owner(ptr: *mut ()) -> (&'static Heap, usize)
+{
+	match slab::object_info(ptr)
+	{
+	// SAFE: Owner tags are always `'static` heaps
+	Ok((class, owner)) => (&*(owner as *const Heap), class),
+	Err(e) => panic!("Heap pointer {:p} corrupted: {:?}", ptr, e),
 	}
 }
 
 /// Attempt to expand in-place
 unsafe fn expand(pointer: *mut (), newsize: usize) -> bool
 {
-	S_GLOBAL_HEAP.lock().expand_alloc(pointer, newsize)
+	if (pointer as usize) < LARGE_START {
+		let (_, class) = slab_owner(pointer);
+		newsize <= slab::class_size(class)
+	}
+	else {
+		match S_LARGE_HEAP.lock().expand_alloc(pointer, newsize)
+		{
+		Some((oldsize, owner)) => {
+			let heap = &*(owner as *const Heap);
+			heap.bytes.fetch_add(newsize - oldsize, Ordering::Relaxed);
+			true
+			},
+		None => false,
+		}
+	}
 }
 unsafe fn shrink(pointer: *mut (), newsize: usize)
 {
-	S_GLOBAL_HEAP.lock().shrink_alloc(pointer, newsize)
+	// NOTE: Slab objects keep their class when shrunk
+	if (pointer as usize) >= LARGE_START {
+		let (oldsize, owner) = S_LARGE_HEAP.lock().shrink_alloc(pointer, newsize);
+		let heap = &*(owner as *const Heap);
+		heap.bytes.fetch_sub(oldsize - newsize, Ordering::Relaxed);
+	}
 }
 
-unsafe fn deallocate(pointer: *mut (), size: usize, align: usize)
+unsafe fn deallocate(pointer: *mut (), size: usize, _align: usize)
 {
-	S_GLOBAL_HEAP.lock().deallocate(pointer as *mut (), size, align);
+	if pointer == ZERO_ALLOC {
+		assert!(size == 0, "ZERO_ALLOC but size({}) != 0", size);
+		return ;
+	}
+	let addr = pointer as usize;
+	assert!(HEAP_START <= addr && addr < HEAP_END, "Freeing non-heap pointer {:p}", pointer);
+	if addr < LARGE_START {
+		let (heap, class) = slab_owner(pointer);
+		assert!(size <= slab::class_size(class), "Freeing {:p} with size {} larger than its class", pointer, size);
+		heap.slabs.lock().deallocate(pointer, &mut GlobalPages);
+		heap.sub_stats(slab::class_size(class));
+	}
+	else {
+		let (size, owner) = S_LARGE_HEAP.lock().deallocate(pointer, size);
+		(&*(owner as *const Heap)).sub_stats(size);
+	}
 }
 
 
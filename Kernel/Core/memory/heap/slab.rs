@@ -0,0 +1,278 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/memory/heap/slab.rs
+//! Size-class (slab) allocator for small objects
+//!
+//! Each slab is a single page holding objects of one size class, with a header at the start of the page
+//! (so the class and owner of an object can be found from its address). Objects are aligned to their
+//! class size.
+use super::Error;
+use super::heapdef::Backing;
+use core::marker::PhantomData;
+
+/// Object sizes for each class
+const CLASS_SIZES: [usize; NUM_CLASSES] = [16, 32, 64, 128, 256, 512, 1024];
+pub const NUM_CLASSES: usize = 7;
+
+const SLAB_MAGIC: u32 = 0x51AB51AB;
+
+/// Source of pages for slabs
+pub trait PageSource
+{
+	fn get_page(&mut self) -> Result<*mut (), Error>;
+	fn release_page(&mut self, page: *mut ());
+}
+
+#[repr(C)]
+struct SlabPage
+{
+	magic: u32,
+	class: u16,
+	used: u16,
+	/// Opaque tag for the owner of the slab
+	owner: usize,
+	free: *mut FreeObj,
+	next: *mut SlabPage,
+	prev: *mut SlabPage,
+}
+struct FreeObj
+{
+	next: *mut FreeObj,
+}
+
+#[derive(Copy,Clone)]
+struct SlabClass
+{
+	/// Slabs with both used and free objects
+	partial: *mut SlabPage,
+	/// A single completely free slab, kept to avoid repeatedly releasing and re-fetching a page
+	empty: *mut SlabPage,
+}
+
+/// A set of slabs, one list for each size class
+pub struct SlabCache
+{
+	classes: [SlabClass; NUM_CLASSES],
+	pages: usize,
+}
+unsafe impl Send for SlabCache {}
+
+/// Size class for an allocation (`None` if it's too large or too aligned for a slab)
+pub fn class_for(size: usize, align: usize) -> Option<usize>
+{
+	let size = ::core::cmp::max(size, align);
+	CLASS_SIZES.iter().position(|&s| s >= size)
+}
+
+/// Object size for a class
+pub fn class_size(class: usize) -> usize
+{
+	CLASS_SIZES[class]
+}
+
+/// Obtain the class and owner tag of an allocated object
+pub unsafe fn object_info(ptr: *mut ()) -> Result<(usize, usize), Error>
+{
+	let page = &*page_of(ptr);
+	if page.magic != SLAB_MAGIC || page.class as usize >= NUM_CLASSES {
+		log_error!("Slab object {:p} has an invalid header (magic={:#x})", ptr, page.magic);
+		return Err( Error::Corrupted );
+	}
+	let size = CLASS_SIZES[page.class as usize];
+	if (ptr as usize - page as *const _ as usize) < first_offset(size) || (ptr as usize) % size != 0 {
+		log_error!("Pointer {:p} isn't an object in its slab (class size {})", ptr, size);
+		return Err( Error::Corrupted );
+	}
+	Ok( (page.class as usize, page.owner) )
+}
+
+fn page_of(ptr: *mut ()) -> *mut SlabPage {
+	(ptr as usize & !(::PAGE_SIZE - 1)) as *mut SlabPage
+}
+/// Offset of the first object in a slab (after the header, rounded up to the class size)
+fn first_offset(size: usize) -> usize {
+	::lib::num::round_up(::core::mem::size_of::<SlabPage>(), size)
+}
+
+impl SlabCache
+{
+	pub const fn new() -> SlabCache {
+		SlabCache {
+			classes: [SlabClass { partial: 0 as *mut _, empty: 0 as *mut _ }; NUM_CLASSES],
+			pages: 0,
+			}
+	}
+
+	/// Number of pages currently held by this cache
+	pub fn page_count(&self) -> usize {
+		self.pages
+	}
+
+	/// Allocate an object of the given class, fetching a new slab (tagged with `owner`) if needed
+	pub unsafe fn allocate<P: PageSource>(&mut self, class: usize, owner: usize, pages: &mut P) -> Result<*mut (), Error>
+	{
+		if self.classes[class].partial.is_null()
+		{
+			let page = if !self.classes[class].empty.is_null() {
+					::core::mem::replace(&mut self.classes[class].empty, ::core::ptr::null_mut())
+				}
+				else {
+					let p = try!(pages.get_page()) as *mut SlabPage;
+					init_page(p, class, owner);
+					self.pages += 1;
+					p
+				};
+			self.push_partial(class, page);
+		}
+
+		let page = &mut *self.classes[class].partial;
+		let obj = page.free;
+		page.free = (*obj).next;
+		page.used += 1;
+		if page.free.is_null() {
+			// - Now full, only partial slabs are tracked
+			self.unlink(class, page);
+		}
+		Ok( obj as *mut () )
+	}
+
+	/// Release an object (which must have been allocated from this cache)
+	pub unsafe fn deallocate<P: PageSource>(&mut self, ptr: *mut (), pages: &mut P)
+	{
+		let page = &mut *page_of(ptr);
+		let class = page.class as usize;
+		assert!(page.magic == SLAB_MAGIC && class < NUM_CLASSES, "Freeing {:p} - invalid slab", ptr);
+		assert!(page.used > 0, "Freeing {:p} - slab has no used objects", ptr);
+
+		let was_full = page.free.is_null();
+		let obj = ptr as *mut FreeObj;
+		(*obj).next = page.free;
+		page.free = obj;
+		page.used -= 1;
+		if was_full {
+			self.push_partial(class, page);
+		}
+		if page.used == 0
+		{
+			self.unlink(class, page);
+			if self.classes[class].empty.is_null() {
+				self.classes[class].empty = page;
+			}
+			else {
+				page.magic = 0;
+				pages.release_page(page as *mut _ as *mut ());
+				self.pages -= 1;
+			}
+		}
+	}
+
+	unsafe fn push_partial(&mut self, class: usize, page: *mut SlabPage)
+	{
+		let next = self.classes[class].partial;
+		(*page).next = next;
+		(*page).prev = ::core::ptr::null_mut();
+		if !next.is_null() {
+			(*next).prev = page;
+		}
+		self.classes[class].partial = page;
+	}
+	unsafe fn unlink(&mut self, class: usize, page: *mut SlabPage)
+	{
+		let (next, prev) = ((*page).next, (*page).prev);
+		if prev.is_null() {
+			self.classes[class].partial = next;
+		}
+		else {
+			(*prev).next = next;
+		}
+		if !next.is_null() {
+			(*next).prev = prev;
+		}
+	}
+}
+
+/// Initialise a new slab (header and free list)
+unsafe fn init_page(page: *mut SlabPage, class: usize, owner: usize)
+{
+	let size = CLASS_SIZES[class];
+	let base = page as usize;
+	let mut free = ::core::ptr::null_mut();
+	let mut ofs = ::PAGE_SIZE - size;
+	while ofs >= first_offset(size)
+	{
+		let obj = (base + ofs) as *mut FreeObj;
+		(*obj).next = free;
+		free = obj;
+		ofs -= size;
+	}
+	*page = SlabPage {
+		magic: SLAB_MAGIC,
+		class: class as u16,
+		used: 0,
+		owner: owner,
+		free: free,
+		next: ::core::ptr::null_mut(),
+		prev: ::core::ptr::null_mut(),
+		};
+}
+
+/// Pages for slabs, allocated from a fixed region
+///
+/// Released pages stay mapped and are re-used by any cache.
+pub struct PagePool<B>
+{
+	top: usize,
+	limit: usize,
+	free: *mut FreeObj,
+	free_count: usize,
+	_backing: PhantomData<B>,
+}
+unsafe impl<B> Send for PagePool<B> {}
+impl<B> PagePool<B>
+{
+	pub const fn new(start: usize, limit: usize) -> PagePool<B> {
+		PagePool {
+			top: start,
+			limit: limit,
+			free: 0 as *mut _,
+			free_count: 0,
+			_backing: PhantomData,
+			}
+	}
+	/// Number of released pages available for re-use
+	pub fn free_count(&self) -> usize {
+		self.free_count
+	}
+}
+impl<B: Backing> PageSource for PagePool<B>
+{
+	fn get_page(&mut self) -> Result<*mut (), Error>
+	{
+		if !self.free.is_null() {
+			let p = self.free;
+			// SAFE: Pages on the free list are mapped and unused
+			self.free = unsafe { (*p).next };
+			self.free_count -= 1;
+			Ok( p as *mut () )
+		}
+		else if self.limit - self.top < ::PAGE_SIZE {
+			Err( Error::OutOfReservation )
+		}
+		else {
+			let p = self.top as *mut ();
+			// SAFE: Address is within the pool's region, and not yet used
+			try!( unsafe { B::map(p, 1) } );
+			self.top += ::PAGE_SIZE;
+			Ok( p )
+		}
+	}
+	fn release_page(&mut self, page: *mut ())
+	{
+		let p = page as *mut FreeObj;
+		// SAFE: Page was provided by `get_page`, and is now unused
+		unsafe { (*p).next = self.free; }
+		self.free = p;
+		self.free_count += 1;
+	}
+}
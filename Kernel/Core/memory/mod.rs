@@ -23,6 +23,8 @@ pub mod memorymap;
 pub mod bump_region;
 pub mod page_cache;
 pub mod page_array;
+pub mod vma;
+pub mod shm;
 
 pub use arch::memory::PAddr;
 /*
@@ -87,7 +89,7 @@ pub unsafe fn buf_to_slice<'a, T>(ptr: *const T, size: usize) -> Option<&'a [T]>
 	if size > 0 && ptr as usize % ::core::mem::align_of::<T>() != 0 {
 		None
 	}
-	else if ! buf_valid(ptr as *const (), size) {
+	else if size.checked_mul(::core::mem::size_of::<T>()).map(|bytes| buf_valid(ptr as *const (), bytes)) != Some(true) {
 		None
 	}
 	else {
@@ -99,7 +101,7 @@ pub unsafe fn buf_to_slice_mut<'a, T>(ptr: *mut T, size: usize) -> Option<&'a mu
 	if size > 0 && ptr as usize % ::core::mem::align_of::<T>() != 0 {
 		None
 	}
-	else if ! buf_valid(ptr as *const (), size) {
+	else if size.checked_mul(::core::mem::size_of::<T>()).map(|bytes| buf_valid(ptr as *const (), bytes)) != Some(true) {
 		None
 	}
 	else {
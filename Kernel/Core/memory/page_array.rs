@@ -43,6 +43,28 @@ impl<T> PageArray<T>
 		}
 	}
 
+	/// Ensure that the page containing entry `idx` is allocated, returning `false` on allocation failure
+	///
+	/// The new page is zeroed (by the allocator), so this should only be used when all-zeroes is the default
+	/// value. Unlike `get_alloc` this doesn't require `&mut` (so no lock is needed for lock-free arrays)
+	pub fn ensure_page(&self, idx: usize) -> bool
+	{
+		let per_page = ::PAGE_SIZE / ::core::mem::size_of::<T>();
+		let page = (self.start as usize + (idx / per_page) * ::PAGE_SIZE) as *mut T;
+		if ::memory::virt::is_reserved( page ) {
+			true
+		}
+		else {
+			match ::memory::virt::allocate( page as *mut (), 1 )
+			{
+			Ok(_) => true,
+			// Another CPU got there first
+			Err(::memory::virt::MapError::RangeInUse) => true,
+			Err(::memory::virt::MapError::OutOfMemory) => false,
+			}
+		}
+	}
+
 	pub fn get_alloc(&mut self, idx: usize) -> &mut T
 	where
 		T: Default
@@ -7,6 +7,7 @@
 use prelude::*;
 use arch::memory::PAddr;
 use arch::memory::virt::TempHandle;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 pub const NOPAGE : PAddr = 1;
 
@@ -17,12 +18,51 @@ impl_fmt! {
 	}
 }
 
+/// Maximum number of entries in the memory map (matches the size of the arch's map buffer)
+const MAX_MAP_ENTS: usize = 16;
+
 static S_MEM_MAP: ::lib::LazyStatic<&'static [::memory::MemoryMapEnt]> = lazystatic_init!();
-/// Tracks the allocation point in S_MEM_MAP : (Entry Index, Address)
-static S_MAPALLOC : ::sync::Mutex<(usize,PAddr)> = mutex_init!( (0,0) );
-// TODO: Multiple stacks based on page colouring
-static S_FREE_STACK : ::sync::Mutex<PAddr> = mutex_init!( NOPAGE );
-// TODO: Reference counts (maybe require arch to expose that)
+/// Tracks the un-allocated portions of S_MEM_MAP
+static S_MAPALLOC : ::sync::Mutex<MapAlloc> = mutex_init!( MapAlloc { idx: 0, ranges: [(0,0); MAX_MAP_ENTS], next_frame: 0 } );
+/// Set once the arch's allocation bitmap tracks all free frames (and freed frames can be reused)
+static S_USE_BITMAP: AtomicBool = AtomicBool::new(false);
+// NOTE: Reference counts and the allocation bitmap are maintained by the arch (`::arch::memory::phys`)
+
+/// Number of free frames (available for allocation)
+static S_FREE_COUNT: AtomicUsize = AtomicUsize::new(0);
+/// Number of free frames below which the registered reclaimers are asked to release memory
+const LOW_WATERMARK: usize = 256;	// 1MB
+/// Sources of reclaimable memory (caches), see `register_reclaimer`
+static S_RECLAIMERS: ::sync::mutex::LazyMutex<Vec<&'static Reclaimer>> = lazymutex_init!();
+/// Set while the reclaimers are being run (prevents recursion if a reclaimer allocates)
+static S_RECLAIMING: AtomicBool = AtomicBool::new(false);
+
+/// A source of reclaimable memory (e.g. a cache of clean data)
+///
+/// Reclaimers are called when free memory drops below a watermark, and before an allocation fails.
+/// They can be called from within any allocation, so must not block (use `try_lock` and skip if contended).
+pub trait Reclaimer: Send + Sync
+{
+	/// Name of the reclaimer (for logging)
+	fn name(&self) -> &str;
+	/// Release up to `count` frames, returning the number released
+	fn reclaim(&self, count: usize) -> usize;
+}
+
+/// Allocation state for the memory map
+///
+/// Until the arch's allocation bitmap is ready, single frames are taken from the bottom of the current
+/// entry and contiguous ranges (which may have address limits) are carved from the top of the highest
+/// suitable entry. Once it's ready, the bitmap is searched instead (so freed frames are reused).
+struct MapAlloc
+{
+	/// Index of the entry used for single-frame allocations
+	idx: usize,
+	/// Remaining free region `[base, top)` of each map entry
+	ranges: [(PAddr,PAddr); MAX_MAP_ENTS],
+	/// Frame index to start the next single-frame bitmap search from
+	next_frame: u64,
+}
 
 /// A handle to a physical page (maintaining a reference to it, even when not mapped)
 pub struct FrameHandle(PAddr);
@@ -40,9 +80,15 @@ pub fn init()
 	if map.len() == 0 {
 		panic!("Empty memory map! Physical memory manager cannot operate");
 	}
+	assert!(map.len() <= MAX_MAP_ENTS, "Memory map has too many entries ({} > {})", map.len(), MAX_MAP_ENTS);
+	let mut lh = S_MAPALLOC.lock();
 	for (i,ent) in map.iter().enumerate()
 	{
 		log_log!("#{} : {:?}", i, ent);
+		if ent.state == ::memory::memorymap::MemoryState::Free {
+			lh.ranges[i] = (ent.start as PAddr, ent.end() as PAddr);
+			S_FREE_COUNT.fetch_add(ent.size as usize / ::PAGE_SIZE, Ordering::Relaxed);
+		}
 	}
 	let mut i = 0;
 	while i != map.len() && map[i].state != ::memory::memorymap::MemoryState::Free {
@@ -51,11 +97,45 @@ pub fn init()
 	if i == map.len() {
 		panic!("No free memory in map.");
 	}
-	*S_MAPALLOC.lock() = (i, map[i].start as PAddr);
+	lh.idx = i;
+	drop(lh);
+	S_RECLAIMERS.init(|| Vec::new());
+
+	// 2. Prepare the arch's allocation tracking (now that frames can be allocated)
+	let max_addr = map.iter().filter(|e| e.state != ::memory::memorymap::MemoryState::Reserved).map(|e| e.end()).max().unwrap_or(0);
+	if ::arch::memory::phys::init_bitmap(max_addr / ::PAGE_SIZE as u64)
+	{
+		// - Mark everything already handed out from the map, then switch to bitmap allocation
+		let mut lh = S_MAPALLOC.lock();
+		for (i,ent) in map.iter().enumerate()
+		{
+			if ent.state != ::memory::memorymap::MemoryState::Free {
+				continue ;
+			}
+			let (base, top) = lh.ranges[i];
+			let mut addr = ent.start;
+			while addr < ent.end() {
+				if !(base as u64 <= addr && addr < top as u64) {
+					::arch::memory::phys::mark_used(addr / ::PAGE_SIZE as u64);
+				}
+				addr += ::PAGE_SIZE as u64;
+			}
+		}
+		lh.next_frame = map[lh.idx].start / ::PAGE_SIZE as u64;
+		S_USE_BITMAP.store(true, Ordering::Release);
+	}
+	else
+	{
+		log_warning!("No allocation bitmap, released frames will not be reused");
+	}
 }
 
 impl FrameHandle
 {
+	/// Returns true if this is the only reference to the frame
+	pub fn is_unique(&self) -> bool {
+		::arch::memory::phys::get_multiref_count(self.0 as u64 / ::PAGE_SIZE as u64) == 0
+	}
 	/// UNSAFE due to using a raw physical address
 	pub unsafe fn from_addr(addr: PAddr) -> FrameHandle {
 		//mark_used(addr);
@@ -109,74 +189,178 @@ fn is_ram(phys: PAddr) -> bool
 	false
 }
 
-pub fn make_unique(page: PAddr, virt_addr: &[u8; ::PAGE_SIZE]) -> PAddr
+/// Obtain a unique copy of a (possibly shared) frame, releasing the caller's reference to the original if a copy is made
+pub fn make_unique(page: PAddr, virt_addr: &[u8; ::PAGE_SIZE]) -> Result<PAddr, Error>
 {
 	if !is_ram(page) {
 		panic!("Calling 'make_unique' on non-RAM page");
 	}
 	else if ::arch::memory::phys::get_multiref_count(page as u64 / ::PAGE_SIZE as u64) == 0 {
-		page
+		Ok( page )
 	}
 	else {
 		// 1. Allocate a new frame in temp region
-		let mut new_frame = ::memory::virt::alloc_free().expect("TODO: handle OOM in make_unique");
+		let mut new_frame = try!( ::memory::virt::alloc_free().map_err(|_| Error) );
 		// 2. Copy in content of old frame
 		new_frame.clone_from_slice( virt_addr );
-		new_frame.into_frame().into_addr()
+		// 3. Release the caller's reference to the shared frame
+		deref_frame(page);
+		Ok( new_frame.into_frame().into_addr() )
 	}
 }
 
-pub fn allocate_range_bits(bits: u8, count: usize) -> PAddr
+/// Allocate `count` physically contiguous frames, all below `1 << bits`
+///
+/// Ranges are taken from the top of the highest suitable memory map entry, so that low memory is
+/// kept for devices with narrower address limits.
+pub fn allocate_range_bits(bits: u8, count: usize) -> Result<PAddr, Error>
 {
-	// XXX: HACK! Falls back to the simple code if possible
-	if bits >= 64 || get_memory_map().last().unwrap().start >> bits == 0
+	let limit: PAddr = if bits as usize >= 8 * ::core::mem::size_of::<PAddr>() { !0 } else { (1 << bits) & !(::PAGE_SIZE as PAddr - 1) };
+	let size = (count * ::PAGE_SIZE) as PAddr;
+	let rv = {
+		let mut h = S_MAPALLOC.lock();
+		let map = get_memory_map();
+		let mut rv = None;
+		if S_USE_BITMAP.load(Ordering::Acquire)
+		{
+			rv = find_free_range_bm(map, limit as u64, count as u64).map(|v| v as PAddr);
+			if let Some(v) = rv {
+				for i in 0 .. count {
+					::arch::memory::phys::mark_used(v as u64 / ::PAGE_SIZE as u64 + i as u64);
+				}
+			}
+		}
+		else
+		{
+			for i in (0 .. map.len()).rev()
+			{
+				let (base, top) = h.ranges[i];
+				if base >= top || base >= limit || top - base < size {
+					continue ;
+				}
+				if top <= limit {
+					// - Entirely below the limit, carve from the top
+					h.ranges[i].1 = top - size;
+					rv = Some(top - size);
+					break ;
+				}
+				else if limit - base >= size {
+					// - Straddles the limit, carve from the bottom (leaving the upper part intact)
+					h.ranges[i].0 = base + size;
+					rv = Some(base);
+					break ;
+				}
+			}
+		}
+		match rv
+		{
+		Some(v) => v,
+		None => {
+			log_warning!("Out of physical memory: allocate_range_bits(bits={}, count={})", bits, count);
+			return Err( Error );
+			},
+		}
+		};
+	S_FREE_COUNT.fetch_sub(count, Ordering::Relaxed);
+	log_trace!("allocate_range_bits(bits={}, count={}) = {:#x}", bits, count, rv);
+	Ok( rv )
+}
+
+/// Search the bitmap for `count` free frames below `limit`, starting from the top of the highest entry
+fn find_free_range_bm(map: &[::memory::MemoryMapEnt], limit: u64, count: u64) -> Option<u64>
+{
+	let page_size = ::PAGE_SIZE as u64;
+	for ent in map.iter().rev()
+	{
+		if ent.state != ::memory::memorymap::MemoryState::Free || ent.start >= limit {
+			continue ;
+		}
+		let first = ent.start / page_size;
+		let mut frame = ::core::cmp::min(ent.end(), limit) / page_size;
+		// - Walk down, counting the run of free frames ending at `frame`
+		let mut run = 0;
+		while frame > first
+		{
+			frame -= 1;
+			if ::arch::memory::phys::is_used(frame) {
+				run = 0;
+			}
+			else {
+				run += 1;
+				if run == count {
+					return Some(frame * page_size);
+				}
+			}
+		}
+	}
+	None
+}
+
+/// Search the bitmap for a single free frame, continuing on from the previous allocation
+fn find_free_frame_bm(h: &mut MapAlloc, map: &[::memory::MemoryMapEnt]) -> Option<u64>
+{
+	let page_size = ::PAGE_SIZE as u64;
+	let start = h.next_frame;
+	// - First pass searches at/above the previous allocation, second wraps around to below it
+	for &(lo, hi) in &[(start, !0), (0, start)]
 	{
-		return allocate_range(count);
-	}
-	// 1. Locate the last block of a suitable bitness
-	// - Take care to correctly handle blocks that straddle bitness boundaries
-	// NOTE: Memory map constructor _can_ break blocks up at common bitness boundaries (16, 24, 32 bits) to make this more efficient
-	// 2. Obtain `count` pages from either the end (if possible) or the start of this block
-	// TODO: If the block is not large enough, return an error (NOPAGE)
-	panic!("TODO: allocate_range_bits(bits={}, count={})", bits, count);
+		for ent in map.iter()
+		{
+			if ent.state != ::memory::memorymap::MemoryState::Free {
+				continue ;
+			}
+			let first = ::core::cmp::max(ent.start / page_size, lo);
+			let last = ::core::cmp::min(ent.end() / page_size, hi);
+			for frame in first .. last
+			{
+				if ! ::arch::memory::phys::is_used(frame) {
+					h.next_frame = frame + 1;
+					return Some(frame);
+				}
+			}
+		}
+	}
+	None
 }
 
-pub fn allocate_range(count: usize) -> PAddr
+/// Allocate a single frame (from the bitmap, or the bottom of the memory map if the bitmap isn't ready)
+fn allocate_frame() -> Result<PAddr, Error>
 {
-	let mut h = S_MAPALLOC.lock();
-	log_trace!("allocate_range: *h = ({},{:#x}) (init)", h.0, h.1);
-	let (mut i,mut addr) = *h;
-	
 	let map = get_memory_map();
-	if i == map.len() {
-		log_error!("Out of physical memory");
-		return NOPAGE;
+	let mut h = S_MAPALLOC.lock();
+	if S_USE_BITMAP.load(Ordering::Acquire)
+	{
+		// NOTE: The bitmap is pre-allocated, so marking under the lock doesn't recurse into the allocator
+		return match find_free_frame_bm(&mut h, map)
+			{
+			Some(frame) => {
+				::arch::memory::phys::mark_used(frame);
+				Ok( (frame * ::PAGE_SIZE as u64) as PAddr )
+				},
+			None => Err( Error ),
+			};
 	}
-	// If there's less than one page left in the map entry, go to the next one
-	if addr + (1 * ::PAGE_SIZE) as PAddr > map[i].end() as PAddr
+	let size = ::PAGE_SIZE as PAddr;
+	log_trace!("allocate_frame: idx={} (init)", h.idx);
+	loop
 	{
-		i += 1;
+		let i = h.idx;
+		if i == map.len() {
+			log_debug!("allocate_frame: Memory map exhausted");
+			return Err( Error );
+		}
+		let (base, top) = h.ranges[i];
+		if base < top && top - base >= size {
+			h.ranges[i].0 = base + size;
+			return Ok( base );
+		}
+		// This entry is exhausted, move to the next free entry
+		let mut i = i + 1;
 		while i != map.len() && map[i].state != ::memory::memorymap::MemoryState::Free {
 			i += 1;
 		}
-		if i == map.len() {
-			log_error!("Out of physical memory");
-			*h = (i, 0);
-			return NOPAGE;
-		}
-		addr = map[i].start as PAddr;
-	}
-	let rv = addr;
-	let shift = (count * ::PAGE_SIZE) as PAddr;
-	if addr + shift > map[i].end() as PAddr {
-		todo!("Handle allocating from ahead in map ({:#x} + {:#x} > {:#x}, start={:#x})", addr, shift, map[i].end(), map[i].start);
-		// TODO: If the shift pushes this allocation over the edge of a map entry, stick the remaining entries onto the free stack and move to the next free block
-	}
-	addr += shift;
-	//log_trace!("allocate_range: rv={:#x}, i={}, addr={:#x}", rv, i, addr);
-	*h = (i, addr);
-	//log_trace!("allocate_range: *h = {:?}", *h);
-	return rv;
+		h.idx = i;
+	}
 }
 
 /// Allocate a page with no fixed alocation, returns a temporary handle to it
@@ -189,77 +373,101 @@ pub fn allocate(address: *mut ()) -> bool {
 	allocate_int(Some(address)).is_ok()
 }
 
-/// Allocate a page at the given (optional) address
-/// 
-/// If no address is provided, a temporary handle is returned
-fn allocate_int( address: Option<*mut ()> ) -> Result<Option<TempHandle<u8>>, Error>
+/// Register a source of reclaimable memory
+pub fn register_reclaimer(reclaimer: &'static Reclaimer)
 {
-	log_trace!("allocate(address={:?})", address);
-	// 1. Pop a page from the free stack
-	// SAFE: Frames on the free are not aliased, alloc is safe
-	unsafe
+	S_RECLAIMERS.lock().push(reclaimer);
+}
+
+/// Prevents reclaimers from running while held
+///
+/// Used by code that allocates frames with locks held that a reclaimer could need (e.g. the heap, as
+/// reclaimers free heap memory).
+pub struct ReclaimHold(bool);
+pub fn hold_reclaim() -> ReclaimHold
+{
+	ReclaimHold( !S_RECLAIMING.swap(true, Ordering::Acquire) )
+}
+impl ::core::ops::Drop for ReclaimHold
+{
+	fn drop(&mut self)
 	{
-		let mut h = S_FREE_STACK.lock();
-		let paddr = *h;
-		if paddr != NOPAGE
-		{
-			match address
-			{
-			Some(address) => {
-				// Check that calling `virt::map` will not cause us to be called
-				if ::arch::memory::virt::can_map_without_alloc(address) {
-					// Map and obtain the next page
-					::memory::virt::map(address, paddr, super::virt::ProtectionMode::KernelRW);
-					*h = *(address as *const PAddr);
-				}
-				else {
-					// Otherwise, do a temp mapping, extract the next page, then drop the lock and map
-					// NOTE: A race here doesn't matter, as lower operations are atomic, and it'd just be slower
-					::memory::virt::with_temp(paddr, |page| *h = *(&page[0] as *const u8 as *const PAddr));
-					drop(h);
-					::memory::virt::map(address, paddr, super::virt::ProtectionMode::KernelRW);
-				}
-				// Zero page - Why? - Should fill it with dropped :)
-				*(address as *mut [u8; ::PAGE_SIZE]) = ::core::mem::zeroed();
-				log_trace!("- {:p} (stack) paddr = {:#x}", address, paddr);
-				mark_used(paddr);
-				return Ok(None);
-				},
-			None => {
-				let handle = ::arch::memory::virt::TempHandle::new(paddr);
-				*h = *(&handle[0] as *const u8 as *const PAddr);
-				log_trace!("- None (stack) paddr = {:#x}", paddr);
-				mark_used(paddr);
-				return Ok( Some(handle) );
-				},
-			}
+		if self.0 {
+			S_RECLAIMING.store(false, Ordering::Release);
 		}
 	}
-	// 2. If none, allocate from map
-	let paddr = allocate_range(1);
-	if paddr != NOPAGE
+}
+
+/// Ask the registered reclaimers to release up to `count` frames, returning the number released
+fn reclaim(count: usize) -> usize
+{
+	// - Only one reclaim pass at a time, and don't recurse if a reclaimer allocates
+	if S_RECLAIMING.swap(true, Ordering::Acquire) {
+		return 0;
+	}
+	let mut rv = 0;
+	// - The registry lock is held while registering (which allocates), so don't block on it
+	if let Some(lh) = S_RECLAIMERS.try_lock()
 	{
-		if let Some(address) = address {
-			// SAFE: Physical address just allocated
-			unsafe {
-				::memory::virt::map(address, paddr, super::virt::ProtectionMode::KernelRW);
-				*(address as *mut [u8; ::PAGE_SIZE]) = ::core::mem::zeroed();
+		for r in lh.iter()
+		{
+			if rv >= count {
+				break ;
+			}
+			let n = r.reclaim(count - rv);
+			if n > 0 {
+				log_debug!("reclaim: '{}' released {} frames", r.name(), n);
 			}
-			log_trace!("- {:p} (range) paddr = {:#x}", address, paddr);
-			mark_used(paddr);
-			return Ok( None );
+			rv += n;
 		}
-		else {
-			log_trace!("- None (range) paddr = {:#x}", paddr);
-			mark_used(paddr);
-			// SAFE: Physical address was just allocated, can't alias
-			let handle = unsafe { ::arch::memory::virt::TempHandle::new(paddr) };
-			return Ok( Some(handle) );
+	}
+	S_RECLAIMING.store(false, Ordering::Release);
+	rv
+}
+
+/// Allocate a page at the given (optional) address
+/// 
+/// If no address is provided, a temporary handle is returned
+fn allocate_int( address: Option<*mut ()> ) -> Result<Option<TempHandle<u8>>, Error>
+{
+	// 1. Under the low watermark, ask the caches to give some memory back
+	let free = S_FREE_COUNT.load(Ordering::Relaxed);
+	if free < LOW_WATERMARK {
+		reclaim(LOW_WATERMARK - free);
+	}
+	// 2. Allocate, and if that fails release whatever can be released then try again
+	match allocate_once(address)
+	{
+	Ok(v) => Ok(v),
+	Err(_) if reclaim(1) > 0 => allocate_once(address),
+	Err(e) => {
+		log_warning!("Out of physical memory");
+		Err(e)
+		},
+	}
+}
+
+fn allocate_once( address: Option<*mut ()> ) -> Result<Option<TempHandle<u8>>, Error>
+{
+	log_trace!("allocate(address={:?})", address);
+	// NOTE: Mapping can allocate, so is done after the allocator lock is released
+	let paddr = try!( allocate_frame() );
+	S_FREE_COUNT.fetch_sub(1, Ordering::Relaxed);
+	if let Some(address) = address {
+		// SAFE: Physical address just allocated
+		unsafe {
+			::memory::virt::map(address, paddr, super::virt::ProtectionMode::KernelRW);
+			*(address as *mut [u8; ::PAGE_SIZE]) = ::core::mem::zeroed();
 		}
+		log_trace!("- {:p} paddr = {:#x}", address, paddr);
+		Ok( None )
+	}
+	else {
+		log_trace!("- None paddr = {:#x}", paddr);
+		// SAFE: Physical address was just allocated, can't alias
+		let handle = unsafe { ::arch::memory::virt::TempHandle::new(paddr) };
+		Ok( Some(handle) )
 	}
-	// 3. Fail
-	log_warning!("Out of physical memory");
-	Err( Error )
 }
 
 pub fn ref_frame(paddr: PAddr)
@@ -280,12 +488,9 @@ pub fn deref_frame(paddr: PAddr)
 	else if ::arch::memory::phys::deref_frame(paddr as u64 / ::PAGE_SIZE as u64) == 0 {
 		// - This page is the only reference.
 		if ::arch::memory::phys::mark_free(paddr as u64 / ::PAGE_SIZE as u64) == true {
-			// Release frame back into the pool
-			// SAFE: This frame is unaliased
-			unsafe {
-				let mut h = S_FREE_STACK.lock();
-				::memory::virt::with_temp(paddr, |page| *(&mut page[0] as *mut u8 as *mut PAddr) = *h);
-				*h = paddr;
+			// Frame is back in the pool (if the bitmap is in use, otherwise it's leaked)
+			if S_USE_BITMAP.load(Ordering::Acquire) {
+				S_FREE_COUNT.fetch_add(1, Ordering::Relaxed);
 			}
 		}
 		else {
@@ -298,9 +503,4 @@ pub fn deref_frame(paddr: PAddr)
 	}
 }
 
-fn mark_used(paddr: PAddr)
-{
-	//::arch::memory::phys::mark_used(paddr / ::PAGE_SIZE as PAddr)
-}
-
 // vim: ft=rust
@@ -0,0 +1,79 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/memory/shm.rs
+//! Anonymous shared memory objects
+//!
+//! A shared memory object is a fixed number of zero-filled pages that can be mapped into any number of
+//! address spaces. Frames are allocated on first touch, and are released once the object and all of its
+//! mappings are gone.
+use prelude::*;
+use lib::mem::Arc;
+use lib::VecMap;
+use memory::phys::FrameHandle;
+use memory::virt::{ProtectionMode, MapError};
+
+pub struct SharedMemory
+{
+	page_count: usize,
+	frames: ::sync::Mutex<VecMap<usize, FrameHandle>>,
+}
+impl_fmt! {
+	Debug(self, f) for SharedMemory {
+		write!(f, "SharedMemory({} pages)", self.page_count)
+	}
+}
+
+impl SharedMemory
+{
+	/// Create a new object covering `size` bytes (rounded up to whole pages)
+	pub fn new(size: usize) -> Arc<SharedMemory>
+	{
+		Arc::new(SharedMemory {
+			// - Rounded without overflowing for sizes near `usize::MAX`
+			page_count: size / ::PAGE_SIZE + if size % ::PAGE_SIZE != 0 { 1 } else { 0 },
+			frames: Default::default(),
+			})
+	}
+
+	pub fn page_count(&self) -> usize {
+		self.page_count
+	}
+	pub fn size(&self) -> usize {
+		self.page_count * ::PAGE_SIZE
+	}
+
+	/// Map the entire object into the current (user) address space
+	///
+	/// Pages are filled on first access (see `::memory::vma`)
+	pub fn map(this: &Arc<SharedMemory>, addr: *mut (), prot: ProtectionMode) -> Result<(), MapError>
+	{
+		match prot
+		{
+		ProtectionMode::UserRO => {},
+		ProtectionMode::UserRW => {},
+		ProtectionMode::UserRX => {},
+		_ => panic!("Invalid protection mode passed to SharedMemory::map - {:?}", prot),
+		}
+		assert!(addr as usize % ::PAGE_SIZE == 0, "SharedMemory::map - Unaligned address {:p}", addr);
+		::memory::vma::map_lazy(addr, this.page_count, prot, ::memory::vma::Backing::Shared { shm: this.clone(), first_page: 0 })
+	}
+
+	/// Obtain a handle to the frame for page `idx` (allocated and zeroed on first use)
+	pub fn get_page(&self, idx: usize) -> Result<FrameHandle, MapError>
+	{
+		assert!(idx < self.page_count, "SharedMemory::get_page({}) OOB {}", idx, self.page_count);
+		use lib::vec_map::Entry;
+		Ok(match self.frames.lock().entry(idx)
+			{
+			Entry::Occupied(e) => e.into_mut().clone(),
+			Entry::Vacant(e) => {
+				let mut newpg = try!(::memory::virt::alloc_free());
+				for b in newpg.iter_mut() {
+					*b = 0;
+				}
+				e.insert(newpg.into_frame()).clone()
+				},
+			})
+	}
+}
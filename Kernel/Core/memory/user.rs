@@ -11,10 +11,13 @@ pub fn read<T: ::lib::POD>(addr: usize) -> Result<T, ()> {
 		Err( () )
 	}
 	else {
-		// TODO: XXX Handle potential for user to alter the AS during this
-		// SAFE: (Assuming single-thread) Alignment and validity checked
+		// SAFE: Alignment and validity checked, and the freeze stops the user unmapping the page during the read
 		unsafe {
-			Ok( ::core::ptr::read(addr as *const T) )
+			match ::memory::freeze::Freeze::new(ptr)
+			{
+			Ok(v) => Ok( ::core::ptr::read(&*v) ),
+			Err(_) => Err( () ),
+			}
 		}
 	}
 }
@@ -128,15 +128,7 @@ where
 }
 
 /// Ensure that the provded pages are valid (i.e. backed by memory)
-pub fn allocate(addr: *mut (), page_count: usize) -> Result<(), MapError> {
-	allocate_int(addr, page_count, false)
-}
-/// Allocate memory for user access
-pub fn allocate_user(addr: *mut (), page_count: usize) -> Result<(), MapError> {
-	allocate_int(addr, page_count, true)
-}
-
-fn allocate_int(addr: *mut (), page_count: usize, is_user: bool) -> Result<(), MapError>
+pub fn allocate(addr: *mut (), page_count: usize) -> Result<(), MapError>
 {
 	use arch::memory::addresses::is_global;
 
@@ -168,17 +160,15 @@ fn allocate_int(addr: *mut (), page_count: usize, is_user: bool) -> Result<(), M
 			return Err( MapError::OutOfMemory );
 		}
 	}
-	if is_user {
-		for pgptr in Pages(addr, page_count) {
-			// SAFE: This region has just been allocated, and is KernelRW, upgrading to allow user access
-			unsafe {
-				::arch::memory::virt::reprotect(pgptr, ProtectionMode::UserRW);
-			}
-		}
-	}
 
 	Ok( () )
 }
+/// Allocate memory for user access
+///
+/// Pages are zero-filled on first access (see `::memory::vma`)
+pub fn allocate_user(addr: *mut (), page_count: usize) -> Result<(), MapError> {
+	::memory::vma::map_lazy(addr, page_count, ProtectionMode::UserRW, ::memory::vma::Backing::Anonymous)
+}
 
 /// Atomically reserves a region of address space
 pub fn reserve(addr: *mut (), page_count: usize) -> Result<Reservation, ()>
@@ -219,13 +209,6 @@ impl Reservation
 		// SAFE: Unique, and owned
 		unsafe { ::core::slice::from_raw_parts_mut( (self.0 as usize + idx * ::PAGE_SIZE) as *mut u8, ::PAGE_SIZE) }
 	}
-	//pub fn map_at(&mut self, idx: usize, frame: FrameHandle) -> Result<(),FrameHandle> {
-	//	assert!(idx < self.1);
-	//	let addr = (self.0 as usize + idx * ::PAGE_SIZE) as *mut ();
-	//	::arch::memory::virt::unmap(addr);
-	//	::arch::memory::virt::map(addr, frame.into_addr(), ProtectionMode::KernelRW);
-	//	Ok( () )
-	//}
 	pub fn finalise(self, final_mode: ProtectionMode) -> Result<(),()> {
 		log_trace!("Reservation::finalise(final_mode={:?})", final_mode);
 		for addr in Pages(self.0, self.1) {
@@ -274,15 +257,24 @@ pub unsafe fn reprotect_user(addr: *mut (), prot: ProtectionMode) -> Result<(),(
 		Err( () )
 	}
 	else {
-		if prot == ProtectionMode::Unmapped {
-			if let Some(paddr) = ::arch::memory::virt::unmap(addr) {
-				::memory::phys::deref_frame(paddr);
+		// Pages borrowed by the kernel (see `::memory::freeze`) can't be changed
+		::memory::freeze::with_unfrozen(addr, 1, || {
+			if prot == ProtectionMode::Unmapped {
+				// Write back changes if this is part of a writeback file mapping
+				if let Err(e) = ::memory::vma::flush(addr, 1) {
+					log_error!("Flushing {:p} before unmap failed: {:?}", addr, e);
+				}
+				// NOTE: Lazy entries are just cleared (no frame to release)
+				if let Some(paddr) = ::arch::memory::virt::unmap(addr) {
+					::memory::phys::deref_frame(paddr);
+				}
 			}
-		}
-		else {
-			::arch::memory::virt::reprotect(addr, prot);
-		}
-		Ok( () )
+			else {
+				// Demand-paged entries need to be present before their protection can change
+				::memory::vma::populate(addr);
+				::arch::memory::virt::reprotect(addr, prot);
+			}
+			}).map_err(|_| ())
 	}
 }
 
@@ -550,10 +542,7 @@ impl ops::DerefMut for FreePage {
 pub fn alloc_dma(bits: u8, count: usize, module: &'static str) -> Result<AllocHandle,MapError>
 {
 	// 1. Allocate enough pages within the specified range
-	let phys = ::memory::phys::allocate_range_bits(bits, count);
-	if phys == ::memory::phys::NOPAGE {
-		return Err( MapError::OutOfMemory );
-	}
+	let phys = try!( ::memory::phys::allocate_range_bits(bits, count) );
 	// 2. Map that
 	// SAFE: Physical address has just been allocated
 	unsafe {
@@ -575,6 +564,9 @@ fn count_free_in_range(addr: *const Page, count: usize) -> usize
 }
 
 /// Allocate a new kernel stack
+///
+/// The first page of each stack slot is never mapped, so an overflow faults (see `is_stack_guard`)
+/// instead of corrupting the stack below.
 pub fn alloc_stack() -> AllocHandle
 {
 	let _lock = s_kernelspace_lock.lock();
@@ -584,6 +576,7 @@ pub fn alloc_stack() -> AllocHandle
 		if ! ::arch::memory::virt::is_reserved( (pos + addresses::STACK_SIZE - ::PAGE_SIZE) as *const () )
 		{
 			let count = addresses::STACK_SIZE / ::PAGE_SIZE;
+			// - Skip the guard page
 			for ofs in (1 .. count).map(|x| x * ::PAGE_SIZE)
 			{
 				::memory::phys::allocate( (pos + ofs) as *mut () );
@@ -600,6 +593,12 @@ pub fn alloc_stack() -> AllocHandle
 	panic!("ERROR: Out of stacks");
 }
 
+/// Returns true if the address is within the guard page of a kernel stack
+pub fn is_stack_guard(addr: usize) -> bool
+{
+	addresses::STACKS_BASE <= addr && addr < addresses::STACKS_END && (addr - addresses::STACKS_BASE) % addresses::STACK_SIZE < ::PAGE_SIZE
+}
+
 impl fmt::Display for MapError
 {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
@@ -0,0 +1,517 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/memory/vma.rs
+//! Per-address-space list of demand-paged regions (VMAs)
+//!
+//! Pages within a VMA are marked as reserved (but not present) in the page tables, and are filled
+//! by the page fault handler on first touch.
+use prelude::*;
+use memory::virt::{ProtectionMode, MapError};
+
+/// Source of data for pages within a region
+#[derive(Clone)]
+pub enum Backing
+{
+	/// Anonymous memory, zero-filled on first access
+	Anonymous,
+	/// File-backed, page `n` of the region comes from byte offset `ofs + n * PAGE_SIZE` of the file
+	File {
+		node: ::vfs::node::CacheHandle,
+		ofs: u64,
+	},
+	/// Shared memory object, page `n` of the region is page `first_page + n` of the object
+	Shared {
+		shm: ::lib::mem::Arc<::memory::shm::SharedMemory>,
+		first_page: usize,
+	},
+}
+impl Backing
+{
+	/// Backing for a region starting `pages` pages further in
+	fn advanced(&self, pages: usize) -> Backing {
+		match *self
+		{
+		Backing::Anonymous => Backing::Anonymous,
+		Backing::File { ref node, ofs } => Backing::File { node: node.clone(), ofs: ofs + (pages * ::PAGE_SIZE) as u64 },
+		Backing::Shared { ref shm, first_page } => Backing::Shared { shm: shm.clone(), first_page: first_page + pages },
+		}
+	}
+}
+
+/// A single demand-paged region
+#[derive(Clone)]
+struct Vma
+{
+	start: usize,
+	page_count: usize,
+	prot: ProtectionMode,
+	backing: Backing,
+	/// Lowest address a stack region can grow down to (zero if the region doesn't grow)
+	///
+	/// The page below this is a guard page, which is never mapped.
+	grow_limit: usize,
+}
+impl Vma
+{
+	fn end(&self) -> usize {
+		self.start + self.page_count * ::PAGE_SIZE
+	}
+	fn contains(&self, addr: usize) -> bool {
+		self.start <= addr && addr < self.end()
+	}
+	/// Range reserved for growth of a stack region (including the guard page)
+	fn growth_range(&self) -> Option<(usize, usize)> {
+		if self.grow_limit != 0 {
+			Some( (self.grow_limit - ::PAGE_SIZE, self.start) )
+		}
+		else {
+			None
+		}
+	}
+	/// Writable shared file mapping (changes are written back to the file)
+	fn is_writeback(&self) -> bool {
+		match self.backing
+		{
+		Backing::File { .. } => self.prot == ProtectionMode::UserRW,
+		Backing::Anonymous => false,
+		Backing::Shared { .. } => false,
+		}
+	}
+	/// File node and page index backing the page at `addr`
+	fn file_page(&self, addr: usize) -> Option<(&::vfs::node::CacheHandle, u64)> {
+		match self.backing
+		{
+		Backing::File { ref node, ofs } => Some( (node, ofs / ::PAGE_SIZE as u64 + ((addr - self.start) / ::PAGE_SIZE) as u64) ),
+		Backing::Anonymous => None,
+		Backing::Shared { .. } => None,
+		}
+	}
+}
+
+/// List of demand-paged regions in the current address space (stored as process-local data)
+#[derive(Default)]
+pub struct VmaList
+{
+	regions: ::sync::Mutex<Vec<Vma>>,
+}
+impl ::core::ops::Drop for VmaList
+{
+	fn drop(&mut self)
+	{
+		// Process is being destroyed, write back anything still dirty in writeback regions
+		for r in self.regions.lock().iter().filter(|r| r.is_writeback())
+		{
+			if let Some((node, first)) = r.file_page(r.start) {
+				if let Err(e) = node.flush_pages(first, r.page_count as u64) {
+					log_error!("Flushing mapping {:#x}+{}pg on exit failed: {:?}", r.start, r.page_count, e);
+				}
+			}
+		}
+	}
+}
+
+/// Register a demand-paged region, marking its pages as reserved but not present
+///
+/// If the architecture can't represent lazy mappings, the pages are filled immediately.
+pub fn map_lazy(addr: *mut (), page_count: usize, prot: ProtectionMode, backing: Backing) -> Result<(), MapError>
+{
+	assert_eq!(addr as usize % ::PAGE_SIZE, 0);
+	let start = addr as usize;
+	let end = match page_count.checked_mul(::PAGE_SIZE).and_then(|len| start.checked_add(len))
+		{
+		Some(v) if v > start => v,
+		_ => return Err( MapError::RangeInUse ),
+		};
+	if ::arch::memory::addresses::is_global(start) || ::arch::memory::addresses::is_global(end - 1) {
+		return Err( MapError::RangeInUse );
+	}
+
+	let list = ::threads::get_process_local::<VmaList>();
+	let mut lh = list.regions.lock();
+	// 1. Ensure range is free
+	try!( check_free(&lh, start, end) );
+	// 2. Forget any stale regions covered by this one (their pages have already been unmapped)
+	remove_range(&mut lh, start, end);
+	lh.push(Vma {
+		start: start,
+		page_count: page_count,
+		prot: prot,
+		backing: backing,
+		grow_limit: 0,
+		});
+
+	// 3. Mark the pages
+	mark_pages(lh.last().unwrap(), start, end)
+}
+
+/// Register a growable (anonymous, read-write) stack ending at `top`
+///
+/// `initial_pages` are reserved immediately, and the stack grows down on faults until it is `max_pages`
+/// long. The page below that limit is left unmapped as a guard, and faults on it are reported as a
+/// stack overflow.
+pub fn map_stack(top: *mut (), initial_pages: usize, max_pages: usize) -> Result<(), MapError>
+{
+	assert_eq!(top as usize % ::PAGE_SIZE, 0);
+	assert!(0 < initial_pages && initial_pages <= max_pages);
+	let top = top as usize;
+	if max_pages >= top / ::PAGE_SIZE {
+		return Err( MapError::RangeInUse );
+	}
+	let limit = top - max_pages * ::PAGE_SIZE;
+	let start = top - initial_pages * ::PAGE_SIZE;
+	if ::arch::memory::addresses::is_global(limit - ::PAGE_SIZE) || ::arch::memory::addresses::is_global(top - 1) {
+		return Err( MapError::RangeInUse );
+	}
+
+	let list = ::threads::get_process_local::<VmaList>();
+	let mut lh = list.regions.lock();
+	// - The entire growth range (and the guard) must be free
+	try!( check_free(&lh, limit - ::PAGE_SIZE, top) );
+	remove_range(&mut lh, limit - ::PAGE_SIZE, top);
+	lh.push(Vma {
+		start: start,
+		page_count: initial_pages,
+		prot: ProtectionMode::UserRW,
+		backing: Backing::Anonymous,
+		grow_limit: limit,
+		});
+	mark_pages(lh.last().unwrap(), start, top)
+}
+
+/// Release a stack registered by `map_stack` (all pages, and the growth reservation)
+pub fn unmap_stack(top: *mut ()) -> Result<(), ()>
+{
+	let top = top as usize;
+	let list = ::threads::get_process_local::<VmaList>();
+	let find = |regions: &[Vma]| regions.iter().rposition(|r| r.grow_limit != 0 && r.end() == top);
+	// - The freeze list is locked before the region list, so get the stack's extent first
+	let limit = {
+		let lh = list.regions.lock();
+		match find(&lh[..])
+		{
+		Some(i) => lh[i].grow_limit,
+		None => return Err( () ),
+		}
+		};
+	// - Pages borrowed by the kernel (see `::memory::freeze`) can't be released
+	let rv = ::memory::freeze::with_unfrozen(limit as *const (), (top - limit) / ::PAGE_SIZE, || {
+		let mut lh = list.regions.lock();
+		let vma = match find(&lh[..])
+			{
+			Some(i) => lh.remove(i),
+			None => return Err( () ),
+			};
+		for pgptr in (vma.start .. top).step_by(::PAGE_SIZE)
+		{
+			// SAFE: Pages are owned by the (now removed) stack region
+			unsafe {
+				if let Some(paddr) = ::arch::memory::virt::unmap(pgptr as *mut ()) {
+					::memory::phys::deref_frame(paddr);
+				}
+			}
+		}
+		Ok( () )
+		});
+	match rv
+	{
+	Ok(v) => v,
+	Err(_) => Err( () ),
+	}
+}
+
+/// Maximum size of a user stack (in pages), from the `USER_STACK_LIMIT` boot option
+pub fn user_stack_limit() -> usize
+{
+	let kb = match ::config::get_string(::config::Value::UserStackLimit).parse::<usize>()
+		{
+		Ok(v) => v,
+		Err(_) => {
+			log_warning!("USER_STACK_LIMIT is not a number, using 1024KiB");
+			1024
+			},
+		};
+	::core::cmp::max(1, kb * 1024 / ::PAGE_SIZE)
+}
+
+/// Grow a stack region to cover `addr`, or report a stack overflow (called by the page fault handler)
+///
+/// Returns `true` if the stack was extended. Returns `false` if the address isn't below a stack, or if
+/// it's the stack's guard page (or the stack can't grow into the required range).
+pub fn handle_stack_fault(addr: usize) -> bool
+{
+	let page = addr & !(::PAGE_SIZE - 1);
+	if ::arch::memory::addresses::is_global(page) {
+		return false;
+	}
+	let list = ::threads::get_process_local::<VmaList>();
+	let mut lh = list.regions.lock();
+	let idx = match lh.iter().position(|r| match r.growth_range() { Some((s,e)) => s <= page && page < e, None => false })
+		{
+		Some(v) => v,
+		None => return false,
+		};
+	let (old_start, limit) = (lh[idx].start, lh[idx].grow_limit);
+	if page < limit {
+		::threads::log_stack_overflow(addr);
+		return false;
+	}
+	// - Another mapping could have been placed within the range (on architectures without lazy mappings)
+	if let Err(_) = check_free(&lh, page, old_start) {
+		log_error!("Stack at {:#x} can't grow to {:#x}, range in use", old_start, page);
+		::threads::log_stack_overflow(addr);
+		return false;
+	}
+	{
+		let vma = &mut lh[idx];
+		vma.page_count += (old_start - page) / ::PAGE_SIZE;
+		vma.start = page;
+	}
+	log_debug!("Stack grown from {:#x} to {:#x}", old_start, page);
+	match mark_pages(&lh[idx], page, old_start)
+	{
+	Ok(_) => true,
+	Err(e) => {
+		log_error!("Growing stack to {:#x} failed: {:?}", page, e);
+		false
+		},
+	}
+}
+
+/// Forget the provided range, trimming or splitting regions that partially overlap it (called once the pages have been unmapped)
+pub fn remove(addr: *mut (), page_count: usize)
+{
+	let start = addr as usize;
+	let end = start.saturating_add(page_count.saturating_mul(::PAGE_SIZE));
+	let list = ::threads::get_process_local::<VmaList>();
+	remove_range(&mut list.regions.lock(), start, end);
+}
+
+/// Remove `[start, end)` from the region list
+///
+/// Split regions keep their place in the list (later regions take priority when looking up an address).
+fn remove_range(regions: &mut Vec<Vma>, start: usize, end: usize)
+{
+	let mut i = 0;
+	while i < regions.len()
+	{
+		let (r_start, r_end) = (regions[i].start, regions[i].end());
+		if !(r_start < end && start < r_end) {
+			i += 1;
+			continue ;
+		}
+		// - The part after the range becomes a new region (only the lowest part of a stack can grow)
+		let tail = if end < r_end {
+				let r = &regions[i];
+				Some(Vma {
+					start: end,
+					page_count: (r_end - end) / ::PAGE_SIZE,
+					prot: r.prot,
+					backing: r.backing.advanced( (end - r_start) / ::PAGE_SIZE ),
+					grow_limit: if r_start < start { 0 } else { r.grow_limit },
+					})
+			}
+			else {
+				None
+			};
+		if r_start < start {
+			// - Keep the part before the range
+			regions[i].page_count = (start - r_start) / ::PAGE_SIZE;
+			i += 1;
+		}
+		else {
+			regions.remove(i);
+		}
+		if let Some(t) = tail {
+			regions.insert(i, t);
+			i += 1;
+		}
+	}
+}
+
+/// Fill a lazily-mapped page (called by the page fault handler)
+///
+/// Returns `false` if the address isn't part of a demand-paged region, or the backing couldn't be read.
+pub fn handle_fault(addr: usize) -> bool
+{
+	let page = addr & !(::PAGE_SIZE - 1);
+	if ::arch::memory::addresses::is_global(page) || ::arch::memory::virt::get_lazy(page as *const ()).is_none() {
+		return false;
+	}
+	let list = ::threads::get_process_local::<VmaList>();
+	// Newer regions take priority (older overlapping regions are stale)
+	// - The region is copied so the list isn't locked while the page is read (which can block on I/O)
+	let vma = list.regions.lock().iter().rev().find(|r| r.contains(page)).cloned();
+	match vma
+	{
+	Some(vma) => match fill_page(&vma, page)
+		{
+		Ok(_) => true,
+		Err(e) => {
+			log_error!("Demand paging of {:#x} failed: {:?}", page, e);
+			false
+			},
+		},
+	None => {
+		log_error!("Lazy page {:#x} has no backing region", page);
+		false
+		},
+	}
+}
+
+/// Handle a write to a read-only page of a writeback region (called by the page fault handler)
+///
+/// Marks the backing cache page as dirty and makes the page writable.
+pub fn handle_write_fault(addr: usize) -> bool
+{
+	let page = addr & !(::PAGE_SIZE - 1);
+	if ::arch::memory::addresses::is_global(page) {
+		return false;
+	}
+	match ::arch::memory::virt::get_info(page as *const ())
+	{
+	Some((_, ProtectionMode::UserRO)) => {},
+	_ => return false,
+	}
+	let list = ::threads::get_process_local::<VmaList>();
+	let lh = list.regions.lock();
+	match lh.iter().rev().find(|r| r.contains(page))
+	{
+	Some(vma) if vma.is_writeback() => {
+		let (node, idx) = vma.file_page(page).unwrap();
+		node.mark_dirty(idx);
+		// SAFE: Page is part of a writeback mapping, which allows writes
+		unsafe {
+			::arch::memory::virt::reprotect(page as *mut (), ProtectionMode::UserRW);
+		}
+		true
+		},
+	_ => false,
+	}
+}
+
+/// Write modified pages of writeback regions within the provided range back to their files
+pub fn flush(addr: *mut (), page_count: usize) -> Result<(), ::vfs::Error>
+{
+	let start = addr as usize & !(::PAGE_SIZE - 1);
+	let end = match page_count.checked_mul(::PAGE_SIZE).and_then(|l| start.checked_add(l))
+		{
+		Some(v) => v,
+		None => return Err( ::vfs::Error::InvalidParameter ),
+		};
+	if page_count > 0 && (::arch::memory::addresses::is_global(start) || ::arch::memory::addresses::is_global(end - 1)) {
+		return Err( ::vfs::Error::InvalidParameter );
+	}
+	let list = ::threads::get_process_local::<VmaList>();
+	let lh = list.regions.lock();
+	for page in (start .. end).step_by(::PAGE_SIZE)
+	{
+		let vma = match lh.iter().rev().find(|r| r.contains(page))
+			{
+			Some(v) if v.is_writeback() => v,
+			_ => continue,
+			};
+		// - Make the page read-only again (so the next write marks it dirty) before writing the content
+		if let Some((_, ProtectionMode::UserRW)) = ::arch::memory::virt::get_info(page as *const ()) {
+			// SAFE: Restricting access to a page that the VMA says is present
+			unsafe {
+				::arch::memory::virt::reprotect(page as *mut (), ProtectionMode::UserRO);
+			}
+		}
+		let (node, idx) = vma.file_page(page).unwrap();
+		try!( node.flush_pages(idx, 1) );
+	}
+	Ok( () )
+}
+
+/// Populate a page now (if it's lazily mapped), so it can be handled like a normal mapping
+pub fn populate(addr: *const ())
+{
+	if ::arch::memory::virt::get_lazy(addr).is_some() {
+		handle_fault(addr as usize);
+	}
+}
+
+/// Ensure that a range is unused (no mapped pages, and not reserved for stack growth)
+fn check_free(regions: &[Vma], start: usize, end: usize) -> Result<(), MapError>
+{
+	for pgptr in (start .. end).step_by(::PAGE_SIZE)
+	{
+		if ::arch::memory::virt::is_reserved( pgptr as *const () ) {
+			log_trace!("Address {:#x} in range {:#x}-{:#x} reserved", pgptr, start, end);
+			return Err( MapError::RangeInUse );
+		}
+	}
+	for r in regions
+	{
+		if let Some((s,e)) = r.growth_range() {
+			if s < end && start < e {
+				log_trace!("Range {:#x}-{:#x} overlaps stack growth area {:#x}-{:#x}", start, end, s, e);
+				return Err( MapError::RangeInUse );
+			}
+		}
+	}
+	Ok( () )
+}
+
+/// Mark the pages of a (free) range within a region as demand-paged (or fill them immediately)
+fn mark_pages(vma: &Vma, start: usize, end: usize) -> Result<(), MapError>
+{
+	for pgptr in (start .. end).step_by(::PAGE_SIZE)
+	{
+		// SAFE: Range is free, and not in the kernel's half
+		unsafe {
+			if ! ::arch::memory::virt::map_lazy(pgptr as *mut (), vma.prot) {
+				let paddr = try!( get_frame(vma, pgptr) );
+				::arch::memory::virt::map(pgptr as *mut (), paddr, vma.prot);
+				// - Writes can't be tracked without faulting, so assume the page will be modified
+				if vma.is_writeback() {
+					let (node, idx) = vma.file_page(pgptr).unwrap();
+					node.mark_dirty(idx);
+				}
+			}
+		}
+	}
+	Ok( () )
+}
+
+fn fill_page(vma: &Vma, page: usize) -> Result<(), MapError>
+{
+	let paddr = try!( get_frame(vma, page) );
+	// Writeback pages start read-only, so the first write can mark them as dirty
+	let prot = if vma.is_writeback() { ProtectionMode::UserRO } else { vma.prot };
+	// SAFE: Replaces a lazy entry (atomically), and the frame reference is owned by the mapping
+	if ! unsafe { ::arch::memory::virt::fill_lazy(page as *mut (), paddr, prot) } {
+		// Lost the race with another CPU (or the page was unmapped), release the frame
+		::memory::phys::deref_frame(paddr);
+	}
+	Ok( () )
+}
+
+/// Obtain a referenced frame containing the initial data for a page of the region
+fn get_frame(vma: &Vma, page: usize) -> Result<::memory::PAddr, MapError>
+{
+	let frame = match vma.backing
+		{
+		Backing::Anonymous => {
+			let mut newpg = try!(::memory::virt::alloc_free());
+			for b in newpg.iter_mut() {
+				*b = 0;
+			}
+			newpg.into_frame()
+			},
+		Backing::File { ref node, ofs } => {
+			let idx = ofs / ::PAGE_SIZE as u64 + ((page - vma.start) / ::PAGE_SIZE) as u64;
+			match node.get_page(idx)
+			{
+			Ok(v) => v,
+			Err(e) => {
+				log_error!("Reading page {} of mapped file failed: {:?}", idx, e);
+				return Err( MapError::OutOfMemory );
+				},
+			}
+			},
+		Backing::Shared { ref shm, first_page } => try!(shm.get_page( first_page + (page - vma.start) / ::PAGE_SIZE )),
+		};
+	Ok( frame.into_addr() )
+}
@@ -148,6 +148,8 @@ struct LogicalVolume
 	chunk_size: Option<usize>,
 	/// Physical regions that compose this logical volume
 	regions: Vec<PhysicalRegion>,
+	/// Capacity of a RAM-backed volume (in blocks, 0 = unbounded), only used when there are no regions
+	ram_blocks: usize,
 }
 /// Physical region used by a logical volume
 struct PhysicalRegion
@@ -334,6 +336,7 @@ fn new_simple_lv(name: String, pv_id: usize, block_size: usize, base: u64, size:
 		block_size: block_size,
 		chunk_size: None,
 		regions: vec![ PhysicalRegion{ volume: pv_id, block_count: size as usize, first_block: base } ],
+		ram_blocks: 0,
 		} );
 	
 	log_log!("Logical Volume: {} {}", lv.name, SizePrinter(size*block_size as u64));
@@ -378,9 +381,17 @@ impl_fmt!{
 
 impl VolumeHandle
 {
-	pub fn new_ramdisk(_count: usize) -> VolumeHandle {
+	/// Create a handle to a RAM-backed volume of `count` pages (0 = unbounded)
+	///
+	/// The volume has no backing storage, it's used to give in-memory filesystems (ramfs) a size limit.
+	pub fn new_ramdisk(count: usize) -> VolumeHandle {
 		VolumeHandle {
-			handle: Arc::new(LogicalVolume::default())
+			handle: Arc::new(LogicalVolume {
+				name: String::from("ramdisk"),
+				block_size: ::PAGE_SIZE,
+				ram_blocks: count,
+				..LogicalVolume::default()
+				})
 		}
 	}
 	/// Acquire an unique handle to a logical volume
@@ -415,6 +426,15 @@ impl VolumeHandle
 	pub fn idx(&self) -> usize {
 		self.handle.index
 	}
+	/// Number of blocks in the volume (`None` if unbounded)
+	pub fn capacity(&self) -> Option<u64> {
+		if self.handle.regions.len() == 0 {
+			if self.handle.ram_blocks == 0 { None } else { Some(self.handle.ram_blocks as u64) }
+		}
+		else {
+			Some( self.handle.regions.iter().map(|r| r.block_count as u64).sum() )
+		}
+	}
 	pub fn name(&self) -> &str {
 		&self.handle.name
 	}
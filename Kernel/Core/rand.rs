@@ -0,0 +1,49 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/rand.rs
+//! Kernel random number source
+//!
+//! A mixing generator that stirs in the cycle counter and timestamp on every call. This is intended
+//! for address-space randomisation and similar uses, NOT as a cryptographic source.
+use sync::Spinlock;
+
+static S_STATE: Spinlock<u64> = Spinlock::new(0x243F_6A88_85A3_08D3);
+
+/// SplitMix64 finaliser
+fn mix(mut z: u64) -> u64
+{
+	z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+	z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+	z ^ (z >> 31)
+}
+
+/// Stir in the available entropy sources (cycle counter, timestamp, and a stack address)
+fn stir(state: u64) -> u64
+{
+	let stack_marker = 0u8;
+	let sources = ::arch::cycle_count()
+		^ ::time::ticks().rotate_left(32)
+		^ (&stack_marker as *const _ as usize as u64).rotate_left(17);
+	mix(state.wrapping_add(0x9E37_79B9_7F4A_7C15) ^ sources)
+}
+
+/// Obtain a random 64-bit value
+pub fn get_u64() -> u64
+{
+	let mut lh = S_STATE.lock();
+	*lh = stir(*lh);
+	mix(*lh)
+}
+
+/// Fill a buffer with random bytes
+pub fn fill(buf: &mut [u8])
+{
+	for chunk in buf.chunks_mut(8)
+	{
+		let v = get_u64();
+		for (i, b) in chunk.iter_mut().enumerate() {
+			*b = (v >> (i * 8)) as u8;
+		}
+	}
+}
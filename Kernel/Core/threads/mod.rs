@@ -6,7 +6,9 @@
 use prelude::*;
 
 mod thread;
+mod registry;
 mod thread_list;
+mod run_queue;
 mod wait_queue;
 
 mod worker_thread;
@@ -16,10 +18,14 @@ mod sleep_object;
 pub use self::thread::{Thread,ThreadPtr};
 pub use self::thread::{ThreadHandle,ProcessHandle};
 pub use self::thread::new_idle_thread;
+pub use self::thread::{ThreadInfo,ProcessInfo};
+pub use self::thread::{list_threads,list_processes,get_thread_info,get_process};
 
 pub use self::worker_thread::WorkerThread;
 
 pub use self::thread_list::{ThreadList,THREADLIST_INIT};
+pub use self::run_queue::Priority;
+use self::run_queue::{RunQueue,RUNQUEUE_INIT};
 pub use self::sleep_object::{SleepObject,SleepObjectRef};
 pub use self::wait_queue::WaitQueue;
 
@@ -28,15 +34,46 @@ use lib::mem::aref::{Aref,ArefBorrow};
 /// A bitset of wait events
 pub type EventMask = u32;
 
+/// Per-CPU scheduler state
+struct CpuSched
+{
+	/// Threads waiting to run on this CPU
+	run_queue: ::sync::Spinlock<RunQueue>,
+	/// Ticks remaining in the current thread's time slice
+	slice_remaining: ::core::sync::atomic::AtomicUsize,
+	/// Set by the timer tick when the current thread should be preempted
+	preempt_pending: ::core::sync::atomic::AtomicBool,
+	/// Set while the CPU's idle thread is halted waiting for work
+	is_idle: ::core::sync::atomic::AtomicBool,
+	/// Timestamp that the current thread was last charged CPU time up to
+	last_account: ::core::sync::atomic::AtomicU64,
+}
+const CPUSCHED_INIT: CpuSched = CpuSched {
+	run_queue: ::sync::Spinlock::new(RUNQUEUE_INIT),
+	slice_remaining: ::core::sync::atomic::ATOMIC_USIZE_INIT,
+	preempt_pending: ::core::sync::atomic::ATOMIC_BOOL_INIT,
+	is_idle: ::core::sync::atomic::ATOMIC_BOOL_INIT,
+	last_account: ::core::sync::atomic::AtomicU64::new(0),
+	};
+
+/// Maximum number of CPUs the kernel handles
+///
+/// NOTE: The Makefile extracts this value for the assembly sources, keep it on a single line
+pub const MAX_CPUS: usize = 8;
+// Fails to compile if `per_cpu_init!` doesn't produce `MAX_CPUS` entries
+#[allow(dead_code)]
+const PER_CPU_INIT_CHECK: [u8; MAX_CPUS] = per_cpu_init!(0);
+
 // ----------------------------------------------
 // Statics
-//static s_all_threads:	::sync::Mutex<Map<uint,*const Thread>> = mutex_init!(Map{});
-#[allow(non_upper_case_globals)]
-static s_runnable_threads: ::sync::Spinlock<ThreadList> = ::sync::Spinlock::new(THREADLIST_INIT);
+static S_CPU_SCHED: [CpuSched; MAX_CPUS] = per_cpu_init!(CPUSCHED_INIT);
 static S_PID0: ::lib::LazyStatic<::lib::mem::Arc<thread::Process>> = ::lib::LazyStatic::new();
 // Spinlocked due to low contention, and because the current thread is pushed to it
 static S_TO_REAP_THREADS: ::sync::Spinlock<ThreadList> = ::sync::Spinlock::new(THREADLIST_INIT);
 
+/// Number of timer ticks a thread can run for before being preempted
+const TIME_SLICE_TICKS: usize = 10;
+
 // ----------------------------------------------
 // Code
 /// Initialise the threading subsystem
@@ -69,16 +106,22 @@ fn reap_threads() -> bool
 
 pub fn idle_thread()
 {
+	// NOTE: Idle threads are never placed on a run queue, so stay on the same CPU
+	let sched = cur_sched();
 	loop
 	{
 		if ! reap_threads()
 		{
 			// SAFE: I know what I'm doing, and we trust idle() to re-enable them
 			unsafe { ::arch::sync::stop_interrupts(); }
+			// Flag as idle before checking the queues, so a thread made runnable after the check triggers a wakeup IPI
+			sched.is_idle.store(true, ::core::sync::atomic::Ordering::SeqCst);
 			if let Some(thread) = get_thread_to_run() {
+				sched.is_idle.store(false, ::core::sync::atomic::Ordering::SeqCst);
 				// SAFE: We turned them off, we turn them back on
 				unsafe { ::arch::sync::start_interrupts(); }
 				log_debug!("Idle task switch to {:?}", thread);
+				account_cpu_time(sched);
 				::arch::threads::switch_to(thread);
 			}
 			else {
@@ -100,18 +143,138 @@ pub fn yield_time()
 	reap_threads();
 
 	// Add current thread to active queue, then reschedule
-	s_runnable_threads.lock().push( get_cur_thread() );
+	push_local( get_cur_thread() );
 	reschedule();
 }
 
+/// Returns the scheduler state for the current CPU
+fn cur_sched() -> &'static CpuSched
+{
+	&S_CPU_SCHED[::arch::threads::cpu_num()]
+}
+
+/// Push a thread onto the current CPU's run queue
+fn push_local(t: ThreadPtr)
+{
+	let _irq_lock = ::arch::sync::hold_interrupts();
+	cur_sched().run_queue.lock().push(t);
+}
+
+/// Mark a (woken) thread as runnable, handing it to an idle CPU if there is one
+#[is_safe(irq)]	// Holds interrupts before locking
+fn push_runnable(t: ThreadPtr)
+{
+	use core::sync::atomic::Ordering;
+	let _irq_lock = ::arch::sync::hold_interrupts();
+	let cur = ::arch::threads::cpu_num();
+	let idle_cpu = (0 .. ::arch::threads::num_cpus())
+		.filter(|&i| i != cur)
+		.find(|&i| S_CPU_SCHED[i].is_idle.load(Ordering::SeqCst));
+	match idle_cpu
+	{
+	Some(cpu) => {
+		S_CPU_SCHED[cpu].run_queue.lock_irqsafe().push(t);
+		::arch::threads::send_reschedule(cpu);
+		},
+	None => {
+		S_CPU_SCHED[cur].run_queue.lock_irqsafe().push(t);
+		},
+	}
+}
+
+/// Timer tick handler, called from the architecture's timer interrupt
+///
+/// Requests preemption if the current thread's time slice has expired, or if a higher priority thread is runnable.
+#[is_safe(irq)]
+pub fn timer_tick()
+{
+	use core::sync::atomic::Ordering;
+	let sched = cur_sched();
+	account_cpu_time(sched);
+	let remaining = sched.slice_remaining.load(Ordering::Relaxed);
+	if remaining <= 1 {
+		sched.preempt_pending.store(true, Ordering::Relaxed);
+	}
+	else {
+		sched.slice_remaining.store(remaining - 1, Ordering::Relaxed);
+	}
+
+	check_priority_preempt(sched);
+}
+
+/// Charge the time since the last accounting point to the current thread
+#[is_safe(irq)]
+fn account_cpu_time(sched: &CpuSched)
+{
+	use core::sync::atomic::Ordering;
+	let now = ::arch::cur_timestamp();
+	let prev = sched.last_account.swap(now, Ordering::Relaxed);
+	let cur = ::arch::threads::borrow_thread();
+	if !cur.is_null() && prev != 0 && now > prev {
+		// SAFE: Checks for NULL, and the current thread is valid while executing. Only an atomic is touched
+		unsafe { (*cur).add_cpu_time(now - prev); }
+	}
+}
+
+/// Reschedule request handler, called when another CPU has pushed a thread to this CPU's run queue
+#[is_safe(irq)]
+pub fn reschedule_ipi()
+{
+	check_priority_preempt(cur_sched());
+}
+
+/// Request preemption if a thread with higher priority than the current is waiting
+#[is_safe(irq)]
+fn check_priority_preempt(sched: &CpuSched)
+{
+	// NOTE: try_lock, as the interrupted code may hold the run queue lock
+	if let Some(lh) = sched.run_queue.try_lock_cpu()
+	{
+		let cur = ::arch::threads::borrow_thread();
+		// SAFE: Checks for NULL, and the current thread is valid while executing. Only a Copy field is read
+		if !cur.is_null() && lh.has_above( unsafe { (*cur).priority } ) {
+			sched.preempt_pending.store(true, ::core::sync::atomic::Ordering::Relaxed);
+		}
+	}
+}
+
+/// Preemption point, called by architecture code when an interrupt is about to return to userland
+///
+/// NOTE: Kernel code is not preempted, it only yields when blocking (or by calling `yield_time`)
+pub fn preempt_check()
+{
+	if cur_sched().preempt_pending.swap(false, ::core::sync::atomic::Ordering::Relaxed)
+	{
+		log_trace!("preempt_check - Preempting {:?}", get_thread_id());
+		push_local( get_cur_thread() );
+		reschedule();
+	}
+}
+
+/// Set the scheduling priority of the current thread
+pub fn set_priority(priority: Priority)
+{
+	let mut cur = get_cur_thread();
+	log_debug!("set_priority({:?}) - {:?}", priority, cur);
+	cur.priority = priority;
+	rel_cur_thread(cur);
+}
+
 pub fn yield_to(thread: ThreadPtr)
 {
 	log_debug!("Yielding CPU to {:?}", thread);
-	s_runnable_threads.lock().push( get_cur_thread() );
+	account_cpu_time(cur_sched());
+	push_local( get_cur_thread() );
 	::arch::threads::switch_to( thread );
 }
 
 pub fn terminate_thread() -> !
+{
+	exit_thread(0)
+}
+
+/// Terminate the current thread, passing `status` to anything waiting on it
+pub fn exit_thread(status: u32) -> !
 {
 	// NOTE: If TID0 (aka init's main thread) terminates, panic the kernel
 	if with_cur_thread(|cur| cur.get_tid() == 0) {
@@ -123,7 +286,9 @@ pub fn terminate_thread() -> !
 	//
 	// Set state to "Dead"
 	let mut this_thread = get_cur_thread();
-	this_thread.set_state( thread::RunState::Dead(0) );
+	this_thread.set_state( thread::RunState::Dead(status) );
+	// - Wake anything joining this thread (the thread is pushed to the reap list after, so the block stays valid)
+	this_thread.mark_exit(status);
 	S_TO_REAP_THREADS.lock().push( this_thread );
 	// Reschedule
 	// - The idle thread will handle reaping?
@@ -172,6 +337,19 @@ pub fn get_process_id() -> thread::ProcessID {
 	p.get_process_info().get_pid()
 }
 
+/// Report a stack overflow by the current thread (called by the fault handlers)
+pub fn log_stack_overflow(addr: usize)
+{
+	let p = ::arch::threads::borrow_thread();
+	if p.is_null() {
+		log_error!("Stack overflow (no current thread) - accessed {:#x}", addr);
+	}
+	else {
+		// SAFE: Checks for NULL, and the thread should be vaild while executing
+		log_error!("Stack overflow in thread {} - accessed {:#x}", unsafe { &*p }, addr);
+	}
+}
+
 fn with_cur_thread<T, F: FnOnce(&thread::Thread)->T>(fcn: F) -> T
 {
 	// SAFE: Checks for NULL, and the thread should be vaild while executing
@@ -227,10 +405,14 @@ pub fn get_process_local<T: Send+Sync+::core::any::Any+Default+'static>() -> Are
 #[doc(hidden)]
 pub fn reschedule()
 {
+	account_cpu_time(cur_sched());
 	loop
 	{
 		if let Some(thread) = get_thread_to_run()
 		{
+			let sched = cur_sched();
+			sched.slice_remaining.store(TIME_SLICE_TICKS, ::core::sync::atomic::Ordering::Relaxed);
+			sched.preempt_pending.store(false, ::core::sync::atomic::Ordering::Relaxed);
 			if &*thread as *const _ == ::arch::threads::borrow_thread() as *const _
 			{
 				log_debug!("Task switch to self, idle");
@@ -279,17 +461,20 @@ fn rel_cur_thread(t: ThreadPtr)
 fn get_thread_to_run() -> Option<ThreadPtr>
 {
 	let _irq_lock = ::arch::sync::hold_interrupts();
-	let mut handle = s_runnable_threads.lock();
-	if handle.empty()
-	{
-		// WTF? At least an idle thread should be ready
-		None
+	let cur = ::arch::threads::cpu_num();
+	// 1. Pop off this CPU's queue
+	if let Some(t) = S_CPU_SCHED[cur].run_queue.lock().pop() {
+		return Some(t);
 	}
-	else
+	// 2. Nothing local, steal from another CPU
+	for i in (0 .. ::arch::threads::num_cpus()).filter(|&i| i != cur)
 	{
-		// 2. Pop off a new thread
-		handle.pop()
+		if let Some(t) = S_CPU_SCHED[i].run_queue.lock().pop() {
+			log_trace!("get_thread_to_run - CPU{} took {:?} from CPU{}", cur, t, i);
+			return Some(t);
+		}
 	}
+	None
 }
 
 // vim: ft=rust
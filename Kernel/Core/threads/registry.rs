@@ -0,0 +1,81 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/threads/registry.rs
+//! Global tables of live threads and processes (with ID allocation)
+use prelude::*;
+use lib::VecMap;
+
+/// Table of IDs to (non-owning) object pointers
+///
+/// IDs are allocated by a rolling cursor, so recently freed IDs are not immediately reused.
+pub struct IdTable
+{
+	/// Last allocated ID
+	last: u32,
+	/// Maximum (exclusive) ID
+	max: u32,
+	/// Allocated IDs, the pointer is NULL until `set` is called
+	items: VecMap<u32, RawPtr>,
+}
+
+/// Non-owning pointer to an `Arc` allocation (from `Arc::as_raw`)
+struct RawPtr(*const ());
+// Only ever dereferenced (via `Arc::try_from_raw`) with the table locked
+unsafe impl Send for RawPtr {}
+
+impl IdTable
+{
+	pub const fn new(max: u32) -> IdTable {
+		IdTable {
+			last: 0,
+			max: max,
+			items: VecMap::new_const(),
+		}
+	}
+
+	/// Reserve a free ID (greater than zero)
+	pub fn allocate(&mut self) -> Option<u32>
+	{
+		// The maximum is small enough that this can't overflow
+		for ofs in 1 .. self.max
+		{
+			let id = (self.last + ofs) % self.max;
+			if id == 0 {
+				continue ;
+			}
+			if self.items.get(&id).is_none() {
+				self.items.insert(id, RawPtr(::core::ptr::null()));
+				self.last = id;
+				return Some(id);
+			}
+		}
+		None
+	}
+
+	/// Set the object for an ID (either reserved by `allocate`, or a fixed ID like 0)
+	pub fn set(&mut self, id: u32, ptr: *const ())
+	{
+		self.items.insert(id, RawPtr(ptr));
+	}
+	/// Release an ID for re-use
+	pub fn release(&mut self, id: u32)
+	{
+		self.items.remove(&id);
+	}
+
+	/// Object pointer for an ID (`None` if the ID is free or still only reserved)
+	pub fn get(&self, id: u32) -> Option<*const ()>
+	{
+		match self.items.get(&id)
+		{
+		Some(p) if !p.0.is_null() => Some(p.0),
+		_ => None,
+		}
+	}
+	/// List all registered objects
+	pub fn entries(&self) -> Vec<(u32, *const ())>
+	{
+		self.items.iter().filter(|e| !(e.1).0.is_null()).map(|(&id, p)| (id, p.0)).collect()
+	}
+}
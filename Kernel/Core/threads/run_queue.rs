@@ -0,0 +1,69 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/threads/run_queue.rs
+//! Priority-ordered list of runnable threads
+use super::{ThreadList,ThreadPtr,THREADLIST_INIT};
+
+/// Thread scheduling priority
+///
+/// Higher priorities are always scheduled first, threads of equal priority are round-robin.
+#[derive(Copy,Clone,Debug,PartialEq,Eq,PartialOrd,Ord)]
+pub enum Priority
+{
+	/// Background work, only runs when nothing else is runnable
+	Low = 0,
+	/// Default for user and kernel threads
+	Normal = 1,
+	/// Latency-sensitive kernel workers (e.g. the GUI compositor)
+	High = 2,
+	/// Interrupt handling workers
+	Realtime = 3,
+}
+impl Default for Priority {
+	fn default() -> Priority { Priority::Normal }
+}
+
+/// Number of distinct priority levels (and hence run queues)
+pub const NUM_PRIORITIES: usize = 4;
+
+/// Set of runnable threads, with one list per priority level
+pub struct RunQueue
+{
+	queues: [ThreadList; NUM_PRIORITIES],
+}
+
+pub const RUNQUEUE_INIT: RunQueue = RunQueue {
+	queues: [THREADLIST_INIT, THREADLIST_INIT, THREADLIST_INIT, THREADLIST_INIT],
+	};
+
+impl RunQueue
+{
+	/// Returns true if there are no runnable threads
+	pub fn empty(&self) -> bool
+	{
+		self.queues.iter().all(|q| q.empty())
+	}
+	/// Push a thread to the back of its priority's list
+	pub fn push(&mut self, t: ThreadPtr)
+	{
+		let prio = t.priority as usize;
+		self.queues[prio].push(t);
+	}
+	/// Pop the next thread from the highest non-empty priority
+	pub fn pop(&mut self) -> Option<ThreadPtr>
+	{
+		for q in self.queues.iter_mut().rev()
+		{
+			if let Some(t) = q.pop() {
+				return Some(t);
+			}
+		}
+		None
+	}
+	/// Returns true if a thread with priority above `prio` is waiting
+	pub fn has_above(&self, prio: Priority) -> bool
+	{
+		self.queues[prio as usize + 1 ..].iter().any(|q| !q.empty())
+	}
+}
@@ -5,7 +5,7 @@
 //! Sleep object
 use core::ops;
 use super::thread::{ThreadPtr, RunState};
-use super::s_runnable_threads;
+use super::push_runnable;
 
 /// An object on which a thread can sleep, woken by various event sources
 ///
@@ -100,7 +100,7 @@ impl<'a> SleepObject<'a>
 		if let Some(mut t) = lh.thread.take()
 		{
 			t.set_state( RunState::Runnable );
-			s_runnable_threads.lock().push(t);
+			push_runnable(t);
 		}
 		else
 		{
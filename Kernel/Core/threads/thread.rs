@@ -13,6 +13,7 @@
  */
 use prelude::*;
 use lib::mem::Arc;
+use core::sync::atomic::Ordering;
 
 /// Thread identifier (unique)
 pub type ThreadID = u32;
@@ -42,6 +43,8 @@ pub struct Process
 	address_space: ::memory::virt::AddressSpace,
 	// TODO: use of a tuple here looks a little crufty
 	exit_status: ::sync::Mutex< (Option<u32>, Option<::threads::sleep_object::SleepObjectRef>) >,
+	/// CPU time (in ms) used by threads that have been destroyed
+	exited_cpu_time: ::core::sync::atomic::AtomicU64,
 	pub proc_local_data: ::sync::RwLock<Vec< ::lib::mem::aref::Aref<::core::any::Any+Sync+Send> >>,
 }
 /// Handle to a process, used for spawning and communicating
@@ -57,9 +60,15 @@ struct SharedBlock
 	name: String,
 	tid: ThreadID,
 	process: Arc<Process>,
+	/// Exit value (set when the thread terminates), and the objects to signal on termination
+	exit_status: ::sync::Mutex< (Option<u32>, Vec<::threads::sleep_object::SleepObjectRef>) >,
+	/// CPU time used (in ms)
+	cpu_time: ::core::sync::atomic::AtomicU64,
 }
 
 /// An owning thread handle
+///
+/// Dropping the handle blocks until the thread terminates, use `detach` to release it without waiting.
 pub struct ThreadHandle
 {
 	block: Arc<SharedBlock>,
@@ -76,6 +85,8 @@ pub struct Thread
 	block: Arc<SharedBlock>,
 	/// Execution state
 	pub run_state: RunState,
+	/// Scheduling priority
+	pub priority: super::Priority,
 	
 	/// CPU state
 	pub cpu_state: ::arch::threads::State,
@@ -84,62 +95,135 @@ pub struct Thread
 }
 assert_trait!{Thread : Send}
 
-/// Last allocated TID (because TID0 is allocated differently)
-static S_LAST_TID: ::core::sync::atomic::AtomicUsize = ::core::sync::atomic::ATOMIC_USIZE_INIT;
-const C_MAX_TID: usize = 0x7FFF_FFF0;	// Leave 16 TIDs spare at end of 31 bit number
-static S_LAST_PID: ::core::sync::atomic::AtomicUsize = ::core::sync::atomic::ATOMIC_USIZE_INIT;
-const C_MAX_PID: usize = 0x007F_FFF0;	// Leave 16 PIDs spare at end of 23 bit number
+const C_MAX_TID: u32 = 0x7FFF_FFF0;	// Leave 16 TIDs spare at end of 31 bit number
+const C_MAX_PID: u32 = 0x007F_FFF0;	// Leave 16 PIDs spare at end of 23 bit number
+/// All live threads (pointers to `SharedBlock`, removed when the block is dropped)
+static S_THREADS: ::sync::Mutex<super::registry::IdTable> = ::sync::Mutex::new(super::registry::IdTable::new(C_MAX_TID));
+/// All live processes (removed when the `Process` is dropped)
+static S_PROCESSES: ::sync::Mutex<super::registry::IdTable> = ::sync::Mutex::new(super::registry::IdTable::new(C_MAX_PID));
 
 fn allocate_tid() -> ThreadID
 {
-	// Preemptively prevent rollover
-	if S_LAST_TID.load(::core::sync::atomic::Ordering::Relaxed) == C_MAX_TID - 1 {
-		panic!("TODO: Handle TID exhaustion by searching for free");
-	}
-	let rv = S_LAST_TID.fetch_add(1, ::core::sync::atomic::Ordering::Relaxed);
-	// Handle rollover after (in case of heavy contention)
-	if rv >= C_MAX_TID {
-		panic!("TODO: Handle TID exhaustion by searching for free (raced)");
-	}
-	
-	(rv + 1) as ThreadID
+	S_THREADS.lock().allocate().expect("TID exhaustion")
 }
 
-fn allocate_pid() -> u32
+fn allocate_pid() -> ProcessID
 {
-	// Preemptively prevent rollover
-	if S_LAST_PID.load(::core::sync::atomic::Ordering::Relaxed) == C_MAX_PID - 1 {
-		panic!("TODO: Handle PID exhaustion by searching for free");
-	}
-	let rv = S_LAST_PID.fetch_add(1, ::core::sync::atomic::Ordering::Relaxed);
-	// Handle rollover after (in case of heavy contention)
-	if rv >= C_MAX_PID {
-		panic!("TODO: Handle PID exhaustion by searching for free (raced)");
-	}
-	
-	(rv + 1) as u32
+	S_PROCESSES.lock().allocate().expect("PID exhaustion")
+}
+
+/// Summary of a live thread
+#[derive(Debug)]
+pub struct ThreadInfo
+{
+	pub tid: ThreadID,
+	pub pid: ProcessID,
+	pub name: String,
+	/// Exit value, if the thread has terminated (but is still referenced by a handle)
+	pub exit_status: Option<u32>,
+	/// CPU time used (in ms)
+	pub cpu_time: u64,
+}
+/// Summary of a live process
+#[derive(Debug)]
+pub struct ProcessInfo
+{
+	pub pid: ProcessID,
+	pub name: String,
+	/// Number of threads (including terminated threads that still have handles)
+	pub num_threads: usize,
+	pub exit_status: Option<u32>,
+	/// CPU time used by all threads (in ms)
+	pub cpu_time: u64,
+	/// Number of pages of memory mapped into the process's address space
+	pub resident_pages: usize,
+}
+
+/// Obtain references to all registered objects of a table
+///
+/// UNSAFE: The table must only contain pointers from `Arc::<T>::as_raw`, removed in `T`'s destructor
+unsafe fn collect_live<T>(table: &::sync::Mutex<super::registry::IdTable>) -> Vec<Arc<T>>
+{
+	// NOTE: The references are dropped by the caller, after the lock is released (as dropping the last one will re-lock)
+	let lh = table.lock();
+	lh.entries().into_iter().filter_map(|(_,p)| Arc::try_from_raw(p)).collect()
+}
+
+/// List all live threads
+pub fn list_threads() -> Vec<ThreadInfo>
+{
+	// SAFE: S_THREADS only contains SharedBlock pointers, removed on drop
+	let blocks: Vec<Arc<SharedBlock>> = unsafe { collect_live(&S_THREADS) };
+	blocks.iter().map(|b| b.info()).collect()
+}
+/// List all live processes
+pub fn list_processes() -> Vec<ProcessInfo>
+{
+	// SAFE: S_PROCESSES only contains Process pointers, removed on drop
+	let processes: Vec<Arc<Process>> = unsafe { collect_live(&S_PROCESSES) };
+	// SAFE: As above
+	let blocks: Vec<Arc<SharedBlock>> = unsafe { collect_live(&S_THREADS) };
+	processes.iter()
+		.map(|p| {
+			let threads = blocks.iter().filter(|b| b.process.pid == p.pid);
+			ProcessInfo {
+				pid: p.pid,
+				name: p.name.clone(),
+				num_threads: threads.clone().count(),
+				exit_status: p.exit_status.lock().0,
+				cpu_time: p.exited_cpu_time.load(Ordering::Relaxed) + threads.map(|b| b.cpu_time.load(Ordering::Relaxed)).sum::<u64>(),
+				resident_pages: p.address_space.resident_pages(),
+			}
+			})
+		.collect()
+}
+/// Look up a thread by ID
+pub fn get_thread_info(tid: ThreadID) -> Option<ThreadInfo>
+{
+	let block: Option<Arc<SharedBlock>> = {
+		let lh = S_THREADS.lock();
+		// SAFE: S_THREADS only contains SharedBlock pointers, removed on drop (which can't complete while locked)
+		lh.get(tid).and_then(|p| unsafe { Arc::try_from_raw(p) })
+		};
+	block.map(|b| b.info())
+}
+/// Obtain a handle to a process by ID
+pub fn get_process(pid: ProcessID) -> Option<ProcessHandle>
+{
+	let process: Option<Arc<Process>> = {
+		let lh = S_PROCESSES.lock();
+		// SAFE: S_PROCESSES only contains Process pointers, removed on drop (which can't complete while locked)
+		lh.get(pid).and_then(|p| unsafe { Arc::try_from_raw(p) })
+		};
+	process.map(|p| ProcessHandle(p))
 }
 
 impl Process
 {
 	pub fn new_pid0() -> Arc<Process> {
-		Arc::new(Process {
+		let rv = Arc::new(Process {
 			name: String::from("PID0"),
 			pid: 0,
 			exit_status: Default::default(),
+			exited_cpu_time: Default::default(),
 			address_space: ::memory::virt::AddressSpace::pid0(),
 			proc_local_data: ::sync::RwLock::new( Vec::new() ),
-		})
+		});
+		S_PROCESSES.lock().set(0, Arc::as_raw(&rv));
+		rv
 	}
 	pub fn new<S: Into<String>+::core::fmt::Debug>(name: S, addr_space: ::memory::virt::AddressSpace) -> Arc<Process>
 	{
-		Arc::new(Process {
+		let rv = Arc::new(Process {
 			pid: allocate_pid(),
 			name: name.into(),
 			exit_status: Default::default(),
+			exited_cpu_time: Default::default(),
 			address_space: addr_space,
 			proc_local_data: ::sync::RwLock::new( Vec::new() ),
-		})
+		});
+		S_PROCESSES.lock().set(rv.pid, Arc::as_raw(&rv));
+		rv
 	}
 	
 	fn empty_cpu_state(&self) -> ::arch::threads::State {
@@ -274,8 +358,13 @@ impl ::core::ops::Drop for ProcessHandle {
 impl ThreadHandle
 {
 	pub fn new<F: FnOnce()+Send+'static, S: Into<String>>(name: S, fcn: F, process: Arc<Process>) -> ThreadHandle
+	{
+		ThreadHandle::new_with_priority(name, super::Priority::Normal, fcn, process)
+	}
+	pub fn new_with_priority<F: FnOnce()+Send+'static, S: Into<String>>(name: S, priority: super::Priority, fcn: F, process: Arc<Process>) -> ThreadHandle
 	{
 		let mut thread = Thread::new_boxed(allocate_tid(), name, process);
+		thread.priority = priority;
 		let handle = ThreadHandle {
 			block: thread.block.clone(),
 			};
@@ -286,6 +375,76 @@ impl ThreadHandle
 		
 		handle
 	}
+
+	/// Start a new userland thread in the current process
+	///
+	/// `arg` is passed to the entrypoint as its first argument
+	pub fn new_user(ip: usize, sp: usize, arg: usize) -> ThreadHandle
+	{
+		let process = super::with_cur_thread(|cur| cur.block.process.clone());
+		let tid = allocate_tid();
+		let name = format!("{}#{}", process.name, tid);
+		log_trace!("ThreadHandle::new_user(ip={:#x}, sp={:#x}, arg={:#x}) - {}", ip, sp, arg, name);
+		let mut thread = Thread::new_boxed(tid, name, process);
+		let handle = ThreadHandle {
+			block: thread.block.clone(),
+			};
+		::arch::threads::start_thread( &mut thread,
+			// SAFE: Addresses are only used in userland, so any errors are the user's problem
+			move || unsafe {
+				::arch::drop_to_user(ip, sp, arg)
+				}
+			);
+		super::yield_to(thread);
+		handle
+	}
+
+	pub fn get_tid(&self) -> ThreadID {
+		self.block.tid
+	}
+
+	/// Release this handle without waiting for the thread to terminate
+	pub fn detach(self) {
+		log_trace!("detach({:?})", self);
+		// SAFE: `self` is forgotten immediately after the block handle is read out
+		let block = unsafe { ::core::ptr::read(&self.block) };
+		::core::mem::forget(self);
+		::core::mem::drop(block);
+	}
+
+	/// Block until the thread terminates, returning its exit value
+	pub fn wait(&self) -> u32 {
+		let mut obj = ::threads::SleepObject::new("ThreadHandle::wait");
+		self.bind_wait_terminate(&mut obj);
+		while self.get_exit_status().is_none() {
+			obj.wait();
+		}
+		self.clear_wait_terminate(&mut obj);
+		self.get_exit_status().unwrap()
+	}
+
+	pub fn bind_wait_terminate(&self, obj: &mut ::threads::SleepObject) {
+		log_trace!("bind_wait_terminate({:?}, obj={:p})", self, obj);
+		let mut lh = self.block.exit_status.lock();
+		if let Some(_status) = lh.0 {
+			obj.signal();
+		}
+		else {
+			lh.1.push( obj.get_ref() );
+		}
+	}
+	pub fn clear_wait_terminate(&self, obj: &mut ::threads::SleepObject) -> bool {
+		log_trace!("clear_wait_terminate({:?}, obj={:p})", self, obj);
+		let mut lh = self.block.exit_status.lock();
+		lh.1.retain(|v| !v.is_from(obj));
+		
+		lh.0.is_some()
+	}
+
+	/// Returns the thread's exit value (or `None` if still running)
+	pub fn get_exit_status(&self) -> Option<u32> {
+		self.block.exit_status.lock().0
+	}
 }
 impl ::core::fmt::Debug for ThreadHandle
 {
@@ -297,7 +456,8 @@ impl ::core::fmt::Debug for ThreadHandle
 impl ::core::ops::Drop for ThreadHandle
 {
 	fn drop(&mut self) {
-		panic!("TODO: Wait for thread to terminate when handle is dropped");
+		let status = self.wait();
+		log_debug!("Dropped handle to {} (exited with {:#x})", self.block, status);
 	}
 }
 
@@ -373,12 +533,13 @@ impl Thread
 	{
 		let rv = box Thread {
 			cpu_state: process.empty_cpu_state(),
-			block: Arc::new( SharedBlock { tid: tid, name: name.into(), process: process } ),
+			block: Arc::new( SharedBlock { tid: tid, name: name.into(), process: process, exit_status: Default::default(), cpu_time: Default::default() } ),
 			run_state: RunState::Runnable,
+			priority: Default::default(),
 			next: None,
 			};
 		
-		// TODO: Add to global list of threads (removed on destroy)
+		S_THREADS.lock().set(tid, Arc::as_raw(&rv.block));
 		log_debug!("Creating thread {:?}", rv);
 		
 		ThreadPtr::new( rv )
@@ -403,6 +564,22 @@ impl Thread
 	pub fn get_process_info(&self) -> &Process {
 		&*self.block.process
 	}
+
+	/// Add to this thread's CPU time
+	#[is_safe(irq)]
+	pub fn add_cpu_time(&self, ms: u64) {
+		self.block.cpu_time.fetch_add(ms, Ordering::Relaxed);
+	}
+
+	/// Record this thread's exit value and wake anything waiting on it
+	pub fn mark_exit(&self, status: u32) {
+		let mut lh = self.block.exit_status.lock();
+		assert!(lh.0.is_none(), "Thread {:?} exited twice", self);
+		lh.0 = Some(status);
+		for sleep_ref in lh.1.iter() {
+			sleep_ref.signal();
+		}
+	}
 }
 
 pub fn new_idle_thread(cpu: usize) -> ThreadPtr {
@@ -419,6 +596,14 @@ impl ::core::fmt::Display for SharedBlock
 	}
 }
 
+impl ::core::fmt::Display for Thread
+{
+	fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result
+	{
+		write!(f, "{}", self.block)
+	}
+}
+
 impl ::core::fmt::Debug for Thread
 {
 	fn fmt(&self, f: &mut ::core::fmt::Formatter) -> Result<(),::core::fmt::Error>
@@ -427,6 +612,37 @@ impl ::core::fmt::Debug for Thread
 	}
 }
 
+impl SharedBlock
+{
+	fn info(&self) -> ThreadInfo {
+		ThreadInfo {
+			tid: self.tid,
+			pid: self.process.pid,
+			name: self.name.clone(),
+			exit_status: self.exit_status.lock().0,
+			cpu_time: self.cpu_time.load(Ordering::Relaxed),
+		}
+	}
+}
+impl ::core::ops::Drop for SharedBlock
+{
+	fn drop(&mut self)
+	{
+		// Only now is the TID free for re-use (handles can outlive the thread)
+		S_THREADS.lock().release(self.tid);
+		self.process.exited_cpu_time.fetch_add(self.cpu_time.load(Ordering::Relaxed), Ordering::Relaxed);
+	}
+}
+
+impl ::core::ops::Drop for Process
+{
+	fn drop(&mut self)
+	{
+		log_debug!("Destroying process {}", self);
+		S_PROCESSES.lock().release(self.pid);
+	}
+}
+
 impl_fmt! {
 	Display(self, f) for Process {
 		write!(f, "PID{}:'{}'", self.pid, self.name)
@@ -437,7 +653,6 @@ impl ::core::ops::Drop for Thread
 {
 	fn drop(&mut self)
 	{
-		// TODO: Remove self from the global thread map
 		log_debug!("Destroying thread {:?} - {} handles to block, {} to process", self, Arc::strong_count(&self.block), Arc::strong_count(&self.block.process));
 	}
 }
@@ -15,14 +15,23 @@ impl WorkerThread
 	/// Construct a new worker thread
 	pub fn new<F: FnOnce()+Send+'static>(name: &str, fcn: F) -> WorkerThread
 	{
-		let handle = super::thread::ThreadHandle::new(name, fcn, super::S_PID0.clone());
+		WorkerThread::with_priority(name, super::Priority::Normal, fcn)
+	}
+	/// Construct a new worker thread with a non-default scheduling priority
+	pub fn with_priority<F: FnOnce()+Send+'static>(name: &str, priority: super::Priority, fcn: F) -> WorkerThread
+	{
+		let handle = super::thread::ThreadHandle::new_with_priority(name, priority, fcn, super::S_PID0.clone());
 		WorkerThread(handle)
 	}
 
 	// TODO: Allow the worker to return a value?
 	pub fn wait(&self) -> Result<(),()>
 	{
-		todo!("Wait for worker thread to terminate");
+		match self.0.wait()
+		{
+		0 => Ok( () ),
+		_ => Err( () ),
+		}
 	}
 }
 
@@ -35,6 +35,44 @@ impl_fmt! {
 }
 
 
+/// Wall-clock time (milliseconds since 1970-01-01 00:00 UTC)
+#[derive(Copy,Clone,Debug,Default,PartialEq,Eq,PartialOrd,Ord)]
+pub struct Timestamp(i64);
+impl Timestamp
+{
+	pub fn from_millis(ms: i64) -> Timestamp {
+		Timestamp(ms)
+	}
+	pub fn from_unix_seconds(s: i64) -> Timestamp {
+		Timestamp(s * 1000)
+	}
+	/// Construct from a (Gregorian) calendar date and time of day, in UTC
+	///
+	/// `month` and `day` are 1-based
+	pub fn from_civil(year: i32, month: u8, day: u8, hour: u8, minute: u8, second: u8, ms: u16) -> Timestamp {
+		// Days since the epoch (from Howard Hinnant's `days_from_civil`)
+		let y = (if month <= 2 { year - 1 } else { year }) as i64;
+		let era = (if y >= 0 { y } else { y - 399 }) / 400;
+		let yoe = y - era * 400;
+		let mp = (month as i64 + 9) % 12;
+		let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+		let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+		let days = era * 146097 + doe - 719468;
+
+		let secs = days * 86400 + hour as i64 * 3600 + minute as i64 * 60 + second as i64;
+		Timestamp(secs * 1000 + ms as i64)
+	}
+
+	pub fn as_millis(&self) -> i64 {
+		self.0
+	}
+	pub fn as_unix_seconds(&self) -> i64 {
+		// Round towards negative infinity
+		if self.0 >= 0 { self.0 / 1000 } else { (self.0 - 999) / 1000 }
+	}
+}
+
+
 pub struct CacheTimer(::sync::atomic::AtomicValue<TickCount>);
 impl Default for CacheTimer {
 	fn default() -> Self {
@@ -52,5 +90,15 @@ impl CacheTimer
 	}
 }
 
+#[test]
+fn test_timestamp_civil()
+{
+	assert_eq!(Timestamp::from_civil(1970, 1, 1, 0, 0, 0, 0), Timestamp::from_millis(0));
+	assert_eq!(Timestamp::from_civil(1980, 1, 1, 0, 0, 0, 0), Timestamp::from_unix_seconds(315532800));
+	assert_eq!(Timestamp::from_civil(2000, 2, 29, 12, 30, 15, 500), Timestamp::from_millis(951827415500));
+	assert_eq!(Timestamp::from_civil(1969, 12, 31, 23, 59, 59, 0), Timestamp::from_unix_seconds(-1));
+	assert_eq!(Timestamp::from_unix_seconds(-1).as_unix_seconds(), -1);
+}
+
 // vim: ft=rust
 
@@ -5,8 +5,9 @@
 //! Opened file interface
 #[allow(unused_imports)]
 use prelude::*;
-use super::node::{CacheHandle,NodeType};
+use super::node::{CacheHandle,NodeType,FileLock};
 use lib::byte_str::{ByteStr,ByteString};
+use lib::mem::Arc;
 use super::Path;
 
 #[derive(Debug,Clone)]
@@ -19,6 +20,23 @@ pub struct Any {
 pub struct File {
 	node: CacheHandle,
 	mode: FileOpenMode,
+	/// Lock on the node (shared between clones of this handle, released when the last is dropped)
+	lock: Arc<OpenLock>,
+	/// Private copy of modified data (for `UniqueRW`)
+	private: Option<Arc<PrivateCopy>>,
+}
+/// Lock held on a file node by open handles
+#[derive(Debug)]
+struct OpenLock {
+	node: CacheHandle,
+	lock: FileLock,
+}
+/// Copy-on-write overlay used by `UniqueRW` handles
+struct PrivateCopy(::sync::Mutex<PrivateData>);
+struct PrivateData {
+	size: u64,
+	/// Pages that have been written through this handle
+	pages: ::lib::VecMap<u64, Box<[u8]>>,
 }
 #[derive(Debug,Clone)]
 /// Directory (for enumeration)
@@ -86,10 +104,20 @@ impl Any
 		let node = try!(CacheHandle::from_path(path));
 		Ok(Any { node: node })
 	}
+	/// Open the specified path, without following a symbolic link in the final component
+	pub fn open_nofollow(path: &Path) -> super::Result<Any> {
+		log_trace!("Any::open_nofollow({:?})", path);
+		let node = try!(CacheHandle::from_path_nofollow(path));
+		Ok(Any { node: node })
+	}
 
 	pub fn get_class(&self) -> super::node::NodeClass {
 		self.node.get_class()
 	}
+	/// Obtain the node's metadata (size, timestamps, permissions)
+	pub fn metadata(&self) -> super::node::Metadata {
+		self.node.get_metadata()
+	}
 	
 	/// Upgrade the handle to a directory handle
 	pub fn to_dir(self) -> super::Result<Dir> {
@@ -139,21 +167,59 @@ impl File
 		if !node.is_file() {
 			return Err(super::Error::TypeMismatch);
 		}
+		// - Check the volume's mount options
+		let options = node.mount_options();
 		match mode
 		{
-		// TODO: Mark file as shared
-		// TODO: Check permissions (must be readable in current context)
 		FileOpenMode::SharedRO => {},
-		// TODO: Mark file as shared
-		// TODO: Check permissions (must be executable in current context)
-		FileOpenMode::Execute => {},
-		_ => todo!("Acquire lock depending on mode({:?})", mode),
+		FileOpenMode::Execute => if options.no_exec {
+				return Err(super::Error::PermissionDenied);
+			},
+		_ => if options.read_only {
+				return Err(super::Error::ReadOnlyFilesystem);
+			},
 		}
-		Ok(File { node: node, mode: mode })
+		// TODO: Check permissions (must be readable/writable/executable in current context)
+		let lock = match mode
+			{
+			// Shared modes (and the unique copy, as the underlying file must not change under it)
+			FileOpenMode::SharedRO => FileLock::Shared,
+			FileOpenMode::Execute => FileLock::Shared,
+			FileOpenMode::UniqueRW => FileLock::Shared,
+			FileOpenMode::ExclRW => FileLock::Exclusive,
+			FileOpenMode::Append => FileLock::Append,
+			FileOpenMode::Unsynch => FileLock::Unsynch,
+			};
+		try!(node.lock_file(lock));
+		let private = match mode
+			{
+			FileOpenMode::UniqueRW => Some(Arc::new(PrivateCopy(::sync::Mutex::new(PrivateData {
+				size: node.get_valid_size(),
+				pages: ::lib::VecMap::new(),
+				})))),
+			_ => None,
+			};
+		Ok(File {
+			lock: Arc::new(OpenLock { node: node.clone(), lock: lock }),
+			node: node,
+			mode: mode,
+			private: private,
+			})
 	}
 	
+	/// Obtain the file's metadata (size, timestamps, permissions)
+	pub fn metadata(&self) -> super::node::Metadata {
+		let mut rv = self.node.get_metadata();
+		rv.size = self.size();
+		rv
+	}
+
 	pub fn size(&self) -> u64 {
-		self.node.get_valid_size()
+		match self.private
+		{
+		Some(ref p) => p.0.lock().size,
+		None => self.node.get_valid_size(),
+		}
 	}
 
 	/// Read data from the file at the specified offset
@@ -162,11 +228,31 @@ impl File
 	/// slice).
 	pub fn read(&self, ofs: u64, dst: &mut [u8]) -> super::Result<usize> {
 		assert!(self.node.is_file());
-		self.node.read(ofs, dst)
+		match self.mode
+		{
+		FileOpenMode::Append => Err(super::Error::PermissionDenied),
+		FileOpenMode::UniqueRW => {
+			let mut lh = self.private.as_ref().expect("UniqueRW without private copy").0.lock();
+			lh.read(&self.node, ofs, dst)
+			},
+		_ => self.node.read(ofs, dst),
+		}
 	}
+	/// Write data to the file at the specified offset (ignored for `Append` handles, which always write to the end)
+	///
+	/// Returns the number of bytes written
 	pub fn write(&self, ofs: u64, src: &[u8]) -> super::Result<usize> {
 		assert!(self.node.is_file());
-		todo!("Handle::write({:#x}, {:p}+{}", ofs, src.as_ptr(), src.len());
+		match self.mode
+		{
+		FileOpenMode::SharedRO | FileOpenMode::Execute => Err(super::Error::PermissionDenied),
+		FileOpenMode::UniqueRW => {
+			let mut lh = self.private.as_ref().expect("UniqueRW without private copy").0.lock();
+			lh.write(&self.node, ofs, src)
+			},
+		FileOpenMode::Append => self.node.append(src),
+		FileOpenMode::ExclRW | FileOpenMode::Unsynch => self.node.write(ofs, src),
+		}
 	}
 
 	
@@ -182,8 +268,9 @@ impl File
 			{
 			FileOpenMode::Execute => {},
 			FileOpenMode::SharedRO => {},
-			//FileOpenMode::ExclRW => /* NOTE: Needs extra checks to ensure that aliasing does not occur */
-			//FileOpenMode::UniqueRW => /* NOTE: Needs extra checks to ensure that aliasing does not occur */
+			// NOTE: Mappings share the node's page cache, so writes through other mappings are visible
+			FileOpenMode::ExclRW => {},
+			FileOpenMode::UniqueRW => {},
 			_ => return Err(super::Error::PermissionDenied),
 			},
 		// Executable - Execute mode only
@@ -203,92 +290,153 @@ impl File
 		// Writeback - Requires exclusive access to the file (or a copy)
 		MemoryMapMode::WriteBack => match self.mode
 			{
-			//FileOpenMode::ExclRW => /* NOTE: Needs extra checks to ensure that aliasing does not occur */
-			//FileOpenMode::UniqueRW => /* NOTE: Needs extra checks to ensure that aliasing does not occur */
+			FileOpenMode::ExclRW => {},
+			FileOpenMode::UniqueRW => {},
+			FileOpenMode::Unsynch => {},
 			_ => return Err(super::Error::PermissionDenied),
 			},
 		}
+		// - Writes to a unique copy must not reach the underlying file, so map it as COW
+		// TODO: The mapping doesn't see data written with `write` on a UniqueRW handle
+		let mode = match (mode, &self.mode)
+			{
+			(MemoryMapMode::WriteBack, &FileOpenMode::UniqueRW) => MemoryMapMode::COW,
+			(mode, _) => mode,
+			};
 		
-		// TODO: Handle unaligned mappings somehow
-		// - Depends on several qirks:
-		//  > Unaligned address could write to an existing page (converting it to a private) - But how would that interact with existing mappings?
-		//  > Unaligned sizes would usually cause a new anon mapping, but if its unaligned becuase of EOF, it should just be COW as usual
+		// TODO: Handle unaligned addresses somehow
+		// - Unaligned address could write to an existing page (converting it to a private) - But how would that interact with existing mappings?
 		assert!(address % ::PAGE_SIZE == 0, "TODO: Unaligned memory_map (address={})", address);
-		assert!(size % ::PAGE_SIZE == 0, "TODO: Unaligned memory_map (size={})", size);
+		// - Unaligned sizes are only allowed when the mapping reaches EOF (the tail of the last page is zero-filled)
+		if size % ::PAGE_SIZE != 0 && ofs + (size as u64) < self.size() {
+			return Err( super::Error::Unknown("memory_map unaligned size not at EOF") );
+		}
 		if address % ::PAGE_SIZE != (ofs % ::PAGE_SIZE as u64) as usize {
 			return Err( super::Error::Unknown("memory_map alignment mismatch") );
 		}
 		// - Limit checking (ofs + size must be within size of the file)
 		// TODO: Limit checking
-		// - Reserve the region to be mapped (reserve sticks a zero page in)
-		let page_count = size / ::PAGE_SIZE;
-		let mut resv = match ::memory::virt::reserve(address as *mut (), page_count)
-			{
-			Ok(v) => v,
-			Err(e) => {
-				log_notice!("mmap reserve error {:?}", e);
-				return Err( super::Error::Locked );
-				},
-			};
-		// - Obtain handles to each cached page, and map into the reservation
-		for i in 0 .. page_count {
-			let page = ofs / ::PAGE_SIZE as u64 + i as u64;
-			// 1. Search the node for this particular page
-			//let lh = self.page_cache.read();
-			//  - If found, map over region
-			// 2. Drop lock, read data from file, and try again
-			//drop(lh)
-			try!( self.node.read(page * ::PAGE_SIZE as u64, resv.get_mut_page(i)) );
-			// 3. Acquire write on lock, and attempt to insert a handle to this page
-			//let lh = self.page_cache.write();
-			//match lh.try_insert(pag, self.get_page_handle(i))
-			//{
-			//Ok(_) => {},	// Inserted correctly
-			//Err(h) => {	// Another handle made a page for this first
-			//	resv.map_at(i, h);	// - Map over our original attempt
-			//	},
-			//}
-		}
-		resv.finalise( match mode
+		// - Register the region for demand paging, pages are read (via the node's page cache) on first access
+		//  > Read-only, executable, and COW mappings all share the node's frames (COW copies on write)
+		let page_count = (size + ::PAGE_SIZE - 1) / ::PAGE_SIZE;
+		let prot = match mode
 			{
 			MemoryMapMode::ReadOnly  => ::memory::virt::ProtectionMode::UserRO,
 			MemoryMapMode::Execute   => ::memory::virt::ProtectionMode::UserRX,
 			MemoryMapMode::COW       => ::memory::virt::ProtectionMode::UserCOW,
 			MemoryMapMode::WriteBack => ::memory::virt::ProtectionMode::UserRW,
-			})
-			.unwrap();
+			};
+		let backing = ::memory::vma::Backing::File { node: self.node.clone(), ofs: ofs };
+		match ::memory::vma::map_lazy(address as *mut (), page_count, prot, backing)
+		{
+		Ok(_) => {},
+		Err(::memory::virt::MapError::OutOfMemory) => return Err( super::Error::OutOfMemory ),
+		Err(e) => {
+			log_notice!("mmap reserve error {:?}", e);
+			return Err( super::Error::Locked );
+			},
+		}
 		log_debug!("- Mapped at {:p} + {:#x}", address as *mut (), page_count * ::PAGE_SIZE);
 		Ok(MemoryMapHandle {
 			handle: self,
 			base: address as *mut (),
-			len: page_count * ::PAGE_SIZE,
+			len: size,
 			})
 	}
 }
-impl ::core::ops::Drop for File
+impl ::core::ops::Drop for OpenLock
 {
 	fn drop(&mut self) {
-		match self.mode
+		self.node.unlock_file(self.lock);
+	}
+}
+
+impl ::core::fmt::Debug for PrivateCopy
+{
+	fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+		write!(f, "PrivateCopy")
+	}
+}
+impl PrivateData
+{
+	fn read(&mut self, node: &CacheHandle, ofs: u64, dst: &mut [u8]) -> super::Result<usize> {
+		if ofs >= self.size {
+			return Ok(0);
+		}
+		let len = ::core::cmp::min(self.size - ofs, dst.len() as u64) as usize;
+		let mut pos = 0;
+		while pos < len
 		{
-		FileOpenMode::SharedRO => {},
-		FileOpenMode::Execute => {},
-		_ => todo!("File::drop() - mode={:?}", self.mode),
+			let file_ofs = ofs + pos as u64;
+			let page = file_ofs / ::PAGE_SIZE as u64;
+			let page_ofs = (file_ofs % ::PAGE_SIZE as u64) as usize;
+			let count = ::core::cmp::min(::PAGE_SIZE - page_ofs, len - pos);
+			let dst = &mut dst[pos ..][.. count];
+			match self.pages.get(&page)
+			{
+			Some(data) => dst.clone_from_slice( &data[page_ofs ..][.. count] ),
+			None => {
+				// - Data past the end of the underlying file (after a private extend) reads as zero
+				let n = try!(node.read(file_ofs, dst));
+				for b in &mut dst[n..] {
+					*b = 0;
+				}
+				},
+			}
+			pos += count;
+		}
+		Ok(len)
+	}
+	fn write(&mut self, node: &CacheHandle, ofs: u64, src: &[u8]) -> super::Result<usize> {
+		let mut pos = 0;
+		while pos < src.len()
+		{
+			let file_ofs = ofs + pos as u64;
+			let page = file_ofs / ::PAGE_SIZE as u64;
+			let page_ofs = (file_ofs % ::PAGE_SIZE as u64) as usize;
+			let count = ::core::cmp::min(::PAGE_SIZE - page_ofs, src.len() - pos);
+			if self.pages.get(&page).is_none() {
+				// - Copy the original page content before modifying it
+				let mut data = vec![0u8; ::PAGE_SIZE].into_boxed_slice();
+				let page_start = page * ::PAGE_SIZE as u64;
+				if page_start < self.size {
+					try!(self.read(node, page_start, &mut data));
+				}
+				self.pages.insert(page, data);
+			}
+			let data = self.pages.get_mut(&page).expect("Page just inserted");
+			data[page_ofs ..][.. count].clone_from_slice( &src[pos ..][.. count] );
+			pos += count;
 		}
-		// TODO: For files, we need to release the lock
+		if ofs + src.len() as u64 > self.size {
+			self.size = ofs + src.len() as u64;
+		}
+		Ok(src.len())
 	}
 }
 
+impl<'a> MemoryMapHandle<'a>
+{
+	/// Write modified pages back to the file (only does anything for `WriteBack` mappings)
+	pub fn flush(&self) -> super::Result<()> {
+		::memory::vma::flush(self.base, (self.len + ::PAGE_SIZE - 1) / ::PAGE_SIZE)
+	}
+}
 impl<'a> Drop for MemoryMapHandle<'a>
 {
 	fn drop(&mut self)
 	{
-		assert_eq!(self.len % ::PAGE_SIZE, 0, "TODO: Handle unaligned lengths in MemoryMapHandle::drop");
 		assert_eq!(self.base as usize % ::PAGE_SIZE, 0, "TODO: Handle unaligned addresses in MemoryMapHandle::drop");
-		let npages = self.len / ::PAGE_SIZE;
+		// - An unaligned length is from a mapping that ends at EOF, the last page is still owned
+		let npages = (self.len + ::PAGE_SIZE - 1) / ::PAGE_SIZE;
+		if let Err(e) = self.flush() {
+			log_error!("Flushing mapping of {:?} failed: {:?}", self.handle.node, e);
+		}
 		// SAFE: This is a uniquely owned handle
 		unsafe {
 			::memory::virt::unmap(self.base, npages);
 		}
+		::memory::vma::remove(self.base, npages);
 	}
 }
 
@@ -299,6 +447,11 @@ impl Dir
 		try!(Any::open(path)).to_dir()
 	}
 	
+	/// Obtain the directory's metadata
+	pub fn metadata(&self) -> super::node::Metadata {
+		self.node.get_metadata()
+	}
+
 	pub fn iter(&self) -> DirIter {
 		DirIter {
 			handle: self,
@@ -314,10 +467,19 @@ impl Dir
 	
 	/// Create a new directory
 	pub fn mkdir(&self, name: &str) -> super::Result<Dir> {
-		let node = try!(self.node.create(name.as_ref(), NodeType::Dir));
+		self.create_dir(name.as_ref())
+	}
+	/// Create a new directory (with an arbitary byte name)
+	pub fn create_dir(&self, name: &ByteStr) -> super::Result<Dir> {
+		let node = try!(self.node.create(name, NodeType::Dir));
 		assert!(node.is_dir());
 		Ok( Dir { node: node } )
 	}
+	/// Create a new file, and open it with the provided mode
+	pub fn create_file(&self, name: &ByteStr, mode: FileOpenMode) -> super::Result<File> {
+		let node = try!(self.node.create(name, NodeType::File));
+		File::from_node(node, mode)
+	}
 	/// Create a new symbolic link
 	pub fn symlink(&self, name: &str, target: &Path) -> super::Result<()> {
 		try!(self.node.create(name.as_ref(), NodeType::Symlink(target)));
@@ -334,6 +496,24 @@ impl Dir
 		let node = try!(CacheHandle::from_path_at_node(self.node.clone(), path));
 		Ok(Any{ node: node })
 	}
+	/// Open a path relative to this node, without following a symbolic link in the final component
+	pub fn open_child_path_nofollow(&self, path: &Path) -> super::Result<Any> {
+		let node = try!(CacheHandle::from_path_at_node_nofollow(self.node.clone(), path));
+		Ok(Any{ node: node })
+	}
+
+	/// Remove a (non-directory) child
+	pub fn unlink(&self, name: &ByteStr) -> super::Result<()> {
+		self.node.unlink(name)
+	}
+	/// Remove an empty child directory
+	pub fn rmdir(&self, name: &ByteStr) -> super::Result<()> {
+		self.node.rmdir(name)
+	}
+	/// Atomically move a child to a new name (in `new_dir`, which must be on the same volume)
+	pub fn rename(&self, old_name: &ByteStr, new_dir: &Dir, new_name: &ByteStr) -> super::Result<()> {
+		self.node.rename(old_name, &new_dir.node, new_name)
+	}
 
 
 	/// RETURN: next position
@@ -386,7 +566,7 @@ impl<'a> ::core::iter::Iterator for DirIter<'a> {
 impl Symlink
 {
 	pub fn open(path: &Path) -> super::Result<Symlink> {
-		try!(Any::open(path)).to_symlink()
+		try!(Any::open_nofollow(path)).to_symlink()
 	}
 	pub fn get_target(&self) -> super::Result<ByteString> {
 		self.node.get_target()
@@ -33,6 +33,10 @@ pub enum Error
 	NonDirComponent,
 	/// Symbolic link recursion limit reached
 	RecursionDepthExceeded,
+	/// Directory to be removed was not empty
+	NotEmpty,
+	/// Operation would cross filesystems (e.g. renaming between volumes)
+	CrossFilesystem,
 
 
 	/// Block-level IO Error
@@ -43,6 +47,8 @@ pub enum Error
 	InconsistentFilesystem,
 	/// Volume ran out of space
 	OutOfSpace,
+	/// File would exceed the maximum size supported by the filesystem
+	FileTooLarge,
 
 	/// System has run out of memory
 	OutOfMemory,
@@ -58,6 +64,11 @@ impl From<::metadevs::storage::IoError> for Error {
 		Error::BlockIoError(v)
 	}
 }
+impl From<::memory::page_cache::Error> for Error {
+	fn from(_v: ::memory::page_cache::Error) -> Error {
+		Error::OutOfMemory
+	}
+}
 //impl_fmt! {
 //	Display(self, f) for Error {
 //		match self
@@ -76,6 +87,9 @@ pub mod handle;
 mod path;
 mod ramfs;
 
+/// Size limit of the ramfs mounted at `/temp`
+const TEMP_SIZE: usize = 8 << 20;
+
 fn init()
 {
 	// 1. Initialise global structures
@@ -93,5 +107,7 @@ fn init()
 	root.mkdir("system").unwrap();
 	root.mkdir("volumes").unwrap();
 	root.mkdir("temp").unwrap();
+	// 4. Scratch space for userland (separate instance, so it can be size limited)
+	mount::mount("/temp".as_ref(), VolumeHandle::new_ramdisk(TEMP_SIZE / ::PAGE_SIZE), "ramfs", &["noexec"]).expect("Unable to mount /temp");
 }
 
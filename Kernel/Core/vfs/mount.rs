@@ -24,8 +24,24 @@ pub struct SelfHandle(usize);
 /// Internal representation of a mounted volume
 struct MountedVolume
 {
-	mountpoint_node: CacheHandle,
+	/// Node this volume is mounted over (`None` for the root volume)
+	mountpoint_node: Option<CacheHandle>,
 	fs: Box<Filesystem>,
+	options: MountOptions,
+	/// Lazily unmounted, released once the last open node is closed
+	detached: bool,
+}
+
+/// Parsed mount options
+#[derive(Debug,Default,Copy,Clone)]
+pub struct MountOptions
+{
+	/// `ro` - Modifications to the filesystem are refused
+	pub read_only: bool,
+	/// `noexec` - Files cannot be opened for execution
+	pub no_exec: bool,
+	/// `sync` - Changes are written through to the volume immediately
+	pub sync: bool,
 }
 
 
@@ -35,6 +51,11 @@ pub trait Filesystem:
 {
 	fn root_inode(&self) -> InodeId;
 	fn get_node_by_inode(&self, InodeId) -> Option<Node>;
+
+	/// Write any cached (modified) data back to the volume
+	fn sync(&self) -> super::Result<()> {
+		Ok( () )
+	}
 }
 
 struct NullFs;
@@ -65,7 +86,7 @@ static S_DRIVERS: LazyStatic<RwLock< VecMap<&'static str, &'static Driver> >> =
 /// Mounted volumes
 static S_VOLUMES: LazyStatic<RwLock< SparseVec<MountedVolume> >> = lazystatic_init!();
 /// Root mount
-static S_ROOT_VOLUME: RwLock<Option<Box<Filesystem>>> = RwLock::new(None);
+static S_ROOT_VOLUME: RwLock<Option<MountedVolume>> = RwLock::new(None);
 
 pub fn init()
 {
@@ -77,9 +98,11 @@ pub fn init()
 }
 
 /// Mount a volume at the provided location
-// TODO: Parse options
-pub fn mount(location: &Path, vol: VolumeHandle, fs: &str, _options: &[&str]) -> Result<(),MountError>
+///
+/// Recognised options are `ro`/`rw`, `noexec`/`exec`, and `sync`/`async`
+pub fn mount(location: &Path, vol: VolumeHandle, fs: &str, options: &[&str]) -> Result<(),MountError>
 {
+	let options = try!(MountOptions::parse(options));
 	let drivers = S_DRIVERS.read();
 	// 1. (maybe) detect filesystem
 	let driver = if fs == "" {
@@ -112,10 +135,10 @@ pub fn mount(location: &Path, vol: VolumeHandle, fs: &str, _options: &[&str]) ->
 			};
 		let mut lh = S_ROOT_VOLUME.write();
 		if lh.is_some() {
-			log_warning!("TODO: Support remounting /");
+			log_notice!("/ is already mounted (use `remount` to change its options)");
 			return Err(MountError::MountpointUsed);
 		}
-		*lh = Some(fs);
+		*lh = Some(MountedVolume { mountpoint_node: None, fs: fs, options: options, detached: false });
 	}
 	else
 	{
@@ -134,28 +157,184 @@ pub fn mount(location: &Path, vol: VolumeHandle, fs: &str, _options: &[&str]) ->
 		
 		// 3. Reserve the mountpoint ID (using a placeholder instance)
 		// NOTE: Nothing should know of this index until after mount is completed
-		let vidx = S_VOLUMES.write().insert(MountedVolume { mountpoint_node: nh, fs: Box::new(NullFs) });
+		let vidx = S_VOLUMES.write().insert(MountedVolume { mountpoint_node: Some(nh), fs: Box::new(NullFs), options: options, detached: false });
 
 		// 4. Mount and register volume
-		let fs = match driver.mount(vol, SelfHandle(vidx))
+		let fs = match driver.mount(vol, SelfHandle(vidx + 1))
 			{
 			Ok(v) => v,
-			Err(_) => return Err(MountError::CallFailed),
+			Err(_) => {
+				let v = S_VOLUMES.write().remove(vidx);
+				drop(v);
+				return Err(MountError::CallFailed)
+				},
 			};
 
 		// 5. Store and bind to mountpoint
-		{
+		// NOTE: The node is accessed (and the entry dropped) with the volume list unlocked, as the node
+		//       cache locks the volume list when it's locked.
+		let mp = {
 			let mut lh = S_VOLUMES.write();
 			lh[vidx].fs = fs;
-			if lh[vidx].mountpoint_node.mount(vidx + 1) == false {
-				lh.remove(vidx);
-				return Err(MountError::MountpointUsed);
-			}
+			lh[vidx].mountpoint_node.clone().unwrap()
+			};
+		if mp.mount(vidx + 1) == false {
+			let v = S_VOLUMES.write().remove(vidx);
+			drop(v);
+			return Err(MountError::MountpointUsed);
 		}
 	}
 
 	Ok( () )
 }
+
+/// Change the options of an existing mount (including `/`)
+pub fn remount(location: &Path, options: &[&str]) -> Result<(),MountError>
+{
+	let options = try!(MountOptions::parse(options));
+	let h = try!(Handle::from_mountpoint(location));
+	// - Write out pending changes before (possibly) becoming read-only
+	if options.read_only {
+		if let Err(e) = h.sync() {
+			log_warning!("remount - Sync of {:?} failed: {:?}", location, e);
+			return Err(MountError::CallFailed);
+		}
+	}
+	if h.0 == 0 {
+		S_ROOT_VOLUME.write().as_mut().unwrap().options = options;
+	}
+	else {
+		S_VOLUMES.write()[h.0 - 1].options = options;
+	}
+	Ok( () )
+}
+
+/// Unmount the volume mounted at `location`
+///
+/// Fails with `Busy` if nodes on the volume are still open, unless `force` is set. A forced unmount
+/// detaches the volume immediately and releases it once the last node is closed.
+pub fn unmount(location: &Path, force: bool) -> Result<(),MountError>
+{
+	let h = try!(Handle::from_mountpoint(location));
+	let vidx = h.0;
+	if vidx == 0 {
+		return Err(MountError::RootUnmount);
+	}
+	// 1. Write out modified data
+	if let Err(e) = h.sync() {
+		log_warning!("unmount - Sync of {:?} failed: {:?}", location, e);
+		if !force {
+			return Err(MountError::CallFailed);
+		}
+	}
+	// 2. Detach from the mountpoint, so new lookups don't reach the volume
+	let mp = S_VOLUMES.read()[vidx - 1].mountpoint_node.clone().unwrap();
+	if !mp.unmount(vidx) {
+		return Err(MountError::NotMounted);
+	}
+	// 3. Release (or lazily release) the volume
+	if super::node::volume_in_use(vidx)
+	{
+		if !force {
+			// - Re-attach, leaving the volume as it was
+			mp.mount(vidx);
+			return Err(MountError::Busy);
+		}
+		log_notice!("Lazily unmounting {:?} (nodes are still open)", location);
+	}
+	S_VOLUMES.write()[vidx - 1].detached = true;
+	// Releases the volume now if no nodes are open (otherwise when the last is closed)
+	node_released(vidx);
+	Ok( () )
+}
+
+/// Write all modified data on the volume containing `location` back to disk
+pub fn sync(location: &Path) -> Result<(),MountError>
+{
+	let nh = try!(CacheHandle::from_path(location).map_err(|_| MountError::InvalidMountpoint));
+	match Handle::from_id(nh.mount_id()).sync()
+	{
+	Ok(_) => Ok( () ),
+	Err(_) => Err(MountError::CallFailed),
+	}
+}
+
+/// Write all modified data on every mounted volume back to disk
+pub fn sync_all() -> Result<(),MountError>
+{
+	let ids: Vec<usize> = {
+		let lh = S_VOLUMES.read();
+		(0 .. lh.len()).filter(|&i| lh.get(i).map(|v| !v.detached).unwrap_or(false)).map(|i| i+1).collect()
+		};
+	let mut rv = Ok( () );
+	for id in ::core::iter::once(0).chain(ids)
+	{
+		if let Err(e) = Handle(id).sync() {
+			log_warning!("sync_all - Volume {} failed: {:?}", id, e);
+			rv = Err(MountError::CallFailed);
+		}
+	}
+	rv
+}
+
+/// Called by the node cache when the last handle to a node on volume `id` is closed
+///
+/// Releases lazily unmounted volumes once they're no longer in use.
+pub fn node_released(id: usize)
+{
+	if id == 0 {
+		return ;
+	}
+	let is_detached = match S_VOLUMES.read().get(id - 1)
+		{
+		Some(v) => v.detached,
+		None => false,
+		};
+	if !is_detached {
+		return ;
+	}
+	// NOTE: The node cache is locked while the volume is removed (so no new handles can be created)
+	let removed = super::node::release_volume(id, || {
+		let mut lh = S_VOLUMES.write();
+		match lh.get(id - 1)
+		{
+		Some(v) if v.detached => {},
+		_ => return None,
+		}
+		lh.remove(id - 1)
+		});
+	if let Some(v) = removed {
+		log_log!("Volume {} released", id);
+		drop(v);
+	}
+}
+
+impl MountOptions
+{
+	/// Parse a list of mount option strings
+	pub fn parse(options: &[&str]) -> Result<MountOptions,MountError>
+	{
+		let mut rv = MountOptions::default();
+		for &opt in options
+		{
+			match opt
+			{
+			"" => {},
+			"ro" => rv.read_only = true,
+			"rw" => rv.read_only = false,
+			"noexec" => rv.no_exec = true,
+			"exec" => rv.no_exec = false,
+			"sync" => rv.sync = true,
+			"async" => rv.sync = false,
+			_ => {
+				log_notice!("Unknown mount option '{}'", opt);
+				return Err(MountError::InvalidOption);
+				},
+			}
+		}
+		Ok(rv)
+	}
+}
 #[derive(Debug)]
 pub enum MountError
 {
@@ -164,6 +343,10 @@ pub enum MountError
 	InvalidMountpoint,
 	MountpointUsed,
 	CallFailed,
+	InvalidOption,
+	NotMounted,
+	Busy,
+	RootUnmount,
 }
 impl_fmt! {
 	Display(self,f) for MountError {
@@ -174,6 +357,10 @@ impl_fmt! {
 			&MountError::InvalidMountpoint => "The specified mountpoint was invalid",
 			&MountError::MountpointUsed => "The specified mountpoint was already used",
 			&MountError::CallFailed => "Driver's mount call failed",
+			&MountError::InvalidOption => "An unknown mount option was passed",
+			&MountError::NotMounted => "No volume is mounted at the specified location",
+			&MountError::Busy => "Nodes on the volume are still open",
+			&MountError::RootUnmount => "The root volume cannot be unmounted",
 			})
 	}
 }
@@ -207,9 +394,32 @@ impl Handle
 		}
 	}
 	
+	/// Obtain the handle for the volume mounted at `location` (which must be the volume's root)
+	fn from_mountpoint(location: &Path) -> Result<Handle,MountError> {
+		let nh = try!(CacheHandle::from_path(location).map_err(|_| MountError::InvalidMountpoint));
+		let h = Handle(nh.mount_id());
+		if nh.inode() != h.root_inode() {
+			return Err(MountError::NotMounted);
+		}
+		Ok(h)
+	}
+
 	pub fn id(&self) -> usize {
 		self.0
 	}
+	pub fn options(&self) -> MountOptions {
+		self.with_vol(|v| v.options)
+	}
+
+	/// Write modified cached file data, and then the filesystem's own caches, back to the volume
+	pub fn sync(&self) -> super::Result<()> {
+		try!(super::node::flush_volume(self.0));
+		self.sync_fs()
+	}
+	/// Write the filesystem's own caches back to the volume
+	pub fn sync_fs(&self) -> super::Result<()> {
+		self.with_fs(|fs| fs.sync())
+	}
 	pub fn root_inode(&self) -> InodeId {
 		self.with_fs(|fs| fs.root_inode())
 	}
@@ -219,11 +429,14 @@ impl Handle
 	}
 
 	fn with_fs<R, F: FnOnce(&Filesystem)->R>(&self, f: F) -> R {
+		self.with_vol(|v| f(&*v.fs))
+	}
+	fn with_vol<R, F: FnOnce(&MountedVolume)->R>(&self, f: F) -> R {
 		if self.0 == 0 {
-			f(&**S_ROOT_VOLUME.read().as_ref().unwrap())
+			f(S_ROOT_VOLUME.read().as_ref().unwrap())
 		}
 		else {
-			f(&*S_VOLUMES.read().get(self.0 - 1).unwrap().fs)
+			f(S_VOLUMES.read().get(self.0 - 1).unwrap())
 		}
 	}
 }
@@ -7,7 +7,8 @@ use prelude::*;
 use super::Path;
 use sync::mutex::LazyMutex;
 use lib::byte_str::{ByteStr,ByteString};
-use core::sync::atomic::{self,AtomicUsize};
+use core::sync::atomic::{self,AtomicUsize,AtomicU64};
+use memory::phys::FrameHandle;
 
 pub type InodeId = u64;
 pub type Result<T> = ::core::result::Result<T,super::Error>;
@@ -27,12 +28,41 @@ pub enum NodeClass {
 	Special,
 }
 
+/// Maximum number of symbolic links followed during a single path lookup
+const MAX_SYMLINK_DEPTH: usize = 16;
+
+/// Node metadata, as returned by `NodeBase::get_metadata`
+#[derive(Debug,Default,Clone)]
+pub struct Metadata
+{
+	/// Volume inode number (filled by the VFS)
+	pub inode: InodeId,
+	/// Size of the node's data in bytes
+	pub size: u64,
+	/// Number of directory entries referring to this node
+	pub link_count: u32,
+	/// Unix-style permission bits (`rwxrwxrwx`, plus setuid/setgid/sticky)
+	pub mode: u16,
+	/// Owning user
+	pub uid: u32,
+	/// Owning group
+	pub gid: u32,
+	/// Creation time (if recorded by the filesystem)
+	pub created: Option<::time::Timestamp>,
+	/// Last modification time
+	pub modified: Option<::time::Timestamp>,
+	/// Last access time
+	pub accessed: Option<::time::Timestamp>,
+}
+
 /// Base trait for a VFS node, defines common operation on nodes
 pub trait NodeBase: Send {
 	/// Return the volume's inode number
 	fn get_id(&self) -> InodeId;
 	/// Return an &Any associated with this node (not nessesarily same as `self`, up to the driver)
 	fn get_any(&self) -> &Any;
+	/// Return the node's size, timestamps, and ownership
+	fn get_metadata(&self) -> Metadata;
 }
 /// Trait for "File" nodes
 pub trait File: NodeBase {
@@ -72,7 +102,14 @@ pub trait Dir: NodeBase {
 	/// Create a new name for the provided inode
 	fn link(&self, name: &ByteStr, inode: &NodeBase) -> Result<()>;
 	/// Remove the specified name
+	///
+	/// If the name refers to a directory, the VFS has already checked that it is empty
 	fn unlink(&self, name: &ByteStr) -> Result<()>;
+	/// Atomically move an entry to a new name, possibly in a different directory on this volume
+	///
+	/// `new_dir` is always a directory from the same filesystem (possibly `self`). Fails with
+	/// `AlreadyExists` if `new_name` is already present.
+	fn rename(&self, old_name: &ByteStr, new_dir: &Dir, new_name: &ByteStr) -> Result<()>;
 }
 /// Trait for symbolic link nodes.
 pub trait Symlink: NodeBase {
@@ -101,10 +138,13 @@ pub enum Node
 enum CacheNodeInt
 {
 	File {
-		fsnode: Box<File>
-		
-		// File memory map data
-		//mapped_pages: HashMap<u64,FrameHandle>,
+		fsnode: Box<File>,
+		/// Cached pages of the file's content, shared by all memory mappings
+		mapped_pages: ::sync::Mutex<::lib::VecMap<u64,MappedPage>>,
+		/// Locks held by open handles
+		locks: ::sync::Mutex<FileLocks>,
+		/// Serialises appending writes
+		append_lock: ::sync::Mutex<()>,
 		},
 	Dir {
 		mountpoint: AtomicUsize,	// 0 is invalid (that's root), so means "no mount"
@@ -122,7 +162,7 @@ impl_from!{
 	From<Node>(v) for CacheNodeInt {
 		match v
 		{
-		Node::File(f) => CacheNodeInt::File { fsnode: f },
+		Node::File(f) => CacheNodeInt::File { fsnode: f, mapped_pages: Default::default(), locks: Default::default(), append_lock: Default::default() },
 		Node::Dir(f) => CacheNodeInt::Dir { fsnode: f, mountpoint: AtomicUsize::new(0) },
 		Node::Symlink(f) => CacheNodeInt::Symlink { target: f.read(), fsnode: f },
 		Node::Special(f) => CacheNodeInt::Special { fsnode: f },
@@ -130,31 +170,335 @@ impl_from!{
 	}
 }
 
+/// Lock taken on a file by an open handle (see `handle::FileOpenMode`)
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum FileLock
+{
+	/// File contents must not change (multiple holders, compatible with `Append`)
+	Shared,
+	/// Sole reader/writer (compatible only with `Append`)
+	Exclusive,
+	/// Writes only to the end of the file (multiple holders)
+	Append,
+	/// No synchronisation (multiple holders, incompatible with everything else)
+	Unsynch,
+}
+/// Count of each lock held on a file
+#[derive(Default,Debug)]
+struct FileLocks
+{
+	shared: usize,
+	exclusive: bool,
+	append: usize,
+	unsynch: usize,
+}
+impl FileLocks
+{
+	fn try_acquire(&mut self, lock: FileLock) -> bool {
+		match lock
+		{
+		FileLock::Shared =>
+			if self.exclusive || self.unsynch > 0 {
+				return false;
+			}
+			else {
+				self.shared += 1;
+			},
+		FileLock::Exclusive =>
+			if self.exclusive || self.shared > 0 || self.unsynch > 0 {
+				return false;
+			}
+			else {
+				self.exclusive = true;
+			},
+		FileLock::Append =>
+			if self.unsynch > 0 {
+				return false;
+			}
+			else {
+				self.append += 1;
+			},
+		FileLock::Unsynch =>
+			if self.exclusive || self.shared > 0 || self.append > 0 {
+				return false;
+			}
+			else {
+				self.unsynch += 1;
+			},
+		}
+		true
+	}
+	fn release(&mut self, lock: FileLock) {
+		match lock
+		{
+		FileLock::Shared => { assert!(self.shared > 0); self.shared -= 1; },
+		FileLock::Exclusive => { assert!(self.exclusive); self.exclusive = false; },
+		FileLock::Append => { assert!(self.append > 0); self.append -= 1; },
+		FileLock::Unsynch => { assert!(self.unsynch > 0); self.unsynch -= 1; },
+		}
+	}
+}
+
+/// A page of file content in the node's cache
+struct MappedPage
+{
+	frame: FrameHandle,
+	/// Written through a writeback memory mapping, and not yet flushed to the file
+	dirty: bool,
+}
+
 struct CachedNode
 {
 	refcount: AtomicUsize,
+	/// Current cache key (changes if the node is renamed on a filesystem that identifies nodes by location)
+	inode: AtomicU64,
 	node: CacheNodeInt,
 }
 
 pub struct CacheHandle
 {
 	mountpt: usize,
-	inode: InodeId,
 	ptr: *const CachedNode,
 }
 unsafe impl Sync for CacheHandle {}
 unsafe impl Send for CacheHandle {}
 
 static S_NODE_CACHE: LazyMutex<::lib::VecMap<(usize,InodeId),Box<CachedNode>>> = lazymutex_init!();
+/// Nodes removed from the cache while still open (e.g. unlinked files), freed when the last handle is dropped
+///
+/// NOTE: Always locked after S_NODE_CACHE (if both are held)
+static S_DETACHED_NODES: LazyMutex<Vec<(usize,Box<CachedNode>)>> = lazymutex_init!();
 
 pub fn init()
 {
 	S_NODE_CACHE.init(|| Default::default());
+	S_DETACHED_NODES.init(|| Vec::new());
+	::memory::phys::register_reclaimer(&S_NODE_RECLAIMER);
+}
+
+/// Releases cached file pages that aren't mapped anywhere and don't need to be written back
+struct NodeReclaimer;
+static S_NODE_RECLAIMER: NodeReclaimer = NodeReclaimer;
+impl ::memory::phys::Reclaimer for NodeReclaimer
+{
+	fn name(&self) -> &str {
+		"vfs node cache"
+	}
+	fn reclaim(&self, count: usize) -> usize
+	{
+		let mut rv = 0;
+		// NOTE: Called from within allocations, so only uncontended locks are taken
+		let lh = match S_NODE_CACHE.try_lock() { Some(v) => v, None => return 0 };
+		for (_, cn) in lh.iter()
+		{
+			if rv >= count {
+				break ;
+			}
+			if let CacheNodeInt::File { ref mapped_pages, .. } = cn.node
+			{
+				if let Some(mut pages) = mapped_pages.try_lock()
+				{
+					pages.retain(|_, p| if rv < count && !p.dirty && p.frame.is_unique() {
+							rv += 1;
+							false
+						}
+						else {
+							true
+						});
+				}
+			}
+		}
+		rv
+	}
 }
 
 impl_fmt! {
 	Debug(self, f) for CacheHandle {
-		write!(f, "CacheHandle {{ {}:{:#x} {:p} }}", self.mountpt, self.inode, self.ptr)
+		write!(f, "CacheHandle {{ {}:{:#x} {:p} }}", self.mountpt, self.inode(), self.ptr)
+	}
+}
+
+impl Drop for CacheHandle
+{
+	fn drop(&mut self) {
+		// NOTE: Cached nodes are left in the cache (they're only removed when the volume is released, or when
+		// evicted after an unlink), detached nodes are freed here.
+		// SAFE: self.ptr is valid until the count reaches zero (and isn't dereferenced after that)
+		let prev = unsafe { (*self.ptr).refcount.fetch_sub(1, atomic::Ordering::Release) };
+		if prev == 1 {
+			let ptr = self.ptr;
+			let dead = {
+				let mut lh = S_DETACHED_NODES.lock();
+				// - Pointer compare only, the node may already have been freed by `detach`
+				match lh.iter().position(|&(_, ref cn)| &**cn as *const CachedNode == ptr && cn.refcount.load(atomic::Ordering::Acquire) == 0)
+				{
+				Some(i) => Some(lh.swap_remove(i)),
+				None => None,
+				}
+				};
+			// - Drop the node (which may call into the filesystem) with the list unlocked
+			drop(dead);
+			super::mount::node_released(self.mountpt);
+		}
+	}
+}
+
+/// Remove a node from the cache, so later lookups go to the filesystem
+///
+/// If the node is still open, it's moved to the detached list (and freed when the last handle is dropped)
+fn detach(mountpt: usize, inode: InodeId)
+{
+	let dead = {
+		let mut lh = S_NODE_CACHE.lock();
+		let cn = match lh.remove(&(mountpt, inode))
+			{
+			Some(v) => v,
+			None => return,
+			};
+		let mut dlh = S_DETACHED_NODES.lock();
+		if cn.refcount.load(atomic::Ordering::Acquire) == 0 {
+			Some(cn)
+		}
+		else {
+			dlh.push( (mountpt, cn) );
+			None
+		}
+		};
+	drop(dead);
+}
+/// Remove a node from the cache if it has no open handles
+fn evict_unused(mountpt: usize, inode: InodeId)
+{
+	let dead = {
+		let mut lh = S_NODE_CACHE.lock();
+		let unused = match lh.get(&(mountpt, inode))
+			{
+			Some(cn) => cn.refcount.load(atomic::Ordering::Acquire) == 0,
+			None => false,
+			};
+		if unused { lh.remove(&(mountpt, inode)) } else { None }
+		};
+	drop(dead);
+}
+/// Move a cached node to a new inode number (after a rename changed its ID)
+///
+/// Any node already cached with the new ID is detached.
+fn rekey(mountpt: usize, old_inode: InodeId, new_inode: InodeId)
+{
+	let dead = {
+		let mut lh = S_NODE_CACHE.lock();
+		let cn = match lh.remove(&(mountpt, old_inode))
+			{
+			Some(v) => v,
+			None => return,
+			};
+		let dead = if cn.refcount.load(atomic::Ordering::Acquire) == 0 {
+				// - No open handles, just let it be re-loaded
+				Some(cn)
+			}
+			else {
+				cn.inode.store(new_inode, atomic::Ordering::Relaxed);
+				match lh.insert( (mountpt, new_inode), cn )
+				{
+				Some(prev) => {
+					let mut dlh = S_DETACHED_NODES.lock();
+					if prev.refcount.load(atomic::Ordering::Acquire) == 0 {
+						Some(prev)
+					}
+					else {
+						dlh.push( (mountpt, prev) );
+						None
+					}
+					},
+				None => None,
+				}
+			};
+		dead
+		};
+	drop(dead);
+}
+
+/// Returns true if any node on the specified volume has an open handle
+pub fn volume_in_use(mountpt: usize) -> bool
+{
+	S_NODE_CACHE.lock().iter()
+		.any(|(&(m,_), cn)| m == mountpt && cn.refcount.load(atomic::Ordering::Acquire) > 0)
+	|| S_DETACHED_NODES.lock().iter().any(|&(m, _)| m == mountpt)
+}
+
+/// Remove all of a volume's nodes from the cache, if none are open
+///
+/// `release` is called with the cache locked (so no new handles can be created), and the nodes are only
+/// removed if it returns `Some`.
+pub fn release_volume<R, F: FnOnce()->Option<R>>(mountpt: usize, release: F) -> Option<R>
+{
+	let (rv, nodes) = {
+		let mut lh = S_NODE_CACHE.lock();
+		if lh.iter().any(|(&(m,_), cn)| m == mountpt && cn.refcount.load(atomic::Ordering::Acquire) > 0) {
+			return None;
+		}
+		if S_DETACHED_NODES.lock().iter().any(|&(m, _)| m == mountpt) {
+			return None;
+		}
+		let rv = match release()
+			{
+			Some(v) => v,
+			None => return None,
+			};
+		let keys: Vec<_> = lh.iter().filter(|&(&(m,_), _)| m == mountpt).map(|(&k, _)| k).collect();
+		let nodes: Vec<_> = keys.iter().filter_map(|k| lh.remove(k)).collect();
+		(rv, nodes)
+		};
+	// - Nodes are dropped before the caller drops the filesystem instance
+	drop(nodes);
+	Some(rv)
+}
+
+/// Write modified pages of every cached file on the specified volume back to the file
+pub fn flush_volume(mountpt: usize) -> Result<()>
+{
+	let handles: Vec<CacheHandle> = S_NODE_CACHE.lock().iter()
+		.filter(|&(&(m,_), cn)| m == mountpt && cn.has_dirty_pages())
+		.map(|(&(m,_), cn)| {
+			cn.refcount.fetch_add(1, atomic::Ordering::Relaxed);
+			CacheHandle { mountpt: m, ptr: &**cn }
+			})
+		.collect();
+	for h in handles
+	{
+		try!(h.flush_all());
+	}
+	Ok( () )
+}
+
+/// Copy newly written file data into any cached pages it overlaps
+fn update_cached_pages(mapped_pages: &::sync::Mutex<::lib::VecMap<u64,MappedPage>>, ofs: u64, data: &[u8]) -> Result<()>
+{
+	let mut pos = 0;
+	while pos < data.len()
+	{
+		let file_ofs = ofs + pos as u64;
+		let page = file_ofs / ::PAGE_SIZE as u64;
+		let page_ofs = (file_ofs % ::PAGE_SIZE as u64) as usize;
+		let len = ::core::cmp::min(::PAGE_SIZE - page_ofs, data.len() - pos);
+		let frame = mapped_pages.lock().get(&page).map(|p| p.frame.clone());
+		if let Some(frame) = frame {
+			let mut cpage = try!(::memory::page_cache::S_PAGE_CACHE.map(&frame));
+			cpage.data_mut()[page_ofs ..][.. len].clone_from_slice( &data[pos ..][.. len] );
+		}
+		pos += len;
+	}
+	Ok( () )
+}
+
+impl CachedNode
+{
+	fn has_dirty_pages(&self) -> bool {
+		match self.node
+		{
+		CacheNodeInt::File { ref mapped_pages, .. } => mapped_pages.lock().iter().any(|(_, p)| p.dirty),
+		_ => false,
+		}
 	}
 }
 
@@ -167,7 +511,6 @@ impl Clone for CacheHandle
 		}
 		CacheHandle {
 			mountpt: self.mountpt,
-			inode: self.inode,
 			ptr: self.ptr,
 			}
 	}
@@ -190,7 +533,7 @@ impl CacheHandle
 			Entry::Vacant(e) =>
 				match super::mount::Handle::from_id(mountpoint).get_node(inode)
 				{
-				Some(node) => e.insert(Box::new(CachedNode { node: node.into(), refcount: AtomicUsize::new(1) })),
+				Some(node) => e.insert(Box::new(CachedNode { node: node.into(), inode: AtomicU64::new(inode), refcount: AtomicUsize::new(1) })),
 				None => return Err( super::Error::NotFound ),
 				},
 			};
@@ -202,7 +545,6 @@ impl CacheHandle
 		// - This handles the edge case where a volume is being unmounted
 		let rv = CacheHandle {
 			mountpt: mountpoint,
-			inode: inode,
 			ptr: ptr,
 			};
 
@@ -223,88 +565,127 @@ impl CacheHandle
 	}
 	
 	
-	pub fn from_path_at_node(mut node_h: CacheHandle, path: &Path) -> super::Result<CacheHandle>
+	/// Look up a path relative to a node (absolute paths are also relative to the node), following symbolic links
+	pub fn from_path_at_node(node_h: CacheHandle, path: &Path) -> super::Result<CacheHandle>
 	{
 		log_function!("CacheHandle::from_path_at_node(node_h={:?}, {:?})", node_h, path);
-		let path = if path.is_absolute() {
-				try!(path.split_off_first().ok_or(super::Error::MalformedPath)).1
-			}
-			else {
-				path
-			};
-		// Iterate path components
-		for seg in path
+		Self::lookup_at_node(node_h, path, true)
+	}
+	/// Look up a path relative to a node, without following a symbolic link in the final component
+	pub fn from_path_at_node_nofollow(node_h: CacheHandle, path: &Path) -> super::Result<CacheHandle>
+	{
+		log_function!("CacheHandle::from_path_at_node_nofollow(node_h={:?}, {:?})", node_h, path);
+		Self::lookup_at_node(node_h, path, false)
+	}
+
+	/// Obtain a node handle using a path
+	pub fn from_path(path: &Path) -> super::Result<CacheHandle>
+	{
+		log_function!("CacheHandle::from_path({:?})", path);
+		// TODO: Support path caching?
+		let root = try!(Self::root_for_path(path));
+		Self::lookup_at_node(root, path, true)
+	}
+	/// Obtain a node handle using a path, without following a symbolic link in the final component
+	pub fn from_path_nofollow(path: &Path) -> super::Result<CacheHandle>
+	{
+		log_function!("CacheHandle::from_path_nofollow({:?})", path);
+		let root = try!(Self::root_for_path(path));
+		Self::lookup_at_node(root, path, false)
+	}
+
+	/// Check that a path is absolute, and acquire the root node
+	fn root_for_path(path: &Path) -> super::Result<CacheHandle>
+	{
+		if !path.is_absolute() {
+			return Err(super::Error::MalformedPath);
+		}
+		let mph = super::mount::Handle::from_id(0);
+		CacheHandle::from_ids( mph.id(), mph.root_inode() )
+	}
+
+	fn lookup_at_node(node_h: CacheHandle, path: &Path, follow_last: bool) -> super::Result<CacheHandle>
+	{
+		let mut link_depth = 0;
+		let mut stack = try!(Self::walk_path(vec![node_h], path, follow_last, &mut link_depth));
+		let rv = stack.pop().expect("walk_path returned an empty stack");
+		log_trace!("CacheHandle::lookup_at_node() {:?}", rv);
+		Ok( rv )
+	}
+
+	/// Walk `path` starting at the last node of `stack`
+	///
+	/// `stack` is the chain of nodes from the start of the lookup (used to resolve `..` and relative symbolic
+	/// links). The first node acts as the root of the walk (`..` can't leave it, and absolute paths are relative to it).
+	fn walk_path(mut stack: Vec<CacheHandle>, path: &Path, follow_last: bool, link_depth: &mut usize) -> super::Result<Vec<CacheHandle>>
+	{
+		if path.is_absolute() {
+			stack.truncate(1);
+		}
+		let mut it = path.iter().peekable();
+		while let Some(seg) = it.next()
 		{
+			let is_last = it.peek().is_none();
 			log_trace!("seg = {:?}", seg);
-			// Loop resolving symbolic links
-			loop
-			{
-				// TODO: Should symlinks be handled in this function? Or should the passed path be without symlinks?
-				node_h = if let CacheNodeInt::Symlink { ref target, .. } = *node_h.as_ref() {
-					//log_debug!("- seg={:?} : SYMLINK {:?}", seg, name);
-					let linkpath = Path::new(&target);
-					if linkpath.is_absolute() {
-						try!(CacheHandle::from_path(linkpath))
-					}
-					else {
-						//TODO: To make this work (or any path-relative symlink), the current position in
-						//      `path` needs to be known.
-						// It can be hacked up though...
-						let parent_segs_len = seg.as_ref().as_ptr() as usize - AsRef::<[u8]>::as_ref(path).as_ptr() as usize;
-						let parent = ByteStr::new( &AsRef::<[u8]>::as_ref(path)[..parent_segs_len] );
-
-						//let segs = [ &path[..pos], &link ];
-						//let p = PathChain::new(&segs);
-						//try!( CacheHandle::from_path(p) )
-						// Recurse with a special chained path type
-						// (that iterates but can't be sliced).
-						todo!("Relative symbolic links {:?} relative to {:?}", target, parent)
-					}
+			if seg == "" || seg == "." {
+				continue ;
+			}
+			if seg == ".." {
+				if stack.len() > 1 {
+					stack.pop();
 				}
-				else {
-					break;
-				};
+				continue ;
 			}
 
 			// Look up this component in the current node
-			node_h = match *node_h.as_ref()
+			let next_h = {
+				let cur_h = stack.last().expect("walk_path with an empty stack");
+				match *cur_h.as_ref()
 				{
 				CacheNodeInt::Dir { fsnode: ref dir, .. } => {
-					//log_debug!("- seg={:?} : DIR", seg);
-					let next_id = match dir.lookup(seg)
-						{
-						Ok(v) => v,
-						Err(_) => return Err(super::Error::NotFound),
-						};
-					try!(CacheHandle::from_ids( node_h.mountpt, next_id ))
+					let next_id = try!(dir.lookup(seg));
+					try!(CacheHandle::from_ids( cur_h.mountpt, next_id ))
 					},
 				_ => return Err(super::Error::NonDirComponent),
+				}
 				};
-		}
-		log_trace!("CacheHandle::from_path_at_node() {:?}", node_h);
-		Ok( node_h )
-	}
 
-	/// Obtain a node handle using a path
-	pub fn from_path(path: &Path) -> super::Result<CacheHandle>
-	{
-		log_function!("CacheHandle::from_path({:?})", path);
-		// TODO: Support path caching?
-		
-		// - Remove the leading / from the absolute path
-		//  > Also checks that it's actually abolsute
-		let (first_comp, path) = try!( path.split_off_first().ok_or(super::Error::MalformedPath) );
-		if first_comp.len() != 0 {
-			return Err(super::Error::MalformedPath);
+			if let CacheNodeInt::Symlink { ref target, .. } = *next_h.as_ref()
+			{
+				if is_last && !follow_last {
+					stack.push(next_h.clone());
+					continue ;
+				}
+				*link_depth += 1;
+				if *link_depth > MAX_SYMLINK_DEPTH {
+					return Err(super::Error::RecursionDepthExceeded);
+				}
+				let linkpath = Path::new(target);
+				log_trace!("- seg={:?} : SYMLINK {:?}", seg, linkpath);
+				// Relative links are resolved from the directory containing the link, absolute links from the root of
+				// this walk (so a lookup through a directory handle can't escape that directory).
+				stack = try!(Self::walk_path(stack, linkpath, true, link_depth));
+				continue ;
+			}
+			stack.push(next_h);
 		}
-
-		// Acquire the root vnode
-		let mph = super::mount::Handle::from_id(0);
-		let node_h = try!(CacheHandle::from_ids( mph.id(), mph.root_inode() ));
-
-		CacheHandle::from_path_at_node(node_h, path)
+		Ok( stack )
 	}
 	
+	/// ID of the mounted volume this node is on
+	pub fn mount_id(&self) -> usize {
+		self.mountpt
+	}
+	/// Inode number of this node (within its volume)
+	pub fn inode(&self) -> InodeId {
+		// SAFE: The pointer is valid while this handle exists
+		unsafe { (*self.ptr).inode.load(atomic::Ordering::Relaxed) }
+	}
+	/// Options of the volume this node is on
+	pub fn mount_options(&self) -> super::mount::MountOptions {
+		super::mount::Handle::from_id(self.mountpt).options()
+	}
+
 	pub fn get_class(&self) -> NodeClass {
 		match self.as_ref()
 		{
@@ -333,6 +714,19 @@ impl CacheHandle
 		&CacheNodeInt::Symlink { ref fsnode, .. } => fsnode.get_any(),
 		}
 	}
+
+	pub fn get_metadata(&self) -> Metadata {
+		let mut rv = match self.as_ref()
+			{
+			&CacheNodeInt::Dir { ref fsnode, .. } => fsnode.get_metadata(),
+			&CacheNodeInt::File { ref fsnode, .. } => fsnode.get_metadata(),
+			&CacheNodeInt::Special { ref fsnode, .. } => fsnode.get_metadata(),
+			&CacheNodeInt::Symlink { ref fsnode, .. } => fsnode.get_metadata(),
+			};
+		// - Drivers don't nessesarily know their own inode number (it's the VFS's cache key)
+		rv.inode = self.inode();
+		rv
+	}
 }
 /// Directory methods
 impl CacheHandle
@@ -341,12 +735,114 @@ impl CacheHandle
 		match self.as_ref()
 		{
 		&CacheNodeInt::Dir { ref fsnode, .. } => {
+			if self.mount_options().read_only {
+				return Err( super::Error::ReadOnlyFilesystem );
+			}
+			match fsnode.lookup(name)
+			{
+			Ok(_) => return Err( super::Error::AlreadyExists ),
+			Err(super::Error::NotFound) => {},
+			Err(e) => return Err(e),
+			}
 			let inode = try!(fsnode.create(name, ty));
 			Ok( try!(CacheHandle::from_ids(self.mountpt, inode)) )
 			},
 		_ => Err( super::Error::Unknown("Calling create on non-directory") ),
 		}
 	}
+	/// Remove a non-directory entry
+	pub fn unlink(&self, name: &ByteStr) -> super::Result<()> {
+		self.remove_entry(name, false)
+	}
+	/// Remove an (empty) directory
+	pub fn rmdir(&self, name: &ByteStr) -> super::Result<()> {
+		self.remove_entry(name, true)
+	}
+	fn remove_entry(&self, name: &ByteStr, is_dir: bool) -> super::Result<()> {
+		match self.as_ref()
+		{
+		&CacheNodeInt::Dir { ref fsnode, .. } => {
+			if self.mount_options().read_only {
+				return Err( super::Error::ReadOnlyFilesystem );
+			}
+			if name == "" || name == "." || name == ".." {
+				return Err( super::Error::InvalidParameter );
+			}
+			// - Check the type of the child (and that directories are empty and not in use as a mountpoint)
+			let inode = try!(fsnode.lookup(name));
+			let child = try!(CacheHandle::from_ids(self.mountpt, inode));
+			if child.is_dir() != is_dir {
+				return Err( super::Error::TypeMismatch );
+			}
+			if is_dir {
+				if child.mount_id() != self.mountpt || child.is_mountpoint() {
+					return Err( super::Error::Locked );
+				}
+				let mut has_ents = false;
+				try!(child.read_dir(0, &mut |_, _| { has_ents = true; false }));
+				if has_ents {
+					return Err( super::Error::NotEmpty );
+				}
+			}
+			try!(fsnode.unlink(name));
+
+			// - Drop the cached node if this was its last link, so the inode isn't found again once the
+			//   filesystem reuses it (open handles keep the node alive until they're closed)
+			let is_dead = is_dir || child.get_metadata().link_count == 0;
+			drop(child);
+			if is_dead {
+				detach(self.mountpt, inode);
+			}
+			else {
+				evict_unused(self.mountpt, inode);
+			}
+			Ok( () )
+			},
+		_ => Err( super::Error::Unknown("Calling unlink on non-directory") ),
+		}
+	}
+	/// Move the entry `old_name` in this directory to `new_name` in `new_dir`
+	pub fn rename(&self, old_name: &ByteStr, new_dir: &CacheHandle, new_name: &ByteStr) -> super::Result<()> {
+		match (self.as_ref(), new_dir.as_ref())
+		{
+		(&CacheNodeInt::Dir { ref fsnode, .. }, &CacheNodeInt::Dir { fsnode: ref new_fsnode, .. }) => {
+			if self.mountpt != new_dir.mountpt {
+				return Err( super::Error::CrossFilesystem );
+			}
+			if self.mount_options().read_only {
+				return Err( super::Error::ReadOnlyFilesystem );
+			}
+			for &n in &[old_name, new_name] {
+				if n == "" || n == "." || n == ".." {
+					return Err( super::Error::InvalidParameter );
+				}
+			}
+			let old_inode = try!(fsnode.lookup(old_name));
+			{
+				let child = try!(CacheHandle::from_ids(self.mountpt, old_inode));
+				if child.mount_id() != self.mountpt || child.is_mountpoint() {
+					return Err( super::Error::Locked );
+				}
+				// - A directory can't be moved into itself or one of its descendants (that would detach the subtree)
+				if child.is_dir() && new_dir.inode() != self.inode() && try!(child.subtree_contains(new_dir.inode())) {
+					return Err( super::Error::InvalidParameter );
+				}
+			}
+			try!(fsnode.rename(old_name, &**new_fsnode, new_name));
+
+			// - Some filesystems (e.g. FAT) identify nodes by their location, so the cached node has to move
+			match new_fsnode.lookup(new_name)
+			{
+			Ok(new_inode) => if new_inode != old_inode {
+				rekey(self.mountpt, old_inode, new_inode);
+				},
+			Err(_) => detach(self.mountpt, old_inode),
+			}
+			Ok( () )
+			},
+		_ => Err( super::Error::Unknown("Calling rename on non-directory") ),
+		}
+	}
 	pub fn read_dir(&self, ofs: usize, items: &mut ReadDirCallback) -> super::Result<usize> {
 		match self.as_ref()
 		{
@@ -354,6 +850,35 @@ impl CacheHandle
 		_ => Err( super::Error::Unknown("Calling read_dir on non-directory") ),
 		}
 	}
+	/// Returns true if `target` is this directory or one of its descendants (on the same volume)
+	fn subtree_contains(&self, target: InodeId) -> super::Result<bool> {
+		let mut pending = vec![self.clone()];
+		while let Some(dir) = pending.pop()
+		{
+			if dir.inode() == target {
+				return Ok(true);
+			}
+			// - Collect the children first, so the directory isn't being read while they're opened
+			let mut children = Vec::new();
+			let mut ofs = 0;
+			loop
+			{
+				let next = try!(dir.read_dir(ofs, &mut |inode, _name| { children.push(inode); true }));
+				if next == ofs {
+					break ;
+				}
+				ofs = next;
+			}
+			for inode in children
+			{
+				let child = try!(CacheHandle::from_ids(dir.mountpt, inode));
+				if child.is_dir() {
+					pending.push(child);
+				}
+			}
+		}
+		Ok(false)
+	}
 	pub fn open_child(&self, name: &ByteStr) -> super::Result<CacheHandle> {
 		match self.as_ref()
 		{
@@ -387,6 +912,16 @@ impl CacheHandle
 		_ => false,
 		}
 	}
+	/// Returns `true` if `filesystem_id` was mounted here (and has been unbound)
+	pub fn unmount(&self, filesystem_id: usize) -> bool {
+		match self.as_ref()
+		{
+		&CacheNodeInt::Dir { ref mountpoint, .. } => {
+			mountpoint.compare_and_swap(filesystem_id, 0, atomic::Ordering::Relaxed) == filesystem_id
+			},
+		_ => false,
+		}
+	}
 }
 /// Normal file methods
 impl CacheHandle
@@ -406,6 +941,167 @@ impl CacheHandle
 		_ => Err( super::Error::Unknown("Calling read on non-file") ),
 		}
 	}
+	/// Write data to the file, extending it if the write reaches past the end
+	///
+	/// Returns the number of bytes written
+	pub fn write(&self, ofs: u64, src: &[u8]) -> super::Result<usize> {
+		match self.as_ref()
+		{
+		&CacheNodeInt::File { ref fsnode, ref mapped_pages, .. } => {
+			let options = self.mount_options();
+			if options.read_only {
+				return Err( super::Error::ReadOnlyFilesystem );
+			}
+			let size = fsnode.size();
+			// - Writes starting past the end zero-fill the gap first
+			if ofs > size {
+				try!(fsnode.truncate(ofs));
+			}
+			// - Drivers only extend from the end of the file, so the in-place and extending portions are written separately
+			let inplace = if ofs < size { ::core::cmp::min(size - ofs, src.len() as u64) as usize } else { 0 };
+			let mut written = 0;
+			if inplace > 0 {
+				written += try!(fsnode.write(ofs, &src[..inplace]));
+			}
+			if written == inplace && inplace < src.len() {
+				written += try!(fsnode.write(ofs + inplace as u64, &src[inplace..]));
+			}
+			// - Keep the cached pages (shared with memory mappings) coherent
+			try!(update_cached_pages(mapped_pages, ofs, &src[..written]));
+			if options.sync {
+				try!(super::mount::Handle::from_id(self.mountpt).sync_fs());
+			}
+			Ok(written)
+			},
+		_ => Err( super::Error::Unknown("Calling write on non-file") ),
+		}
+	}
+	/// Atomically write data to the end of the file
+	pub fn append(&self, src: &[u8]) -> super::Result<usize> {
+		match self.as_ref()
+		{
+		&CacheNodeInt::File { ref append_lock, .. } => {
+			let _lh = append_lock.lock();
+			self.write(self.get_valid_size(), src)
+			},
+		_ => Err( super::Error::Unknown("Calling append on non-file") ),
+		}
+	}
+
+	/// Acquire a lock on the file (fails with `Locked` if it conflicts with an existing lock)
+	pub fn lock_file(&self, lock: FileLock) -> super::Result<()> {
+		match self.as_ref()
+		{
+		&CacheNodeInt::File { ref locks, .. } =>
+			if locks.lock().try_acquire(lock) {
+				Ok( () )
+			}
+			else {
+				Err( super::Error::Locked )
+			},
+		_ => Err( super::Error::Unknown("Calling lock_file on non-file") ),
+		}
+	}
+	/// Release a lock acquired with `lock_file`
+	pub fn unlock_file(&self, lock: FileLock) {
+		match self.as_ref()
+		{
+		&CacheNodeInt::File { ref locks, .. } => locks.lock().release(lock),
+		_ => panic!("Calling unlock_file on non-file"),
+		}
+	}
+
+	/// Obtain a handle to the (shared) frame containing the specified page of the file
+	pub fn get_page(&self, page: u64) -> super::Result<FrameHandle> {
+		match self.as_ref()
+		{
+		&CacheNodeInt::File { ref fsnode, ref mapped_pages, .. } => {
+			// 1. Search the cache for this page
+			if let Some(p) = mapped_pages.lock().get(&page) {
+				return Ok( p.frame.clone() );
+			}
+			// 2. Read data from the file into a new frame (without the lock held)
+			let mut cpage = try!(::memory::page_cache::S_PAGE_CACHE.create());
+			let len = try!(fsnode.read(page * ::PAGE_SIZE as u64, cpage.data_mut()));
+			for b in cpage.data_mut()[len..].iter_mut() {
+				*b = 0;
+			}
+			// 3. Insert, unless another caller got there first
+			use lib::vec_map::Entry;
+			Ok(match mapped_pages.lock().entry(page)
+				{
+				Entry::Occupied(e) => e.into_mut().frame.clone(),
+				Entry::Vacant(e) => e.insert( MappedPage { frame: cpage.get_frame_handle(), dirty: false } ).frame.clone(),
+				})
+			},
+		_ => Err( super::Error::Unknown("Calling get_page on non-file") ),
+		}
+	}
+	/// Flag a cached page as modified (it will be written back by `flush_pages`)
+	pub fn mark_dirty(&self, page: u64) {
+		match self.as_ref()
+		{
+		&CacheNodeInt::File { ref mapped_pages, .. } =>
+			if let Some(p) = mapped_pages.lock().get_mut(&page) {
+				p.dirty = true;
+			},
+		_ => {},
+		}
+	}
+	/// Write modified cached pages in the range `first .. first+count` back to the file
+	///
+	/// Only data before the current end of the file is written (the file is never extended)
+	pub fn flush_pages(&self, first: u64, count: u64) -> super::Result<()> {
+		match self.as_ref()
+		{
+		&CacheNodeInt::File { ref fsnode, ref mapped_pages, .. } => {
+			let mut written = false;
+			for page in first .. first + count
+			{
+				// - Take the dirty flag with the lock held, and write with it released
+				let frame = match mapped_pages.lock().get_mut(&page)
+					{
+					Some(p) => if p.dirty {
+							p.dirty = false;
+							p.frame.clone()
+						}
+						else {
+							continue
+						},
+					None => continue,
+					};
+				let ofs = page * ::PAGE_SIZE as u64;
+				let size = fsnode.size();
+				if ofs >= size {
+					continue ;
+				}
+				let len = ::core::cmp::min(::PAGE_SIZE as u64, size - ofs) as usize;
+				let cpage = try!(::memory::page_cache::S_PAGE_CACHE.map(&frame));
+				try!(fsnode.write(ofs, &cpage.data()[..len]));
+				written = true;
+			}
+			// - Volumes mounted `sync` have the filesystem's caches written out too
+			if written && self.mount_options().sync {
+				try!(super::mount::Handle::from_id(self.mountpt).sync_fs());
+			}
+			Ok( () )
+			},
+		_ => Err( super::Error::Unknown("Calling flush_pages on non-file") ),
+		}
+	}
+	/// Write all modified cached pages back to the file
+	pub fn flush_all(&self) -> super::Result<()> {
+		let pages: Vec<u64> = match self.as_ref()
+			{
+			&CacheNodeInt::File { ref mapped_pages, .. } => mapped_pages.lock().iter().filter(|&(_, p)| p.dirty).map(|(&k, _)| k).collect(),
+			_ => return Ok( () ),
+			};
+		for page in pages
+		{
+			try!(self.flush_pages(page, 1));
+		}
+		Ok( () )
+	}
 }
 
 
@@ -424,13 +1120,9 @@ impl CacheHandle
 impl CacheHandle
 {
 	fn as_ref(&self) -> &CacheNodeInt {
-		let lh = S_NODE_CACHE.lock();
-		// SAFE: While this handle is active, the box will be present
+		// SAFE: While this handle is active, the box will be present (either in the cache, or on the detached list)
 		unsafe {
-			let box_r = lh.get( &(self.mountpt, self.inode) ).expect("Cached node with open handle absent");
-			let cn_ref: &CachedNode = &**box_r;
-			// Dereference a raw pointer to disconnect the lifetimes
-			&*(&cn_ref.node as *const _)
+			&(*self.ptr).node
 		}
 	}
 }
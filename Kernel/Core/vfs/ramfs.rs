@@ -1,22 +1,24 @@
 // "Tifflin" Kernel
 // - By John Hodge (thePowersGang)
 //
-// Core/vfs/mod.rs
-//! Virtual File System
+// Core/vfs/ramfs.rs
+//! In-memory filesystem (used for the root, and scratch mounts like `/temp`)
 use prelude::*;
 use vfs;
 use super::{mount, node};
 use metadevs::storage::VolumeHandle;
-use lib::{VecMap,SparseVec};
+use lib::VecMap;
 use lib::byte_str::{ByteStr,ByteString};
-use lib::mem::aref::{Aref,ArefInner,ArefBorrow};
+use lib::mem::Arc;
+use lib::mem::aref::{ArefInner,ArefBorrow};
+use core::sync::atomic::{AtomicUsize,Ordering};
 
 pub struct Driver;
 pub static S_DRIVER: Driver = Driver;
 
 enum RamFile
 {
-	//File(RamFileFile),
+	File(RamFileFile),
 	Dir(RamFileDir),
 	Symlink(RamFileSymlink),
 }
@@ -30,13 +32,29 @@ struct RamFileSymlink
 {
 	target: super::PathBuf,
 }
-//#[derive(Default)]
-//struct RamFileFile
-//{
-//	ofs: usize,
-//	size: usize,
-//}
-struct FileRef(ArefBorrow<RamFSInner>,ArefBorrow<RamFile>);
+/// Regular file, stored as a sparse map of page-sized chunks
+struct RamFileFile
+{
+	usage: Arc<PageUsage>,
+	data: ::sync::RwLock<RamFileData>,
+}
+#[derive(Default)]
+struct RamFileData
+{
+	size: u64,
+	/// Pages of file data, keyed by page index (missing pages are all zeroes)
+	pages: VecMap<u64,Box<[u8]>>,
+}
+/// Page accounting for a filesystem instance
+///
+/// Shared with the files, so that an unlinked file's pages are only released once the last handle is closed.
+struct PageUsage
+{
+	/// Maximum number of pages (0 = unbounded)
+	max: usize,
+	used: AtomicUsize,
+}
+struct FileRef(ArefBorrow<RamFSInner>, Arc<RamFile>, node::InodeId);
 
 struct RamFS
 {
@@ -45,9 +63,23 @@ struct RamFS
 struct RamFSInner
 {
 	_vh: VolumeHandle,
+	usage: Arc<PageUsage>,
 	// TODO: Store as much data (and metadata) as possible on the volume
 	// - Possibly by using an allocation pool backed onto the volume
-	nodes: ::sync::Mutex< SparseVec<Aref<RamFile>> >,
+	nodes: ::sync::Mutex<RamNodes>,
+}
+#[derive(Default)]
+struct RamNodes
+{
+	/// Inode numbers are never reused (the node cache can still hold an unlinked node)
+	next_inode: usize,
+	ents: VecMap<usize,NodeEnt>,
+}
+struct NodeEnt
+{
+	/// Number of directory entries referring to this node
+	links: usize,
+	node: Arc<RamFile>,
 }
 
 pub fn init()
@@ -63,14 +95,21 @@ impl mount::Driver for Driver
 		Ok(0)
 	}
 	fn mount(&self, vol: VolumeHandle, _: mount::SelfHandle) -> super::Result<Box<mount::Filesystem>> {
+		// The volume's capacity is used as the size limit
+		let max_pages = match vol.capacity()
+			{
+			Some(blocks) => ((blocks * vol.block_size() as u64 + ::PAGE_SIZE as u64 - 1) / ::PAGE_SIZE as u64) as usize,
+			None => 0,
+			};
 		let rv = Box::new(RamFS {
 			// SAFE: ArefInner must not change addresses, but because you can't move out of a boxed trait, we're good
 			inner: unsafe { ArefInner::new( RamFSInner {
 				_vh: vol,
+				usage: Arc::new(PageUsage { max: max_pages, used: AtomicUsize::new(0) }),
 				nodes: Default::default(),
 				}) },
 			});
-		let root_inode = rv.inner.nodes.lock().insert( Aref::new(RamFile::Dir(Default::default())) );
+		let root_inode = rv.inner.add_node( RamFile::Dir(Default::default()) );
 		assert_eq!(root_inode, 0);
 		Ok(rv)
 	}
@@ -84,22 +123,77 @@ impl mount::Filesystem for RamFS
 	fn get_node_by_inode(&self, id: node::InodeId) -> Option<node::Node> {
 		log_trace!("RamFS::get_node_by_inode({})", id);
 		let nodes = self.inner.nodes.lock();
-		if id >= nodes.len() as node::InodeId {
-			log_log!("RamFile::get_node_by_inode - Inode {} out of range", id);
-			None
-		}
-		else {
-			let fr = Box::new(FileRef(
-				self.inner.borrow(),
-				nodes[id as usize].borrow()
-				));
-			match *nodes[id as usize]
+		let ent = match nodes.ents.get(&(id as usize))
 			{
-			RamFile::Dir(_) => Some(node::Node::Dir(fr)),
-			RamFile::Symlink(_) => Some(node::Node::Symlink(fr)),
-			//RamFile::File(_) => todo!("normal files"),
-			}
+			Some(v) => v,
+			None => {
+				log_log!("RamFile::get_node_by_inode - Inode {} not present", id);
+				return None;
+				},
+			};
+		let fr = Box::new(FileRef( self.inner.borrow(), ent.node.clone(), id ));
+		match *ent.node
+		{
+		RamFile::Dir(_) => Some(node::Node::Dir(fr)),
+		RamFile::Symlink(_) => Some(node::Node::Symlink(fr)),
+		RamFile::File(_) => Some(node::Node::File(fr)),
+		}
+	}
+}
+
+impl RamFSInner
+{
+	/// Register a new node (with a single link), returning its inode number
+	fn add_node(&self, node: RamFile) -> usize {
+		let mut lh = self.nodes.lock();
+		let inode = lh.next_inode;
+		lh.next_inode += 1;
+		lh.ents.insert(inode, NodeEnt { links: 1, node: Arc::new(node) });
+		inode
+	}
+	/// Remove a link to a node, dropping the node once the last link is gone
+	fn release_link(&self, inode: usize) {
+		let mut lh = self.nodes.lock();
+		let remove = match lh.ents.get_mut(&inode)
+			{
+			Some(e) => { e.links -= 1; e.links == 0 },
+			None => panic!("ramfs: Directory entry refers to missing inode {}", inode),
+			};
+		if remove {
+			lh.ents.remove(&inode);
+		}
+	}
+}
+
+impl PageUsage
+{
+	fn alloc_page(&self) -> vfs::Result<Box<[u8]>> {
+		let prev = self.used.fetch_add(1, Ordering::Relaxed);
+		if self.max != 0 && prev >= self.max {
+			self.used.fetch_sub(1, Ordering::Relaxed);
+			return Err(vfs::Error::OutOfSpace);
 		}
+		Ok( vec![0u8; ::PAGE_SIZE].into_boxed_slice() )
+	}
+	fn release_pages(&self, count: usize) {
+		self.used.fetch_sub(count, Ordering::Relaxed);
+	}
+}
+
+impl RamFileFile
+{
+	fn new(usage: Arc<PageUsage>) -> RamFileFile {
+		RamFileFile {
+			usage: usage,
+			data: Default::default(),
+			}
+	}
+}
+impl Drop for RamFileFile
+{
+	fn drop(&mut self) {
+		let count = self.data.write().pages.iter().count();
+		self.usage.release_pages(count);
 	}
 }
 
@@ -118,14 +212,38 @@ impl FileRef {
 		_ => panic!("Called FileRef::symlink() on non-symlink"),
 		}
 	}
+	fn file(&self) -> &RamFileFile {
+		match &*self.1
+		{
+		&RamFile::File(ref e) => e,
+		_ => panic!("Called FileRef::file() on non-file"),
+		}
+	}
+	fn is_same_fs(&self, other: &FileRef) -> bool {
+		&*self.0 as *const RamFSInner == &*other.0 as *const RamFSInner
+	}
 }
 impl node::NodeBase for FileRef {
 	fn get_id(&self) -> node::InodeId {
-		unimplemented!()
+		self.2
 	}
 	fn get_any(&self) -> &::core::any::Any {
 		self
 	}
+	fn get_metadata(&self) -> node::Metadata {
+		// TODO: Record timestamps (needs a wall clock)
+		node::Metadata {
+			size: match &*self.1
+				{
+				&RamFile::Dir(_) => 0,
+				&RamFile::Symlink(ref e) => AsRef::<[u8]>::as_ref(&*e.target).len() as u64,
+				&RamFile::File(ref e) => e.data.read().size,
+				},
+			link_count: self.0.nodes.lock().ents.get(&(self.2 as usize)).map(|e| e.links).unwrap_or(0) as u32,
+			mode: 0o777,
+			..Default::default()
+			}
+	}
 }
 impl node::Dir for FileRef {
 	fn lookup(&self, name: &ByteStr) -> vfs::Result<node::InodeId> {
@@ -136,7 +254,7 @@ impl node::Dir for FileRef {
 		None => Err(vfs::Error::NotFound),
 		}
 	}
-	
+
 	fn read(&self, start_ofs: usize, callback: &mut node::ReadDirCallback) -> node::Result<usize> {
 		let lh = self.dir().ents.read();
 		let mut count = 0;
@@ -150,9 +268,12 @@ impl node::Dir for FileRef {
 		}
 		Ok(start_ofs + count)
 	}
-	
+
 	fn create(&self, name: &ByteStr, nodetype: node::NodeType) -> vfs::Result<node::InodeId> {
 		use lib::vec_map::Entry;
+		if name == "" {
+			return Err(vfs::Error::InvalidParameter);
+		}
 		let mut lh = self.dir().ents.write();
 		match lh.entry(From::from(name))
 		{
@@ -161,22 +282,100 @@ impl node::Dir for FileRef {
 			let nn = match nodetype
 				{
 				node::NodeType::Dir  => RamFile::Dir (Default::default()),
-				//node::NodeType::File => RamFile::File(Default::default()),
-				node::NodeType::File => return Err(vfs::Error::Unknown("TODO: Files")),
+				node::NodeType::File => RamFile::File(RamFileFile::new(self.0.usage.clone())),
 				node::NodeType::Symlink(v) =>
 					RamFile::Symlink(RamFileSymlink{target: From::from(v)}),
 				};
-			let inode = self.0.nodes.lock().insert( Aref::new(nn) );
+			let inode = self.0.add_node(nn);
 			e.insert(inode);
 			Ok(inode as node::InodeId)
 			},
 		}
 	}
 	fn link(&self, name: &ByteStr, node: &node::NodeBase) -> vfs::Result<()> {
-		todo!("<FileRef as Dir>::link({:?}, inode={})", name, node.get_id())
+		use lib::vec_map::Entry;
+		let other: &FileRef = match node.get_any().downcast_ref()
+			{
+			Some(v) => v,
+			None => return Err(vfs::Error::CrossFilesystem),
+			};
+		if !self.is_same_fs(other) {
+			return Err(vfs::Error::CrossFilesystem);
+		}
+		if let RamFile::Dir(_) = *other.1 {
+			// Directories can only have one name
+			return Err(vfs::Error::TypeMismatch);
+		}
+		let mut lh = self.dir().ents.write();
+		match lh.entry(From::from(name))
+		{
+		Entry::Occupied(_) => Err(vfs::Error::AlreadyExists),
+		Entry::Vacant(e) => {
+			let inode = other.2 as usize;
+			match self.0.nodes.lock().ents.get_mut(&inode)
+			{
+			Some(ent) => ent.links += 1,
+			// The node was unlinked while open
+			None => return Err(vfs::Error::NotFound),
+			}
+			e.insert(inode);
+			Ok( () )
+			},
+		}
 	}
 	fn unlink(&self, name: &ByteStr) -> vfs::Result<()> {
-		todo!("<FileRef as Dir>::unlink({:?})", name)
+		let mut lh = self.dir().ents.write();
+		match lh.remove(&ByteString::from(name))
+		{
+		Some(inode) => {
+			self.0.release_link(inode);
+			Ok( () )
+			},
+		None => Err(vfs::Error::NotFound),
+		}
+	}
+	fn rename(&self, old_name: &ByteStr, new_dir: &node::Dir, new_name: &ByteStr) -> vfs::Result<()> {
+		let new_dir: &FileRef = match new_dir.get_any().downcast_ref()
+			{
+			Some(v) => v,
+			None => return Err(vfs::Error::CrossFilesystem),
+			};
+		if !self.is_same_fs(new_dir) {
+			return Err(vfs::Error::CrossFilesystem);
+		}
+		if new_name == "" {
+			return Err(vfs::Error::InvalidParameter);
+		}
+		let old_key = ByteString::from(old_name);
+		let new_key = ByteString::from(new_name);
+
+		if self.2 == new_dir.2
+		{
+			let mut lh = self.dir().ents.write();
+			if lh.get(new_name).is_some() {
+				return Err(vfs::Error::AlreadyExists);
+			}
+			let inode = try!(lh.remove(&old_key).ok_or(vfs::Error::NotFound));
+			lh.insert(new_key, inode);
+		}
+		else
+		{
+			// Lock both directories (in inode order, to avoid deadlocking with a reverse rename)
+			let (mut src_lh, mut dst_lh) = if self.2 < new_dir.2 {
+					let a = self.dir().ents.write();
+					(a, new_dir.dir().ents.write())
+				}
+				else {
+					let b = new_dir.dir().ents.write();
+					(self.dir().ents.write(), b)
+				};
+			if dst_lh.get(new_name).is_some() {
+				return Err(vfs::Error::AlreadyExists);
+			}
+			let inode = try!(src_lh.remove(&old_key).ok_or(vfs::Error::NotFound));
+			dst_lh.insert(new_key, inode);
+		}
+		Ok( () )
 	}
 }
 impl node::Symlink for FileRef {
@@ -184,4 +383,113 @@ impl node::Symlink for FileRef {
 		ByteString::from( ByteStr::new(&*self.symlink().target) )
 	}
 }
-
+impl node::File for FileRef {
+	fn size(&self) -> u64 {
+		self.file().data.read().size
+	}
+	fn truncate(&self, newsize: u64) -> vfs::Result<u64> {
+		let file = self.file();
+		let mut lh = file.data.write();
+		if newsize < lh.size {
+			// Release pages past the new end, and zero the tail of the last partial page
+			let keep_pages = (newsize + ::PAGE_SIZE as u64 - 1) / ::PAGE_SIZE as u64;
+			let count = lh.pages.iter().filter(|&(&k,_)| k >= keep_pages).count();
+			if count > 0 {
+				lh.pages.retain(|&k,_| k < keep_pages);
+				file.usage.release_pages(count);
+			}
+			let tail_ofs = (newsize % ::PAGE_SIZE as u64) as usize;
+			if tail_ofs != 0 {
+				if let Some(p) = lh.pages.get_mut(&(keep_pages - 1)) {
+					for b in p[tail_ofs..].iter_mut() {
+						*b = 0;
+					}
+				}
+			}
+		}
+		// Growing just extends the size (missing pages read as zero)
+		lh.size = newsize;
+		Ok(newsize)
+	}
+	fn clear(&self, ofs: u64, size: u64) -> vfs::Result<()> {
+		let file = self.file();
+		let mut lh = file.data.write();
+		let end = ::core::cmp::min(ofs.saturating_add(size), lh.size);
+		let mut pos = ofs;
+		while pos < end
+		{
+			let page = pos / ::PAGE_SIZE as u64;
+			let page_ofs = (pos % ::PAGE_SIZE as u64) as usize;
+			let len = ::core::cmp::min(::PAGE_SIZE as u64 - page_ofs as u64, end - pos) as usize;
+			if page_ofs == 0 && len == ::PAGE_SIZE {
+				// Whole page cleared, release it
+				if lh.pages.remove(&page).is_some() {
+					file.usage.release_pages(1);
+				}
+			}
+			else if let Some(p) = lh.pages.get_mut(&page) {
+				for b in p[page_ofs..][..len].iter_mut() {
+					*b = 0;
+				}
+			}
+			pos += len as u64;
+		}
+		Ok( () )
+	}
+	fn read(&self, ofs: u64, buf: &mut [u8]) -> vfs::Result<usize> {
+		let lh = self.file().data.read();
+		if ofs >= lh.size {
+			return Ok(0);
+		}
+		let len = ::core::cmp::min(buf.len() as u64, lh.size - ofs) as usize;
+		let mut done = 0;
+		while done < len
+		{
+			let pos = ofs + done as u64;
+			let page = pos / ::PAGE_SIZE as u64;
+			let page_ofs = (pos % ::PAGE_SIZE as u64) as usize;
+			let n = ::core::cmp::min(::PAGE_SIZE - page_ofs, len - done);
+			let dst = &mut buf[done..][..n];
+			match lh.pages.get(&page)
+			{
+			Some(p) => dst.clone_from_slice( &p[page_ofs..][..n] ),
+			None => for b in dst.iter_mut() { *b = 0; },
+			}
+			done += n;
+		}
+		Ok(len)
+	}
+	fn write(&self, ofs: u64, buf: &[u8]) -> vfs::Result<usize> {
+		if ofs.checked_add(buf.len() as u64).is_none() {
+			return Err(vfs::Error::FileTooLarge);
+		}
+		let file = self.file();
+		let mut lh = file.data.write();
+		let mut done = 0;
+		while done < buf.len()
+		{
+			let pos = ofs + done as u64;
+			let page = pos / ::PAGE_SIZE as u64;
+			let page_ofs = (pos % ::PAGE_SIZE as u64) as usize;
+			let n = ::core::cmp::min(::PAGE_SIZE - page_ofs, buf.len() - done);
+			// Only the pages written to are allocated (so writing far past the end doesn't fill the gap)
+			if lh.pages.get(&page).is_none() {
+				match file.usage.alloc_page()
+				{
+				Ok(p) => { lh.pages.insert(page, p); },
+				// Out of space, return a short write (if anything was written)
+				Err(e) => if done == 0 { return Err(e) } else { break },
+				}
+			}
+			if let Some(p) = lh.pages.get_mut(&page) {
+				p[page_ofs..][..n].clone_from_slice( &buf[done..][..n] );
+			}
+			done += n;
+		}
+		let end = ofs + done as u64;
+		if end > lh.size {
+			lh.size = end;
+		}
+		Ok(done)
+	}
+}
@@ -20,7 +20,8 @@ use kernel::sync::mutex::LazyMutex;
 //  > read/write (unbuffered)
 //  > read_inner/get/edit (buffered)
 //
-// - The global cache is registered with the PMM as a source of reclaimable memory
+// - The global cache is registered with the PMM as a source of reclaimable memory (unreferenced
+//   clean blocks are evicted when memory is low)
 
 #[macro_use]
 extern crate kernel;
@@ -58,6 +59,7 @@ struct CachedBlock
 
 
 static S_BLOCK_CACHE: LazyMutex<Cache> = LazyMutex::new();
+static S_RECLAIMER: Reclaimer = Reclaimer;
 //static S_BLOCK_CACHE: Mutex<Cache> = Mutex::new(Cache {
 //	map: ::kernel::lib::VecMap::new(),
 //	});
@@ -110,7 +112,7 @@ impl CacheHandle
 		let cache_block = block - block % self.blocks_per_page();
 		let handle = {
 			use kernel::lib::vec_map::Entry;
-			let mut lh = S_BLOCK_CACHE.lock_init(|| Default::default());
+			let mut lh = lock_cache();
 			let handle = match lh.map.entry( (self.vh.idx(), cache_block) )
 				{
 				Entry::Occupied(v) => v.into_mut().borrow(),
@@ -182,6 +184,30 @@ impl CacheHandle
 
 		Ok( rv )
 	}
+
+	/// Write all modified cached blocks for this volume back to disk
+	pub fn flush(&self) -> Result<(), IoError>
+	{
+		// - Collect the dirty blocks with the cache locked, then write them without the lock
+		let dirty: Vec<u64> = lock_cache().map.iter()
+			.filter(|&(&(vol, _), b)| vol == self.vh.idx() && b.is_dirty.load(Ordering::Acquire))
+			.map(|(&(_, blk), _)| blk)
+			.collect();
+		for blk in dirty
+		{
+			let cached_block = try!(self.get_block_meta(blk));
+			try!(cached_block.0.flush(&self.vh));
+		}
+		Ok( () )
+	}
+}
+
+fn lock_cache() -> ::kernel::sync::mutex::HeldLazyMutex<'static, Cache>
+{
+	S_BLOCK_CACHE.lock_init(|| {
+		::kernel::memory::phys::register_reclaimer(&S_RECLAIMER);
+		Default::default()
+		})
 }
 
 fn map_cached_frame(frame: &::kernel::memory::phys::FrameHandle) -> ::kernel::memory::page_cache::CachedPage
@@ -191,6 +217,31 @@ fn map_cached_frame(frame: &::kernel::memory::phys::FrameHandle) -> ::kernel::me
 	::kernel::memory::page_cache::S_PAGE_CACHE.map(frame).expect("TODO: OOM in CachedBlock::borrow")
 }
 
+/// Evicts unreferenced clean blocks when memory is low
+struct Reclaimer;
+impl ::kernel::memory::phys::Reclaimer for Reclaimer
+{
+	fn name(&self) -> &str {
+		"block cache"
+	}
+	fn reclaim(&self, count: usize) -> usize
+	{
+		let mut rv = 0;
+		// NOTE: Called from within allocations (possibly with the cache locked), so don't block
+		if let Some(mut lh) = S_BLOCK_CACHE.try_lock()
+		{
+			lh.map.retain(|_, b| if rv < count && b.reference_count.load(Ordering::Acquire) == 0 && !b.is_dirty.load(Ordering::Acquire) {
+					rv += 1;
+					false
+				}
+				else {
+					true
+				});
+		}
+		rv
+	}
+}
+
 // --------------------------------------------------------------------
 impl CachedBlock
 {
@@ -263,15 +314,9 @@ impl<'a> ::core::ops::Drop for MetaBlockHandle<'a>
 {
 	fn drop(&mut self)
 	{
-		// TODO: Clearing this when the refcount reaches zero will lead to a lot of mapping/unmapping
-		// - Maybe keep a local queue or run a purge every now and then. Using a LRU list could work.
-		/*
-		if self.0.reference_count.fetch_sub(1, Ordering::Release) == 1
-		{
-			let mut wh = self.0.mapping.write();
-			*wh = None;
-		}
-		*/
+		// NOTE: The mapping is kept when the count reaches zero (to avoid repeated mapping/unmapping), the
+		// block (and its mapping) is released by the reclaimer when memory is low.
+		self.0.reference_count.fetch_sub(1, Ordering::Release);
 	}
 }
 
@@ -0,0 +1,301 @@
+// "Tifflin" Kernel - exFAT Filesystem Driver
+// - By John Hodge (thePowersGang)
+//
+// Modules/fs_exfat/dir.rs
+use kernel::prelude::*;
+use kernel::lib::mem::aref::ArefBorrow;
+use kernel::vfs::{self, node};
+use kernel::lib::byte_str::ByteStr;
+use super::on_disk;
+use super::file::FileNode;
+use super::{FilesystemInner,DataExtent,InodeRef};
+use utf16::Str16;
+
+/// Largest possible entry set (File, Stream Extension, and 17 File Name entries)
+pub const MAX_SET_ENTS: usize = 19;
+/// Longest name (in UTF-16 code units)
+const MAX_NAME_LEN: usize = 255;
+
+pub struct DirNode
+{
+	fs: ArefBorrow<FilesystemInner>,
+	extent: DataExtent,
+	id: node::InodeId,
+	/// Metadata from the parent's entry set (defaults for the root)
+	metadata: node::Metadata,
+}
+impl_fmt! {
+	Debug(self, f) for DirNode {
+		write!(f, "{{cluster={:#x}}}", self.extent.first_cluster)
+	}
+}
+
+/// Decoded entry set (File, Stream Extension and File Name entries)
+struct EntrySet
+{
+	attributes: u16,
+	name: [u16; MAX_NAME_LEN],
+	name_len: usize,
+	name_hash: u16,
+	extent: DataExtent,
+	valid_length: u64,
+	created: ::kernel::time::Timestamp,
+	modified: ::kernel::time::Timestamp,
+	accessed: ::kernel::time::Timestamp,
+}
+
+impl DirNode {
+	pub fn new(fs: ArefBorrow<FilesystemInner>, extent: DataExtent, id: node::InodeId) -> DirNode {
+		DirNode {
+			fs: fs,
+			extent: extent,
+			id: id,
+			metadata: node::Metadata { mode: 0o777, link_count: 1, ..Default::default() },
+		}
+	}
+	pub fn new_root(fs: ArefBorrow<FilesystemInner>) -> Box<DirNode> {
+		// The root is always FAT-chained
+		let extent = DataExtent { first_cluster: fs.root_cluster, length: 0, contiguous: false };
+		Box::new(Self::new(fs, extent, 0))
+	}
+}
+
+impl node::NodeBase for DirNode {
+	fn get_id(&self) -> node::InodeId {
+		self.id
+	}
+	fn get_any(&self) -> &::core::any::Any {
+		self
+	}
+	fn get_metadata(&self) -> node::Metadata {
+		self.metadata.clone()
+	}
+}
+
+impl DirNode {
+	/// Obtain the node described by the entry set starting at index `idx`
+	pub fn node_at(&self, idx: u32) -> Option<node::Node>
+	{
+		let mut rv = None;
+		if let Err(e) = self.for_each_set(idx as usize, |set_idx, set| {
+				if set_idx == idx as usize {
+					rv = Some(set);
+				}
+				false
+				})
+		{
+			log_warning!("node_at({:?}, {}): Error reading entry set - {:?}", self, idx, e);
+			return None;
+		}
+		let set = match rv
+			{
+			Some(v) => v,
+			None => return None,
+			};
+		let id = InodeRef::new(&self.extent, idx).to_id();
+		if set.attributes & on_disk::ATTR_DIRECTORY != 0 {
+			let mut dn = DirNode::new(self.fs.reborrow(), set.extent, id);
+			dn.metadata = set.metadata();
+			Some(node::Node::Dir(Box::new(dn)))
+		}
+		else {
+			Some(node::Node::File(FileNode::new_boxed(
+				self.fs.reborrow(), id, set.extent, set.valid_length, set.metadata()
+				)))
+		}
+	}
+
+	/// Call `f` with each valid entry set (and the index of its first entry), starting at entry `start`, until it
+	/// returns false
+	///
+	/// Returns the entry index to resume from (after the last set passed to `f`, or at the end marker)
+	fn for_each_set<F: FnMut(usize, EntrySet) -> bool>(&self, start: usize, mut f: F) -> vfs::Result<usize> {
+		let mut ents: Vec<[u8; 32]> = Vec::new();
+		let mut expected = 0;
+		let mut set_start = start;
+		let mut resume = start;
+		try!(self.fs.scan_dir(self.extent.clusters(&self.fs), start, |idx, ent| {
+			let ty = ent[0];
+			if ty == on_disk::ENTRY_END {
+				resume = idx;
+				return false;
+			}
+			if ty == on_disk::ENTRY_FILE && ent[1] as usize + 1 >= 3 && ent[1] as usize + 1 <= MAX_SET_ENTS {
+				ents.clear();
+				set_start = idx;
+				expected = ent[1] as usize + 1;
+			}
+			else if ty & on_disk::ENTRY_IN_USE != 0 && ty & on_disk::ENTRY_SECONDARY != 0 && ents.len() > 0 {
+				// Secondary entry in the current set
+			}
+			else {
+				// Unused entry, other primary entry (bitmap, up-case table, label, ...), or a stray secondary
+				ents.clear();
+				resume = idx + 1;
+				return true;
+			}
+			let mut e = [0u8; 32];
+			e.clone_from_slice(ent);
+			ents.push(e);
+			if ents.len() == expected {
+				resume = idx + 1;
+				let cont = match decode_set(&ents)
+					{
+					Some(set) => f(set_start, set),
+					None => {
+						log_notice!("Invalid entry set at {} in {:?}", set_start, self);
+						true
+						},
+					};
+				ents.clear();
+				cont
+			}
+			else {
+				true
+			}
+			}));
+		Ok(resume)
+	}
+}
+
+/// Decode (and validate) an entry set
+fn decode_set(ents: &[[u8; 32]]) -> Option<EntrySet> {
+	let file = on_disk::FileEnt::read(&ents[0]);
+	if on_disk::set_checksum(ents) != file.set_checksum {
+		return None;
+	}
+	if ents[1][0] != on_disk::ENTRY_STREAM {
+		return None;
+	}
+	let stream = on_disk::StreamEnt::read(&ents[1]);
+	let name_len = stream.name_length as usize;
+	let name_ents = (name_len + on_disk::NAME_CHARS_PER_ENT - 1) / on_disk::NAME_CHARS_PER_ENT;
+	if name_len == 0 || 2 + name_ents > ents.len() {
+		return None;
+	}
+	let mut name = [0u16; MAX_NAME_LEN];
+	for (i, e) in ents[2 .. 2 + name_ents].iter().enumerate()
+	{
+		if e[0] != on_disk::ENTRY_NAME {
+			return None;
+		}
+		let mut chars = [0u16; on_disk::NAME_CHARS_PER_ENT];
+		on_disk::read_name_chars(e, &mut chars);
+		let ofs = i * on_disk::NAME_CHARS_PER_ENT;
+		let n = ::core::cmp::min(on_disk::NAME_CHARS_PER_ENT, name_len - ofs);
+		name[ofs..][..n].clone_from_slice(&chars[..n]);
+	}
+	Some(EntrySet {
+		attributes: file.attributes,
+		name: name,
+		name_len: name_len,
+		name_hash: stream.name_hash,
+		extent: DataExtent {
+			first_cluster: stream.first_cluster,
+			length: stream.data_length,
+			contiguous: stream.flags & on_disk::FLAG_NO_FAT_CHAIN != 0,
+			},
+		valid_length: stream.valid_data_length,
+		created: on_disk::decode_timestamp(file.create_time, file.create_10ms, file.create_utc_ofs),
+		modified: on_disk::decode_timestamp(file.modified_time, file.modified_10ms, file.modified_utc_ofs),
+		accessed: on_disk::decode_timestamp(file.accessed_time, 0, file.accessed_utc_ofs),
+		})
+}
+
+impl EntrySet {
+	fn name(&self) -> &[u16] {
+		&self.name[..self.name_len]
+	}
+	fn metadata(&self) -> node::Metadata {
+		node::Metadata {
+			size: self.extent.length,
+			link_count: 1,
+			// No permissions, just a read-only flag
+			mode: if self.attributes & on_disk::ATTR_READONLY != 0 { 0o555 } else { 0o777 },
+			created: Some(self.created),
+			modified: Some(self.modified),
+			accessed: Some(self.accessed),
+			..Default::default()
+			}
+	}
+}
+
+/// Convert a (UTF-8) name into UTF-16 for comparison
+fn encode_name(name: &ByteStr) -> Option<Vec<u16>> {
+	let s = match ::core::str::from_utf8(name.as_bytes())
+		{
+		Ok(v) => v,
+		Err(_) => return None,
+		};
+	let mut rv = Vec::new();
+	for c in s.chars()
+	{
+		let v = c as u32;
+		if v < 0x10000 {
+			rv.push(v as u16);
+		}
+		else {
+			let v = v - 0x10000;
+			rv.push(0xD800 | (v >> 10) as u16);
+			rv.push(0xDC00 | (v & 0x3FF) as u16);
+		}
+	}
+	if rv.len() > MAX_NAME_LEN {
+		None
+	}
+	else {
+		Some(rv)
+	}
+}
+
+impl node::Dir for DirNode {
+	fn lookup(&self, name: &ByteStr) -> node::Result<node::InodeId> {
+		let name = match encode_name(name)
+			{
+			Some(v) => v,
+			None => return Err(vfs::Error::NotFound),
+			};
+		// The stored hash allows most entries to be skipped without a full comparison
+		let hash = self.fs.name_hash(&name);
+		let mut rv = None;
+		try!(self.for_each_set(0, |idx, set| {
+			if set.name_hash == hash && self.fs.names_equal(set.name(), &name) {
+				rv = Some(idx);
+				false
+			}
+			else {
+				true
+			}
+			}));
+		match rv
+		{
+		Some(idx) => Ok( InodeRef::new(&self.extent, idx as u32).to_id() ),
+		None => Err(vfs::Error::NotFound),
+		}
+	}
+	fn read(&self, ofs: usize, callback: &mut node::ReadDirCallback) -> node::Result<usize> {
+		self.for_each_set(ofs, |idx, set| {
+			let inode = InodeRef::new(&self.extent, idx as u32).to_id();
+			match Str16::new(set.name())
+			{
+			Some(name) => callback(inode, &mut name.wtf8()),
+			None => {
+				log_notice!("Invalid UTF-16 name at {} in {:?}", idx, self);
+				true
+				},
+			}
+			})
+	}
+	fn create(&self, _name: &ByteStr, _nodetype: node::NodeType) -> node::Result<node::InodeId> {
+		Err( vfs::Error::ReadOnlyFilesystem )
+	}
+	fn link(&self, _name: &ByteStr, _node: &node::NodeBase) -> node::Result<()> {
+		Err( vfs::Error::ReadOnlyFilesystem )
+	}
+	fn unlink(&self, _name: &ByteStr) -> node::Result<()> {
+		Err( vfs::Error::ReadOnlyFilesystem )
+	}
+	fn rename(&self, _old_name: &ByteStr, _new_dir: &node::Dir, _new_name: &ByteStr) -> node::Result<()> {
+		Err( vfs::Error::ReadOnlyFilesystem )
+	}
+}
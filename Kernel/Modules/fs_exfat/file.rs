@@ -0,0 +1,128 @@
+// "Tifflin" Kernel - exFAT Filesystem Driver
+// - By John Hodge (thePowersGang)
+//
+// Modules/fs_exfat/file.rs
+use kernel::prelude::*;
+use kernel::lib::mem::aref::ArefBorrow;
+use kernel::vfs::{self, node};
+use super::{FilesystemInner,DataExtent};
+
+const ERROR_SHORTCHAIN: vfs::Error = vfs::Error::Unknown("Cluster chain terminated early");
+
+pub struct FileNode
+{
+	fs: ArefBorrow<FilesystemInner>,
+	id: node::InodeId,
+	extent: DataExtent,
+	/// Bytes past this point haven't been written, and read as zero
+	valid_length: u64,
+	/// Metadata from the entry set
+	metadata: node::Metadata,
+}
+
+impl FileNode
+{
+	pub fn new_boxed(fs: ArefBorrow<FilesystemInner>, id: node::InodeId, extent: DataExtent, valid_length: u64, metadata: node::Metadata) -> Box<FileNode> {
+		Box::new(FileNode {
+			fs: fs,
+			id: id,
+			extent: extent,
+			valid_length: ::core::cmp::min(valid_length, extent.length),
+			metadata: metadata,
+			})
+	}
+
+	/// Read from the valid (on-disk) portion of the file
+	fn read_valid(&self, ofs: u64, buf: &mut [u8]) -> node::Result<()> {
+		let cluster_size = self.fs.cluster_size;
+		// Seek to correct position in the cluster chain
+		let mut clusters = self.extent.clusters(&self.fs);
+		for _ in 0 .. (ofs / cluster_size as u64) {
+			clusters.next();
+		}
+		let mut c_ofs = (ofs % cluster_size as u64) as usize;
+
+		let mut cur_read_ofs = 0;
+		let mut bounce = Vec::new();
+		while cur_read_ofs < buf.len()
+		{
+			let dst = &mut buf[cur_read_ofs..];
+			if c_ofs == 0 && dst.len() >= cluster_size {
+				// Whole clusters, read directly
+				let (cluster, count) = match clusters.next_extent( dst.len() / cluster_size )
+					{
+					Some(v) => v,
+					None => return Err(ERROR_SHORTCHAIN),
+					};
+				let bytes = count * cluster_size;
+				try!(self.fs.read_clusters(cluster, &mut dst[..bytes]));
+				cur_read_ofs += bytes;
+			}
+			else {
+				// Partial cluster, bounce
+				let cluster = match clusters.next()
+					{
+					Some(v) => v,
+					None => return Err(ERROR_SHORTCHAIN),
+					};
+				if bounce.len() == 0 {
+					bounce = vec![0u8; cluster_size];
+				}
+				try!(self.fs.read_cluster(cluster, &mut bounce));
+				let bytes = ::core::cmp::min(cluster_size - c_ofs, dst.len());
+				dst[..bytes].clone_from_slice( &bounce[c_ofs..][..bytes] );
+				cur_read_ofs += bytes;
+				c_ofs = 0;
+			}
+		}
+		Ok( () )
+	}
+}
+impl node::NodeBase for FileNode {
+	fn get_id(&self) -> node::InodeId {
+		self.id
+	}
+	fn get_any(&self) -> &::core::any::Any {
+		self
+	}
+	fn get_metadata(&self) -> node::Metadata {
+		self.metadata.clone()
+	}
+}
+impl node::File for FileNode {
+	fn size(&self) -> u64 {
+		self.extent.length
+	}
+	fn truncate(&self, _newsize: u64) -> node::Result<u64> {
+		Err( vfs::Error::ReadOnlyFilesystem )
+	}
+	fn clear(&self, _ofs: u64, _size: u64) -> node::Result<()> {
+		Err( vfs::Error::ReadOnlyFilesystem )
+	}
+	fn read(&self, ofs: u64, buf: &mut [u8]) -> node::Result<usize> {
+		// Sanity check and bound parameters
+		if ofs > self.extent.length {
+			return Err( vfs::Error::InvalidParameter );
+		}
+		let maxread = self.extent.length - ofs;
+		let buf = if buf.len() as u64 > maxread { &mut buf[..maxread as usize] } else { buf };
+
+		// Split at the valid data length
+		let valid_bytes = if ofs < self.valid_length {
+				::core::cmp::min(buf.len() as u64, self.valid_length - ofs) as usize
+			}
+			else {
+				0
+			};
+		if valid_bytes > 0 {
+			try!(self.read_valid(ofs, &mut buf[..valid_bytes]));
+		}
+		for b in &mut buf[valid_bytes..] {
+			*b = 0;
+		}
+		Ok( buf.len() )
+	}
+	fn write(&self, _ofs: u64, _buf: &[u8]) -> node::Result<usize> {
+		Err( vfs::Error::ReadOnlyFilesystem )
+	}
+}
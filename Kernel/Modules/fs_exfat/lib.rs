@@ -0,0 +1,501 @@
+// "Tifflin" Kernel - exFAT Filesystem Driver
+// - By John Hodge (thePowersGang)
+//
+// Modules/fs_exfat/lib.rs
+//! exFAT Filesystem driver (read-only)
+#![feature(linkage)]
+#![no_std]
+
+#[macro_use] extern crate kernel;
+use kernel::prelude::*;
+
+use kernel::vfs::{self, mount, node};
+use kernel::metadevs::storage::{self,VolumeHandle,SizePrinter};
+use kernel::lib::mem::aref::ArefInner;
+use kernel::lib::mem::Arc;
+use kernel::lib::byteorder::{ByteOrder,LittleEndian};
+
+extern crate utf16;
+extern crate blockcache;
+extern crate block_cache;
+
+module_define!{FS_EXFAT, [VFS], init}
+
+/// FAT entry values
+const FAT_BAD_CLUSTER: u32 = 0xFFFF_FFF7;
+const FAT_EOC: u32 = 0xFFFF_FFFF;
+
+/// Size of the up-case table, if the on-disk one is unusable (only maps ASCII)
+const DEFAULT_UPCASE_LEN: usize = 128;
+
+/// on-disk structures
+mod on_disk;
+/// Directory IO
+mod dir;
+/// File IO
+mod file;
+
+/// Driver strucutre
+struct Driver;
+
+struct Filesystem
+{
+	inner: ArefInner<FilesystemInner>
+}
+impl ::core::ops::Deref for Filesystem {
+	type Target = FilesystemInner;
+	fn deref(&self) -> &FilesystemInner { &self.inner }
+}
+
+pub struct FilesystemInner
+{
+	vh: ::block_cache::CacheHandle,
+
+	/// Bytes per sector (a multiple of the volume's block size)
+	bps: usize,
+	cluster_size: usize,
+	/// Number of clusters in the cluster heap
+	cluster_count: u32,
+	/// First sector of the (active) FAT
+	fat_sector: u64,
+	/// First sector of the cluster heap (cluster #2)
+	heap_sector: u64,
+	root_cluster: u32,
+
+	/// Up-case table, used for name comparisons (characters past the end map to themselves)
+	upcase: Vec<u16>,
+
+	/// A cache of metadata clusters (i.e. directories)
+	metadata_block_cache: ::blockcache::BlockCache,
+}
+
+/// Location of a node's data
+#[derive(Copy,Clone,Debug)]
+struct DataExtent
+{
+	first_cluster: u32,
+	/// Allocated length in bytes (unused for FAT-chained directories, which aren't required to record it)
+	length: u64,
+	/// Clusters are allocated contiguously (the FAT chain isn't valid)
+	contiguous: bool,
+}
+
+/// Inode IDs encode the location of a node's entry set (the root directory is inode 0)
+///
+/// - Bits 0-31: First cluster of the parent directory
+/// - Bits 32-62: Index of the File entry within the parent
+/// - Bit 63: Set if the parent directory is contiguous
+#[derive(Debug)]
+struct InodeRef
+{
+	dir_first_cluster: u32,
+	dir_contiguous: bool,
+	index: u32,
+}
+
+/// Iterable cluster list
+enum ClusterList<'a> {
+	Range(::core::ops::Range<u32>),
+	Chained(&'a FilesystemInner, u32),
+}
+
+
+static S_DRIVER: Driver = Driver;
+
+fn init()
+{
+	let h = mount::DriverRegistration::new("exfat", &S_DRIVER);
+	// TODO: Remember the registration for unloading
+	::core::mem::forget(h);
+}
+
+/// Read the first 512 bytes of the volume
+fn read_boot_sector(vol: &VolumeHandle) -> Result<on_disk::BootSector, storage::IoError> {
+	let mut blk = vec![0u8; ::core::cmp::max(512, vol.block_size())];
+	try!( vol.read_blocks(0, &mut blk) );
+	Ok( on_disk::BootSector::read(&blk[..512]) )
+}
+
+impl mount::Driver for Driver
+{
+	fn detect(&self, vol: &VolumeHandle) -> vfs::Result<usize> {
+		let bs = try!(read_boot_sector(vol));
+		if &bs.fs_name == b"EXFAT   " && bs.signature == 0xAA55 {
+			Ok(1)
+		}
+		else {
+			Ok(0)
+		}
+	}
+	fn mount(&self, vol: VolumeHandle, _mounthandle: mount::SelfHandle) -> vfs::Result<Box<mount::Filesystem>> {
+		let bs = try!(read_boot_sector(&vol));
+		let vol = ::block_cache::CacheHandle::new(vol);
+		if &bs.fs_name != b"EXFAT   " {
+			return Err(vfs::Error::TypeMismatch);
+		}
+		if bs.fs_revision >> 8 != 1 {
+			return Err(vfs::Error::Unknown("Unsupported exFAT revision"));
+		}
+		// Sector sizes are 512-4096 bytes, and clusters are at most 32MiB
+		if bs.bps_shift < 9 || bs.bps_shift > 12 || bs.bps_shift as u32 + bs.spc_shift as u32 > 25 {
+			return Err(vfs::Error::InconsistentFilesystem);
+		}
+		let bps = 1usize << bs.bps_shift;
+		// Sectors are accessed as whole volume blocks
+		if bps < vol.block_size() {
+			return Err(vfs::Error::Unknown("exFAT sector size is smaller than the volume's block size"));
+		}
+		if bs.fat_count == 0 || bs.fat_count > 2 || bs.root_cluster < 2 || bs.root_cluster - 2 >= bs.cluster_count {
+			return Err(vfs::Error::InconsistentFilesystem);
+		}
+		let cluster_size = bps << bs.spc_shift;
+		log_debug!("exFAT {} {}-byte sectors, Size {}, {} clusters of {}", bs.volume_length, bps,
+			SizePrinter(bs.volume_length << bs.bps_shift), bs.cluster_count, cluster_size);
+
+		// The second FAT is used if the volume flags select it
+		let active_fat = if bs.fat_count == 2 && bs.volume_flags & 1 != 0 { 1 } else { 0 };
+
+		let mut inner = FilesystemInner {
+			vh: vol,
+			bps: bps,
+			cluster_size: cluster_size,
+			cluster_count: bs.cluster_count,
+			fat_sector: bs.fat_offset as u64 + active_fat * bs.fat_length as u64,
+			heap_sector: bs.cluster_heap_offset as u64,
+			root_cluster: bs.root_cluster,
+			upcase: Vec::new(),
+			metadata_block_cache: ::blockcache::BlockCache::new(),
+			};
+
+		// Locate the allocation bitmap and up-case table in the root directory
+		let mut bitmap = None;
+		let mut upcase = None;
+		try!(inner.scan_dir(ClusterList::Chained(&inner, inner.root_cluster), 0, |_, ent| {
+			match ent[0]
+			{
+			on_disk::ENTRY_END => return false,
+			on_disk::ENTRY_BITMAP => {
+				let e = on_disk::TableEnt::read(ent);
+				// Flag bit 0 selects which FAT the bitmap goes with
+				if (e.flags & 1) as u64 == active_fat {
+					bitmap = Some(e);
+				}
+				},
+			on_disk::ENTRY_UPCASE => upcase = Some(on_disk::TableEnt::read(ent)),
+			on_disk::ENTRY_LABEL => {
+				let mut label = [0u16; 11];
+				for (i, c) in label.iter_mut().enumerate() {
+					*c = LittleEndian::read_u16(&ent[2 + i*2..]);
+				}
+				let len = ::core::cmp::min(ent[1] as usize, label.len());
+				log_debug!("Label: {:?}", ::utf16::Str16::new(&label[..len]));
+				},
+			_ => {},
+			}
+			true
+			}));
+
+		match bitmap
+		{
+		Some(ref e) if e.data_length * 8 >= inner.cluster_count as u64 => {},
+		_ => {
+			log_notice!("exFAT: Allocation bitmap missing or too small");
+			return Err(vfs::Error::InconsistentFilesystem);
+			},
+		}
+		let upcase = match upcase
+			{
+			Some(e) => try!(inner.load_upcase(&e)),
+			None => {
+				log_notice!("exFAT: No up-case table, using a default");
+				default_upcase()
+				},
+			};
+		inner.upcase = upcase;
+
+		Ok(Box::new(Filesystem {
+			// SAFE: Saving to a Box, so won't move
+			inner: unsafe { ArefInner::new(inner) },
+			}))
+	}
+}
+
+/// Up-case table only covering ASCII
+fn default_upcase() -> Vec<u16> {
+	Vec::from_fn(DEFAULT_UPCASE_LEN, |i| if b'a' as usize <= i && i <= b'z' as usize { (i - 0x20) as u16 } else { i as u16 })
+}
+
+type Cluster = Arc<[u8]>;
+
+impl FilesystemInner
+{
+	/// Convert a sector number into a volume block number
+	fn sector_to_block(&self, sector: u64) -> u64 {
+		sector * (self.bps / self.vh.block_size()) as u64
+	}
+	fn cluster_to_sector(&self, cluster: u32) -> u64 {
+		assert!(cluster >= 2);
+		assert!(cluster - 2 < self.cluster_count);
+		self.heap_sector + (((cluster - 2) as u64 * self.cluster_size as u64) / self.bps as u64)
+	}
+	/// Load a cluster from disk
+	fn read_cluster(&self, cluster: u32, dst: &mut [u8]) -> Result<(), storage::IoError> {
+		assert_eq!(dst.len(), self.cluster_size);
+		self.read_clusters(cluster, dst)
+	}
+	fn read_clusters(&self, cluster: u32, dst: &mut [u8]) -> Result<(), storage::IoError> {
+		log_trace!("Filesystem::read_clusters({:#x}, {})", cluster, dst.len() / self.cluster_size);
+		assert_eq!(dst.len() % self.cluster_size, 0);
+		let block = self.sector_to_block(self.cluster_to_sector(cluster));
+		self.vh.read_blocks(block, dst)
+	}
+	/// Load a cluster via the metadata cache
+	fn load_cluster(&self, cluster: u32) -> Result<Cluster, storage::IoError>
+	{
+		self.metadata_block_cache.get(
+			cluster,
+			|_| {
+				let mut buf: Cluster = Arc::from_iter( (0..self.cluster_size).map(|_| 0) );
+				try!(self.read_cluster( cluster, Arc::get_mut(&mut buf).unwrap() ));
+				Ok( buf )
+			})
+	}
+
+	/// Obtain the next cluster in a chain
+	fn get_next_cluster(&self, cluster: u32) -> Result< Option<u32>, storage::IoError > {
+		let bs = self.vh.block_size();
+		let pos = self.fat_sector as usize * self.bps + cluster as usize * 4;
+		let block_idx = (pos / bs) as u64;
+		let val = {
+			let blk = try!(self.vh.get_block(block_idx));
+			let ofs = (block_idx - blk.index()) as usize * bs + pos % bs;
+			LittleEndian::read_u32(&blk.data()[ofs..])
+			};
+		if val == FAT_EOC {
+			Ok(None)
+		}
+		else if val == FAT_BAD_CLUSTER || val < 2 || val - 2 >= self.cluster_count {
+			Err(storage::IoError::Unknown("exFAT: Invalid FAT entry"))
+		}
+		else {
+			Ok(Some(val))
+		}
+	}
+
+	/// Call `f` with each raw entry of a directory (starting at index `start`), until it returns false
+	fn scan_dir<F: FnMut(usize, &[u8]) -> bool>(&self, clusters: ClusterList, start: usize, mut f: F) -> vfs::Result<()> {
+		let ents_per_cluster = self.cluster_size / 32;
+		let mut idx = start - start % ents_per_cluster;
+		for c in clusters.skip(start / ents_per_cluster)
+		{
+			let cluster = try!(self.load_cluster(c));
+			for ent in cluster.chunks(32)
+			{
+				if idx >= start && !f(idx, ent) {
+					return Ok( () );
+				}
+				idx += 1;
+			}
+		}
+		Ok( () )
+	}
+
+	/// Read and decompress the up-case table
+	fn load_upcase(&self, ent: &on_disk::TableEnt) -> vfs::Result<Vec<u16>> {
+		// Compressed tables are small (~6KiB), a full one is 128KiB
+		if ent.data_length > 0x20000 || ent.data_length % 2 != 0 {
+			log_notice!("exFAT: Up-case table has a bad size ({:#x})", ent.data_length);
+			return Ok( default_upcase() );
+		}
+		let len = ent.data_length as usize;
+		let mut data = vec![0u8; (len + self.cluster_size - 1) / self.cluster_size * self.cluster_size];
+		let mut ofs = 0;
+		for c in ClusterList::Chained(self, ent.first_cluster)
+		{
+			if ofs == data.len() {
+				break;
+			}
+			try!(self.read_cluster(c, &mut data[ofs..][..self.cluster_size]));
+			ofs += self.cluster_size;
+		}
+		if ofs < len {
+			return Err(vfs::Error::InconsistentFilesystem);
+		}
+		let data = &data[..len];
+		if on_disk::table_checksum(data) != ent.checksum {
+			log_notice!("exFAT: Up-case table checksum mismatch");
+			return Ok( default_upcase() );
+		}
+
+		// 0xFFFF introduces a run of characters that map to themselves
+		let mut rv = Vec::new();
+		let mut it = data.chunks(2).map(|v| LittleEndian::read_u16(v));
+		while let Some(v) = it.next()
+		{
+			if v == 0xFFFF {
+				let count = it.next().unwrap_or(0);
+				for _ in 0 .. count {
+					let c = rv.len() as u16;
+					rv.push(c);
+				}
+			}
+			else {
+				rv.push(v);
+			}
+			if rv.len() >= 0x10000 {
+				break;
+			}
+		}
+		rv.truncate(0x10000);
+		log_debug!("Up-case table: {} entries", rv.len());
+		Ok(rv)
+	}
+	fn upcase_char(&self, c: u16) -> u16 {
+		if (c as usize) < self.upcase.len() { self.upcase[c as usize] } else { c }
+	}
+	/// Hash of a name, as stored in the stream extension entry
+	fn name_hash(&self, name: &[u16]) -> u16 {
+		name.iter().fold(0u16, |hash, &c| {
+			let c = self.upcase_char(c);
+			let hash = hash.rotate_right(1).wrapping_add(c & 0xFF);
+			hash.rotate_right(1).wrapping_add(c >> 8)
+			})
+	}
+	/// Compare two names using the up-case table
+	fn names_equal(&self, a: &[u16], b: &[u16]) -> bool {
+		a.len() == b.len() && a.iter().zip(b.iter()).all(|(&x, &y)| self.upcase_char(x) == self.upcase_char(y))
+	}
+}
+
+impl mount::Filesystem for Filesystem
+{
+	fn root_inode(&self) -> node::InodeId {
+		0
+	}
+	fn get_node_by_inode(&self, id: node::InodeId) -> Option<node::Node> {
+		if id == 0 {
+			Some(node::Node::Dir(dir::DirNode::new_root(self.inner.borrow())))
+		}
+		else {
+			// Read the entry set in the parent directory
+			let r = InodeRef::from(id);
+			// NOTE: A contiguous parent's length isn't known here, but only needs to cover the entry set
+			let parent = DataExtent {
+				first_cluster: r.dir_first_cluster,
+				length: (r.index as u64 + dir::MAX_SET_ENTS as u64) * 32,
+				contiguous: r.dir_contiguous,
+				};
+			dir::DirNode::new(self.inner.borrow(), parent, 0).node_at(r.index)
+		}
+	}
+}
+
+impl DataExtent
+{
+	fn clusters<'a>(&self, fs: &'a FilesystemInner) -> ClusterList<'a> {
+		if self.first_cluster == 0 {
+			ClusterList::Range(0 .. 0)
+		}
+		else if self.contiguous {
+			let count = ((self.length + fs.cluster_size as u64 - 1) / fs.cluster_size as u64) as u32;
+			ClusterList::Range(self.first_cluster .. self.first_cluster + count)
+		}
+		else {
+			ClusterList::Chained(fs, self.first_cluster)
+		}
+	}
+}
+
+impl InodeRef
+{
+	fn new(dir: &DataExtent, index: u32) -> InodeRef {
+		assert!(index < 0x8000_0000);
+		InodeRef {
+			dir_first_cluster: dir.first_cluster,
+			dir_contiguous: dir.contiguous,
+			index: index,
+		}
+	}
+	fn to_id(&self) -> node::InodeId {
+		(self.dir_first_cluster as u64)
+		| (self.index as u64) << 32
+		| if self.dir_contiguous { 1 << 63 } else { 0 }
+	}
+}
+
+impl From<node::InodeId> for InodeRef {
+	fn from(v: node::InodeId) -> InodeRef {
+		InodeRef {
+			dir_first_cluster: v as u32,
+			dir_contiguous: (v >> 63) != 0,
+			index: ((v >> 32) & 0x7FFF_FFFF) as u32,
+		}
+	}
+}
+
+impl<'a> ClusterList<'a> {
+	/// Returns an extent of at most `max_clusters` contigious clusters
+	pub fn next_extent(&mut self, max_clusters: usize) -> Option<(u32, usize)> {
+		match *self
+		{
+		ClusterList::Range(ref mut r) =>
+			if r.start == r.end {
+				None
+			}
+			else {
+				let rv = r.start;
+				let count = ::core::cmp::min(max_clusters, (r.end - r.start) as usize);
+				r.start += count as u32;
+				Some( (rv, count) )
+			},
+		ClusterList::Chained(fs, ref mut next) =>
+			if *next == 0 {
+				None
+			}
+			else {
+				let rv = *next;
+				let mut count = 0;
+				while *next != 0 && *next == rv + count as u32 && count < max_clusters
+				{
+					*next = match fs.get_next_cluster(*next)
+						{
+						Ok(Some(v)) => v,
+						Ok(None) => 0,
+						Err(e) => {
+							log_warning!("Error when reading cluster chain - {:?}", e);
+							return None;	// Inconsistency, terminate asap
+							},
+						};
+					count += 1;
+				}
+				Some( (rv, count) )
+			},
+		}
+	}
+}
+impl<'a> ::core::iter::Iterator for ClusterList<'a> {
+	type Item = u32;
+	fn next(&mut self) -> Option<u32> {
+		match *self
+		{
+		ClusterList::Range(ref mut r) => r.next(),
+		ClusterList::Chained(fs, ref mut next) =>
+			if *next == 0 {
+				None
+			}
+			else {
+				let rv = *next;
+				*next = match fs.get_next_cluster(*next)
+					{
+					Ok(Some(v)) => v,
+					Ok(None) => 0,
+					Err(e) => {
+						log_warning!("Error when reading cluster chain - {:?}", e);
+						return None;	// Inconsistency, terminate asap
+						},
+					};
+				Some( rv )
+			},
+		}
+	}
+}
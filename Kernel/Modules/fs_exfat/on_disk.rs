@@ -0,0 +1,190 @@
+// "Tifflin" Kernel - exFAT Filesystem Driver
+// - By John Hodge (thePowersGang)
+//
+// Modules/fs_exfat/on_disk.rs
+//! On-Disk structures and flags
+#[allow(unused_imports)]
+use kernel::prelude::*;
+use kernel::lib::byteorder::{ByteOrder,LittleEndian};
+
+/// Directory entry types (bit 7 is the "in use" flag, bit 6 marks secondary entries)
+pub const ENTRY_END      : u8 = 0x00;
+pub const ENTRY_IN_USE   : u8 = 0x80;
+pub const ENTRY_SECONDARY: u8 = 0x40;
+pub const ENTRY_BITMAP   : u8 = 0x81;	// Allocation bitmap
+pub const ENTRY_UPCASE   : u8 = 0x82;	// Up-case table
+pub const ENTRY_LABEL    : u8 = 0x83;	// Volume label
+pub const ENTRY_FILE     : u8 = 0x85;	// File (first entry of a set)
+pub const ENTRY_STREAM   : u8 = 0xC0;	// Stream extension
+pub const ENTRY_NAME     : u8 = 0xC1;	// File name
+
+pub const ATTR_READONLY : u16 = 0x01;
+pub const ATTR_DIRECTORY: u16 = 0x10;
+
+/// Stream extension flag: Clusters are contiguous, and the FAT chain is not valid
+pub const FLAG_NO_FAT_CHAIN: u8 = 0x02;
+
+/// Number of name characters in each File Name entry
+pub const NAME_CHARS_PER_ENT: usize = 15;
+
+pub struct BootSector
+{
+	pub fs_name: [u8; 8],
+	pub partition_offset: u64,
+	pub volume_length: u64,
+	pub fat_offset: u32,
+	pub fat_length: u32,
+	pub cluster_heap_offset: u32,
+	pub cluster_count: u32,
+	pub root_cluster: u32,
+	pub serial: u32,
+	pub fs_revision: u16,
+	pub volume_flags: u16,
+	pub bps_shift: u8,
+	pub spc_shift: u8,
+	pub fat_count: u8,
+	pub signature: u16,
+}
+impl BootSector {
+	pub fn read(src: &[u8]) -> BootSector {
+		assert_eq!(src.len(), 512);
+		let mut fs_name = [0; 8];
+		fs_name.clone_from_slice(&src[3..11]);
+		BootSector {
+			fs_name: fs_name,
+			partition_offset: LittleEndian::read_u64(&src[64..]),
+			volume_length: LittleEndian::read_u64(&src[72..]),
+			fat_offset: LittleEndian::read_u32(&src[80..]),
+			fat_length: LittleEndian::read_u32(&src[84..]),
+			cluster_heap_offset: LittleEndian::read_u32(&src[88..]),
+			cluster_count: LittleEndian::read_u32(&src[92..]),
+			root_cluster: LittleEndian::read_u32(&src[96..]),
+			serial: LittleEndian::read_u32(&src[100..]),
+			fs_revision: LittleEndian::read_u16(&src[104..]),
+			volume_flags: LittleEndian::read_u16(&src[106..]),
+			bps_shift: src[108],
+			spc_shift: src[109],
+			fat_count: src[110],
+			signature: LittleEndian::read_u16(&src[510..]),
+		}
+	}
+}
+
+/// Allocation bitmap and up-case table entries (both describe a chain of clusters)
+pub struct TableEnt
+{
+	pub flags: u8,
+	pub checksum: u32,
+	pub first_cluster: u32,
+	pub data_length: u64,
+}
+impl TableEnt {
+	pub fn read(src: &[u8]) -> TableEnt {
+		TableEnt {
+			flags: src[1],
+			checksum: LittleEndian::read_u32(&src[4..]),
+			first_cluster: LittleEndian::read_u32(&src[20..]),
+			data_length: LittleEndian::read_u64(&src[24..]),
+		}
+	}
+}
+
+pub struct FileEnt
+{
+	pub secondary_count: u8,
+	pub set_checksum: u16,
+	pub attributes: u16,
+	pub create_time: u32,
+	pub modified_time: u32,
+	pub accessed_time: u32,
+	pub create_10ms: u8,
+	pub modified_10ms: u8,
+	pub create_utc_ofs: u8,
+	pub modified_utc_ofs: u8,
+	pub accessed_utc_ofs: u8,
+}
+impl FileEnt {
+	pub fn read(src: &[u8]) -> FileEnt {
+		FileEnt {
+			secondary_count: src[1],
+			set_checksum: LittleEndian::read_u16(&src[2..]),
+			attributes: LittleEndian::read_u16(&src[4..]),
+			create_time: LittleEndian::read_u32(&src[8..]),
+			modified_time: LittleEndian::read_u32(&src[12..]),
+			accessed_time: LittleEndian::read_u32(&src[16..]),
+			create_10ms: src[20],
+			modified_10ms: src[21],
+			create_utc_ofs: src[22],
+			modified_utc_ofs: src[23],
+			accessed_utc_ofs: src[24],
+		}
+	}
+}
+
+pub struct StreamEnt
+{
+	pub flags: u8,
+	pub name_length: u8,
+	pub name_hash: u16,
+	pub valid_data_length: u64,
+	pub first_cluster: u32,
+	pub data_length: u64,
+}
+impl StreamEnt {
+	pub fn read(src: &[u8]) -> StreamEnt {
+		StreamEnt {
+			flags: src[1],
+			name_length: src[3],
+			name_hash: LittleEndian::read_u16(&src[4..]),
+			valid_data_length: LittleEndian::read_u64(&src[8..]),
+			first_cluster: LittleEndian::read_u32(&src[20..]),
+			data_length: LittleEndian::read_u64(&src[24..]),
+		}
+	}
+}
+
+/// Read the characters from a File Name entry
+pub fn read_name_chars(src: &[u8], dst: &mut [u16; NAME_CHARS_PER_ENT]) {
+	for (i, c) in dst.iter_mut().enumerate() {
+		*c = LittleEndian::read_u16(&src[2 + i*2..]);
+	}
+}
+
+/// Checksum of an entry set (skipping the checksum field in the first entry)
+pub fn set_checksum(ents: &[[u8; 32]]) -> u16 {
+	let mut sum = 0u16;
+	for (i, ent) in ents.iter().enumerate() {
+		for (j, &b) in ent.iter().enumerate() {
+			if i == 0 && (j == 2 || j == 3) {
+				continue ;
+			}
+			sum = sum.rotate_right(1).wrapping_add(b as u16);
+		}
+	}
+	sum
+}
+/// Checksum of the up-case table
+pub fn table_checksum(data: &[u8]) -> u32 {
+	data.iter().fold(0u32, |sum, &b| sum.rotate_right(1).wrapping_add(b as u32))
+}
+
+/// Decode a timestamp (with the 10ms increment and UTC offset fields)
+pub fn decode_timestamp(ts: u32, increment_10ms: u8, utc_ofs: u8) -> ::kernel::time::Timestamp {
+	let (date, time) = ((ts >> 16) as u16, ts as u16);
+	let year = 1980 + (date >> 9) as i32;
+	let month = ((date >> 5) & 0xF) as u8;
+	let day = (date & 0x1F) as u8;
+	let hour = (time >> 11) as u8;
+	let minute = ((time >> 5) & 0x3F) as u8;
+	let second = ((time & 0x1F) * 2) as u8 + increment_10ms / 100;
+	let t = ::kernel::time::Timestamp::from_civil(year, month, day, hour, minute, second, (increment_10ms % 100) as u16 * 10);
+	// Bit 7 marks the offset as valid, the rest is a signed count of 15 minute intervals
+	if utc_ofs & 0x80 != 0 {
+		let ofs_mins = (((utc_ofs << 1) as i8) >> 1) as i64 * 15;
+		::kernel::time::Timestamp::from_millis(t.as_millis() - ofs_mins * 60 * 1000)
+	}
+	else {
+		// No offset recorded, treat as UTC
+		t
+	}
+}
@@ -22,6 +22,10 @@ impl Dir
 			}
 	}
 
+	pub fn inode(&self) -> &::inodes::Inode {
+		&self.inode
+	}
+
 
 	/// Returns (block_index, offset)
 	fn find_name(&self, name: &ByteStr) -> vfs::node::Result<(usize, usize, vfs::node::InodeId)>
@@ -38,10 +42,8 @@ impl Dir
 				if ent.d_rec_len == 0 {
 					return Err( vfs::Error::InconsistentFilesystem );
 				}
-				else if ent.d_inode == 0 {
-					return Err(vfs::Error::NotFound);
-				}
-				else if &ent.d_name == name.as_ref()
+				// Entries with a zero inode are unused
+				else if ent.d_inode != 0 && &ent.d_name == name.as_ref()
 				{
 					return Ok( (blk_index, offset, ent.d_inode as vfs::node::InodeId) );
 				}
@@ -54,9 +56,12 @@ impl Dir
 	}
 
 
+	/// Returns (block_index, offset) of either an unused entry large enough for the name, or an entry with enough
+	/// slack to be split. The directory is expanded if there's no space.
 	fn find_free(&self, name: &ByteStr) -> vfs::node::Result<(u32, usize)>
 	{
 		assert!(name.len() <= 255);
+		let required = ::ondisk::DirEnt::size_for(name.len());
 		// Linear search
 		// TODO: Later revisions have B+ trees
 		for (blk_index, vol_blk) in self.inode.blocks().enumerate()
@@ -66,12 +71,13 @@ impl Dir
 			let mut offset = 0;
 			for ent in DirEnts(&blk_data)
 			{
+				let used = if ent.d_inode == 0 { 0 } else { ::ondisk::DirEnt::size_for(ent.d_name.len()) };
 				if ent.d_rec_len == 0 {
 					return Err( vfs::Error::InconsistentFilesystem );
 				}
-				else if ent.d_inode == 0 && (ent.d_rec_len - 8) <= name.len() as u16
+				else if ent.d_rec_len as usize >= used + required
 				{
-					// Free entry with sufficient space!
+					// Free entry (or free space in an entry) with sufficient space!
 					return Ok( (blk_index as u32, offset) );
 				}
 				else {
@@ -80,30 +86,88 @@ impl Dir
 			}
 		}
 
-		todo!("Dir::find_free - expand directory");
+		// No space, expand the directory by a block (containing a single unused entry)
+		let blk_index = self.inode.max_blocks();
+		let vol_blk = try!(self.inode.get_or_alloc_block(blk_index, true));
+		let fs_block_size = self.inode.fs.fs_block_size;
+		try!(self.inode.fs.edit_block(vol_blk, |blk_data| {
+			::ondisk::DirEnt::init(blk_data, 0, fs_block_size as u16, 0, b"");
+			Ok( () )
+			}));
+		self.inode.set_size( (blk_index as u64 + 1) * fs_block_size as u64 );
+		try!(self.inode.flush());
+		Ok( (blk_index, 0) )
 	}
 
-	fn add_dir_ent(&self, name: &ByteStr, inode: u32) -> Result<(), vfs::Error>
+	fn add_dir_ent(&self, name: &ByteStr, inode: u32, d_type: u8) -> Result<(), vfs::Error>
 	{
 		// 1. Find a suitable slot
 		let (blk, ofs) = try!(self.find_free(name));
 		// 2. Fill said slot
-		let vol_blk = try!( self.inode.blocks_from(blk as u32).next_or_err() );
+		let vol_blk = try!( self.inode.get_block_addr(blk) );
 		self.inode.fs.edit_block(vol_blk, |blk_data| {
-				match ::ondisk::DirEnt::new_mut(&mut blk_data[ofs/4 ..])
-				{
-				None => return Err(vfs::Error::InconsistentFilesystem),
-				Some(ent) => {
-					ent.d_name_len = name.len() as u8;
-					ent.d_inode = inode as u32;
-					},
+				let (ent_inode, rec_len, used) = match ::ondisk::DirEnt::new(&blk_data[ofs/4 ..])
+					{
+					None => return Err(vfs::Error::InconsistentFilesystem),
+					Some(ent) => (ent.d_inode, ent.d_rec_len as usize, ::ondisk::DirEnt::size_for(ent.d_name.len())),
+					};
+				if ent_inode == 0 {
+					// Unused entry, take over the entire record
+					::ondisk::DirEnt::init(&mut blk_data[ofs/4 ..], inode, rec_len as u16, d_type, name.as_ref());
+				}
+				else {
+					// Split the slack off the end of the existing entry
+					::ondisk::DirEnt::new_mut(&mut blk_data[ofs/4 ..]).unwrap().d_rec_len = used as u16;
+					::ondisk::DirEnt::init(&mut blk_data[(ofs + used)/4 ..], inode, (rec_len - used) as u16, d_type, name.as_ref());
 				}
-				// - Now that name length is longer, update the name
-				::ondisk::DirEnt::new_mut(&mut blk_data[ofs/4 ..]).unwrap()
-					.d_name.clone_from_slice( name.as_ref() );
 				Ok( () )
 				})
 	}
+
+	/// Clear the entry at the specified location (as returned by `find_name`), returning the inode it referred to
+	fn clear_dir_ent(&self, blk: usize, ofs: usize) -> Result<u32, vfs::Error>
+	{
+		let vol_blk = try!( self.inode.blocks_from(blk as u32).next_or_err() );
+		self.inode.fs.edit_block(vol_blk, |blk_data| {
+			match ::ondisk::DirEnt::new_mut(&mut blk_data[ofs/4 ..])
+			{
+			None => Err(vfs::Error::InconsistentFilesystem),
+			Some(ent) => {
+				// Clear inode and name length
+				let inode = ent.d_inode;
+				ent.d_inode = 0;
+				ent.d_name_len = 0;
+				Ok( inode )
+				},
+			}
+			})
+	}
+}
+
+/// Point a directory's '..' entry at a new parent
+fn set_parent(inode: &::inodes::Inode, parent: u32) -> vfs::node::Result<()>
+{
+	let vol_blk = try!(inode.get_block_addr(0));
+	inode.fs.edit_block(vol_blk, |blk_data| {
+		let mut ofs = 0;
+		while ofs + ::ondisk::DIRENT_MIN_SIZE / 4 <= blk_data.len()
+		{
+			let ent = match ::ondisk::DirEnt::new_mut(&mut blk_data[ofs ..])
+				{
+				Some(v) => v,
+				None => break,
+				};
+			if ent.d_rec_len == 0 {
+				break ;
+			}
+			if &ent.d_name == b".." {
+				ent.d_inode = parent;
+				return Ok( () );
+			}
+			ofs += ent.u32_len();
+		}
+		Err( vfs::Error::InconsistentFilesystem )
+		})
 }
 
 impl vfs::node::NodeBase for Dir
@@ -114,6 +178,9 @@ impl vfs::node::NodeBase for Dir
 	fn get_any(&self) -> &::core::any::Any {
 		self
 	}
+	fn get_metadata(&self) -> vfs::node::Metadata {
+		self.inode.get_metadata()
+	}
 }
 impl vfs::node::Dir for Dir
 {
@@ -185,7 +252,8 @@ impl vfs::node::Dir for Dir
 				}
 				else {
 					if callback(ent.d_inode as vfs::node::InodeId, &mut ent.d_name.iter().cloned()) == false {
-						return Some(cur_ofs);
+						// The entry was consumed, resume after it
+						return Some(cur_ofs + ent.u32_len() * 4);
 					}
 				}
 				
@@ -201,17 +269,32 @@ impl vfs::node::Dir for Dir
 		{
 			Err( vfs::Error::ReadOnlyFilesystem )
 		}
+		else if name.len() > 255
+		{
+			Err(vfs::Error::InvalidParameter)
+		}
 		else
 		{
 			let _lh = self.inode.write_lock();
 
+			let is_dir = nodetype == vfs::node::NodeType::Dir;
 			let ino_id = try!( self.inode.fs.allocate_inode(self.inode.get_id() as u32, nodetype) );
-			match self.add_dir_ent(name, ino_id)
+			let d_type = self.inode.fs.dirent_type(if is_dir { ::ondisk::S_IFDIR } else { ::ondisk::S_IFREG });
+			match self.add_dir_ent(name, ino_id, d_type)
 			{
-			Ok(()) => Ok(ino_id as vfs::node::InodeId),
+			Ok(()) => {
+				if is_dir {
+					// The new directory's '..' links back here
+					try!(self.inode.inc_link_count());
+				}
+				Ok(ino_id as vfs::node::InodeId)
+				},
 			Err(e) => {
-				// Call with_inode to force the inode to be deallocated
-				let _ = self.inode.fs.with_inode(ino_id, |_| Ok(()));
+				// Release the (unreferenced) inode
+				// - This doesn't go via the VFS, to avoid caching a node for it
+				if let Ok(ino) = ::inodes::Inode::from_id(self.inode.fs.reborrow(), ino_id) {
+					let _ = ino.destroy();
+				}
 				Err(e)
 				},
 			}
@@ -228,22 +311,43 @@ impl vfs::node::Dir for Dir
 		}
 		else if name.len() > 255
 		{
-			Err(vfs::Error::Unknown("Filename too long"))
+			Err(vfs::Error::InvalidParameter)
 		}
 		else
 		{
+			let file: &::file::File = match node.get_any().downcast_ref()
+				{
+				Some(v) => v,
+				// - Directories can't be hard linked
+				None if node.get_any().is::<Dir>() => return Err(vfs::Error::PermissionDenied),
+				None => return Err(vfs::Error::CrossFilesystem),
+				};
+			let ino = file.inode();
+			if &*ino.fs as *const ::instance::InstanceInner != &*self.inode.fs as *const _ {
+				return Err(vfs::Error::CrossFilesystem);
+			}
+
 			let _lh = self.inode.write_lock();
 
-			// TODO: How can I be sure that the passed inode number is valid? (or that it stays valid)
-			let inode = node.get_id();
-			try!(self.add_dir_ent(name, inode as u32));
-			// 3. Update inode's link count
-			try!(self.inode.fs.with_inode(inode as u32, |ino| {
-				ino.inc_link_count();
-				Ok( () )
-				}));
-			
-			todo!("link(name={:?}, inode={:?})", name, inode);
+			match self.find_name(name)
+			{
+			Ok(_) => return Err(vfs::Error::AlreadyExists),
+			Err(vfs::Error::NotFound) => {},
+			Err(e) => return Err(e),
+			}
+
+			// 1. Update inode's link count (first, so a failure can't leave an under-counted inode)
+			try!(ino.inc_link_count());
+			// 2. Add the entry
+			let d_type = self.inode.fs.dirent_type(ino.i_mode_fmt());
+			match self.add_dir_ent(name, ino.get_id() as u32, d_type)
+			{
+			Ok(()) => Ok( () ),
+			Err(e) => {
+				let _ = ino.dec_link_count();
+				Err(e)
+				},
+			}
 		}
 	}
 	fn unlink(&self, name: &ByteStr) -> vfs::node::Result<()> {
@@ -260,27 +364,75 @@ impl vfs::node::Dir for Dir
 			let _lh = self.inode.write_lock();
 
 			let (blk, ofs, _) = try!(self.find_name(name));
+			let inode = try!(self.clear_dir_ent(blk, ofs));
 
-			let vol_blk = try!( self.inode.blocks_from(blk as u32).next_or_err() );
-
-			self.inode.fs.edit_block(vol_blk, |blk_data| {
-				match ::ondisk::DirEnt::new_mut(&mut blk_data[ofs/4 ..])
-				{
-				None => return Err(vfs::Error::InconsistentFilesystem),
-				Some(ent) => {
-					// Clear name length
-					ent.d_name_len = 0;
-					// Decrement inode's reference count
-					try!(self.inode.fs.with_inode(ent.d_inode, |ino| {
-						ino.dec_link_count();
-						Ok( () )
-						}));
-					Ok( () )
-					},
+			// Decrement inode's reference count (it's released once no longer open)
+			// NOTE: The VFS has already checked that directories are empty
+			self.inode.fs.with_inode(inode, |ino| {
+				if ino.i_mode_fmt() == ::ondisk::S_IFDIR {
+					// The directory's '..' no longer refers to us, and its own '.' goes with it
+					try!(self.inode.dec_link_count());
+					try!(ino.dec_link_count());
 				}
+				ino.dec_link_count()
 				})
 		}
 	}
+	fn rename(&self, old_name: &ByteStr, new_dir: &vfs::node::Dir, new_name: &ByteStr) -> vfs::node::Result<()> {
+		if self.inode.fs.is_readonly()
+		{
+			Err( vfs::Error::ReadOnlyFilesystem )
+		}
+		else if old_name == "" || new_name == ""
+		{
+			Err( vfs::Error::InvalidParameter )
+		}
+		else if new_name.len() > 255
+		{
+			Err(vfs::Error::InvalidParameter)
+		}
+		else
+		{
+			let new_dir: &Dir = match new_dir.get_any().downcast_ref()
+				{
+				Some(v) => v,
+				None => return Err(vfs::Error::CrossFilesystem),
+				};
+			let same_dir = new_dir.inode.get_id() == self.inode.get_id();
+
+			// Lock both directories (in inode order, to avoid deadlocking with a reverse rename)
+			let (_lh1, _lh2) = if same_dir {
+					(self.inode.write_lock(), None)
+				}
+				else if self.inode.get_id() < new_dir.inode.get_id() {
+					(self.inode.write_lock(), Some(new_dir.inode.write_lock()))
+				}
+				else {
+					(new_dir.inode.write_lock(), Some(self.inode.write_lock()))
+				};
+
+			let (blk, ofs, inode) = try!(self.find_name(old_name));
+			match new_dir.find_name(new_name)
+			{
+			Ok(_) => return Err(vfs::Error::AlreadyExists),
+			Err(vfs::Error::NotFound) => {},
+			Err(e) => return Err(e),
+			}
+			let mode_fmt = try!(self.inode.fs.with_inode(inode as u32, |ino| Ok(ino.i_mode_fmt())));
+
+			// Add the new name before removing the old one, so the inode is always reachable
+			try!(new_dir.add_dir_ent(new_name, inode as u32, self.inode.fs.dirent_type(mode_fmt)));
+			try!(self.clear_dir_ent(blk, ofs));
+
+			if !same_dir && mode_fmt == ::ondisk::S_IFDIR {
+				// Moving a directory to a new parent, so update '..' (and the link it represents)
+				try!(self.inode.fs.with_inode(inode as u32, |ino| set_parent(ino, new_dir.inode.get_id() as u32)));
+				try!(new_dir.inode.inc_link_count());
+				try!(self.inode.dec_link_count());
+			}
+			Ok( () )
+		}
+	}
 }
 
 
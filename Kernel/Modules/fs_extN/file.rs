@@ -5,6 +5,9 @@
 //! Regular file
 use kernel::vfs;
 
+/// Largest file size supported (the high 32 bits of the size aren't used)
+const MAX_FILE_SIZE: u64 = 0xFFFF_FFFF;
+
 pub struct File
 {
 	inode: ::inodes::Inode,
@@ -20,9 +23,89 @@ impl File
 			}
 	}
 
+	pub fn inode(&self) -> &::inodes::Inode {
+		&self.inode
+	}
+
 	fn fs_block_size(&self) -> usize {
 		self.inode.fs.fs_block_size
 	}
+
+	/// Write data, allocating blocks as required (the inode's write lock must be held)
+	fn write_locked(&self, ofs: u64, buf: &[u8]) -> vfs::Result<usize>
+	{
+		let bs = self.fs_block_size();
+		let mut written = 0;
+		while written < buf.len()
+		{
+			let pos = ofs + written as u64;
+			let (blk_idx, blk_ofs) = ::kernel::lib::num::div_rem(pos, bs as u64);
+			let blk_ofs = blk_ofs as usize;
+			let len = ::core::cmp::min(bs - blk_ofs, buf.len() - written);
+
+			// Partially written blocks are read-modify-write (with new blocks zeroed first)
+			let is_partial = len != bs;
+			let blkid = match self.inode.get_or_alloc_block(blk_idx as u32, is_partial)
+				{
+				Ok(v) => v,
+				// Return a short write if the disk fills up part-way through
+				Err(vfs::Error::OutOfSpace) if written > 0 => break,
+				Err(e) => return Err(e),
+				};
+			let src = &buf[written ..][.. len];
+			if is_partial {
+				try!(self.inode.fs.edit_block(blkid, |blk_data| {
+					::kernel::lib::as_byte_slice_mut(blk_data)[blk_ofs ..][.. len].clone_from_slice(src);
+					Ok( () )
+					}));
+			}
+			else {
+				try!(self.inode.fs.write_blocks(blkid, src));
+			}
+			written += len;
+
+			if pos + len as u64 > self.inode.i_size() {
+				self.inode.set_size(pos + len as u64);
+			}
+		}
+		Ok( written )
+	}
+
+	/// Resize the file (the inode's write lock must be held)
+	fn truncate_locked(&self, newsize: u64) -> vfs::node::Result<u64>
+	{
+		let bs = self.fs_block_size() as u64;
+		let size = self.inode.i_size();
+		if newsize < size
+		{
+			self.inode.set_size(newsize);
+			try!(self.inode.free_blocks_from( ::kernel::lib::num::div_up(newsize, bs) as u32 ));
+		}
+		else if newsize > size
+		{
+			// Zero the tail of the current final block (it may contain stale data)
+			let tail_ofs = (size % bs) as usize;
+			if tail_ofs != 0 {
+				let blkid = try!(self.inode.get_block_addr( (size / bs) as u32 ));
+				if blkid != 0 {
+					try!(self.inode.fs.edit_block(blkid, |blk_data| {
+						for b in &mut ::kernel::lib::as_byte_slice_mut(blk_data)[tail_ofs ..] {
+							*b = 0;
+						}
+						Ok( () )
+						}));
+				}
+			}
+			// Then allocate zeroed blocks for the new space
+			for blk_idx in ::kernel::lib::num::div_up(size, bs) .. ::kernel::lib::num::div_up(newsize, bs)
+			{
+				try!(self.inode.get_or_alloc_block(blk_idx as u32, true));
+				self.inode.set_size( ::core::cmp::min(newsize, (blk_idx + 1) * bs) );
+			}
+			self.inode.set_size(newsize);
+		}
+		Ok( newsize )
+	}
 }
 
 impl vfs::node::NodeBase for File
@@ -33,6 +116,9 @@ impl vfs::node::NodeBase for File
 	fn get_any(&self) -> &::core::any::Any {
 		self
 	}
+	fn get_metadata(&self) -> vfs::node::Metadata {
+		self.inode.get_metadata()
+	}
 }
 impl vfs::node::File for File
 {
@@ -74,12 +160,13 @@ impl vfs::node::File for File
 			let blk_data = ::kernel::lib::as_byte_slice(&blk_data[..]);
 			if buf.len() <= partial_bytes
 			{
-				buf.clone_from_slice( blk_data );
-				read_bytes += buf.len();
+				let len = buf.len();
+				buf.clone_from_slice( &blk_data[blk_ofs ..][.. len] );
+				read_bytes += len;
 			}
 			else
 			{
-				buf[..partial_bytes].clone_from_slice(blk_data);
+				buf[..partial_bytes].clone_from_slice( &blk_data[blk_ofs ..] );
 				read_bytes += partial_bytes;
 			}
 		}
@@ -101,7 +188,8 @@ impl vfs::node::File for File
 		{
 			let blk_data = try!(self.inode.fs.get_block_uncached( try!(blocks.next_or_err()) ));
 			let blk_data = ::kernel::lib::as_byte_slice(&blk_data[..]);
-			buf[read_bytes..].clone_from_slice(&blk_data);
+			let len = buf.len() - read_bytes;
+			buf[read_bytes..].clone_from_slice( &blk_data[.. len] );
 			read_bytes = buf.len();
 		}
 
@@ -110,21 +198,20 @@ impl vfs::node::File for File
 	}
 
 	fn truncate(&self, newsize: u64) -> vfs::node::Result<u64> {
-		if newsize == 0
-		{
-			todo!("truncate - 0");
-		}
-		else if newsize == self.inode.i_size()
+		if self.inode.fs.is_readonly()
 		{
-			Ok( newsize )
+			Err( vfs::Error::ReadOnlyFilesystem )
 		}
-		else if newsize < self.inode.i_size()
+		else if newsize > MAX_FILE_SIZE
 		{
-			todo!("truncate - shrink");
+			Err( vfs::Error::FileTooLarge )
 		}
 		else
 		{
-			todo!("truncate - grow");
+			let _lh = self.inode.write_lock();
+			let rv = self.truncate_locked(newsize);
+			try!(self.inode.flush());
+			rv
 		}
 	}
 	fn clear(&self, ofs: u64, size: u64) -> vfs::node::Result<()> {
@@ -136,10 +223,25 @@ impl vfs::node::File for File
 			Err( vfs::Error::InvalidParameter )
 		}
 		else {
-			// 1. Leading partial
-			// 2. Inner
-			// 3. Trailing partial
-			todo!("clear");
+			let bs = self.fs_block_size() as u64;
+			let mut pos = ofs;
+			while pos < ofs + size
+			{
+				let (blk_idx, blk_ofs) = ::kernel::lib::num::div_rem(pos, bs);
+				let len = ::core::cmp::min(bs - blk_ofs, ofs + size - pos) as usize;
+				let blkid = try!(self.inode.get_block_addr(blk_idx as u32));
+				// - Unallocated (sparse) blocks are already empty
+				if blkid != 0 {
+					try!(self.inode.fs.edit_block(blkid, |blk_data| {
+						for b in &mut ::kernel::lib::as_byte_slice_mut(blk_data)[blk_ofs as usize ..][.. len] {
+							*b = 0;
+						}
+						Ok( () )
+						}));
+				}
+				pos += len as u64;
+			}
+			Ok( () )
 		}
 	}
 	fn write(&self, ofs: u64, buf: &[u8]) -> vfs::Result<usize> {
@@ -147,43 +249,20 @@ impl vfs::node::File for File
 		{
 			Err( vfs::Error::ReadOnlyFilesystem )
 		}
-		else if ofs == self.inode.i_size()
-		{
-			todo!("write - extend");
-		}
-		else if ofs > self.inode.i_size() || buf.len() as u64 > self.inode.i_size() || ofs + buf.len() as u64 > self.inode.i_size() {
+		else if ofs > self.inode.i_size() {
 			Err( vfs::Error::InvalidParameter )
 		}
+		else if ofs + buf.len() as u64 > MAX_FILE_SIZE {
+			Err( vfs::Error::FileTooLarge )
+		}
 		else {
-			// NOTE: In this section, we're free to read-modify-write blocks without fear, as the VFS itself handles
-			//       the file "borrow checking". A file race is the userland's problem (if a SharedRW handle is used)
-			let (blk_idx, blk_ofs) = ::kernel::lib::num::div_rem(ofs, self.fs_block_size() as u64);
-			let mut blocks = self.inode.blocks_from(blk_idx as u32);
-			let mut written = 0;
-			// 1. Leading partial
-			let blk_ofs = blk_ofs as usize;
-			if blk_ofs > 0
-			{
-				todo!("write - mutate - leading partial {}", blk_ofs);
-				//written += blk_ofs;
-			}
-			// 2. Inner
-			while buf.len() - written >= self.fs_block_size()
-			{
-				let remain_blocks = (buf.len() - written)/self.fs_block_size();
-				let (blkid, count) = try!(blocks.next_extent_or_err( remain_blocks as u32 ));
-				let byte_count = count as usize * self.fs_block_size();
-				try!(self.inode.fs.write_blocks(blkid, &buf[written ..][.. byte_count]));
-				written += byte_count;
-			}
-			// 3. Trailing partial
-			let trailing_bytes = buf.len() - written;
-			if trailing_bytes > 0
-			{
-				todo!("write - mutate - trailing partial {}", trailing_bytes);
-			}
-
-			Ok( written )
+			// NOTE: The lock is only needed to serialise block allocation and size updates, read-modify-write of
+			//       blocks is safe as the VFS itself handles the file "borrow checking". A file race is the
+			//       userland's problem (if a SharedRW handle is used)
+			let _lh = self.inode.write_lock();
+			let rv = self.write_locked(ofs, buf);
+			try!(self.inode.flush());
+			rv
 		}
 	}
 }
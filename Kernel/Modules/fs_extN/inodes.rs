@@ -2,17 +2,23 @@
 //
 //
 //! 
+use kernel::prelude::*;
 use instance::InstancePtr;
 use kernel::vfs;
+use kernel::sync::RwLock;
 use core::sync::atomic::{AtomicBool,Ordering};
 
 pub struct Inode
 {
 	pub fs: InstancePtr,
 	inode_idx: u32,
-	ondisk: ::ondisk::Inode,
+	ondisk: RwLock<::ondisk::Inode>,
+	/// Held for write while the inode's contents are modified (see `write_lock`)
+	lock: RwLock<()>,
 
 	is_dirty: AtomicBool,
+	/// Set when the last link is removed, the inode is released when this (the only in-memory copy) is dropped
+	is_unlinked: AtomicBool,
 }
 
 impl Inode
@@ -23,24 +29,61 @@ impl Inode
 		Ok(Inode {
 			fs: fs,
 			inode_idx: id,
-			ondisk: od,
+			ondisk: RwLock::new(od),
+			lock: RwLock::new( () ),
 			is_dirty: AtomicBool::new(false),
+			is_unlinked: AtomicBool::new(false),
 			})
 	}
 
-	pub fn dec_link_count(&self) {
-		todo!("Inode::dec_link_count");
+	/// Remove a link to this inode
+	///
+	/// Once the last link is gone, the inode is released when it's dropped (i.e. when the VFS closes the last
+	/// handle), so open files keep their contents and the inode number isn't reused while still cached.
+	pub fn dec_link_count(&self) -> vfs::Result<()> {
+		let remaining = self.edit(|od| {
+			od.i_links_count = od.i_links_count.saturating_sub(1);
+			od.i_links_count
+			});
+		if remaining == 0 {
+			self.is_unlinked.store(true, Ordering::Relaxed);
+		}
+		self.flush()
 	}
-	pub fn inc_link_count(&self) {
-		todo!("Inode::inc_link_count");
+	pub fn inc_link_count(&self) -> vfs::Result<()> {
+		self.edit(|od| od.i_links_count += 1);
+		self.flush()
 	}
 
+	/// Release all blocks owned by this inode and return it to the free pool
+	///
+	/// Used once the last link is gone and the inode is no longer in use
+	pub fn destroy(&self) -> vfs::Result<()> {
+		let is_dir = self.i_mode_fmt() == ::ondisk::S_IFDIR;
+		try!(self.free_blocks_from(0));
+		self.edit(|od| {
+			od.i_links_count = 0;
+			od.i_size = 0;
+			// No wall clock is available, but a non-zero deletion time is what marks the inode as deleted
+			od.i_dtime = ::core::cmp::max(od.i_ctime, 1);
+			});
+		try!(self.flush());
+		self.fs.free_inode(self.inode_idx, is_dir)
+	}
+
+	/// Update the cached on-disk data (written back by `flush`)
+	fn edit<F: FnOnce(&mut ::ondisk::Inode)->R, R>(&self, f: F) -> R {
+		let rv = f(&mut self.ondisk.write());
+		self.is_dirty.store(true, Ordering::Relaxed);
+		rv
+	}
 
 	pub fn flush(&self) -> vfs::Result<()>
 	{
 		if self.is_dirty.swap(false, Ordering::Relaxed)
 		{
-			try!(self.fs.write_inode(self.inode_idx, &self.ondisk));
+			let od = *self.ondisk.read();
+			try!(self.fs.write_inode(self.inode_idx, &od));
 		}
 		Ok( () )
 	}
@@ -50,6 +93,12 @@ impl Drop for Inode
 {
 	fn drop(&mut self)
 	{
+		if self.is_unlinked.load(Ordering::Relaxed)
+		{
+			if let Err(e) = self.destroy() {
+				log_warning!("Inode::drop - Unable to release unlinked inode {}: {:?}", self.inode_idx, e);
+			}
+		}
 		if self.is_dirty.load(Ordering::Relaxed)
 		{
 			log_warning!("Inode::drop - Dirty node being dropped, writing back and ignoring errors");
@@ -61,10 +110,34 @@ impl Drop for Inode
 impl Inode
 {
 	pub fn i_mode_fmt(&self) -> u16 {
-		self.ondisk.i_mode & ::ondisk::S_IFMT
+		self.ondisk.read().i_mode & ::ondisk::S_IFMT
 	}
 	pub fn i_size(&self) -> u64 {
-		self.ondisk.i_size as u64
+		self.ondisk.read().i_size as u64
+	}
+	/// Set the size (the caller is responsible for allocating/releasing blocks)
+	pub fn set_size(&self, size: u64) {
+		assert!(size <= ::core::u32::MAX as u64);
+		self.edit(|od| od.i_size = size as u32);
+	}
+
+	pub fn get_metadata(&self) -> vfs::node::Metadata {
+		use kernel::time::Timestamp;
+		let od = self.ondisk.read();
+		// Linux stores the high 16 bits of the UID/GID in the OS-dependent area
+		let ids_hi = od._osd2[1];
+		vfs::node::Metadata {
+			inode: self.get_id(),
+			size: od.i_size as u64,
+			link_count: od.i_links_count as u32,
+			mode: od.i_mode & !::ondisk::S_IFMT,
+			uid: od.i_uid as u32 | (ids_hi & 0xFFFF) << 16,
+			gid: od.i_gid as u32 | (ids_hi >> 16) << 16,
+			// NOTE: `i_ctime` is the inode change time, not creation
+			created: None,
+			modified: Some(Timestamp::from_unix_seconds(od.i_mtime as i64)),
+			accessed: Some(Timestamp::from_unix_seconds(od.i_atime as i64)),
+			}
 	}
 }
 
@@ -86,8 +159,9 @@ impl Inode
 
 impl Inode
 {
+	/// Lock the inode for modification (serialising directory updates and file resizes)
 	pub fn write_lock(&self) -> ::kernel::sync::rwlock::Write<()> {
-		todo!("Inode::write_lock");
+		self.lock.write()
 	}
 
 	pub fn get_extent_from_block(&self, block_idx: u32, max_blocks: u32) -> vfs::node::Result<(u32, u32)>
@@ -102,13 +176,14 @@ impl Inode
 		let di_base = si_base + u32_per_fs_block;
 		let ti_base = di_base + u32_per_fs_block*u32_per_fs_block;
 
+		let i_block = self.ondisk.read().i_block;
 		if block_idx < si_base
 		{
-			let fs_start = self.ondisk.i_block[block_idx as usize];
+			let fs_start = i_block[block_idx as usize];
 			let max_blocks = ::core::cmp::min( si_base - block_idx, max_blocks );
 			for num in 1 .. max_blocks
 			{
-				if fs_start + num != self.ondisk.i_block[(block_idx + num) as usize] {
+				if fs_start + num != i_block[(block_idx + num) as usize] {
 					return Ok( (fs_start, num) );
 				}
 			}
@@ -118,7 +193,7 @@ impl Inode
 		{
 			let idx = block_idx - si_base;
 			// TODO: Have locally a mutex-protected cached filesystem block (linked to a global cache manager)
-			let si_block = try!( self.fs.get_block( self.ondisk.i_block[SI_BLOCK] ) );
+			let si_block = try!( self.fs.get_block( i_block[SI_BLOCK] ) );
 			
 			let fs_start = si_block[idx as usize];
 			let max_blocks = ::core::cmp::min( di_base - block_idx, max_blocks );
@@ -134,7 +209,7 @@ impl Inode
 		{
 			let idx = block_idx - di_base;
 			let (blk, idx) = (idx / u32_per_fs_block, idx % u32_per_fs_block);
-			let di_block = try!( self.fs.get_block( self.ondisk.i_block[DI_BLOCK] ) );
+			let di_block = try!( self.fs.get_block( i_block[DI_BLOCK] ) );
 			let di_block = try!( self.fs.get_block( di_block[blk as usize] ) );
 
 
@@ -154,7 +229,7 @@ impl Inode
 			let idx = block_idx - ti_base;
 			let (blk, idx) = (idx / u32_per_fs_block, idx % u32_per_fs_block);
 			let (blk_o, blk_i) = (blk / u32_per_fs_block, blk % u32_per_fs_block);
-			let ti_block = try!( self.fs.get_block( self.ondisk.i_block[TI_BLOCK] ) );
+			let ti_block = try!( self.fs.get_block( i_block[TI_BLOCK] ) );
 			let ti_block = try!( self.fs.get_block( ti_block[blk_o as usize] ) );
 			let ti_block = try!( self.fs.get_block( ti_block[blk_i as usize] ) );
 
@@ -171,46 +246,55 @@ impl Inode
 		}
 	}
 
+	/// Returns the address of the specified block (zero if it hasn't been allocated)
 	pub fn get_block_addr(&self, block_idx: u32) -> vfs::node::Result<u32>
+	{
+		let (slot, path, depth) = self.block_path(block_idx);
+		let mut addr = self.ondisk.read().i_block[slot];
+		for &idx in &path[..depth]
+		{
+			if addr == 0 {
+				break ;
+			}
+			// TODO: Have locally a mutex-protected cached filesystem block (linked to a global cache manager)
+			addr = try!( self.fs.get_block(addr) )[idx as usize];
+		}
+		Ok( addr )
+	}
+
+	/// Decompose a block index into the `i_block` slot, and the index within each level of indirect block
+	///
+	/// Returns (slot, indexes, depth)
+	fn block_path(&self, block_idx: u32) -> (usize, [u32; 3], usize)
 	{
 		let u32_per_fs_block = (self.fs.fs_block_size / ::core::mem::size_of::<u32>()) as u32;
 
 		let si_base = 12;
-		let di_base = 12 + u32_per_fs_block ;
-		let ti_base = 12 + u32_per_fs_block + u32_per_fs_block*u32_per_fs_block;
+		let di_base = si_base + u32_per_fs_block;
+		let ti_base = di_base + u32_per_fs_block*u32_per_fs_block;
 
 		if block_idx < si_base
 		{
 			// Direct block
-			Ok( self.ondisk.i_block[block_idx as usize] )
+			(block_idx as usize, [0; 3], 0)
 		}
 		else if block_idx < di_base
 		{
 			// Single-indirect block
-			let idx = block_idx - si_base;
-			// TODO: Have locally a mutex-protected cached filesystem block (linked to a global cache manager)
-			let si_block = try!( self.fs.get_block( self.ondisk.i_block[12] ) );
-			Ok( si_block[ idx as usize ] )
+			(12, [block_idx - si_base, 0, 0], 1)
 		}
 		else if block_idx < ti_base
 		{
 			// Double-indirect block
 			let idx = block_idx - di_base;
-			let (blk, idx) = (idx / u32_per_fs_block, idx % u32_per_fs_block);
-			let di_block = try!( self.fs.get_block( self.ondisk.i_block[13] ) );
-			let di_block = try!( self.fs.get_block( di_block[blk as usize] ) );
-			Ok( di_block[idx as usize] )
+			(13, [idx / u32_per_fs_block, idx % u32_per_fs_block, 0], 2)
 		}
 		else
 		{
 			// Triple-indirect block
 			let idx = block_idx - ti_base;
 			let (blk, idx) = (idx / u32_per_fs_block, idx % u32_per_fs_block);
-			let (blk_o, blk_i) = (blk / u32_per_fs_block, blk % u32_per_fs_block);
-			let ti_block = try!( self.fs.get_block( self.ondisk.i_block[14] ) );
-			let ti_block = try!( self.fs.get_block( ti_block[blk_o as usize] ) );
-			let ti_block = try!( self.fs.get_block( ti_block[blk_i as usize] ) );
-			Ok( ti_block[idx as usize] )
+			(14, [blk / u32_per_fs_block, blk % u32_per_fs_block, idx], 3)
 		}
 	}
 
@@ -232,6 +316,151 @@ impl Inode
 	}
 }
 
+/// Block map modification
+impl Inode
+{
+	/// Obtain the address of a block, allocating it (and any indirect blocks needed to reach it) if not present
+	///
+	/// Newly allocated data blocks are zeroed if `zero` is set
+	pub fn get_or_alloc_block(&self, block_idx: u32, zero: bool) -> vfs::node::Result<u32>
+	{
+		let (slot, path, depth) = self.block_path(block_idx);
+
+		let mut addr = self.ondisk.read().i_block[slot];
+		if addr == 0 {
+			addr = try!(self.alloc_block(depth > 0 || zero));
+			self.edit(|od| od.i_block[slot] = addr);
+		}
+		for (level, &idx) in path[..depth].iter().enumerate()
+		{
+			// NOTE: The block handle is released before the block is edited
+			let next = try!(self.fs.get_block(addr))[idx as usize];
+			addr = if next != 0 {
+					next
+				}
+				else {
+					let new_block = try!(self.alloc_block(level + 1 < depth || zero));
+					try!(self.fs.edit_block(addr, |blk_data| { blk_data[idx as usize] = new_block; Ok( () ) }));
+					new_block
+				};
+		}
+		Ok( addr )
+	}
+
+	/// Release all blocks from `first_block` onwards (including indirect blocks that are no longer needed)
+	pub fn free_blocks_from(&self, first_block: u32) -> vfs::node::Result<()>
+	{
+		let u32_per_fs_block = (self.fs.fs_block_size / ::core::mem::size_of::<u32>()) as u64;
+
+		let i_block = self.ondisk.read().i_block;
+		let mut base = 0u64;
+		for slot in 0 .. 15
+		{
+			let (depth, count) = match slot
+				{
+				0 ... 11 => (0, 1),
+				12 => (1, u32_per_fs_block),
+				13 => (2, u32_per_fs_block * u32_per_fs_block),
+				_ => (3, u32_per_fs_block * u32_per_fs_block * u32_per_fs_block),
+				};
+			if i_block[slot] != 0 && base + count > first_block as u64
+			{
+				let released = if depth == 0 {
+						try!(self.release_block(i_block[slot]));
+						true
+					}
+					else {
+						try!(self.free_indirect(i_block[slot], depth, base, first_block as u64))
+					};
+				if released {
+					self.edit(|od| od.i_block[slot] = 0);
+				}
+			}
+			base += count;
+		}
+		Ok( () )
+	}
+
+	/// Release blocks from `first_block` onwards within the indirect block `addr` (`depth` levels above the data
+	/// blocks, with the first entry covering block `base`)
+	///
+	/// Returns true if the indirect block itself was released
+	fn free_indirect(&self, addr: u32, depth: u32, base: u64, first_block: u64) -> vfs::node::Result<bool>
+	{
+		let u32_per_fs_block = (self.fs.fs_block_size / ::core::mem::size_of::<u32>()) as u64;
+		let span = u32_per_fs_block.pow(depth - 1);
+
+		let mut entries = Vec::new();
+		entries.extend_from_slice( &try!(self.fs.get_block(addr))[..] );
+
+		let mut cleared = Vec::new();
+		let mut in_use = false;
+		for (i, &ent) in entries.iter().enumerate()
+		{
+			let ent_base = base + i as u64 * span;
+			if ent == 0 {
+				continue ;
+			}
+			if ent_base + span <= first_block {
+				in_use = true;
+				continue ;
+			}
+			let released = if depth == 1 {
+					try!(self.release_block(ent));
+					true
+				}
+				else {
+					try!(self.free_indirect(ent, depth - 1, ent_base, first_block))
+				};
+			if released {
+				cleared.push(i);
+			}
+			else {
+				in_use = true;
+			}
+		}
+
+		if !in_use {
+			try!(self.release_block(addr));
+			Ok( true )
+		}
+		else {
+			if cleared.len() > 0 {
+				try!(self.fs.edit_block(addr, |blk_data| {
+					for &i in &cleared {
+						blk_data[i] = 0;
+					}
+					Ok( () )
+					}));
+			}
+			Ok( false )
+		}
+	}
+
+	/// Allocate a block for this inode (accounted in `i_blocks`), optionally zeroing it
+	fn alloc_block(&self, zero: bool) -> vfs::node::Result<u32>
+	{
+		let block = try!(self.fs.allocate_block(self.inode_idx));
+		if zero {
+			if let Err(e) = self.fs.edit_block(block, |blk_data| { for v in blk_data.iter_mut() { *v = 0; } Ok( () ) }) {
+				let _ = self.fs.free_block(block);
+				return Err(e);
+			}
+		}
+		let sectors = self.fs.sectors_per_block();
+		self.edit(|od| od.i_blocks += sectors);
+		Ok( block )
+	}
+	/// Release a block owned by this inode
+	fn release_block(&self, block: u32) -> vfs::node::Result<()>
+	{
+		try!(self.fs.free_block(block));
+		let sectors = self.fs.sectors_per_block();
+		self.edit(|od| od.i_blocks = od.i_blocks.saturating_sub(sectors));
+		Ok( () )
+	}
+}
+
 /// Iterator over block numbers owned by an inode
 pub struct Blocks<'a>
 {
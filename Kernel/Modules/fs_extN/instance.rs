@@ -19,7 +19,20 @@ pub struct InstanceInner
 	pub fs_block_size: usize,
 
 	mount_handle: vfs::mount::SelfHandle,
+	/// Group descriptors and superblock free counts (modified by allocations)
+	alloc_state: ::kernel::sync::Mutex<AllocState>,
+}
+
+struct AllocState
+{
 	group_descriptors: Vec<::ondisk::GroupDesc>,
+	free_blocks: u32,
+	free_inodes: u32,
+}
+
+/// Byte offset of the group descriptor table (the filesystem block following the superblock)
+fn gdt_offset(sb: &::ondisk::Superblock, fs_block_size: usize) -> u64 {
+	(sb.data.s_first_data_block as u64 + 1) * fs_block_size as u64
 }
 
 pub enum FeatureState
@@ -72,14 +85,11 @@ impl Instance
 		let superblock_idx = (1024 / vol_bs) as u64;
 		let superblock_ofs = (1024 % vol_bs) as usize;
 
-		let (superblock, first_block) = {
+		let superblock = {
 			let mut first_block: Vec<u32> = vec![0; ::core::cmp::max(1024, vol_bs)/4];
 			try!(vol.read_blocks(superblock_idx, ::kernel::lib::as_byte_slice_mut(&mut first_block[..])));
 			assert!(superblock_ofs % 4 == 0);
-			(
-				*::ondisk::Superblock::from_slice(&first_block[superblock_ofs/4 ..][..1024/4]),
-				first_block,
-				)
+			*::ondisk::Superblock::from_slice(&first_block[superblock_ofs/4 ..][..1024/4])
 			};
 
 
@@ -106,53 +116,23 @@ impl Instance
 		let num_groups = ::kernel::lib::num::div_up(superblock.data.s_blocks_count, superblock.data.s_blocks_per_group);
 
 		// Read group descriptor table
-		// - This always resides immediately after the superblock
+		// - This always resides in the filesystem block after the superblock
 		let group_descs = {
-			use kernel::lib::{as_byte_slice_mut,as_byte_slice};
 			#[allow(non_snake_case)]
 			let GROUP_DESC_SIZE = ::core::mem::size_of::<::ondisk::GroupDesc>();
 
-			let groups_per_vol_block = vol_bs / GROUP_DESC_SIZE;
-
 			let mut gds: Vec<::ondisk::GroupDesc> = vec![Default::default(); num_groups as usize];
 
-			let (n_skip, mut block) = if vol_bs % (2*1024) == 0 {
-					// Volume block size is larger than the superblock
-					// - This means that at least 2048 bytes of the group descriptors are in the same block as the superblock
-					let n_shared = (vol_bs - 2*1024) / GROUP_DESC_SIZE;
-
-					let src = as_byte_slice(&first_block[2*1024/4..]);
-					let count = ::core::cmp::min(n_shared, gds.len());
-					assert_eq!(src.len(), count * GROUP_DESC_SIZE);
-					as_byte_slice_mut(&mut gds[..count]).clone_from_slice( src );
-					(count, 1)
-				}
-				else {
-					// Volume BS <= superblock
-					(0, (2*1024 / vol_bs) as u64)
-				};
+			let gdt_ofs = gdt_offset(&superblock, fs_block_size);
+			let (block, ofs) = ::kernel::lib::num::div_rem(gdt_ofs, vol_bs as u64);
+			let ofs = ofs as usize;
+			let n_bytes = gds.len() * GROUP_DESC_SIZE;
+			log_trace!("GDT: block={}, ofs={}, n_bytes={}", block, ofs, n_bytes);
 
-			let rem_count = gds.len() - n_skip;
-			let tail_count = rem_count % groups_per_vol_block;
-			let body_count = rem_count - tail_count;
-			log_trace!("n_skip={}, block={}, rem_count={},  tail_count={}, body_count={}",
-				n_skip, block, rem_count,  tail_count, body_count);
-
-			if body_count > 0 
-			{
-				try!(vol.read_blocks(block, as_byte_slice_mut(&mut gds[n_skip .. ][ .. body_count])));
-				block += (body_count / groups_per_vol_block) as u64;
-			}
-
-			if tail_count > 0
-			{
-				let ofs = n_skip + body_count;
-				// Read a single volume block into a buffer, then populate from that
-				let mut buf: Vec<u8> = vec![0; vol_bs];
-				try!(vol.read_blocks(block, &mut buf));
-				let n_bytes = (gds.len() - ofs) * GROUP_DESC_SIZE;
-				as_byte_slice_mut(&mut gds[ofs ..]).clone_from_slice( &buf[..n_bytes] );
-			}
+			// Read the covering volume blocks into a buffer, then populate from that
+			let mut buf: Vec<u8> = vec![0; ::kernel::lib::num::round_up(ofs + n_bytes, vol_bs)];
+			try!(vol.read_blocks(block, &mut buf));
+			::kernel::lib::as_byte_slice_mut(&mut gds[..]).clone_from_slice( &buf[ofs ..][.. n_bytes] );
 
 			gds
 			};
@@ -167,7 +147,11 @@ impl Instance
 			is_readonly: is_readonly,
 			fs_block_size: fs_block_size,
 			superblock: superblock,
-			group_descriptors: group_descs,
+			alloc_state: ::kernel::sync::Mutex::new(AllocState {
+				group_descriptors: group_descs,
+				free_blocks: superblock.data.s_free_blocks_count,
+				free_inodes: superblock.data.s_free_inodes_count,
+				}),
 			mount_handle: mount_handle,
 			vol: ::block_cache::CacheHandle::new(vol),
 			};
@@ -210,6 +194,9 @@ impl vfs::mount::Filesystem for Instance
 			},
 		}
 	}
+	fn sync(&self) -> vfs::Result<()> {
+		Ok( try!(self.0.vol.flush()) )
+	}
 }
 
 impl InstanceInner
@@ -218,6 +205,23 @@ impl InstanceInner
 	{
 		self.is_readonly
 	}
+
+	/// Directory entry type for an inode format (zero if the filesystem doesn't store types in entries)
+	pub fn dirent_type(&self, mode_fmt: u16) -> u8
+	{
+		if self.superblock.data.s_rev_level == 0 || self.superblock.ext.s_feature_incompat & ::ondisk::FEAT_INCOMPAT_FILETYPE == 0 {
+			0
+		}
+		else {
+			match mode_fmt
+			{
+			::ondisk::S_IFREG => ::ondisk::FT_REG_FILE,
+			::ondisk::S_IFDIR => ::ondisk::FT_DIR,
+			::ondisk::S_IFLNK => ::ondisk::FT_SYMLINK,
+			_ => 0,
+			}
+		}
+	}
 }
 
 /// Structure representing a view into a BlockCache entry
@@ -300,8 +304,15 @@ impl InstanceInner
 	/// Write a sequence of blocks from a user-provided buffer
 	pub fn write_blocks(&self, first_block: u32, data: &[u8]) -> vfs::node::Result<()>
 	{
-		// TODO: Requires maybe interfacing with the cache used by get_block?
-		try!( self.vol.write_blocks( first_block as u64 * self.vol_blocks_per_fs_block(), data) );
+		assert!(data.len() % self.fs_block_size == 0);
+		// Written through the cache, as freed metadata blocks (or metadata sharing a cache page) may still be cached
+		for (i, src) in data.chunks(self.fs_block_size).enumerate()
+		{
+			try!(self.edit_block(first_block + i as u32, |blk_data| {
+				::kernel::lib::as_byte_slice_mut(blk_data).clone_from_slice(src);
+				Ok( () )
+				}));
+		}
 		Ok( () )
 	}
 }
@@ -320,7 +331,8 @@ impl InstanceInner
 	fn get_inode_pos(&self, inode_num: u32) -> (u64, usize) {
 		let (group, ofs) = self.get_inode_grp_id(inode_num);
 
-		let base_blk_id = self.group_descriptors[group as usize].bg_inode_table as u64 * self.vol_blocks_per_fs_block();
+		let inode_table = self.alloc_state.lock().group_descriptors[group as usize].bg_inode_table;
+		let base_blk_id = inode_table as u64 * self.vol_blocks_per_fs_block();
 		let ofs_bytes = (ofs as usize) * self.s_inode_size();
 		let (sub_blk_id, sub_blk_ofs) = (ofs_bytes / self.vol.block_size(), ofs_bytes % self.vol.block_size());
 
@@ -337,30 +349,74 @@ impl InstanceInner
 		// - This prevents us from having to maintain our own node cache
 
 		let node = try!(self.mount_handle.get_node(inode_num as vfs::node::InodeId));
-		match node.get_any().downcast_ref()
-		{
-		Some(our_in) => fcn(our_in),
-		None => Err(vfs::Error::Unknown("BUG: Node wasn't an extN inode")),
+		let node_any = node.get_any();
+		if let Some(file) = node_any.downcast_ref::<::file::File>() {
+			fcn(file.inode())
+		}
+		else if let Some(dir) = node_any.downcast_ref::<::dir::Dir>() {
+			fcn(dir.inode())
+		}
+		else {
+			Err(vfs::Error::Unknown("BUG: Node wasn't an extN inode"))
 		}
 	}
 
 	/// Allocate a new inode number, possibly in the same block group as `parent_inode_num`.
+	///
+	/// The new inode has a single link, and directories are populated with '.' and '..' entries (the caller is
+	/// responsible for updating the parent's link count)
 	pub fn allocate_inode(&self, parent_inode_num: u32, nodetype: vfs::node::NodeType) -> vfs::node::Result< u32 >
 	{
-		let (grp, _idx) = self.get_inode_grp_id(parent_inode_num);
-		let gd = &self.group_descriptors[grp as usize];
-		if gd.bg_free_inodes_count > 0
-		{
-			todo!("InstanceInner::allocate_inode - Current BG");
-		}
-		else if self.superblock.data.s_free_inodes_count > 0
-		{
-			todo!("InstanceInner::allocate_inode - Search for any BG");
-		}
-		else
+		let mode = match nodetype
+			{
+			vfs::node::NodeType::File => ::ondisk::S_IFREG | 0o644,
+			vfs::node::NodeType::Dir => ::ondisk::S_IFDIR | 0o755,
+			vfs::node::NodeType::Symlink(_) => return Err(vfs::Error::Unknown("TODO: Creating extN symbolic links")),
+			};
+		let is_dir = mode & ::ondisk::S_IFMT == ::ondisk::S_IFDIR;
+
+		let inode_num = try!(self.allocate_inode_num(parent_inode_num, is_dir));
+
+		let mut inode = ::ondisk::Inode::default();
+		inode.i_mode = mode;
+		inode.i_links_count = 1;
+		if is_dir
 		{
-			Err(vfs::Error::OutOfSpace)
+			// Directories start with a single block, containing '.' and '..'
+			// - '.' counts as the second link
+			let block = match self.allocate_block(inode_num)
+				{
+				Ok(v) => v,
+				Err(e) => {
+					try!(self.free_inode(inode_num, is_dir));
+					return Err(e);
+					},
+				};
+			let d_type = self.dirent_type(::ondisk::S_IFDIR);
+			let fs_block_size = self.fs_block_size;
+			try!(self.edit_block(block, |blk_data| {
+				for v in blk_data.iter_mut() {
+					*v = 0;
+				}
+				let dot_len = ::ondisk::DirEnt::size_for(1);
+				::ondisk::DirEnt::init(blk_data, inode_num, dot_len as u16, d_type, b".");
+				::ondisk::DirEnt::init(&mut blk_data[dot_len/4 ..], parent_inode_num, (fs_block_size - dot_len) as u16, d_type, b"..");
+				Ok( () )
+				}));
+			inode.i_links_count = 2;
+			inode.i_size = self.fs_block_size as u32;
+			inode.i_blocks = self.sectors_per_block();
+			inode.i_block[0] = block;
 		}
+
+		// Clear the entire slot (including any extended fields), then write the new inode
+		try!(self.edit_inode_slot(inode_num, |slot| {
+			for b in slot.iter_mut() {
+				*b = 0;
+			}
+			}));
+		try!(self.write_inode(inode_num, &inode));
+		Ok( inode_num )
 	}
 
 	/// Read an inode descriptor from the disk
@@ -371,21 +427,201 @@ impl InstanceInner
 
 		let mut rv = ::ondisk::Inode::default();
 		{
-			// NOTE: Unused fields in the inode are zero
-			let slice = &mut ::kernel::lib::as_byte_slice_mut(&mut rv)[.. self.s_inode_size()];
-			try!( self.vol.read_inner(vol_block, blk_ofs, slice) );
+			// NOTE: Unused fields in the inode are zero, and extended fields are ignored
+			let len = ::core::cmp::min(::core::mem::size_of::<::ondisk::Inode>(), self.s_inode_size());
+			let cached_block = try!(self.vol.get_block(vol_block));
+			let ofs = (vol_block - cached_block.index()) as usize * self.vol.block_size() + blk_ofs;
+			::kernel::lib::as_byte_slice_mut(&mut rv)[.. len].clone_from_slice( &cached_block.data()[ofs ..][.. len] );
 		}
 		log_trace!("- rv={:?}", rv);
 		Ok( rv )
 	}
 	/// Write an inode descriptor back to the disk
 	pub fn write_inode(&self, inode_num: u32, inode_data: &::ondisk::Inode) -> vfs::Result< () >
+	{
+		let src = ::kernel::lib::as_byte_slice(inode_data);
+		self.edit_inode_slot(inode_num, |slot| {
+			let len = ::core::cmp::min(src.len(), slot.len());
+			slot[.. len].clone_from_slice( &src[.. len] );
+			})
+	}
+
+	/// Edit the raw on-disk slot (`s_inode_size` bytes) for an inode
+	fn edit_inode_slot<F: FnOnce(&mut [u8])>(&self, inode_num: u32, f: F) -> vfs::Result< () >
 	{
 		let (vol_block, blk_ofs) = self.get_inode_pos(inode_num);
-		
-		let slice = &::kernel::lib::as_byte_slice(inode_data)[.. self.s_inode_size()];
-		try!( self.vol.write_inner(vol_block, blk_ofs, slice) );
+		let size = self.s_inode_size();
+		try!( self.vol.edit(vol_block, 1, |data| f(&mut data[blk_ofs ..][.. size])) );
+		Ok( () )
+	}
+}
+
+/// Block and inode allocation
+impl InstanceInner
+{
+	/// Allocate a block, preferring the block group containing `near_inode`
+	pub fn allocate_block(&self, near_inode: u32) -> vfs::node::Result<u32>
+	{
+		let (start_grp, _) = self.get_inode_grp_id(near_inode);
 
+		let mut lh = self.alloc_state.lock();
+		if lh.free_blocks == 0 {
+			return Err(vfs::Error::OutOfSpace);
+		}
+		let n_groups = lh.group_descriptors.len() as u32;
+		for grp in (start_grp .. n_groups).chain(0 .. start_grp)
+		{
+			let gd = lh.group_descriptors[grp as usize];
+			if gd.bg_free_blocks_count == 0 {
+				continue ;
+			}
+			match try!(self.bitmap_alloc(gd.bg_block_bitmap, self.group_block_count(grp)))
+			{
+			Some(idx) => {
+				lh.group_descriptors[grp as usize].bg_free_blocks_count -= 1;
+				lh.free_blocks -= 1;
+				try!(self.write_alloc_state(&lh, grp));
+				return Ok( self.superblock.data.s_first_data_block + grp * self.superblock.data.s_blocks_per_group + idx );
+				},
+			None => log_warning!("{}: Group #{} reports {} free blocks, but the bitmap is full",
+				self.vol.name(), grp, gd.bg_free_blocks_count),
+			}
+		}
+		Err(vfs::Error::OutOfSpace)
+	}
+	/// Return a block to the free pool
+	pub fn free_block(&self, block: u32) -> vfs::node::Result<()>
+	{
+		let first_block = self.superblock.data.s_first_data_block;
+		if block < first_block || block >= self.superblock.data.s_blocks_count {
+			log_error!("{}: Freeing out-of-range block {}", self.vol.name(), block);
+			return Err(vfs::Error::InconsistentFilesystem);
+		}
+		let (grp, idx) = ::kernel::lib::num::div_rem(block - first_block, self.superblock.data.s_blocks_per_group);
+
+		let mut lh = self.alloc_state.lock();
+		let bitmap = lh.group_descriptors[grp as usize].bg_block_bitmap;
+		if try!(self.bitmap_free(bitmap, idx)) {
+			lh.group_descriptors[grp as usize].bg_free_blocks_count += 1;
+			lh.free_blocks += 1;
+			try!(self.write_alloc_state(&lh, grp));
+		}
+		else {
+			log_warning!("{}: Block {} freed while already free", self.vol.name(), block);
+		}
+		Ok( () )
+	}
+
+	/// Allocate an inode number, preferring the block group containing `parent_inode_num`
+	fn allocate_inode_num(&self, parent_inode_num: u32, is_dir: bool) -> vfs::node::Result<u32>
+	{
+		let (start_grp, _) = self.get_inode_grp_id(parent_inode_num);
+
+		let mut lh = self.alloc_state.lock();
+		if lh.free_inodes == 0 {
+			return Err(vfs::Error::OutOfSpace);
+		}
+		let n_groups = lh.group_descriptors.len() as u32;
+		for grp in (start_grp .. n_groups).chain(0 .. start_grp)
+		{
+			let gd = lh.group_descriptors[grp as usize];
+			if gd.bg_free_inodes_count == 0 {
+				continue ;
+			}
+			match try!(self.bitmap_alloc(gd.bg_inode_bitmap, self.s_inodes_per_group()))
+			{
+			Some(idx) => {
+				{
+					let gd = &mut lh.group_descriptors[grp as usize];
+					gd.bg_free_inodes_count -= 1;
+					if is_dir {
+						gd.bg_used_dirs_count += 1;
+					}
+				}
+				lh.free_inodes -= 1;
+				try!(self.write_alloc_state(&lh, grp));
+				return Ok( grp * self.s_inodes_per_group() + idx + 1 );
+				},
+			None => log_warning!("{}: Group #{} reports {} free inodes, but the bitmap is full",
+				self.vol.name(), grp, gd.bg_free_inodes_count),
+			}
+		}
+		Err(vfs::Error::OutOfSpace)
+	}
+	/// Return an inode number to the free pool
+	pub fn free_inode(&self, inode_num: u32, is_dir: bool) -> vfs::node::Result<()>
+	{
+		let (grp, idx) = self.get_inode_grp_id(inode_num);
+
+		let mut lh = self.alloc_state.lock();
+		let bitmap = lh.group_descriptors[grp as usize].bg_inode_bitmap;
+		if try!(self.bitmap_free(bitmap, idx)) {
+			{
+				let gd = &mut lh.group_descriptors[grp as usize];
+				gd.bg_free_inodes_count += 1;
+				if is_dir {
+					gd.bg_used_dirs_count -= 1;
+				}
+			}
+			lh.free_inodes += 1;
+			try!(self.write_alloc_state(&lh, grp));
+		}
+		else {
+			log_warning!("{}: Inode {} freed while already free", self.vol.name(), inode_num);
+		}
+		Ok( () )
+	}
+
+	/// Find and set the first clear bit (below `count`) in a bitmap block
+	fn bitmap_alloc(&self, bitmap_block: u32, count: u32) -> vfs::node::Result<Option<u32>>
+	{
+		self.edit_block(bitmap_block, |words| {
+			for (i, w) in words.iter_mut().enumerate()
+			{
+				if *w == !0 {
+					continue ;
+				}
+				let idx = i as u32 * 32 + (!*w).trailing_zeros();
+				if idx >= count {
+					break ;
+				}
+				*w |= 1 << (idx % 32);
+				return Ok( Some(idx) );
+			}
+			Ok( None )
+			})
+	}
+	/// Clear a bit in a bitmap block, returning false if it was already clear
+	fn bitmap_free(&self, bitmap_block: u32, idx: u32) -> vfs::node::Result<bool>
+	{
+		self.edit_block(bitmap_block, |words| {
+			let mask = 1 << (idx % 32);
+			let word = &mut words[(idx / 32) as usize];
+			let was_set = *word & mask != 0;
+			*word &= !mask;
+			Ok( was_set )
+			})
+	}
+
+	/// Write a group descriptor and the superblock's free counts back to disk
+	///
+	/// NOTE: The backup copies aren't updated (matching other implementations, fsck only checks the primary)
+	fn write_alloc_state(&self, state: &AllocState, group: u32) -> vfs::node::Result<()>
+	{
+		use kernel::lib::as_byte_slice;
+		let vol_bs = self.vol.block_size() as u64;
+
+		let gd_ofs = gdt_offset(&self.superblock, self.fs_block_size) + group as u64 * ::core::mem::size_of::<::ondisk::GroupDesc>() as u64;
+		let (block, ofs) = ::kernel::lib::num::div_rem(gd_ofs, vol_bs);
+		let src = as_byte_slice(&state.group_descriptors[group as usize]);
+		try!(self.vol.edit(block, 1, |data| data[ofs as usize ..][.. src.len()].clone_from_slice(src)));
+
+		let (block, ofs) = ::kernel::lib::num::div_rem((1024 + ::ondisk::S_FREE_COUNTS_OFS) as u64, vol_bs);
+		try!(self.vol.edit(block, 1, |data| {
+			let data = &mut data[ofs as usize ..];
+			data[0..4].clone_from_slice( as_byte_slice(&state.free_blocks) );
+			data[4..8].clone_from_slice( as_byte_slice(&state.free_inodes) );
+			}));
 		Ok( () )
 	}
 }
@@ -401,6 +637,17 @@ impl InstanceInner
 		(self.fs_block_size / self.vol.block_size()) as u64
 	}
 
+	/// Number of 512-byte units in a block (the unit of `i_blocks`)
+	pub fn sectors_per_block(&self) -> u32 {
+		(self.fs_block_size / 512) as u32
+	}
+
+	/// Number of blocks in a group (the final group may be short)
+	fn group_block_count(&self, group: u32) -> u32 {
+		let first_block = self.superblock.data.s_first_data_block + group * self.superblock.data.s_blocks_per_group;
+		::core::cmp::min(self.superblock.data.s_blocks_per_group, self.superblock.data.s_blocks_count - first_block)
+	}
+
 	fn s_inode_size(&self) -> usize {
 		if self.superblock.data.s_rev_level > 0 {
 			self.superblock.ext.s_inode_size as usize
@@ -6,6 +6,8 @@
 #![allow(dead_code)]
 
 pub const S_MAGIC_OFS: usize = (3*4*4 + 2*4);
+/// Offset of `s_free_blocks_count` (followed by `s_free_inodes_count`) in the superblock
+pub const S_FREE_COUNTS_OFS: usize = 3*4;
 
 macro_rules! pod_impls {
 	($t:ty) => {
@@ -361,6 +363,11 @@ pub struct DirEnt
 }
 pub const DIRENT_MIN_SIZE: usize = 8;
 
+// Values for `DirEnt.d_type`
+pub const FT_REG_FILE: u8 = 1;
+pub const FT_DIR: u8 = 2;
+pub const FT_SYMLINK: u8 = 7;
+
 //pod_impls!{ DirEnt }
 
 impl DirEnt
@@ -407,10 +414,24 @@ impl DirEnt
 	}
 
 
+	/// Write a new entry header and name into the start of `buf`
+	pub fn init(buf: &mut [u32], inode: u32, rec_len: u16, d_type: u8, name: &[u8])
+	{
+		assert!(name.len() <= 255);
+		assert!(Self::size_for(name.len()) <= rec_len as usize && rec_len as usize <= buf.len() * 4);
+		buf[0] = inode;
+		buf[1] = rec_len as u32 | (name.len() as u32) << 16 | (d_type as u32) << 24;
+		::kernel::lib::as_byte_slice_mut(&mut buf[2..])[.. name.len()].clone_from_slice(name);
+	}
+
 	/// Returns the number of 32-bit integers this entry takes up
 	pub fn u32_len(&self) -> usize {
 		(self.d_rec_len as usize + 3) / 4
 	}
+	/// Returns the minimum record length for a name of the specified length
+	pub fn size_for(name_len: usize) -> usize {
+		(DIRENT_MIN_SIZE + name_len + 3) & !3
+	}
 }
 
 impl_fmt! {
@@ -7,16 +7,26 @@ use kernel::lib::mem::aref::ArefBorrow;
 use kernel::vfs::{self, node};
 use kernel::lib::byte_str::ByteStr;
 use super::on_disk;
+use super::names;
 use super::file::FileNode;
 use super::ClusterList;
 use super::FilesystemInner;
 use utf16::Str16;
 
+/// Date stored in new entries (1980-01-01), as there is no wall clock to source the current date from
+const DEFAULT_DATE: u16 = (1 << 5) | 1;
+/// Maximum number of entries in a (non fixed-root) directory
+const MAX_DIR_ENTS: usize = 0x10000;
+
 pub struct DirNode
 {
 	fs: ArefBorrow<::FilesystemInner>,
 	start_cluster: u32,
 	// - Uses the cluster chain
+	/// Inode ID (location of the parent's entry)
+	id: node::InodeId,
+	/// Metadata from the parent's entry (defaults for the root)
+	metadata: node::Metadata,
 }
 impl_fmt! {
 	Debug(self, f) for DirNode {
@@ -24,11 +34,23 @@ impl_fmt! {
 	}
 }
 
+/// Location and contents of a named entry
+struct FoundEnt
+{
+	/// Index of the first long name entry (or the short entry, if there is no long name)
+	first_idx: u16,
+	/// Index of the short entry
+	idx: u16,
+	ent: DirEntShort,
+}
+
 impl DirNode {
 	pub fn new(fs: ArefBorrow<FilesystemInner>, start_cluster: u32) -> DirNode {
 		DirNode {
+			id: if start_cluster == fs.root_first_cluster { super::InodeRef::root(start_cluster).to_id() } else { 0 },
 			fs: fs,
 			start_cluster: start_cluster,
+			metadata: node::Metadata { mode: 0o777, link_count: 1, ..Default::default() },
 		}
 	}
 	pub fn new_boxed(fs: ArefBorrow<FilesystemInner>, start_cluster: u32) -> Box<DirNode> {
@@ -38,11 +60,14 @@ impl DirNode {
 
 impl node::NodeBase for DirNode {
 	fn get_id(&self) -> node::InodeId {
-		todo!("DirNode::get_id")
+		self.id
 	}
 	fn get_any(&self) -> &::core::any::Any {
 		self
 	}
+	fn get_metadata(&self) -> node::Metadata {
+		self.metadata.clone()
+	}
 }
 
 impl DirNode {
@@ -58,45 +83,337 @@ impl DirNode {
 			ClusterList::Chained(self.fs.reborrow(), self.start_cluster)
 		}
 	}
+	/// Upper limit on the number of entries in this directory
+	fn max_slots(&self) -> usize {
+		if self.is_fixed_root() {
+			self.fs.root_sector_count as usize * self.fs.bps / 32
+		}
+		else {
+			MAX_DIR_ENTS
+		}
+	}
+	/// Cluster number stored in a child directory's '..' entry
+	fn dotdot_cluster(&self) -> u32 {
+		if self.start_cluster == self.fs.root_first_cluster { 0 } else { self.start_cluster }
+	}
 
-	/// Locate a node in this directory by its first data cluster
-	pub fn find_node(&self, ent_cluster: u32) -> Option<node::Node>
+	/// Obtain the node described by the short entry at index `idx`
+	pub fn node_at(&self, idx: u16) -> Option<node::Node>
 	{
-		match self.find_ent_by_cluster(ent_cluster)
+		let e = match self.with_slot(idx, |slot| DirEnts::new(slot).next().unwrap())
+			{
+			Ok(DirEnt::Short(e)) => e,
+			Ok(_) => return None,
+			Err(e) => {
+				log_warning!("node_at({:?}, {}): Error reading entry - {:?}", self, idx, e);
+				return None;
+				},
+			};
+		if e.attributes & on_disk::ATTR_DIRECTORY != 0 {
+			// A zero cluster (in '..') refers to the root
+			let mut dn = if e.cluster == 0 {
+					DirNode::new(self.fs.reborrow(), self.fs.root_first_cluster)
+				}
+				else {
+					let mut dn = DirNode::new(self.fs.reborrow(), e.cluster);
+					dn.id = super::InodeRef::new(self.start_cluster, idx).to_id();
+					dn
+				};
+			dn.metadata = e.metadata();
+			Some(node::Node::Dir(Box::new(dn)))
+		}
+		else {
+			Some(node::Node::File(FileNode::new_boxed(
+				self.fs.reborrow(), self.start_cluster, idx, e.cluster, e.size, e.metadata()
+				)))
+		}
+	}
+
+	/// Call `f` with each raw entry (and its index) until it returns false
+	///
+	/// Returns the index at which iteration stopped
+	fn scan_slots<F: FnMut(usize, &[u8]) -> bool>(&self, mut f: F) -> vfs::Result<usize> {
+		let max = self.max_slots();
+		let mut idx = 0;
+		for c in self.clusters()
 		{
-		None => None,
-		Some(e) =>
-			if e.attributes & on_disk::ATTR_DIRECTORY != 0 {
-				Some(node::Node::Dir(DirNode::new_boxed(self.fs.reborrow(), ent_cluster)))
-			}
-			else if e.attributes & on_disk::ATTR_VOLUMEID != 0 {
-				None
+			let cluster = try!(self.fs.load_cluster(c));
+			for slot in cluster.chunks(32)
+			{
+				if idx == max || !f(idx, slot) {
+					return Ok(idx);
+				}
+				idx += 1;
 			}
-			else {
-				Some(node::Node::File(FileNode::new_boxed(
-					self.fs.reborrow(), self.start_cluster, ent_cluster, e.size
-					)))
-			},
 		}
+		Ok(idx)
 	}
-	
-	fn find_ent_by_cluster(&self, ent_cluster: u32) -> Option<DirEntShort> {
-		log_trace!("find_ent_by_cluster(self={:?}, ent_cluster={})", self, ent_cluster);
-		for c in self.clusters()
+	/// Run `f` on the raw entry at index `idx`
+	fn with_slot<R, F: FnOnce(&[u8]) -> R>(&self, idx: u16, f: F) -> vfs::Result<R> {
+		let ents_per_cluster = self.fs.cluster_size / 32;
+		let c = match self.clusters().nth(idx as usize / ents_per_cluster)
+			{
+			Some(v) => v,
+			None => return Err(vfs::Error::InconsistentFilesystem),
+			};
+		let cluster = try!(self.fs.load_cluster(c));
+		Ok( f(&cluster[(idx as usize % ents_per_cluster) * 32 ..][..32]) )
+	}
+	/// Modify `count` raw entries starting at `start`, writing the affected clusters back to disk
+	///
+	/// NOTE: Caller must hold `fs.dir_lock`
+	fn edit_slots<F: FnMut(usize, &mut [u8])>(&self, start: usize, count: usize, mut f: F) -> vfs::Result<()> {
+		let ents_per_cluster = self.fs.cluster_size / 32;
+		let end = start + count;
+		let mut clusters = self.clusters().skip(start / ents_per_cluster);
+		let mut idx = start;
+		while idx < end
 		{
-			let cluster = match self.fs.load_cluster(c) {
-				Ok(v) => v,
-				Err(_) => return None,
+			let c = match clusters.next()
+				{
+				Some(v) => v,
+				None => return Err(vfs::Error::InconsistentFilesystem),
 				};
-			for ent in DirEnts::new(&cluster) {
-				if let DirEnt::Short(e) = ent {
-					if e.cluster == ent_cluster {
-						return Some(e);
+			let mut data = vec![0u8; self.fs.cluster_size];
+			data.clone_from_slice( &try!(self.fs.load_cluster(c))[..] );
+			let base = idx - idx % ents_per_cluster;
+			while idx < end && idx < base + ents_per_cluster
+			{
+				f(idx, &mut data[(idx - base) * 32 ..][..32]);
+				idx += 1;
+			}
+			try!(self.fs.write_dir_cluster(c, &data));
+		}
+		Ok( () )
+	}
+
+	/// Locate an entry by name (ignoring ASCII case, as FAT is case-insensitive)
+	fn find_name(&self, name: &ByteStr) -> vfs::Result<Option<FoundEnt>> {
+		let mut lfn = LFN::new();
+		let mut lfn_start = 0;
+		let mut rv = None;
+		try!(self.scan_slots(|idx, slot| {
+			match DirEnts::new(slot).next().unwrap()
+			{
+			DirEnt::End => return false,
+			DirEnt::Short(e) => {
+				let has_lfn = lfn.is_valid();
+				if eq_ignore_case(e.name().as_bytes().iter().cloned(), name.as_bytes())
+					|| (has_lfn && eq_ignore_case(lfn.name().wtf8(), name.as_bytes()))
+				{
+					rv = Some(FoundEnt {
+						first_idx: (if has_lfn { lfn_start } else { idx }) as u16,
+						idx: idx as u16,
+						ent: e,
+						});
+					return false;
+				}
+				lfn.clear();
+				},
+			DirEnt::Long(e) => {
+				if e.id & 0x40 != 0 {
+					lfn_start = idx;
+				}
+				lfn.add(&e)
+				},
+			DirEnt::Empty => {
+				lfn.clear();
+				},
+			}
+			true
+			}));
+		Ok(rv)
+	}
+
+	/// Select a short name for `name`, returning the name, case flags, and if a long name is needed
+	fn pick_short_name(&self, name: &[u8]) -> vfs::Result<([u8; 11], u8, bool)> {
+		let mut existing = Vec::new();
+		try!(self.scan_slots(|_, slot| {
+			match slot[0]
+			{
+			0 => return false,
+			0xE5 => {},
+			_ => if slot[11] != on_disk::ATTR_LFN {
+					let mut n = [0u8; 11];
+					n.clone_from_slice(&slot[..11]);
+					existing.push(n);
+				},
+			}
+			true
+			}));
+		match names::pick_short_name(name, &existing)
+		{
+		Some(v) => Ok(v),
+		// - Every numeric tail is in use
+		None => Err(vfs::Error::OutOfSpace),
+		}
+	}
+
+	/// Locate `count` consecutive free entries, extending the directory if needed
+	///
+	/// Returns the first index, and if the entry after the run needs to become the end marker
+	fn find_free_run(&self, count: usize) -> vfs::Result<(usize, bool)> {
+		let mut run_start = 0;
+		let mut run_len = 0;
+		let mut seen_end = false;
+		let mut found = false;
+		let mut has_next = false;
+		let total = try!(self.scan_slots(|idx, slot| {
+			if found {
+				has_next = true;
+				return false;
+			}
+			if seen_end || slot[0] == 0 || slot[0] == 0xE5 {
+				if slot[0] == 0 {
+					seen_end = true;
+				}
+				if run_len == 0 {
+					run_start = idx;
+				}
+				run_len += 1;
+				found = run_len == count;
+			}
+			else {
+				run_len = 0;
+			}
+			true
+			}));
+		if found {
+			// If the run used the end marker, the following entry (if any) becomes the new marker
+			return Ok( (run_start, seen_end && has_next) );
+		}
+
+		// Not enough space, extend the directory with zeroed clusters (appending to any free run at the end)
+		let start = if run_len > 0 { run_start } else { total };
+		if self.is_fixed_root() || start + count > MAX_DIR_ENTS {
+			return Err(vfs::Error::OutOfSpace);
+		}
+		let ents_per_cluster = self.fs.cluster_size / 32;
+		let mut last = match self.clusters().last()
+			{
+			Some(v) => v,
+			None => return Err(vfs::Error::InconsistentFilesystem),
+			};
+		let mut have = total;
+		while have < start + count
+		{
+			last = try!(self.fs.alloc_cluster(Some(last), true));
+			have += ents_per_cluster;
+		}
+		Ok( (start, false) )
+	}
+
+	/// Add a new entry (with optional long name), returning the index of the short entry
+	///
+	/// NOTE: Caller must hold `fs.dir_lock`
+	fn add_entry(&self, lfn: Option<&[u16]>, ent: &on_disk::DirEnt) -> vfs::Result<u16> {
+		let lfn_count = lfn.map(|n| (n.len() + 12) / 13).unwrap_or(0);
+		let count = lfn_count + 1;
+		let (start, set_end) = try!(self.find_free_run(count));
+		let checksum = on_disk::short_name_checksum(&ent.name);
+		try!(self.edit_slots(start, count + if set_end { 1 } else { 0 }, |idx, slot| {
+			let i = idx - start;
+			if i < lfn_count {
+				// Long name entries are stored last-first, and padded with 0xFFFF after the NUL terminator
+				let name = lfn.unwrap();
+				let seq = lfn_count - i;
+				let mut chars = [0xFFFFu16; 13];
+				for j in 0 .. 13
+				{
+					let p = (seq-1)*13 + j;
+					if p < name.len() {
+						chars[j] = name[p];
+					}
+					else if p == name.len() {
+						chars[j] = 0;
 					}
 				}
+				on_disk::DirEntLong {
+					id: seq as u8 | if i == 0 { 0x40 } else { 0 },
+					name1: [chars[0], chars[1], chars[2], chars[3], chars[4]],
+					attrib: on_disk::ATTR_LFN,
+					ty: 0,
+					checksum: checksum,
+					name2: [chars[5], chars[6], chars[7], chars[8], chars[9], chars[10]],
+					first_cluster: 0,
+					name3: [chars[11], chars[12]],
+					}.write(slot);
 			}
+			else if i == lfn_count {
+				ent.write(slot);
+			}
+			else {
+				slot[0] = 0;
+			}
+			}));
+		Ok( (start + lfn_count) as u16 )
+	}
+	/// Mark an entry (and its long name) as deleted
+	///
+	/// NOTE: Caller must hold `fs.dir_lock`
+	fn remove_entry(&self, ent: &FoundEnt) -> vfs::Result<()> {
+		self.edit_slots(ent.first_idx as usize, (ent.idx - ent.first_idx) as usize + 1, |_, slot| slot[0] = 0xE5)
+	}
+
+	/// Update the short entry at `idx`, provided it still refers to a node starting at `expected_cluster`
+	///
+	/// Used by open files to record their size and first cluster.
+	///
+	/// NOTE: Caller must hold `fs.dir_lock`
+	pub fn update_ent<F: FnOnce(&mut on_disk::DirEnt)>(&self, idx: u16, expected_cluster: u32, f: F) -> vfs::Result<()> {
+		let mut f = Some(f);
+		try!(self.edit_slots(idx as usize, 1, |_, slot| {
+			let mut ent = on_disk::DirEnt::read(&mut &slot[..]);
+			let cluster = (ent.cluster as u32) | (ent.cluster_hi as u32) << 16;
+			if ent.name[0] == 0 || ent.name[0] == 0xE5 || cluster != expected_cluster {
+				// - Open files track moves of their entry, so this is a corrupted directory
+				log_notice!("update_ent: Entry {} no longer refers to cluster {:#x}", idx, expected_cluster);
+				return ;
+			}
+			(f.take().unwrap())(&mut ent);
+			ent.write(slot);
+			}));
+		Ok( () )
+	}
+}
+
+/// Compare a name against a (byte) iterator, ignoring ASCII case
+fn eq_ignore_case<I: Iterator<Item=u8>>(mut a: I, b: &[u8]) -> bool {
+	let mut b = b.iter();
+	loop
+	{
+		match (a.next(), b.next())
+		{
+		(None, None) => return true,
+		(Some(x), Some(&y)) if x.to_ascii_lowercase() == y.to_ascii_lowercase() => {},
+		_ => return false,
 		}
-		None
+	}
+}
+
+/// Validate a name for a new entry, and encode it as UTF-16 (for the long name entries)
+fn encode_long_name(name: &ByteStr) -> vfs::Result<Vec<u16>> {
+	match names::encode_long_name(name.as_bytes())
+	{
+	Some(v) => Ok(v),
+	None => Err(vfs::Error::InvalidParameter),
+	}
+}
+/// Construct a new short entry
+fn new_short_ent(name: [u8; 11], lcase: u8, attribs: u8, cluster: u32) -> on_disk::DirEnt {
+	on_disk::DirEnt {
+		name: name,
+		attribs: attribs,
+		lcase: lcase,
+		creation_ds: 0,
+		creation_time: 0,
+		creation_date: DEFAULT_DATE,
+		accessed_date: DEFAULT_DATE,
+		cluster_hi: (cluster >> 16) as u16,
+		modified_time: 0,
+		modified_date: DEFAULT_DATE,
+		cluster: cluster as u16,
+		size: 0,
 	}
 }
 
@@ -121,9 +438,9 @@ struct DirEntShort {
 	cluster: u32,
 	size: u32,
 	attributes: u8,
-	//creation_time: ::kernel::time::Timestamp,
-	//modified_time: ::kernel::time::Timestamp,
-	//accessed_time: ::kernel::time::Timestamp,
+	creation_time: Option<::kernel::time::Timestamp>,
+	modified_time: Option<::kernel::time::Timestamp>,
+	accessed_time: Option<::kernel::time::Timestamp>,
 }
 impl_fmt! {
 	Debug(self,f) for DirEntShort {
@@ -231,6 +548,9 @@ impl<'a> ::core::iter::Iterator for DirEnts<'a> {
 					cluster: (ent.cluster as u32) | (ent.cluster_hi as u32) << 16,
 					size: ent.size,
 					attributes: ent.attribs,
+					creation_time: on_disk::decode_timestamp(ent.creation_date, ent.creation_time, ent.creation_ds),
+					modified_time: on_disk::decode_timestamp(ent.modified_date, ent.modified_time, 0),
+					accessed_time: on_disk::decode_timestamp(ent.accessed_date, 0, 0),
 					}) )
 			}
 		}
@@ -240,8 +560,17 @@ impl DirEntShort {
 	fn name(&self) -> &ByteStr {
 		ByteStr::new( (&self.name).split(|&e|e==0).next().unwrap() )
 	}
-	fn inode(&self, parent_dir: u32) -> node::InodeId {
-		super::InodeRef::new(self.cluster, parent_dir).to_id()
+	fn metadata(&self) -> node::Metadata {
+		node::Metadata {
+			size: self.size as u64,
+			link_count: 1,
+			// FAT has no permissions, just a read-only flag
+			mode: if self.attributes & on_disk::ATTR_READONLY != 0 { 0o555 } else { 0o777 },
+			created: self.creation_time,
+			modified: self.modified_time,
+			accessed: self.accessed_time,
+			..Default::default()
+			}
 	}
 }
 
@@ -296,34 +625,16 @@ impl LFN {
 
 impl node::Dir for DirNode {
 	fn lookup(&self, name: &ByteStr) -> node::Result<node::InodeId> {
-		// For each cluster in the directory, iterate
-		let mut lfn = LFN::new();
-		for c in self.clusters()
+		match try!(self.find_name(name))
 		{
-			let cluster = try!(self.fs.load_cluster(c));
-			for ent in DirEnts::new(&cluster)
-			{
-				match ent {
-				DirEnt::End => return Err(vfs::Error::NotFound),
-				DirEnt::Short(e) => {
-					if e.name() == name || lfn.name() == name {
-						return Ok( e.inode(self.start_cluster) );
-					}
-					lfn.clear();
-					},
-				DirEnt::Long(e) => lfn.add(&e),
-				DirEnt::Empty => {
-					lfn.clear();
-					},
-				}
-			}
+		Some(e) => Ok( super::InodeRef::new(self.start_cluster, e.idx).to_id() ),
+		None => Err(vfs::Error::NotFound),
 		}
-		Err(vfs::Error::NotFound)
 	}
 	fn read(&self, ofs: usize, callback: &mut node::ReadDirCallback) -> node::Result<usize> {
 		
 		let ents_per_cluster = self.fs.cluster_size / 32;
-		let (cluster_idx, c_ofs) = (ofs / ents_per_cluster, ofs % ents_per_cluster);
+		let (cluster_idx, mut c_ofs) = (ofs / ents_per_cluster, ofs % ents_per_cluster);
 		
 		let mut lfn = LFN::new();
 		let mut cur_ofs = ofs;
@@ -339,8 +650,12 @@ impl node::Dir for DirNode {
 					// On next call, we want to hit this entry (so we can return count=0)
 					return Ok(cur_ofs - 1);
 					},
+				// '.' and '..' are handled by the VFS
+				DirEnt::Short(ref e) if e.name() == "." || e.name() == ".." => {
+					lfn.clear();
+					},
 				DirEnt::Short(e) => {
-					let inode = e.inode(self.start_cluster);
+					let inode = super::InodeRef::new(self.start_cluster, (cur_ofs - 1) as u16).to_id();
 					let cont = if lfn.is_valid() {
 							callback(inode, &mut lfn.name().wtf8())
 						}
@@ -358,18 +673,138 @@ impl node::Dir for DirNode {
 					},
 				}
 			}
+			// Only the first cluster starts part-way through
+			c_ofs = 0;
 		}
 		
 		Ok( cur_ofs )
 	}
 	fn create(&self, name: &ByteStr, nodetype: node::NodeType) -> node::Result<node::InodeId> {
-		todo!("DirNode::create('{:?}', {:?})", name, nodetype);
+		let is_dir = match nodetype
+			{
+			node::NodeType::File => false,
+			node::NodeType::Dir => true,
+			// FAT has no symbolic links
+			node::NodeType::Symlink(_) => return Err(vfs::Error::PermissionDenied),
+			};
+		let lfn = try!(encode_long_name(name));
+
+		let _lh = self.fs.dir_lock.lock();
+		if try!(self.find_name(name)).is_some() {
+			return Err(vfs::Error::AlreadyExists);
+		}
+		let (short, lcase, need_lfn) = try!(self.pick_short_name(name.as_bytes()));
+
+		// Directories get their first cluster (containing '.' and '..') immediately, files start empty
+		let cluster = if is_dir {
+				let c = try!(self.fs.alloc_cluster(None, true));
+				let mut data = vec![0u8; self.fs.cluster_size];
+				new_short_ent(*b".          ", 0, on_disk::ATTR_DIRECTORY, c).write(&mut data[0..32]);
+				new_short_ent(*b"..         ", 0, on_disk::ATTR_DIRECTORY, self.dotdot_cluster()).write(&mut data[32..64]);
+				if let Err(e) = self.fs.write_dir_cluster(c, &data) {
+					try!(self.fs.free_chain(c));
+					return Err(e.into());
+				}
+				c
+			}
+			else {
+				0
+			};
+		let attribs = if is_dir { on_disk::ATTR_DIRECTORY } else { on_disk::ATTR_ARCHIVE };
+		let ent = new_short_ent(short, lcase, attribs, cluster);
+		match self.add_entry(if need_lfn { Some(&lfn[..]) } else { None }, &ent)
+		{
+		Ok(idx) => Ok( super::InodeRef::new(self.start_cluster, idx).to_id() ),
+		Err(e) => {
+			if cluster != 0 {
+				try!(self.fs.free_chain(cluster));
+			}
+			Err(e)
+			},
+		}
 	}
-	fn link(&self, name: &ByteStr, node: &node::NodeBase) -> node::Result<()> {
-		todo!("DirNode::link('{:?}', {:#x})", name, node.get_id());
+	fn link(&self, _name: &ByteStr, _node: &node::NodeBase) -> node::Result<()> {
+		// FAT has no hard links (nodes are identified by their single directory entry)
+		Err(vfs::Error::PermissionDenied)
 	}
 	fn unlink(&self, name: &ByteStr) -> node::Result<()> {
-		todo!("DirNode::unlink('{:?}')", name);
+		let _lh = self.fs.dir_lock.lock();
+		let found = match try!(self.find_name(name))
+			{
+			Some(v) => v,
+			None => return Err(vfs::Error::NotFound),
+			};
+		try!(self.remove_entry(&found));
+		// - If the file is open, the clusters are kept until it's closed (see `FileNode`'s Drop impl)
+		let is_open = {
+			let mut open_files = self.fs.open_files.lock();
+			match open_files.remove( &(self.start_cluster, found.idx) )
+			{
+			Some(f) => { f.set_location(None); true },
+			None => false,
+			}
+			};
+		if !is_open && found.ent.cluster != 0 {
+			try!(self.fs.free_chain(found.ent.cluster));
+		}
+		Ok( () )
 	}
-}
+	fn rename(&self, old_name: &ByteStr, new_dir: &node::Dir, new_name: &ByteStr) -> node::Result<()> {
+		let new_dir: &DirNode = match new_dir.get_any().downcast_ref()
+			{
+			Some(v) => v,
+			None => return Err(vfs::Error::CrossFilesystem),
+			};
+		if &*self.fs as *const FilesystemInner != &*new_dir.fs as *const FilesystemInner {
+			return Err(vfs::Error::CrossFilesystem);
+		}
+		let lfn = try!(encode_long_name(new_name));
+		let same_dir = self.start_cluster == new_dir.start_cluster;
+
+		let _lh = self.fs.dir_lock.lock();
+		let found = match try!(self.find_name(old_name))
+			{
+			Some(v) => v,
+			None => return Err(vfs::Error::NotFound),
+			};
+		match try!(new_dir.find_name(new_name))
+		{
+		// Changing the case of a name matches the existing entry
+		Some(ref e) if same_dir && e.idx == found.idx => {},
+		Some(_) => return Err(vfs::Error::AlreadyExists),
+		None => {},
+		}
+
+		// Copy the short entry under the new name, before removing the old one
+		let mut ent = try!(self.with_slot(found.idx, |slot| on_disk::DirEnt::read(&mut &slot[..])));
+		let (short, lcase, need_lfn) = try!(new_dir.pick_short_name(new_name.as_bytes()));
+		ent.name = short;
+		ent.lcase = lcase;
+		let new_idx = try!(new_dir.add_entry(if need_lfn { Some(&lfn[..]) } else { None }, &ent));
+		try!(self.remove_entry(&found));
 
+		// Open files are identified by their entry's location, so move them too
+		{
+			let mut open_files = self.fs.open_files.lock();
+			if let Some(f) = open_files.remove( &(self.start_cluster, found.idx) ) {
+				f.set_location(Some( (new_dir.start_cluster, new_idx) ));
+				open_files.insert( (new_dir.start_cluster, new_idx), f );
+			}
+		}
+
+		// A directory moved to a new parent needs its '..' entry updated
+		if !same_dir && found.ent.attributes & on_disk::ATTR_DIRECTORY != 0 && found.ent.cluster != 0 {
+			let parent = new_dir.dotdot_cluster();
+			let moved = DirNode::new(self.fs.reborrow(), found.ent.cluster);
+			try!(moved.edit_slots(1, 1, |_, slot| {
+				let mut e = on_disk::DirEnt::read(&mut &slot[..]);
+				if &e.name == b"..         " {
+					e.cluster = parent as u16;
+					e.cluster_hi = (parent >> 16) as u16;
+					e.write(slot);
+				}
+				}));
+		}
+		Ok( () )
+	}
+}
@@ -4,63 +4,292 @@
 // Modules/fs_fat/dir.rs
 use kernel::prelude::*;
 use kernel::lib::mem::aref::ArefBorrow;
+use kernel::lib::mem::Arc;
 use kernel::vfs::{self, node};
 use super::FilesystemInner;
 
 const ERROR_SHORTCHAIN: vfs::Error = vfs::Error::Unknown("Cluster chain terminated early");
+/// Largest file size representable in a directory entry
+const MAX_FILE_SIZE: u64 = 0xFFFF_FFFF;
 
 pub struct FileNode
 {
 	fs: ArefBorrow<FilesystemInner>,
-	//parent_dir: u32,
+	/// State shared through the filesystem's list of open files
+	shared: Arc<OpenFile>,
+	/// Metadata from the directory entry
+	metadata: node::Metadata,
+}
+/// An open file, updated by the directory code when the file's entry is moved or removed
+pub struct OpenFile
+{
+	/// First cluster of the containing directory and index of the short entry (`None` once unlinked)
+	///
+	/// NOTE: Only changed with `fs.dir_lock` held
+	location: ::kernel::sync::Mutex<Option<(u32,u16)>>,
+	state: ::kernel::sync::RwLock<FileState>,
+}
+impl OpenFile
+{
+	/// Record that the file's entry has moved
+	pub fn set_location(&self, location: Option<(u32,u16)>) {
+		*self.location.lock() = location;
+	}
+}
+/// Allocation state (mirrored in the directory entry)
+struct FileState
+{
 	first_cluster: u32,
 	size: u32,
 }
 
 impl FileNode
 {
-	pub fn new_boxed(fs: ArefBorrow<FilesystemInner>, _parent: u32, first_cluster: u32, size: u32) -> Box<FileNode> {	
+	pub fn new_boxed(fs: ArefBorrow<FilesystemInner>, parent_dir: u32, dir_idx: u16, first_cluster: u32, size: u32, metadata: node::Metadata) -> Box<FileNode> {	
+		// If the file is already open, share that state (it may be newer than the entry)
+		let shared = {
+			use kernel::lib::vec_map::Entry;
+			match fs.open_files.lock().entry( (parent_dir, dir_idx) )
+			{
+			Entry::Occupied(e) => e.get().clone(),
+			Entry::Vacant(e) => e.insert(Arc::new(OpenFile {
+				location: ::kernel::sync::Mutex::new(Some( (parent_dir, dir_idx) )),
+				state: ::kernel::sync::RwLock::new(FileState {
+					first_cluster: first_cluster,
+					size: size,
+					}),
+				})).clone(),
+			}
+			};
 		Box::new(FileNode {
 			fs: fs,
-			//parent_dir: parent,
-			first_cluster: first_cluster,
-			size: size,
+			shared: shared,
+			metadata: metadata,
 			})
 	}
+
+	/// Record the current size and first cluster in the directory entry
+	fn update_dirent(&self, old_first_cluster: u32, st: &FileState) -> vfs::Result<()> {
+		let (size, cluster) = (st.size, st.first_cluster);
+		let _lh = self.fs.dir_lock.lock();
+		let (parent_dir, dir_idx) = match *self.shared.location.lock()
+			{
+			Some(v) => v,
+			// - Unlinked, there's no entry to update
+			None => return Ok( () ),
+			};
+		super::dir::DirNode::new(self.fs.reborrow(), parent_dir).update_ent(dir_idx, old_first_cluster, |e| {
+			e.size = size;
+			e.cluster = cluster as u16;
+			e.cluster_hi = (cluster >> 16) as u16;
+			})
+	}
+
+	/// Write data (with the state locked), allocating clusters as required
+	///
+	/// Returns a short count if the volume fills up
+	fn write_locked(&self, st: &mut FileState, ofs: u64, buf: &[u8]) -> vfs::Result<usize> {
+		if ofs > st.size as u64 {
+			return Err( vfs::Error::InvalidParameter );
+		}
+		if buf.len() == 0 {
+			return Ok(0);
+		}
+		let buf = if ofs + buf.len() as u64 > MAX_FILE_SIZE {
+				if ofs == MAX_FILE_SIZE {
+					return Err( vfs::Error::FileTooLarge );
+				}
+				&buf[.. (MAX_FILE_SIZE - ofs) as usize]
+			}
+			else {
+				buf
+			};
+		let cluster_size = self.fs.cluster_size;
+
+		// Obtain the cluster chain covering the write, extending it if needed
+		let needed = ((ofs + buf.len() as u64 + cluster_size as u64 - 1) / cluster_size as u64) as usize;
+		let mut chain: Vec<u32> = if st.first_cluster == 0 {
+				Vec::new()
+			}
+			else {
+				super::ClusterList::chained(self.fs.reborrow(), st.first_cluster).take(needed).collect()
+			};
+		let mut alloc_err = None;
+		while chain.len() < needed
+		{
+			let prev = chain.last().cloned();
+			match self.fs.alloc_cluster(prev, false)
+			{
+			Ok(c) => {
+				if prev.is_none() {
+					st.first_cluster = c;
+				}
+				chain.push(c);
+				},
+			Err(e) => {
+				alloc_err = Some(e);
+				break;
+				},
+			}
+		}
+		let avail = (chain.len() * cluster_size) as u64;
+		if avail <= ofs {
+			return Err( alloc_err.unwrap_or(vfs::Error::OutOfSpace) );
+		}
+		let len = ::core::cmp::min(buf.len() as u64, avail - ofs) as usize;
+
+		let mut done = 0;
+		while done < len
+		{
+			let pos = ofs + done as u64;
+			let c_idx = (pos / cluster_size as u64) as usize;
+			let c_ofs = (pos % cluster_size as u64) as usize;
+			if c_ofs == 0 && len - done >= cluster_size {
+				// Whole clusters, written directly (merging contiguous clusters)
+				let mut count = 1;
+				while (count+1) * cluster_size <= len - done && c_idx + count < chain.len() && chain[c_idx + count] == chain[c_idx] + count as u32 {
+					count += 1;
+				}
+				let bytes = count * cluster_size;
+				try!(self.fs.write_clusters(chain[c_idx], &buf[done..][..bytes]));
+				done += bytes;
+			}
+			else {
+				// Partial cluster, read-modify-write
+				let n = ::core::cmp::min(cluster_size - c_ofs, len - done);
+				let mut tmp = vec![0u8; cluster_size];
+				try!(self.fs.read_cluster(chain[c_idx], &mut tmp));
+				tmp[c_ofs..][..n].clone_from_slice( &buf[done..][..n] );
+				try!(self.fs.write_clusters(chain[c_idx], &tmp));
+				done += n;
+			}
+		}
+
+		if ofs + len as u64 > st.size as u64 {
+			st.size = (ofs + len as u64) as u32;
+		}
+		Ok(len)
+	}
+	/// Zero `len` bytes starting at `ofs` (which must be within or at the end of the file)
+	fn zero_locked(&self, st: &mut FileState, ofs: u64, len: u64) -> vfs::Result<()> {
+		let zeroes = vec![0u8; self.fs.cluster_size];
+		let mut done = 0;
+		while done < len
+		{
+			let n = ::core::cmp::min(zeroes.len() as u64, len - done) as usize;
+			done += try!(self.write_locked(st, ofs + done, &zeroes[..n])) as u64;
+		}
+		Ok( () )
+	}
+}
+impl Drop for FileNode {
+	fn drop(&mut self) {
+		let first_cluster = {
+			let mut open_files = self.fs.open_files.lock();
+			let location = *self.shared.location.lock();
+			match location
+			{
+			// - Last node for this entry (the other reference is the list's)
+			Some(loc) => {
+				if Arc::strong_count(&self.shared) == 2 {
+					open_files.remove(&loc);
+				}
+				return ;
+				},
+			// - Unlinked while open, the clusters are released once the last node is dropped
+			None if Arc::strong_count(&self.shared) == 1 => self.shared.state.read().first_cluster,
+			None => return,
+			}
+			};
+		if first_cluster != 0 {
+			if let Err(e) = self.fs.free_chain(first_cluster) {
+				log_warning!("Unable to free clusters of unlinked file ({:#x}) - {:?}", first_cluster, e);
+			}
+		}
+	}
 }
 impl node::NodeBase for FileNode {
 	fn get_id(&self) -> node::InodeId {
-		todo!("FileNode::get_id")
+		match *self.shared.location.lock()
+		{
+		Some((parent_dir, dir_idx)) => super::InodeRef::new(parent_dir, dir_idx).to_id(),
+		// - Unlinked files no longer have a location
+		None => 0,
+		}
 	}
 	fn get_any(&self) -> &::core::any::Any {
 		self
 	}
+	fn get_metadata(&self) -> node::Metadata {
+		let link_count = if self.shared.location.lock().is_some() { self.metadata.link_count } else { 0 };
+		node::Metadata { size: self.shared.state.read().size as u64, link_count: link_count, ..self.metadata.clone() }
+	}
 }
 impl node::File for FileNode {
 	fn size(&self) -> u64 {
-		self.size as u64
+		self.shared.state.read().size as u64
 	}
 	fn truncate(&self, newsize: u64) -> node::Result<u64> {
-		todo!("FileNode::truncate({:#x})", newsize);
+		if newsize > MAX_FILE_SIZE {
+			return Err( vfs::Error::FileTooLarge );
+		}
+		let mut st = self.shared.state.write();
+		let old_first_cluster = st.first_cluster;
+		let cluster_size = self.fs.cluster_size as u64;
+		let rv = if newsize < st.size as u64 {
+				// Release clusters past the new end
+				let keep = ((newsize + cluster_size - 1) / cluster_size) as usize;
+				if keep == 0 {
+					if st.first_cluster != 0 {
+						try!(self.fs.free_chain(st.first_cluster));
+						st.first_cluster = 0;
+					}
+				}
+				else {
+					let last = match super::ClusterList::chained(self.fs.reborrow(), st.first_cluster).nth(keep - 1)
+						{
+						Some(v) => v,
+						None => return Err(ERROR_SHORTCHAIN),
+						};
+					try!(self.fs.truncate_chain(last));
+				}
+				st.size = newsize as u32;
+				Ok(newsize)
+			}
+			else {
+				// Zero-fill the extension (allocating clusters)
+				let old_size = st.size as u64;
+				self.zero_locked(&mut st, old_size, newsize - old_size).map(|_| newsize)
+			};
+		try!(self.update_dirent(old_first_cluster, &st));
+		rv
 	}
 	fn clear(&self, ofs: u64, size: u64) -> node::Result<()> {
-		todo!("FileNode::clear({:#x}+{:#x}", ofs, size);
+		let mut st = self.shared.state.write();
+		// Clamp to the current file size
+		let file_size = st.size as u64;
+		if ofs >= file_size {
+			return Ok( () );
+		}
+		let size = ::core::cmp::min(size, file_size - ofs);
+		self.zero_locked(&mut st, ofs, size)
 	}
 	fn read(&self, ofs: u64, buf: &mut [u8]) -> node::Result<usize> {
+		let st = self.shared.state.read();
 		// Sanity check and bound parameters
-		if ofs > self.size as u64 {
+		if ofs > st.size as u64 {
 			// out of range
 			return Err( vfs::Error::InvalidParameter );
 		}
-		if ofs == self.size as u64 {
+		if ofs == st.size as u64 {
 			return Ok(0);
 		}
-		let maxread = (self.size as u64 - ofs) as usize;
+		let maxread = (st.size as u64 - ofs) as usize;
 		let buf = if buf.len() > maxread { &mut buf[..maxread] } else { buf };
 		let read_length = buf.len();
 		
 		// Seek to correct position in the cluster chain
-		let mut clusters = super::ClusterList::chained(self.fs.reborrow(), self.first_cluster);
+		let mut clusters = super::ClusterList::chained(self.fs.reborrow(), st.first_cluster);
 		for _ in 0 .. (ofs/self.fs.cluster_size as u64) {
 			clusters.next();
 		}
@@ -150,7 +379,13 @@ impl node::File for FileNode {
 	}
 	/// Write data to the file, can only grow the file if ofs==size
 	fn write(&self, ofs: u64, buf: &[u8]) -> node::Result<usize> {
-		todo!("FileNode::write({:#x}, {:p})", ofs, ::kernel::lib::SlicePtr(buf));
+		let mut st = self.shared.state.write();
+		let (old_first_cluster, old_size) = (st.first_cluster, st.size);
+		let rv = self.write_locked(&mut st, ofs, buf);
+		if st.first_cluster != old_first_cluster || st.size != old_size {
+			try!(self.update_dirent(old_first_cluster, &st));
+		}
+		rv
 	}
 }
 
@@ -26,12 +26,24 @@ const FAT32_MIN_CLUSTERS: usize = 65525;
 /// FAT Legacy (pre 32) root cluster base. Just has to be above the max cluster num for FAT16
 const FATL_ROOT_CLUSTER: u32 = 0x00FF0000;
 
-const FAT12_EOC: u16 = 0x0FFF;
-const FAT16_EOC: u16 = 0xFFFF;
-const FAT32_EOC: u32 = 0x00FFFFFF;
+/// End-of-chain markers (values written when terminating a chain, anything above `FATn_EOC_MIN` is treated as EOC)
+const FAT12_EOC: u32 = 0x0FFF;
+const FAT16_EOC: u32 = 0xFFFF;
+const FAT32_EOC: u32 = 0x0FFF_FFFF;
+const FAT12_EOC_MIN: u32 = 0x0FF8;
+const FAT16_EOC_MIN: u32 = 0xFFF8;
+const FAT32_EOC_MIN: u32 = 0x0FFF_FFF8;
+
+/// FSInfo sector signatures
+const FSINFO_LEAD_SIG: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIG: u32 = 0x6141_7272;
+/// Value used in FSInfo for an unknown free count / next free cluster
+const FSINFO_UNKNOWN: u32 = 0xFFFF_FFFF;
 
 /// on-disk structures
 mod on_disk;
+/// Short and long name generation
+mod names;
 /// Directory IO
 mod dir;
 /// File IO
@@ -64,12 +76,20 @@ pub struct FilesystemInner
 	vh: ::block_cache::CacheHandle,
 	ty: Size,
 	
+	/// Bytes per sector (a multiple of the volume's block size)
+	bps: usize,
 	spc: usize,
 	cluster_size: usize,
 	/// Total number of data clusters
 	cluster_count: usize,
 	first_fat_sector: usize,
+	/// Number of sectors in each FAT
+	fat_size: usize,
+	/// Number of FAT copies (all are updated on write)
+	fat_count: usize,
 	first_data_sector: usize,
+	/// Sector containing the FAT32 FSInfo structure (if present and valid)
+	fs_info_sector: Option<u64>,
 	
 	root_first_cluster: u32,
 	root_sector_count: u32,
@@ -78,9 +98,29 @@ pub struct FilesystemInner
 	// XXX: Should really use the above line for this, but BlockCache exists
 	/// A cache of metadata clusters (i.e. directories)
 	metadata_block_cache: ::blockcache::BlockCache,
+
+	/// Cluster allocation state (the lock also serialises FAT updates)
+	alloc: ::kernel::sync::Mutex<AllocState>,
+	/// Serialises directory modifications
+	dir_lock: ::kernel::sync::Mutex<()>,
+	/// State of open files, keyed by the location of their directory entry
+	open_files: ::kernel::sync::Mutex<::kernel::lib::VecMap<(u32,u16), Arc<file::OpenFile>>>,
 }
 
-/// Inodes IDs destrucure into two 28-bit cluster IDs, and a 16-bit dir offset
+struct AllocState
+{
+	/// Number of free clusters (`None` if unknown)
+	free_count: Option<u32>,
+	/// Hint for where to start searching for a free cluster
+	next_free: u32,
+	/// Set when the FSInfo sector needs to be updated
+	dirty: bool,
+}
+
+/// Inodes IDs destrucure into two 24-bit cluster IDs, and a 16-bit dir offset
+///
+/// Nodes other than the root are identified by the location of their directory entry (the first cluster of the
+/// containing directory, and the index of the short entry), as a file's first cluster changes when it's written.
 #[derive(Debug)]
 struct InodeRef
 {
@@ -109,16 +149,17 @@ impl mount::Driver for Driver
 {
 	fn detect(&self, vol: &VolumeHandle) -> vfs::Result<usize> {
 		let bs = {
-			let mut bs = [0u8; 512];
-			try!( vol.read_blocks(0, &mut bs) );
-			on_disk::BootSect::read(&bs)
+			// The boot sector is the first 512 bytes of the volume (regardless of the sector size)
+			let mut blk = vec![0u8; ::core::cmp::max(512, vol.block_size())];
+			try!( vol.read_blocks(0, &mut blk) );
+			on_disk::BootSect::read(&blk[..512])
 			};
 		
 		let bps = bs.common().bps;
 		let spc = bs.common().spc;
 		let media_desc = bs.common().media_descriptor;
 		
-		if bps == 0 || spc == 0 || media_desc < 0xf0 {
+		if bps < 512 || !bps.is_power_of_two() || spc == 0 || media_desc < 0xf0 {
 			Ok(0)
 		}
 		else {
@@ -134,8 +175,12 @@ impl mount::Driver for Driver
 			on_disk::BootSect::read(&mut &blk.data()[..512])
 			};
 		let bs_c = bs.common();
-		if bs_c.bps != 512 {
-			return Err(vfs::Error::Unknown("TODO: non 512-byte sector FAT"))
+		if bs_c.bps < 512 || !bs_c.bps.is_power_of_two() {
+			return Err(vfs::Error::Unknown("FAT sector size isn't a power of two"));
+		}
+		// Sectors are accessed as whole volume blocks
+		if (bs_c.bps as usize) < vol.block_size() {
+			return Err(vfs::Error::Unknown("FAT sector size is smaller than the volume's block size"));
 		}
 		if bs_c.fat_count == 0 {
 			return Err(vfs::Error::Unknown("FAT Count is 0"));
@@ -165,7 +210,7 @@ impl mount::Driver for Driver
 		let first_data_sector = bs_c.reserved_sect_count as usize
 			+ fat_size + spare_fat_sectors
 			+ root_dir_sectors;
-		let cluster_count = (total_sectors - first_data_sector - spare_fat_sectors) / spc;
+		let cluster_count = (total_sectors - first_data_sector) / spc;
 		
 		// Determine the FAT type
 		let fat_type = if cluster_count < FAT16_MIN_CLUSTERS {
@@ -177,18 +222,47 @@ impl mount::Driver for Driver
 			else {
 				Size::Fat32
 			};
-		log_debug!("{:?} {} sectors, Size {}", fat_type, total_sectors,
-			SizePrinter((total_sectors*bs_c.bps as usize) as u64));
+		log_debug!("{:?} {} {}-byte sectors, Size {}", fat_type, total_sectors, bps,
+			SizePrinter((total_sectors*bps) as u64));
+
+		// Read the free cluster count from the FSInfo sector (FAT32 only)
+		let (fs_info_sector, free_count, next_free) = match bs.info32()
+			{
+			Some(i) if i.fs_info != 0 && i.fs_info != 0xFFFF => {
+				use kernel::lib::byteorder::{ReadBytesExt,LittleEndian};
+				let sector = i.fs_info as u64;
+				let block = sector * (bps / vol.block_size()) as u64;
+				let blk = try!(vol.get_block(block));
+				let d = &blk.data()[(block - blk.index()) as usize * vol.block_size() ..][.. 512];
+				let lead_sig = (&d[0..]).read_u32::<LittleEndian>().unwrap();
+				let struct_sig = (&d[484..]).read_u32::<LittleEndian>().unwrap();
+				if lead_sig == FSINFO_LEAD_SIG && struct_sig == FSINFO_STRUCT_SIG {
+					let free_count = (&d[488..]).read_u32::<LittleEndian>().unwrap();
+					let next_free = (&d[492..]).read_u32::<LittleEndian>().unwrap();
+					log_debug!("FSInfo: free_count={}, next_free={}", free_count, next_free);
+					(Some(sector), if free_count as usize <= cluster_count { Some(free_count) } else { None }, next_free)
+				}
+				else {
+					log_notice!("FAT32 FSInfo sector {} has bad signatures", sector);
+					(None, None, 2)
+				}
+				},
+			_ => (None, None, 2),
+			};
 		
 		Ok(Box::new(Filesystem {
 			// SAFE: Saving to a Box, so won't move
 			inner: unsafe { ArefInner::new(FilesystemInner {
 				ty: fat_type,
+				bps: bps,
 				spc: spc,
-				cluster_size: spc * vol.block_size(),
+				cluster_size: spc * bps,
 				cluster_count: cluster_count,
 				first_fat_sector: bs_c.reserved_sect_count as usize,
+				fat_size: fat_size,
+				fat_count: bs_c.fat_count as usize,
 				first_data_sector: first_data_sector,
+				fs_info_sector: fs_info_sector,
 				root_first_cluster: match fat_type {
 					Size::Fat32 => bs.info32().unwrap().root_cluster,
 					_ => FATL_ROOT_CLUSTER as u32,
@@ -197,6 +271,14 @@ impl mount::Driver for Driver
 				
 				metadata_block_cache: ::blockcache::BlockCache::new(),
 
+				alloc: ::kernel::sync::Mutex::new(AllocState {
+					free_count: free_count,
+					next_free: next_free,
+					dirty: false,
+					}),
+				dir_lock: ::kernel::sync::Mutex::new(()),
+				open_files: ::kernel::sync::Mutex::new(::kernel::lib::VecMap::new()),
+
 				vh: vol,
 				}) },
 			}))
@@ -212,28 +294,69 @@ impl FilesystemInner
 		assert_eq!(dst.len(), self.cluster_size);
 		self.read_clusters(cluster, dst)
 	}
+	/// Convert a sector number into a volume block number
+	fn sector_to_block(&self, sector: u64) -> u64 {
+		sector * (self.bps / self.vh.block_size()) as u64
+	}
+	/// Obtain the first sector of a cluster
+	fn cluster_to_sector(&self, cluster: u32) -> u64 {
+		if !is!(self.ty, Size::Fat32) && cluster >= FATL_ROOT_CLUSTER {
+			// Root directory (for FAT12/16, where it was not a normal file)
+			let rc = cluster - FATL_ROOT_CLUSTER;
+			assert!( (rc as u64 * self.spc as u64) < self.root_sector_count as u64);
+			(self.first_data_sector - self.root_sector_count as usize) as u64
+			+ (rc * self.spc as u32) as u64
+		}
+		else {
+			// Anything else
+			assert!(cluster >= 2);
+			assert!(cluster - 2 < self.cluster_count as u32);
+			self.first_data_sector as u64 + (cluster as u64 - 2) * self.spc as u64
+		}
+	}
 	fn read_clusters(&self, cluster: u32, dst: &mut [u8]) -> Result<(), storage::IoError> {
 		log_trace!("Filesystem::read_clusters({:#x}, {})", cluster, dst.len() / self.cluster_size);
 		assert_eq!(dst.len() % self.cluster_size, 0);
 		// For now, just read the bytes, screw caching
-		let sector = if !is!(self.ty, Size::Fat32) && cluster >= FATL_ROOT_CLUSTER {
-				// Root directory (for FAT12/16, where it was not a normal file)
-				let rc = cluster - FATL_ROOT_CLUSTER;
-				assert!( (rc as u64 * self.spc as u64) < self.root_sector_count as u64);
-				(self.first_data_sector - self.root_sector_count as usize) as u64
-				+ (rc * self.spc as u32) as u64
-			}
-			else {
-				// Anything else
-				assert!(cluster >= 2);
-				assert!(cluster - 2 < self.cluster_count as u32);
-				self.first_data_sector as u64 + (cluster as u64 - 2) * self.spc as u64
-			};
+		let sector = self.cluster_to_sector(cluster);
 		log_debug!("read_clusters: cluster = {:#x}, sector = 0x{:x}", cluster, sector);
-		try!(self.vh.read_blocks(sector, dst));
+		try!(self.vh.read_blocks(self.sector_to_block(sector), dst));
 		//::kernel::logging::hex_dump("FAT Cluster", &buf);
 		Ok( () )
 	}
+	/// Write (a whole number of) clusters to disk
+	fn write_clusters(&self, cluster: u32, src: &[u8]) -> Result<(), storage::IoError> {
+		log_trace!("Filesystem::write_clusters({:#x}, {})", cluster, src.len() / self.cluster_size);
+		assert_eq!(src.len() % self.cluster_size, 0);
+		let sector = self.cluster_to_sector(cluster);
+		try!(self.vh.write_blocks(self.sector_to_block(sector), src));
+		// Freed directory clusters can be reused for data (or vice versa)
+		for i in 0 .. (src.len() / self.cluster_size) as u32 {
+			self.metadata_block_cache.invalidate(cluster + i);
+		}
+		Ok( () )
+	}
+	/// Write a (modified) directory cluster back to disk, and drop it from the metadata cache
+	fn write_dir_cluster(&self, cluster: u32, src: &[u8]) -> Result<(), storage::IoError> {
+		assert_eq!(src.len(), self.cluster_size);
+		if !is!(self.ty, Size::Fat32) && cluster >= FATL_ROOT_CLUSTER {
+			// The last "cluster" of a fixed root can extend past the end of the root directory
+			let rc = cluster - FATL_ROOT_CLUSTER;
+			let sectors = ::core::cmp::min(self.spc, self.root_sector_count as usize - rc as usize * self.spc);
+			let block = self.sector_to_block(self.cluster_to_sector(cluster));
+			try!(self.vh.write_blocks(block, &src[.. sectors * self.bps]));
+		}
+		else {
+			try!(self.write_clusters(cluster, src));
+		}
+		self.metadata_block_cache.invalidate(cluster);
+		Ok( () )
+	}
+	/// Fill a cluster with zeroes
+	fn zero_cluster(&self, cluster: u32) -> Result<(), storage::IoError> {
+		let zeroes = vec![0u8; self.cluster_size];
+		self.write_clusters(cluster, &zeroes)
+	}
 
 	// TODO: Locking/Cache
 	// - Should this function lock the cluster somehow to prevent accidental overlap?
@@ -244,75 +367,231 @@ impl FilesystemInner
 			cluster,
 			|_| {
 				log_debug!("load_cluster: miss {}", cluster);
-				let mut buf: Cluster = Arc::from_iter( (0..self.spc * self.vh.block_size()).map(|_| 0) );
+				let mut buf: Cluster = Arc::from_iter( (0..self.cluster_size).map(|_| 0) );
 				try!(self.read_cluster( cluster, Arc::get_mut(&mut buf).unwrap() ));
 				Ok( buf )
 			})
 	}
-	
-	/// Obtain the next cluster in a chain
-	fn get_next_cluster(&self, cluster: u32) -> Result< Option<u32>, storage::IoError > {
-		use kernel::lib::byteorder::{ReadBytesExt,LittleEndian};
-		// - Determine what sector contains the requested FAT entry
+
+	/// Byte offset and size of a cluster's FAT entry
+	fn fat_entry_pos(&self, cluster: u32) -> (usize, usize) {
+		match self.ty
+		{
+		// FAT12 packs 2 entries into 3 bytes (so entries can straddle sectors)
+		Size::Fat12 => (cluster as usize * 3 / 2, 2),
+		Size::Fat16 => (cluster as usize * 2, 2),
+		Size::Fat32 => (cluster as usize * 4, 4),
+		}
+	}
+	/// Read bytes from the first FAT
+	fn read_fat_bytes(&self, byte_ofs: usize, dst: &mut [u8]) -> Result<(), storage::IoError> {
 		let bs = self.vh.block_size();
-		let (fat_sector,ofs) = match self.ty
+		let mut done = 0;
+		while done < dst.len()
+		{
+			let pos = self.first_fat_sector * self.bps + byte_ofs + done;
+			let block_idx = (pos / bs) as u64;
+			let ofs = pos % bs;
+			let n = ::core::cmp::min(bs - ofs, dst.len() - done);
+			let blk = try!(self.vh.get_block(block_idx));
+			let start_ofs = (block_idx - blk.index()) as usize * bs;
+			dst[done..][..n].clone_from_slice( &blk.data()[start_ofs + ofs ..][..n] );
+			done += n;
+		}
+		Ok( () )
+	}
+	/// Write bytes to every copy of the FAT
+	fn write_fat_bytes(&self, byte_ofs: usize, src: &[u8]) -> Result<(), storage::IoError> {
+		let bs = self.vh.block_size();
+		for fat in 0 .. self.fat_count
+		{
+			let mut done = 0;
+			while done < src.len()
+			{
+				let pos = (self.first_fat_sector + fat * self.fat_size) * self.bps + byte_ofs + done;
+				let block_idx = (pos / bs) as u64;
+				let ofs = pos % bs;
+				let n = ::core::cmp::min(bs - ofs, src.len() - done);
+				try!(self.vh.edit(block_idx, 1, |data| data[ofs..][..n].clone_from_slice(&src[done..][..n])));
+				done += n;
+			}
+		}
+		Ok( () )
+	}
+	/// Read a cluster's FAT entry
+	fn get_fat_entry(&self, cluster: u32) -> Result<u32, storage::IoError> {
+		let (byte_ofs, len) = self.fat_entry_pos(cluster);
+		let mut buf = [0u8; 4];
+		try!(self.read_fat_bytes(byte_ofs, &mut buf[..len]));
+		let v = (buf[0] as u32) | (buf[1] as u32) << 8 | (buf[2] as u32) << 16 | (buf[3] as u32) << 24;
+		Ok(match self.ty
+		{
+		Size::Fat12 => if cluster % 2 == 0 { v & 0xFFF } else { v >> 4 },
+		Size::Fat16 => v,
+		// Top 4 bits are reserved
+		Size::Fat32 => v & 0x0FFF_FFFF,
+		})
+	}
+	/// Update a cluster's FAT entry (in all FATs)
+	fn set_fat_entry(&self, cluster: u32, value: u32) -> Result<(), storage::IoError> {
+		let (byte_ofs, len) = self.fat_entry_pos(cluster);
+		let raw = match self.ty
 			{
 			Size::Fat12 => {
-				let cps = bs / 3 * 2;	// 2 per 3 bytes
-				(cluster as usize / cps, (cluster as usize % cps) / 2 * 3 )
-				},
-			Size::Fat16 => {
-				let cps = bs / 2;
-				(cluster as usize / cps, (cluster as usize % cps) * 2)
+				// Preserve the other entry sharing these bytes
+				let mut buf = [0u8; 2];
+				try!(self.read_fat_bytes(byte_ofs, &mut buf));
+				let old = (buf[0] as u32) | (buf[1] as u32) << 8;
+				if cluster % 2 == 0 {
+					(old & 0xF000) | (value & 0xFFF)
+				}
+				else {
+					(old & 0x000F) | (value & 0xFFF) << 4
+				}
 				},
+			Size::Fat16 => value & 0xFFFF,
 			Size::Fat32 => {
-				let cps = bs / 4;
-				(cluster as usize / cps, (cluster as usize % cps) * 2)
+				// Preserve the reserved top bits
+				let mut buf = [0u8; 4];
+				try!(self.read_fat_bytes(byte_ofs, &mut buf));
+				let old = (buf[0] as u32) | (buf[1] as u32) << 8 | (buf[2] as u32) << 16 | (buf[3] as u32) << 24;
+				(old & 0xF000_0000) | (value & 0x0FFF_FFFF)
 				},
 			};
+		let bytes = [raw as u8, (raw >> 8) as u8, (raw >> 16) as u8, (raw >> 24) as u8];
+		self.write_fat_bytes(byte_ofs, &bytes[..len])
+	}
+	/// FAT entry value used to terminate a chain
+	fn eoc_value(&self) -> u32 {
+		match self.ty
+		{
+		Size::Fat12 => FAT12_EOC,
+		Size::Fat16 => FAT16_EOC,
+		Size::Fat32 => FAT32_EOC,
+		}
+	}
+	fn is_eoc(&self, val: u32) -> bool {
+		match self.ty
+		{
+		Size::Fat12 => val >= FAT12_EOC_MIN,
+		Size::Fat16 => val >= FAT16_EOC_MIN,
+		Size::Fat32 => val >= FAT32_EOC_MIN,
+		}
+	}
+	
+	/// Obtain the next cluster in a chain
+	fn get_next_cluster(&self, cluster: u32) -> Result< Option<u32>, storage::IoError > {
+		let val = try!(self.get_fat_entry(cluster));
+		if val == 0 {
+			Err(storage::IoError::Unknown("FAT: Zero FAT entry"))
+		}
+		else if self.is_eoc(val) {
+			Ok(None)
+		}
+		else {
+			Ok(Some(val))
+		}
+	}
 
-		// - Read a single sector from the FAT
-		let sector_idx = (self.first_fat_sector + fat_sector) as u64;
-		let sector_data_blk = try!(self.vh.get_block( sector_idx ));
-		let start_ofs = (sector_idx - sector_data_blk.index()) as usize * bs;
-		let sector_data = &sector_data_blk.data()[start_ofs .. ];
-		//log_debug!("Sector {} accessed via cached block at sector {}", sector_idx, sector_data_blk.index());
-		//::kernel::logging::hex_dump("FAT FAT", &sector_data[..bs]);
-
-		// - Extract the entry
-		Ok(match self.ty
+	/// Allocate a free cluster, appending it to the chain ending at `prev`
+	///
+	/// If `zero` is set, the cluster's contents are cleared (required for directories)
+	fn alloc_cluster(&self, prev: Option<u32>, zero: bool) -> vfs::Result<u32> {
+		let mut lh = self.alloc.lock();
+		if lh.free_count == Some(0) {
+			return Err(vfs::Error::OutOfSpace);
+		}
+		let limit = self.cluster_count as u32 + 2;
+		let start = if lh.next_free >= 2 && lh.next_free < limit { lh.next_free } else { 2 };
+		let mut cluster = start;
+		while try!(self.get_fat_entry(cluster)) != 0
 		{
-		Size::Fat12 => {
-			// FAT12 has special handling because it packs 2 entries into 24 bytes
-			let val = {
-				let v24 = (&sector_data[ofs..]).read_uint::<LittleEndian>(3).unwrap();
-				if cluster % 2 == 0 { v24 & 0xFFF } else { v24 >> 12 }
-				} as u16;
-			if val == 0 { return Err(storage::IoError::Unknown("FAT: Zero FAT entry")); }
-			if val == FAT12_EOC { None } else { Some(val as u32) }
-			},
-		Size::Fat16 => {
-			let val = (&sector_data[ofs..]).read_u16::<LittleEndian>().unwrap();
-			if val == 0 { return Err(storage::IoError::Unknown("FAT: Zero FAT entry")); }
-			if val == FAT16_EOC { None } else { Some(val as u32) }
-			},
-		Size::Fat32 => {
-			let val = (&sector_data[ofs..]).read_u32::<LittleEndian>().unwrap();
-			if val == 0 { return Err(storage::IoError::Unknown("FAT: Zero FAT entry")); }
-			if val == FAT32_EOC { None } else { Some(val as u32) }
+			cluster += 1;
+			if cluster == limit {
+				cluster = 2;
+			}
+			if cluster == start {
+				lh.free_count = Some(0);
+				lh.dirty = true;
+				return Err(vfs::Error::OutOfSpace);
+			}
+		}
+		log_debug!("alloc_cluster: {:#x} (after {:?})", cluster, prev);
+		if zero {
+			try!(self.zero_cluster(cluster));
+		}
+		try!(self.set_fat_entry(cluster, self.eoc_value()));
+		if let Some(p) = prev {
+			try!(self.set_fat_entry(p, cluster));
+		}
+		lh.next_free = cluster + 1;
+		if let Some(ref mut v) = lh.free_count {
+			*v -= 1;
+		}
+		lh.dirty = true;
+		Ok(cluster)
+	}
+	/// Release a chain of clusters
+	fn free_chain(&self, first: u32) -> vfs::Result<()> {
+		let mut lh = self.alloc.lock();
+		let limit = self.cluster_count as u32 + 2;
+		let mut cluster = first;
+		// NOTE: Bounded by the cluster count, in case the chain is cyclic
+		for _ in 0 .. self.cluster_count
+		{
+			if cluster < 2 || cluster >= limit {
+				log_warning!("free_chain({:#x}): Chain contains invalid cluster {:#x}", first, cluster);
+				break;
+			}
+			let next = try!(self.get_fat_entry(cluster));
+			try!(self.set_fat_entry(cluster, 0));
+			if let Some(ref mut v) = lh.free_count {
+				*v += 1;
+			}
+			if next == 0 || self.is_eoc(next) {
+				break;
+			}
+			cluster = next;
+		}
+		lh.dirty = true;
+		Ok( () )
+	}
+	/// Terminate a chain after `last`, releasing the remainder
+	fn truncate_chain(&self, last: u32) -> vfs::Result<()> {
+		match try!(self.get_next_cluster(last))
+		{
+		Some(next) => {
+			try!(self.set_fat_entry(last, self.eoc_value()));
+			self.free_chain(next)
 			},
-		})
+		None => Ok( () ),
+		}
+	}
+
+	/// Write the free cluster count back to the FSInfo sector
+	fn write_fs_info(&self) -> vfs::Result<()> {
+		if let Some(sector) = self.fs_info_sector
+		{
+			let mut lh = self.alloc.lock();
+			if lh.dirty {
+				use kernel::lib::byteorder::{ByteOrder,LittleEndian};
+				let free_count = lh.free_count.unwrap_or(FSINFO_UNKNOWN);
+				let next_free = lh.next_free;
+				try!(self.vh.edit(self.sector_to_block(sector), 1, |data| {
+					LittleEndian::write_u32(&mut data[488..], free_count);
+					LittleEndian::write_u32(&mut data[492..], next_free);
+					}));
+				lh.dirty = false;
+			}
+		}
+		Ok( () )
 	}
 }
 
 impl mount::Filesystem for Filesystem
 {
 	fn root_inode(&self) -> node::InodeId {
-		(InodeRef {
-			first_cluster: self.root_first_cluster,
-			dir_first_cluster: 0,
-			dir_offset: 0,
-			}).to_id()
+		InodeRef::root(self.root_first_cluster).to_id()
 	}
 	fn get_node_by_inode(&self, id: node::InodeId) -> Option<node::Node> {
 		let r = InodeRef::from(id);
@@ -320,24 +599,35 @@ impl mount::Filesystem for Filesystem
 			Some(node::Node::Dir(dir::DirNode::new_boxed(self.inner.borrow(), r.first_cluster)))
 		}
 		else {
-			// Reading from the directory starting at r.dir_first_cluster
-			// locate the file with cluster equal to r.first_cluster.
-			// And use that to create the node
+			// Read the entry at r.dir_offset in the directory starting at r.dir_first_cluster
+			// and use that to create the node
 			let dn = dir::DirNode::new(self.inner.borrow(), r.dir_first_cluster);
-			dn.find_node(r.first_cluster)
+			dn.node_at(r.dir_offset)
 		}
 	}
+	fn sync(&self) -> vfs::Result<()> {
+		try!(self.inner.write_fs_info());
+		Ok( try!(self.inner.vh.flush()) )
+	}
 }
 
 impl InodeRef
 {
-	fn new(c: u32, dir_c: u32) -> InodeRef {
-		assert!(c     <= 0x00FF_FFFF);
+	/// Reference to the root directory
+	fn root(first_cluster: u32) -> InodeRef {
+		InodeRef {
+			first_cluster: first_cluster,
+			dir_first_cluster: 0,
+			dir_offset: 0,
+		}
+	}
+	/// Reference to the node described by entry `dir_offset` of the directory starting at `dir_c`
+	fn new(dir_c: u32, dir_offset: u16) -> InodeRef {
 		assert!(dir_c <= 0x00FF_FFFF);
 		InodeRef {
-			first_cluster: c,
+			first_cluster: 0,
 			dir_first_cluster: dir_c,
-			dir_offset: 0,
+			dir_offset: dir_offset,
 		}
 	}
 	fn to_id(&self) -> node::InodeId {
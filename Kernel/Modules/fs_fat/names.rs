@@ -0,0 +1,244 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Modules/fs_fat/names.rs
+//! Short (8.3) and long file name generation
+use kernel::prelude::*;
+use super::on_disk;
+
+/// Characters (other than upper-case letters and digits) valid in a short name
+const SHORT_NAME_SPECIALS: &'static [u8] = b"$%'-_@~`!(){}^#&";
+
+/// Select a short name for `name`, given the short names already in the directory
+///
+/// Returns the name, case flags, and if a long name is needed. `None` if every numeric tail is in use.
+pub fn pick_short_name(name: &[u8], existing: &[[u8; 11]]) -> Option<([u8; 11], u8, bool)> {
+	if let Some((short, lcase)) = exact_short_name(name) {
+		if !existing.contains(&short) {
+			return Some( (short, lcase, false) );
+		}
+	}
+	// Lossy conversion, add a numeric tail (e.g. `LONGNA~1.TXT`) to make it unique
+	let (basis, base_len) = basis_short_name(name);
+	for n in 1 .. 1000000
+	{
+		let short = apply_numeric_tail(&basis, base_len, n);
+		if !existing.contains(&short) {
+			return Some( (short, 0, true) );
+		}
+	}
+	None
+}
+
+/// Validate a name for a new entry, and encode it as UTF-16 (for the long name entries)
+pub fn encode_long_name(name: &[u8]) -> Option<Vec<u16>> {
+	let s = match ::core::str::from_utf8(name)
+		{
+		Ok(v) => v,
+		Err(_) => return None,
+		};
+	if s == "" || s == "." || s == ".." {
+		return None;
+	}
+	let mut rv = Vec::new();
+	for c in s.chars()
+	{
+		match c
+		{
+		'"' | '*' | '/' | ':' | '<' | '>' | '?' | '\\' | '|' => return None,
+		_ if (c as u32) < 0x20 => return None,
+		_ => {},
+		}
+		let v = c as u32;
+		if v < 0x10000 {
+			rv.push(v as u16);
+		}
+		else {
+			let v = v - 0x10000;
+			rv.push(0xD800 | (v >> 10) as u16);
+			rv.push(0xDC00 | (v & 0x3FF) as u16);
+		}
+	}
+	// Long names are limited to 255 UTF-16 units
+	if rv.len() > 255 {
+		return None;
+	}
+	Some(rv)
+}
+
+fn is_short_char(c: u8) -> bool {
+	(b'A' <= c && c <= b'Z') || (b'0' <= c && c <= b'9') || SHORT_NAME_SPECIALS.contains(&c)
+}
+/// Split a name into base and extension (at the last '.')
+fn split_ext(name: &[u8]) -> (&[u8], &[u8]) {
+	match name.iter().rposition(|&c| c == b'.')
+	{
+	Some(p) => (&name[..p], &name[p+1..]),
+	None => (name, &name[..0]),
+	}
+}
+/// Encode a name directly as a 8.3 name (with case flags), if possible
+fn exact_short_name(name: &[u8]) -> Option<([u8; 11], u8)> {
+	let (base, ext) = split_ext(name);
+	if base.len() == 0 || base.len() > 8 || ext.len() > 3 || (ext.len() == 0 && base.len() != name.len()) {
+		return None;
+	}
+	let mut rv = [b' '; 11];
+	let mut lcase = 0;
+	for &(dst_ofs, part, flag) in &[(0, base, on_disk::CASE_LOWER_BASE), (8, ext, on_disk::CASE_LOWER_EXT)]
+	{
+		// Mixed case can't be represented with the flags
+		let has_lower = part.iter().any(|&c| b'a' <= c && c <= b'z');
+		let has_upper = part.iter().any(|&c| b'A' <= c && c <= b'Z');
+		if has_lower && has_upper {
+			return None;
+		}
+		for (i, &c) in part.iter().enumerate()
+		{
+			let c = c.to_ascii_uppercase();
+			if !is_short_char(c) {
+				return None;
+			}
+			rv[dst_ofs + i] = c;
+		}
+		if has_lower {
+			lcase |= flag;
+		}
+	}
+	Some( (rv, lcase) )
+}
+/// Generate the basis for a lossy short name, returning the name and the length of the base
+fn basis_short_name(name: &[u8]) -> ([u8; 11], usize) {
+	// Leading dots are dropped, as are spaces and any other dots
+	let name = &name[name.iter().position(|&c| c != b'.').unwrap_or(name.len()) ..];
+	let (base, ext) = split_ext(name);
+	let map = |c: u8| { let c = c.to_ascii_uppercase(); if is_short_char(c) { c } else { b'_' } };
+	let mut rv = [b' '; 11];
+	let mut base_len = 0;
+	for &c in base.iter().filter(|&&c| c != b' ' && c != b'.').take(8)
+	{
+		rv[base_len] = map(c);
+		base_len += 1;
+	}
+	for (i, &c) in ext.iter().filter(|&&c| c != b' ').take(3).enumerate()
+	{
+		rv[8 + i] = map(c);
+	}
+	if base_len == 0 {
+		rv[0] = b'_';
+		base_len = 1;
+	}
+	(rv, base_len)
+}
+/// Replace the end of the base name with `~n`
+fn apply_numeric_tail(basis: &[u8; 11], base_len: usize, n: u32) -> [u8; 11] {
+	let mut digits = [0u8; 10];
+	let mut n_digits = 0;
+	let mut v = n;
+	loop
+	{
+		digits[n_digits] = b'0' + (v % 10) as u8;
+		n_digits += 1;
+		v /= 10;
+		if v == 0 {
+			break;
+		}
+	}
+	let keep = ::core::cmp::min(base_len, 8 - 1 - n_digits);
+	let mut rv = *basis;
+	for c in &mut rv[keep .. 8] {
+		*c = b' ';
+	}
+	rv[keep] = b'~';
+	for i in 0 .. n_digits {
+		rv[keep + 1 + i] = digits[n_digits - 1 - i];
+	}
+	rv
+}
+
+#[test]
+// Names that fit 8.3 are stored directly, using the case flags for all-lowercase parts
+fn exact_names()
+{
+	assert_eq!(pick_short_name(b"README.TXT", &[]), Some( (*b"README  TXT", 0, false) ));
+	assert_eq!(pick_short_name(b"readme.txt", &[]), Some( (*b"README  TXT", on_disk::CASE_LOWER_BASE|on_disk::CASE_LOWER_EXT, false) ));
+	assert_eq!(pick_short_name(b"readme.TXT", &[]), Some( (*b"README  TXT", on_disk::CASE_LOWER_BASE, false) ));
+	assert_eq!(pick_short_name(b"MAKEFILE", &[]), Some( (*b"MAKEFILE   ", 0, false) ));
+	// Mixed case within a part needs a long name
+	assert_eq!(pick_short_name(b"ReadMe.txt", &[]), Some( (*b"README~1TXT", 0, true) ));
+	// An exact name that's already taken gets a numeric tail
+	assert_eq!(pick_short_name(b"readme.txt", &[*b"README  TXT"]), Some( (*b"README~1TXT", 0, true) ));
+}
+#[test]
+// Lossy basis names drop leading dots, spaces and inner dots, and replace invalid characters
+fn basis_names()
+{
+	assert_eq!(basis_short_name(b".bashrc"), (*b"BASHRC     ", 6));
+	assert_eq!(basis_short_name(b"a b.c d"), (*b"AB      CD ", 2));
+	assert_eq!(basis_short_name(b"my.file.tar.gz"), (*b"MYFILETAGZ ", 8));
+	assert_eq!(basis_short_name(b"a+b=c.html"), (*b"A_B_C   HTM", 5));
+	assert_eq!(basis_short_name(b"..."), (*b"_          ", 1));
+}
+#[test]
+// Numeric tails shorten the base as they grow
+fn numeric_tails()
+{
+	let (basis, len) = basis_short_name(b"longfilename.txt");
+	assert_eq!(&apply_numeric_tail(&basis, len, 1), b"LONGFI~1TXT");
+	assert_eq!(&apply_numeric_tail(&basis, len, 10), b"LONGF~10TXT");
+	assert_eq!(&apply_numeric_tail(&basis, len, 100), b"LONG~100TXT");
+	assert_eq!(&apply_numeric_tail(&basis, len, 999999), b"L~999999TXT");
+	// Short bases aren't padded out
+	let (basis, len) = basis_short_name(b"a+.c");
+	assert_eq!(&apply_numeric_tail(&basis, len, 7), b"A_~7    C  ");
+}
+#[test]
+// Repeatedly creating colliding names yields distinct short names
+fn many_collisions()
+{
+	let mut existing = Vec::new();
+	for i in 0 .. 1200
+	{
+		let (short, lcase, need_lfn) = pick_short_name(b"longfilename.txt", &existing).expect("pick_short_name");
+		assert!(need_lfn);
+		assert_eq!(lcase, 0);
+		assert!(!existing.contains(&short), "Duplicate short name {:?} on iteration {}", short, i);
+		assert!(short.iter().all(|&c| c == b' ' || is_short_char(c)), "Invalid short name {:?}", short);
+		existing.push(short);
+	}
+	assert_eq!(&existing[0], b"LONGFI~1TXT");
+	assert_eq!(&existing[9], b"LONGF~10TXT");
+	assert_eq!(&existing[1199], b"LON~1200TXT");
+}
+#[test]
+// Long names are limited to 255 UTF-16 units (counting surrogate pairs as two)
+fn long_name_limits()
+{
+	let name: Vec<u8> = (0 .. 255).map(|_| b'a').collect();
+	assert_eq!(encode_long_name(&name).map(|v| v.len()), Some(255));
+	let name: Vec<u8> = (0 .. 256).map(|_| b'a').collect();
+	assert_eq!(encode_long_name(&name), None);
+
+	// U+1F600 is encoded as a surrogate pair
+	assert_eq!(encode_long_name("\u{1F600}".as_bytes()), Some(vec![0xD83D, 0xDE00]));
+	let mut name = String::new();
+	for _ in 0 .. 127 {
+		name.push('\u{1F600}');
+	}
+	name.push('a');
+	assert_eq!(encode_long_name(name.as_bytes()).map(|v| v.len()), Some(255));
+	name.push('a');
+	assert_eq!(encode_long_name(name.as_bytes()), None);
+}
+#[test]
+// Reserved names and characters are rejected
+fn long_name_invalid()
+{
+	assert_eq!(encode_long_name(b""), None);
+	assert_eq!(encode_long_name(b"."), None);
+	assert_eq!(encode_long_name(b".."), None);
+	assert_eq!(encode_long_name(b"a:b"), None);
+	assert_eq!(encode_long_name(b"a\x01b"), None);
+	assert_eq!(encode_long_name(b"\xFF\xFE"), None);
+	assert_eq!(encode_long_name(b"..a"), Some(vec![b'.' as u16, b'.' as u16, b'a' as u16]));
+}
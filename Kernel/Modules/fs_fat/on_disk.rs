@@ -37,6 +37,14 @@ fn read_arr<T: AsMut<[u8]>>(s: &mut &[u8]) -> T {
 	s.read(v.as_mut()).unwrap();
 	v
 }
+fn write_u16(d: &mut [u8], v: u16) {
+	use kernel::lib::byteorder::{ByteOrder,LittleEndian};
+	LittleEndian::write_u16(d, v)
+}
+fn write_u32(d: &mut [u8], v: u32) {
+	use kernel::lib::byteorder::{ByteOrder,LittleEndian};
+	LittleEndian::write_u32(d, v)
+}
 fn read_arr16<T: AsMut<[u16]>>(s: &mut &[u8]) -> T {
 	// (mostly) SAFE: 'T' should be POD... but can't enforce that easily
 	let mut v: T = unsafe { ::core::mem::zeroed() };
@@ -226,7 +234,45 @@ impl DirEnt {
 			size: read_u32(src),
 		}
 	}
+	/// Encode into a 32-byte directory entry
+	pub fn write(&self, dst: &mut [u8]) {
+		dst[0..11].clone_from_slice(&self.name);
+		dst[11] = self.attribs;
+		dst[12] = self.lcase;
+		dst[13] = self.creation_ds;
+		write_u16(&mut dst[14..], self.creation_time);
+		write_u16(&mut dst[16..], self.creation_date);
+		write_u16(&mut dst[18..], self.accessed_date);
+		write_u16(&mut dst[20..], self.cluster_hi);
+		write_u16(&mut dst[22..], self.modified_time);
+		write_u16(&mut dst[24..], self.modified_date);
+		write_u16(&mut dst[26..], self.cluster);
+		write_u32(&mut dst[28..], self.size);
+	}
+}
+
+/// Checksum of a short name, stored in the associated long name entries
+pub fn short_name_checksum(name: &[u8; 11]) -> u8 {
+	name.iter().fold(0u8, |sum, &c| ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(c))
+}
+
+/// Decode a directory entry date/time (local time, treated as UTC)
+///
+/// `centis` is the count of 10ms units (0-199) only present for creation times. Returns `None` if the
+/// date isn't set (zero, as left by drivers that don't record creation/access times) or is invalid.
+pub fn decode_timestamp(date: u16, time: u16, centis: u8) -> Option<::kernel::time::Timestamp> {
+	let year = 1980 + (date >> 9) as i32;
+	let month = ((date >> 5) & 0xF) as u8;
+	let day = (date & 0x1F) as u8;
+	if date == 0 || month == 0 || month > 12 || day == 0 {
+		return None;
+	}
+	let hour = (time >> 11) as u8;
+	let minute = ((time >> 5) & 0x3F) as u8;
+	let second = ((time & 0x1F) * 2) as u8 + centis / 100;
+	Some( ::kernel::time::Timestamp::from_civil(year, month, day, hour, minute, second, (centis % 100) as u16 * 10) )
 }
+
 #[derive(Debug)]
 pub struct DirEntLong
 {
@@ -252,5 +298,16 @@ impl DirEntLong {
 			name3: read_arr16(src),
 		}
 	}
+	/// Encode into a 32-byte directory entry
+	pub fn write(&self, dst: &mut [u8]) {
+		dst[0] = self.id;
+		for (i,&c) in self.name1.iter().enumerate() { write_u16(&mut dst[1+i*2..], c); }
+		dst[11] = self.attrib;
+		dst[12] = self.ty;
+		dst[13] = self.checksum;
+		for (i,&c) in self.name2.iter().enumerate() { write_u16(&mut dst[14+i*2..], c); }
+		write_u16(&mut dst[26..], self.first_cluster);
+		for (i,&c) in self.name3.iter().enumerate() { write_u16(&mut dst[28+i*2..], c); }
+	}
 }
 
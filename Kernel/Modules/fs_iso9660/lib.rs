@@ -138,7 +138,7 @@ impl mount::Filesystem for Instance
 	}
 	fn get_node_by_inode(&self, id: node::InodeId) -> Option<node::Node> {
 		if id == 0 {
-			Some(Dir::new_node(self.0.borrow(), self.root_lba, self.root_size) )
+			Some(Dir::new_node(self.0.borrow(), self.root_lba, self.root_size, default_metadata()) )
 		}
 		else {
 			// Look up (or read) parent directory to obtain the info
@@ -164,13 +164,13 @@ impl mount::Filesystem for Instance
 					None
 				}
 				else if ent.flags & (1 << 1) != 0 {
-					Some(Dir::new_node(self.0.borrow(), ent.start, ent.size))
+					Some(Dir::new_node(self.0.borrow(), ent.start, ent.size, ent.metadata(&self.0)))
 				}
 				else if ent.flags & 0x64 != 0 {
 					None
 				}
 				else {
-					Some(File::new_node(self.0.borrow(), ent.start, ent.size))
+					Some(File::new_node(self.0.borrow(), ent.start, ent.size, ent.metadata(&self.0)))
 				}
 			}
 		}
@@ -211,14 +211,16 @@ struct File
 	fs: ArefBorrow<InstanceInner>,
 	first_lba: u32,
 	size: u32,
+	metadata: node::Metadata,
 }
 impl File
 {
-	fn new_node(fs: ArefBorrow<InstanceInner>, first_lba: u32, size: u32) -> node::Node {
+	fn new_node(fs: ArefBorrow<InstanceInner>, first_lba: u32, size: u32, metadata: node::Metadata) -> node::Node {
 		node::Node::File( Box::new( File {
 			fs: fs,
 			first_lba: first_lba,
 			size: size,
+			metadata: metadata,
 			} ) )
 	}
 }
@@ -230,6 +232,9 @@ impl node::NodeBase for File
 	fn get_any(&self) -> &::core::any::Any {
 		self
 	}
+	fn get_metadata(&self) -> node::Metadata {
+		node::Metadata { size: self.size as u64, ..self.metadata.clone() }
+	}
 }
 impl node::File for File
 {
@@ -300,14 +305,16 @@ struct Dir
 	fs: ArefBorrow<InstanceInner>,
 	first_lba: u32,
 	size: u32,
+	metadata: node::Metadata,
 }
 impl Dir
 {
-	fn new_node(fs: ArefBorrow<InstanceInner>, first_lba: u32, size: u32) -> node::Node {
+	fn new_node(fs: ArefBorrow<InstanceInner>, first_lba: u32, size: u32, metadata: node::Metadata) -> node::Node {
 		node::Node::Dir( Box::new( Dir {
 			fs: fs,
 			first_lba: first_lba,
 			size: size,
+			metadata: metadata,
 			} ) )
 	}
 }
@@ -319,6 +326,9 @@ impl node::NodeBase for Dir
 	fn get_any(&self) -> &::core::any::Any {
 		self
 	}
+	fn get_metadata(&self) -> node::Metadata {
+		node::Metadata { size: self.size as u64, ..self.metadata.clone() }
+	}
 }
 impl node::Dir for Dir
 {
@@ -383,6 +393,10 @@ impl node::Dir for Dir
 		// ISO9660 is readonly
 		Err( vfs::Error::ReadOnlyFilesystem )
 	}
+	fn rename(&self, _old_name: &ByteStr, _new_dir: &node::Dir, _new_name: &ByteStr) -> node::Result<()> {
+		// ISO9660 is readonly
+		Err( vfs::Error::ReadOnlyFilesystem )
+	}
 }
 
 
@@ -395,6 +409,8 @@ struct DirEnt<'a>
 	flags: u8,
 	start: u32,
 	size: u32,
+	/// Recording date and time (7-byte format)
+	recorded: [u8; 7],
 	name: &'a [u8],
 	sys_use: &'a [u8],
 }
@@ -408,6 +424,84 @@ impl<'a> ::core::fmt::Debug for DirEnt<'a> {
 
 impl<'a> DirEnt<'a>
 {
+	/// Obtain metadata for this entry (using RockRidge information if present)
+	fn metadata(&self, fs: &InstanceInner) -> node::Metadata {
+		let mut rv = default_metadata();
+		rv.size = self.size as u64;
+		rv.created = decode_date_short(&self.recorded);
+		rv.modified = rv.created;
+		let skip = fs.susp_len_skip.map(|v| v as usize).unwrap_or(!0);
+		if skip <= self.sys_use.len() {
+			for ent in SuspIterator(&self.sys_use[skip..])
+			{
+				match ent
+				{
+				SuspItem::PosixMode { mode, n_links, uid, gid, .. } => {
+					rv.mode = (mode & 0o7777) as u16;
+					rv.link_count = n_links;
+					rv.uid = uid;
+					rv.gid = gid;
+					},
+				SuspItem::Timestamps { flags, data } => {
+					// Entries present in flag order, each either the 7-byte or 17-byte format
+					let long_form = flags & 0x80 != 0;
+					let size = if long_form { 17 } else { 7 };
+					let mut data = data;
+					for bit in 0 .. 7
+					{
+						if flags & (1 << bit) == 0 {
+							continue ;
+						}
+						if data.len() < size {
+							break ;
+						}
+						let ts = if long_form { decode_date_long(&data[..size]) } else { decode_date_short(&data[..size]) };
+						match bit
+						{
+						0 => rv.created = ts,
+						1 => rv.modified = ts,
+						2 => rv.accessed = ts,
+						_ => {},
+						}
+						data = &data[size..];
+					}
+					},
+				_ => {},
+				}
+			}
+		}
+		rv
+	}
+}
+
+/// Metadata for nodes without a directory entry (or RockRidge information)
+fn default_metadata() -> node::Metadata {
+	node::Metadata {
+		mode: 0o555,
+		link_count: 1,
+		..Default::default()
+		}
+}
+/// Decode a 7-byte directory record date (years since 1900, ..., GMT offset in 15 minute units)
+fn decode_date_short(d: &[u8]) -> Option<::kernel::time::Timestamp> {
+	if d[0] == 0 && d[1] == 0 {
+		return None;
+	}
+	let ts = ::kernel::time::Timestamp::from_civil(1900 + d[0] as i32, d[1], d[2], d[3], d[4], d[5], 0);
+	Some( ::kernel::time::Timestamp::from_millis(ts.as_millis() - d[6] as i8 as i64 * 15 * 60 * 1000) )
+}
+/// Decode a 17-byte volume descriptor style date ("YYYYMMDDHHMMSScc" then GMT offset)
+fn decode_date_long(d: &[u8]) -> Option<::kernel::time::Timestamp> {
+	fn num(d: &[u8]) -> u32 {
+		d.iter().fold(0, |v, &c| v * 10 + (c.wrapping_sub(b'0') % 10) as u32)
+	}
+	let year = num(&d[0..4]);
+	if year == 0 {
+		return None;
+	}
+	let ts = ::kernel::time::Timestamp::from_civil(year as i32, num(&d[4..6]) as u8, num(&d[6..8]) as u8,
+		num(&d[8..10]) as u8, num(&d[10..12]) as u8, num(&d[12..14]) as u8, num(&d[14..16]) as u16 * 10);
+	Some( ::kernel::time::Timestamp::from_millis(ts.as_millis() - d[16] as i8 as i64 * 15 * 60 * 1000) )
 }
 
 struct DirSector<'a> {
@@ -486,6 +580,7 @@ impl<'a> DirSector<'a>
 					flags: ent[25],
 					start: LittleEndian::read_u32(&ent[2..]),
 					size: LittleEndian::read_u32(&ent[10..]),
+					recorded: [ent[18], ent[19], ent[20], ent[21], ent[22], ent[23], ent[24]],
 					name: name,
 					sys_use: su,
 					}))
@@ -89,7 +89,7 @@ pub fn init()
 	// Create render thread
 	// SAFE: Called in single-threaded context
 	unsafe { S_EVENT_QUEUE.prep(|| ::kernel::lib::ring_buffer::AtomicRingBuf::new(32)); }
-	S_RENDER_THREAD.init( || ::kernel::threads::WorkerThread::new("GUI Compositor", render_thread) );
+	S_RENDER_THREAD.init( || ::kernel::threads::WorkerThread::with_priority("GUI Compositor", ::kernel::threads::Priority::High, render_thread) );
 }
 
 
@@ -32,6 +32,8 @@ unsafe impl Pod for u32 {}
 unsafe impl Pod for ::values::WaitItem {}
 unsafe impl Pod for ::values::GuiEvent {}	// Kinda lies, but meh
 unsafe impl Pod for ::values::RpcMessage {}
+unsafe impl Pod for ::values::ProcessStats {}
+unsafe impl Pod for ::values::VFSMetadata {}
 
 impl<T: Pod> SyscallArg for Freeze<T>
 {
@@ -40,8 +42,18 @@ impl<T: Pod> SyscallArg for Freeze<T>
 			return Err( ::Error::TooManyArgs );
 		}
 		let ptr = args[0] as *const T;
-		*args = &args[2..];
-		todo!("Freeze {:p}", ptr);
+		*args = &args[1..];
+		// SAFE: Performs data validation, and only accepts user pointers (which are checkable)
+		unsafe {
+			// 1. Ensure that the pointed object is valid (overlaps checks by Freeze, but gives a better error)
+			let v = if let Some(v) = ::kernel::memory::buf_to_slice(ptr, 1) {
+					v
+				} else {
+					return Err( ::Error::InvalidBuffer(ptr as *const (), ::core::mem::size_of::<T>()) );
+				};
+			// 2. Create a freeze on that memory (ensuring that it's not unmapped until the Freeze object drops)
+			Ok( try!(Freeze::new(&v[0])) )
+		}
 	}
 }
 impl<T: Pod> SyscallArg for Freeze<[T]>
@@ -86,11 +98,17 @@ impl<T: Pod> SyscallArg for FreezeMut<T>
 			return Err( ::Error::TooManyArgs );
 		}
 		let ptr = args[0] as *mut T;
-
+		*args = &args[1..];
 		// SAFE: Performs data validation, and only accepts user pointers (which are checkable)
 		unsafe { 
-			// 3. Create a freeze on that memory (ensuring that it's not unmapped until the Freeze object drops)
-			Ok( try!(FreezeMut::new(&mut *ptr)) )
+			// 1. Ensure that the pointed object is valid (overlaps checks by Freeze, but gives a better error)
+			let v = if let Some(v) = ::kernel::memory::buf_to_slice_mut(ptr, 1) {
+					v
+				} else {
+					return Err( ::Error::InvalidBuffer(ptr as *const (), ::core::mem::size_of::<T>()) );
+				};
+			// 2. Create a freeze on that memory (ensuring that it's not unmapped until the Freeze object drops)
+			Ok( try!(FreezeMut::new(&mut v[0])) )
 		}
 	}
 }
@@ -31,14 +31,38 @@ impl ::objects::Object for SyncChannel
 		::values::IPC_RPC_SEND => {
 			let data: Freeze<::values::RpcMessage> = try!(args.get());
 			let obj: u32 = try!(args.get());
-			todo!("IPC_RPC_SEND({:p}, {})", &*data, obj);
+			log_debug!("IPC_RPC_SEND({:p}, {})", &*data, obj);
+			// - Only one message can be pending at a time
+			let mut lh = self.get_peer().message.lock();
+			if lh.is_some() {
+				return Ok( 1 );
+			}
+			// - Object handle 0 (this process) can't be sent, so is used to indicate no object
+			let obj = if obj != 0 { Some( try!(::objects::take_object_raw(obj)) ) } else { None };
+			*lh = Some( (*data, obj) );
+			drop(lh);
+			self.get_peer().queue.wake_one();
+			Ok( 0 )
 			},
 		::values::IPC_RPC_RECV => {
-			let _data: FreezeMut<::values::RpcMessage> = try!(args.get());
+			let mut data: FreezeMut<::values::RpcMessage> = try!(args.get());
 
-			if let Some(msg) = self.take_message()
+			if let Some((msg, obj)) = self.take_message()
 			{
-				todo!("IPC_RPC_RECV - Message present - {:?}", msg);
+				*data = msg;
+				// Return the handle of the received object (or zero if there wasn't one)
+				match obj
+				{
+				Some(obj) => match ::objects::new_object_raw(obj)
+					{
+					Ok(h) => Ok( h as u64 ),
+					Err(e) => {
+						log_notice!("IPC_RPC_RECV - Object dropped, can't be added to the object table: {:?}", e);
+						Ok( 0 )
+						},
+					},
+				None => Ok( 0 ),
+				}
 			}
 			else
 			{
@@ -101,7 +125,8 @@ struct SyncChannelBack
 #[derive(Default)]
 struct SyncChannelSide
 {
-	message: ::kernel::sync::Spinlock<Option<RpcMessage>>,
+	/// Pending message and attached object
+	message: ::kernel::sync::Spinlock<Option<(RpcMessage, Option<::objects::ObjectAlloc>)>>,
 	queue: ::kernel::async::queue::Source,
 }
 
@@ -121,6 +146,13 @@ impl SyncChannel
 		}
 	}
 
+	fn get_peer(&self) -> &SyncChannelSide {
+		// SAFE: Destructor ensures that pointer is valid until both are dead
+		unsafe {
+			&(*self.ptr).sides[1 - self.side_idx as usize]
+		}
+	}
+
 	pub fn wait_upon(&self, waiter: &mut ::kernel::threads::SleepObject) {
 		self.get_side().queue.wait_upon(waiter);
 	}
@@ -131,7 +163,7 @@ impl SyncChannel
 	pub fn has_message(&self) -> bool {
 		self.get_side().message.lock().is_some()
 	}
-	pub fn take_message(&self) -> Option<RpcMessage> {
+	pub fn take_message(&self) -> Option<(RpcMessage, Option<::objects::ObjectAlloc>)> {
 		self.get_side().message.lock().take()
 	}
 }
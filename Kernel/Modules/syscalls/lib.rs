@@ -25,6 +25,7 @@ mod threads;
 mod gui_calls;
 mod vfs;
 mod ipc_calls;
+mod memory_calls;
 mod network_calls;
 
 pub type ObjectHandle = u32;
@@ -151,7 +152,8 @@ fn invoke_int(call_id: u32, args: &mut Args) -> Result<u64,Error>
 			},
 		// - 0/2: Terminate current thread
 		CORE_EXITTHREAD => {
-			threads::terminate(); 0
+			let status: u32 = try!(args.get());
+			threads::terminate(status); 0
 			},
 		// - 0/3: Start process
 		CORE_STARTPROCESS => {
@@ -168,7 +170,8 @@ fn invoke_int(call_id: u32, args: &mut Args) -> Result<u64,Error>
 		CORE_STARTTHREAD => {
 			let ip: usize = try!(args.get());
 			let sp: usize = try!(args.get());
-			threads::newthread(sp, ip) as u64
+			let arg: usize = try!(args.get());
+			threads::newthread(sp, ip, arg) as u64
 			},
 		// - 0/5: Wait for event
 		CORE_WAIT => {
@@ -182,6 +185,19 @@ fn invoke_int(call_id: u32, args: &mut Args) -> Result<u64,Error>
 		CORE_FUTEX_WAKE => {
 			todo!("FUTEX_SLEEP");
 			},
+		CORE_SETPRIORITY => {
+			let priority: u8 = try!(args.get());
+			from_result(threads::set_priority(priority))
+			},
+		CORE_PROCLIST => {
+			let mut buf: FreezeMut<[ProcessStats]> = try!(args.get());
+			threads::list_processes(&mut buf) as u64
+			},
+		CORE_GETRANDOM => {
+			let mut buf: FreezeMut<[u8]> = try!(args.get());
+			::kernel::rand::fill(&mut buf);
+			0
+			},
 		// === 1: Window Manager / GUI
 		// - 1/0: New group (requires permission, has other restrictions)
 		GUI_NEWGROUP => {
@@ -246,6 +262,47 @@ fn invoke_int(call_id: u32, args: &mut Args) -> Result<u64,Error>
 			Err( () ) => error_code(0) as u64,
 			}
 			},
+		MEM_SYNC => {
+			let addr: usize = try!(args.get());
+			let count: usize = try!(args.get());
+			log_debug!("MEM_SYNC({:#x},{})", addr, count);
+			// - The whole range must be in user memory (and the end must not overflow)
+			let end = match count.checked_mul(::kernel::PAGE_SIZE).and_then(|l| (addr & !(::kernel::PAGE_SIZE - 1)).checked_add(l))
+				{
+				Some(v) => v,
+				None => return Err( Error::BadValue ),
+				};
+			if ::kernel::arch::memory::addresses::is_global(addr) || (end > addr && ::kernel::arch::memory::addresses::is_global(end - 1)) {
+				return Err( Error::BadValue );
+			}
+			from_result( ::kernel::memory::vma::flush(addr as *mut (), count)
+				.map(|_| 0u32)
+				.map_err(|e| ::values::VFSError::from(e))
+				)
+			},
+		MEM_SHM_CREATE => {
+			let size: usize = try!(args.get());
+			log_debug!("MEM_SHM_CREATE({:#x})", size);
+			try!( memory_calls::new_shm(size) )
+			},
+		MEM_STACK_ALLOC => {
+			let top: usize = try!(args.get());
+			let size: usize = try!(args.get());
+			log_debug!("MEM_STACK_ALLOC({:#x},{:#x})", top, size);
+			try!( memory_calls::new_stack(top, size) )
+			},
+		MEM_STACK_FREE => {
+			let top: usize = try!(args.get());
+			log_debug!("MEM_STACK_FREE({:#x})", top);
+			if top == 0 || top % ::kernel::PAGE_SIZE != 0 || ::kernel::arch::memory::addresses::is_global(top - 1) {
+				return Err( Error::BadValue );
+			}
+			match ::kernel::memory::vma::unmap_stack(top as *mut ())
+			{
+			Ok( () ) => 0,
+			Err( () ) => error_code(0) as u64,
+			}
+			},
 		// === 3: IPC
 		IPC_NEWPAIR => {
 			match ipc_calls::new_pair()
@@ -0,0 +1,82 @@
+// "Tifflin" Kernel
+// - By John Hodge (thePowersGang)
+//
+// Core/syscalls/memory_calls.rs
+//! Userland interface to shared memory objects and growable stacks
+use kernel::lib::mem::Arc;
+use kernel::memory::shm::SharedMemory;
+use kernel::memory::virt::{ProtectionMode, MapError};
+use args::Args;
+
+impl_from! {
+	From<MapError>(v) for ::values::MemoryError {
+		match v
+		{
+		MapError::RangeInUse => ::values::MemoryError::RangeInUse,
+		MapError::OutOfMemory => ::values::MemoryError::OutOfMemory,
+		}
+	}
+}
+
+/// Create a new shared memory object, returning the object handle
+pub fn new_shm(size: usize) -> Result<u64, ::Error>
+{
+	// - Objects larger than the user address space could never be mapped
+	if size == 0 || size > ::kernel::arch::memory::addresses::USER_END {
+		return Err( ::Error::BadValue );
+	}
+	Ok( ::objects::new_object( Shm(SharedMemory::new(size)) ) as u64 )
+}
+
+/// Reserve a growable stack ending at `top` (at most `size` bytes, limited by the `USER_STACK_LIMIT` boot option)
+pub fn new_stack(top: usize, size: usize) -> Result<u64, ::Error>
+{
+	let pages = size / ::kernel::PAGE_SIZE + if size % ::kernel::PAGE_SIZE != 0 { 1 } else { 0 };
+	if top % ::kernel::PAGE_SIZE != 0 || pages == 0 || pages > ::kernel::memory::vma::user_stack_limit() {
+		return Err( ::Error::BadValue );
+	}
+	// Only the top of the stack is reserved to start with, the rest is added as the stack grows
+	const INITIAL_PAGES: usize = 4;
+	Ok( ::from_result( ::kernel::memory::vma::map_stack(top as *mut (), ::core::cmp::min(pages, INITIAL_PAGES), pages)
+		.map(|_| 0u32)
+		.map_err(|e| ::values::MemoryError::from(e))
+		) )
+}
+
+struct Shm(Arc<SharedMemory>);
+
+impl ::objects::Object for Shm
+{
+	fn class(&self) -> u16 { ::values::CLASS_MEM_SHM }
+	fn as_any(&self) -> &::core::any::Any { self }
+	fn try_clone(&self) -> Option<u32> {
+		Some( ::objects::new_object( Shm(self.0.clone()) ) )
+	}
+	fn handle_syscall_ref(&self, call: u16, args: &mut Args) -> Result<u64,::Error> {
+		Ok(match call
+		{
+		::values::MEM_SHM_GETSIZE => self.0.size() as u64,
+		::values::MEM_SHM_MAP => {
+			let addr: usize = try!(args.get());
+			let mode = match try!(args.get::<u8>())
+				{
+				0 => ProtectionMode::UserRO,
+				1 => ProtectionMode::UserRW,
+				2 => ProtectionMode::UserRX,
+				_ => return Err( ::Error::BadValue ),
+				};
+			log_debug!("MEM_SHM_MAP({:?}, {:#x}, {:?})", self.0, addr, mode);
+			if addr % ::kernel::PAGE_SIZE != 0 || ::kernel::arch::memory::addresses::is_global(addr) {
+				return Err( ::Error::BadValue );
+			}
+			::from_result( SharedMemory::map(&self.0, addr as *mut (), mode)
+				.map(|_| 0u32)
+				.map_err(|e| ::values::MemoryError::from(e))
+				)
+			},
+		_ => return ::objects::object_has_no_such_method_ref("memory_calls::Shm", call),
+		})
+	}
+	fn bind_wait(&self, _flags: u32, _obj: &mut ::kernel::threads::SleepObject) -> u32 { 0 }
+	fn clear_wait(&self, _flags: u32, _obj: &mut ::kernel::threads::SleepObject) -> u32 { 0 }
+}
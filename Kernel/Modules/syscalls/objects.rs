@@ -343,6 +343,32 @@ pub fn give_object(target: &::kernel::threads::ProcessHandle, tag: &str, handle:
 	Ok( () )
 }
 
+/// Remove an object from this process's table (to be moved elsewhere)
+pub fn take_object_raw(handle: u32) -> Result<ObjectAlloc, super::Error> {
+	if handle == 0 {
+		return Err( super::Error::BadValue );
+	}
+	get_process_local::<ProcessObjects>().take_object(handle)
+}
+/// Add an object (removed from another process's table by `take_object_raw`) to this process
+pub fn new_object_raw(obj: ObjectAlloc) -> Result<u32, super::Error> {
+	get_process_local::<ProcessObjects>().find_and_fill_slot(|| UserObject { data: obj })
+}
+
+/// Borrow another object (e.g. one passed as a syscall argument) as a concrete type
+pub fn with_object_ref<T: Object+'static, R, F>(handle: u32, fcn: F) -> Result<R,super::Error>
+where
+	F: FnOnce(&T)->Result<R,super::Error>
+{
+	get_process_local::<ProcessObjects>().with_object(handle, |obj| {
+		match obj.as_any().downcast_ref::<T>()
+		{
+		Some(v) => fcn(v),
+		None => Err( super::Error::BadValue ),
+		}
+		})
+}
+
 pub fn take_object<T: Object+'static>(handle: u32) -> Result<T,super::Error> {
 	let obj = try!(get_process_local::<ProcessObjects>().take_object(handle));
 	// SAFE: ptr::read is called on a pointer to a value that is subsequently forgotten
@@ -49,12 +49,14 @@ pub fn exit(status: u32) {
 	::kernel::threads::exit_process(status);
 }
 #[inline(never)]
-pub fn terminate() {
-	todo!("terminate()");
+pub fn terminate(status: u32) {
+	::kernel::threads::exit_thread(status);
 }
 #[inline(never)]
-pub fn newthread(sp: usize, ip: usize) -> ObjectHandle {
-	todo!("newthread(sp={:#x},ip={:#x})", sp, ip);
+pub fn newthread(sp: usize, ip: usize, arg: usize) -> ObjectHandle {
+	// NOTE: Don't need to validate these values, as they're used only in user-space
+	let handle = ::kernel::threads::ThreadHandle::new_user(ip, sp, arg);
+	::objects::new_object( Thread(Some(handle)) )
 }
 #[inline(never)]
 pub fn newprocess(name: &str,  clone_start: usize, clone_end: usize) -> ObjectHandle {
@@ -64,6 +66,53 @@ pub fn newprocess(name: &str,  clone_start: usize, clone_end: usize) -> ObjectHa
 	::objects::new_object( ProtoProcess(process) )
 }
 
+/// Fill `buf` with information on running processes, returning the total number of processes
+#[inline(never)]
+pub fn list_processes(buf: &mut [values::ProcessStats]) -> usize {
+	let processes = ::kernel::threads::list_processes();
+	for (d, p) in Iterator::zip( buf.iter_mut(), processes.iter() )
+	{
+		let mut name = [0; 32];
+		// Truncate on a character boundary
+		let mut len = ::core::cmp::min(p.name.len(), name.len());
+		while !p.name.is_char_boundary(len) {
+			len -= 1;
+		}
+		name[..len].copy_from_slice(&p.name.as_bytes()[..len]);
+
+		*d = values::ProcessStats {
+			pid: p.pid,
+			num_threads: p.num_threads as u32,
+			cpu_time_ms: p.cpu_time,
+			resident_pages: p.resident_pages as u64,
+			exit_status: p.exit_status.unwrap_or(0),
+			state: match p.exit_status
+				{
+				None => values::ProcessState::Running,
+				Some(_) => values::ProcessState::Exited,
+				}.into(),
+			_rsvd: [0; 3],
+			name: name,
+			};
+	}
+	processes.len()
+}
+
+#[inline(never)]
+pub fn set_priority(priority: u8) -> Result<u32,u32> {
+	let priority = match values::ThreadPriority::try_from(priority)
+		{
+		Ok(values::ThreadPriority::Low) => ::kernel::threads::Priority::Low,
+		Ok(values::ThreadPriority::Normal) => ::kernel::threads::Priority::Normal,
+		Err(v) => {
+			log_notice!("set_priority - Invalid priority {}", v);
+			return Err(0);
+			},
+		};
+	::kernel::threads::set_priority(priority);
+	Ok(0)
+}
+
 // ret: number of events triggered
 #[inline(never)]
 pub fn wait(events: &mut [values::WaitItem], wake_time_mono: u64) -> Result<u32,Error>
@@ -183,3 +232,74 @@ impl ::objects::Object for Process
 		ret
 	}
 }
+
+/// Handle to a userland thread
+///
+/// Dropping the object detaches the thread (use `EV_THREAD_TERMINATED` and `CORE_THREAD_GETEXIT` to join)
+pub struct Thread(Option<::kernel::threads::ThreadHandle>);
+impl Thread
+{
+	fn handle(&self) -> &::kernel::threads::ThreadHandle {
+		self.0.as_ref().expect("Thread handle already released")
+	}
+}
+impl ::objects::Object for Thread
+{
+	fn class(&self) -> u16 { values::CLASS_CORE_THREAD }
+	fn as_any(&self) -> &Any { self }
+	fn try_clone(&self) -> Option<u32> {
+		None
+	}
+	fn handle_syscall_ref(&self, call: u16, _args: &mut Args) -> Result<u64,Error>
+	{
+		match call
+		{
+		values::CORE_THREAD_GETEXIT => {
+			Ok( match self.handle().get_exit_status()
+				{
+				Some(v) => v as u64,
+				None => !0,
+				} )
+			},
+		_ => ::objects::object_has_no_such_method_ref("threads::Thread", call),
+		}
+	}
+	fn handle_syscall_val(&mut self, call: u16, _args: &mut Args) -> Result<u64,Error> {
+		// SAFE: Raw pointer coerced from &mut, forgotten by caller
+		let this = unsafe { ::core::ptr::read(self) };
+		match call
+		{
+		values::CORE_THREAD_DETACH => {
+			// Dropping `this` releases the handle
+			::core::mem::drop(this);
+			Ok(0)
+			},
+		_ => ::objects::object_has_no_such_method_val("threads::Thread", call)
+		}
+	}
+	fn bind_wait(&self, flags: u32, obj: &mut ::kernel::threads::SleepObject) -> u32 {
+		let mut ret = 0;
+		if flags & values::EV_THREAD_TERMINATED != 0 {
+			self.handle().bind_wait_terminate(obj);
+			ret += 1;
+		}
+		ret
+	}
+	fn clear_wait(&self, flags: u32, obj: &mut ::kernel::threads::SleepObject) -> u32 {
+		let mut ret = 0;
+		if flags & values::EV_THREAD_TERMINATED != 0 {
+			if self.handle().clear_wait_terminate(obj) {
+				ret |= values::EV_THREAD_TERMINATED;
+			}
+		}
+		ret
+	}
+}
+impl ::core::ops::Drop for Thread
+{
+	fn drop(&mut self) {
+		if let Some(h) = self.0.take() {
+			h.detach();
+		}
+	}
+}
@@ -37,10 +37,43 @@ impl_from! {
 		Error::PermissionDenied => VFSError::PermissionDenied,
 		Error::Locked => VFSError::FileLocked,
 		Error::MalformedPath => VFSError::MalformedPath,
-		Error::Unknown(reason) => todo!("VFS Error Unknown - '{}'", reason),
-		_ => todo!("VFS Error - {:?}", v),
+		Error::ReadOnlyFilesystem => VFSError::ReadOnlyFilesystem,
+		Error::AlreadyExists => VFSError::AlreadyExists,
+		Error::InvalidParameter => VFSError::InvalidParameter,
+		Error::OutOfSpace => VFSError::OutOfSpace,
+		Error::OutOfMemory => VFSError::OutOfMemory,
+		Error::BlockIoError(_) => VFSError::IoError,
+		Error::InconsistentFilesystem => VFSError::IoError,
+		Error::NotEmpty => VFSError::NotEmpty,
+		Error::CrossFilesystem => VFSError::CrossFilesystem,
+		Error::FileTooLarge => VFSError::FileTooLarge,
+		Error::RecursionDepthExceeded => VFSError::RecursionDepthExceeded,
+		Error::NonDirComponent => VFSError::NonDirComponent,
+		Error::TransientError => VFSError::Unknown,
+		Error::Unknown(reason) => {
+			log_notice!("VFS Error Unknown - '{}'", reason);
+			VFSError::Unknown
+			},
 		}
 	}}
+	From<::kernel::vfs::mount::MountError>(v) for ::values::VFSMountError {{
+		use kernel::vfs::mount::MountError;
+		use values::VFSMountError;
+		map_enums!(
+			(MountError, VFSMountError)
+			match (v) {
+				(UnknownFilesystem),
+				(NoHandler),
+				(InvalidMountpoint),
+				(MountpointUsed),
+				(CallFailed),
+				(InvalidOption),
+				(NotMounted),
+				(Busy),
+				(RootUnmount),
+			}
+		)
+	}}
 	From<node::NodeClass>(v) for ::values::VFSNodeType {
 		match v
 		{
@@ -80,19 +113,41 @@ pub fn init_handles(loader_handle: ::kernel::vfs::handle::File, init_handle: ::k
 
 	// #1: Initial file handle
 	::objects::new_object( File(init_handle) );
-	// #2: Read-only root
-	::objects::new_object(Dir::new( {
+	// - Read-only root (received by `vfs::root`)
+	::objects::push_as_unclaimed("ro:/", ::objects::new_object(Dir::new( {
 		let root = handle::Dir::open(Path::new("/")).unwrap();
 		//root.set_permissions( handle::Perms::readonly() );
 		root
-		}));
+		})));
 
 	// - Read-write handle to /
 	//::objects::push_as_unclaimed( ::objects::new_object( Dir::new( handle::Dir::open(Path::new("/")).unwrap() ) ) );
 	::objects::push_as_unclaimed("RwRoot", ::objects::new_object( Dir::new( handle::Dir::open(Path::new("/")).unwrap() ) ) );
+	// - Mount management
+	::objects::push_as_unclaimed("MountMgr", ::objects::new_object(Mounts));
 }
 
 
+/// Convert node metadata into the userland structure
+fn get_user_metadata(m: node::Metadata, class: node::NodeClass) -> values::VFSMetadata {
+	fn ts(t: Option<::kernel::time::Timestamp>) -> i64 {
+		t.map(|v| v.as_millis()).unwrap_or(values::VFS_TIMESTAMP_NONE)
+	}
+	values::VFSMetadata {
+		inode: m.inode,
+		size: m.size,
+		created: ts(m.created),
+		modified: ts(m.modified),
+		accessed: ts(m.accessed),
+		uid: m.uid,
+		gid: m.gid,
+		link_count: m.link_count,
+		mode: m.mode,
+		node_type: { let v: u32 = values::VFSNodeType::from(class).into(); v as u8 },
+		_rsvd: 0,
+		}
+}
+
 // --------------------------------------------------------------------
 //
 // --------------------------------------------------------------------
@@ -105,9 +160,15 @@ impl objects::Object for Node
 	fn try_clone(&self) -> Option<u32> {
 		Some( ::objects::new_object( Node(self.0.clone()) ) )
 	}
-	fn handle_syscall_ref(&self, call: u16, _args: &mut Args) -> Result<u64,Error> {
+	fn handle_syscall_ref(&self, call: u16, args: &mut Args) -> Result<u64,Error> {
 		match call
 		{
+		values::VFS_NODE_GETMETADATA => {
+			let mut dst: FreezeMut<values::VFSMetadata> = try!(args.get());
+			log_debug!("VFS_NODE_GETMETADATA()");
+			*dst = get_user_metadata(self.0.metadata(), self.0.get_class());
+			Ok(0)
+			},
 		values::VFS_NODE_GETTYPE => {
 			log_debug!("VFS_NODE_GETTYPE()");
 			let v32: u32 = ::values::VFSNodeType::from( self.0.get_class() ).into();
@@ -172,6 +233,12 @@ impl objects::Object for File
 		values::VFS_FILE_GETSIZE => {
 			Ok( self.0.size() )
 			},
+		values::VFS_FILE_GETMETADATA => {
+			let mut dst: FreezeMut<values::VFSMetadata> = try!(args.get());
+			log_debug!("VFS_FILE_GETMETADATA()");
+			*dst = get_user_metadata(self.0.metadata(), node::NodeClass::File);
+			Ok(0)
+			},
 		values::VFS_FILE_READAT => {
 			let ofs: u64 = try!(args.get());
 			let mut dest: FreezeMut<[u8]> = try!(args.get());
@@ -179,7 +246,10 @@ impl objects::Object for File
 			match self.0.read(ofs, &mut dest)
 			{
 			Ok(count) => Ok(count as u64),
-			Err(e) => todo!("File::handle_syscall READAT Error {:?}", e),
+			Err(e) => {
+				log_log!("VFS_FILE_READAT - Error {:?}", e);
+				Ok( super::from_result(to_result(Err::<u32,_>(e))) )
+				},
 			}
 			},
 		values::VFS_FILE_WRITEAT => {
@@ -189,7 +259,10 @@ impl objects::Object for File
 			match self.0.write(ofs, &src)
 			{
 			Ok(count) => Ok(count as u64),
-			Err(e) => todo!("File::handle_syscall WRITEAT Error {:?}", e),
+			Err(e) => {
+				log_log!("VFS_FILE_WRITEAT - Error {:?}", e);
+				Ok( super::from_result(to_result(Err::<u32,_>(e))) )
+				},
 			}
 			},
 		values::VFS_FILE_MEMMAP => {
@@ -219,7 +292,10 @@ impl objects::Object for File
 				::core::mem::forget(h);
 				Ok(0)
 				},
-			Err(e) => todo!("File::handle_syscall MEMMAP Error {:?}", e),
+			Err(e) => {
+				log_log!("VFS_FILE_MEMMAP - Error {:?}", e);
+				Ok( super::from_result(to_result(Err::<u32,_>(e))) )
+				},
 			}
 			},
 		_ => ::objects::object_has_no_such_method_ref("vfs::File", call),
@@ -276,9 +352,75 @@ impl objects::Object for Dir
 					.map( |h| objects::new_object(Node(h)) )
 				)
 			},
+		values::VFS_DIR_OPENPATH_NOFOLLOW => {
+			let path: Freeze<[u8]> = try!(args.get());
+
+			let path = Path::new(&path);
+			log_debug!("VFS_DIR_OPENPATH_NOFOLLOW({:?})", path);
+			super::from_result(
+				to_result( self.handle.open_child_path_nofollow(path) )
+					.map( |h| objects::new_object(Node(h)) )
+				)
+			},
 		values::VFS_DIR_ENUMERATE => {
 			objects::new_object( DirIter::new( self.handle.clone() ) ) as u64
 			},
+		values::VFS_DIR_CREATEFILE => {
+			let name: Freeze<[u8]> = try!(args.get());
+			let mode: u8 = try!(args.get());
+
+			let name = ::kernel::lib::byte_str::ByteStr::new(&*name);
+			let mode = match ::values::VFSFileOpenMode::try_from(mode)
+				{
+				Ok(v) => v,
+				Err(_) => return Err( Error::BadValue ),
+				};
+			log_debug!("VFS_DIR_CREATEFILE({:?}, {:?})", name, mode);
+
+			super::from_result(
+				to_result( self.handle.create_file(name, mode.into()) )
+					.map( |h| objects::new_object(File(h)) )
+				)
+			},
+		values::VFS_DIR_MKDIR => {
+			let name: Freeze<[u8]> = try!(args.get());
+
+			let name = ::kernel::lib::byte_str::ByteStr::new(&*name);
+			log_debug!("VFS_DIR_MKDIR({:?})", name);
+
+			super::from_result(
+				to_result( self.handle.create_dir(name) )
+					.map( |h| objects::new_object(Dir::new(h)) )
+				)
+			},
+		values::VFS_DIR_UNLINK => {
+			let name: Freeze<[u8]> = try!(args.get());
+
+			let name = ::kernel::lib::byte_str::ByteStr::new(&*name);
+			log_debug!("VFS_DIR_UNLINK({:?})", name);
+
+			super::from_result( to_result( self.handle.unlink(name) ).map(|_| 0u32) )
+			},
+		values::VFS_DIR_RMDIR => {
+			let name: Freeze<[u8]> = try!(args.get());
+
+			let name = ::kernel::lib::byte_str::ByteStr::new(&*name);
+			log_debug!("VFS_DIR_RMDIR({:?})", name);
+
+			super::from_result( to_result( self.handle.rmdir(name) ).map(|_| 0u32) )
+			},
+		values::VFS_DIR_RENAME => {
+			let old_name: Freeze<[u8]> = try!(args.get());
+			let new_dir: u32 = try!(args.get());
+			let new_name: Freeze<[u8]> = try!(args.get());
+
+			let old_name = ::kernel::lib::byte_str::ByteStr::new(&*old_name);
+			let new_name = ::kernel::lib::byte_str::ByteStr::new(&*new_name);
+			log_debug!("VFS_DIR_RENAME({:?}, #{} {:?})", old_name, new_dir, new_name);
+
+			let new_dir = try!(::objects::with_object_ref(new_dir, |d: &Dir| Ok(d.handle.clone())));
+			super::from_result( to_result( self.handle.rename(old_name, &new_dir, new_name) ).map(|_| 0u32) )
+			},
 		_ => return ::objects::object_has_no_such_method_ref("vfs::Dir", call),
 		})
 	}
@@ -396,6 +538,119 @@ impl DirEntCache {
 }
 
 
+// --------------------------------------------------------------------
+//
+// --------------------------------------------------------------------
+
+/// Mount management capability
+struct Mounts;
+impl Mounts
+{
+	fn mount(path: &Path, volume: &str, options: &str) -> Result<(), ::values::VFSMountError> {
+		use kernel::metadevs::storage::VolumeHandle;
+		// - `fs=NAME` selects the driver, `size=BYTES` sets the size of a ramfs mount, everything else is a mount option
+		let mut fs = "";
+		let mut size = None;
+		let mut opts: Vec<&str> = Vec::new();
+		for o in options.split(',')
+		{
+			if o.starts_with("fs=") {
+				fs = &o[3..];
+			}
+			else if o.starts_with("size=") {
+				size = match parse_size(&o[5..])
+					{
+					Some(v) => Some(v),
+					None => return Err(::values::VFSMountError::InvalidOption),
+					};
+			}
+			else {
+				opts.push(o);
+			}
+		}
+		let vh = if fs == "ramfs" {
+				// In-memory filesystem, the volume name is ignored
+				VolumeHandle::new_ramdisk( size.map(|v| (v + ::kernel::PAGE_SIZE as u64 - 1) / ::kernel::PAGE_SIZE as u64).unwrap_or(0) as usize )
+			}
+			else if size.is_some() {
+				return Err(::values::VFSMountError::InvalidOption);
+			}
+			else {
+				match VolumeHandle::open_named(volume)
+				{
+				Ok(v) => v,
+				Err(e) => {
+					log_log!("VFS_MOUNTS_MOUNT - Can't open volume '{}': {}", volume, e);
+					return Err(::values::VFSMountError::InvalidVolume);
+					},
+				}
+			};
+		Ok( try!(::kernel::vfs::mount::mount(path, vh, fs, &opts)) )
+	}
+}
+/// Parse a size with an optional `k`/`m`/`g` suffix
+fn parse_size(s: &str) -> Option<u64> {
+	let (num, shift) = match s.as_bytes().last()
+		{
+		Some(&b'k') | Some(&b'K') => (&s[..s.len()-1], 10),
+		Some(&b'm') | Some(&b'M') => (&s[..s.len()-1], 20),
+		Some(&b'g') | Some(&b'G') => (&s[..s.len()-1], 30),
+		_ => (s, 0),
+		};
+	match num.parse::<u64>()
+	{
+	Ok(v) if v != 0 => v.checked_mul(1 << shift),
+	_ => None,
+	}
+}
+impl objects::Object for Mounts
+{
+	fn class(&self) -> u16 { values::CLASS_VFS_MOUNTS }
+	fn as_any(&self) -> &Any { self }
+	fn try_clone(&self) -> Option<u32> {
+		Some( ::objects::new_object(Mounts) )
+	}
+	fn handle_syscall_ref(&self, call: u16, args: &mut Args) -> Result<u64,Error> {
+		use kernel::vfs::mount;
+		Ok(match call
+		{
+		values::VFS_MOUNTS_MOUNT => {
+			let path: Freeze<[u8]> = try!(args.get());
+			let volume: Freeze<str> = try!(args.get());
+			let options: Freeze<str> = try!(args.get());
+			let path = Path::new(&path);
+			log_debug!("VFS_MOUNTS_MOUNT({:?}, {:?}, {:?})", path, &*volume, &*options);
+			super::from_result( Mounts::mount(path, &volume, &options).map(|_| 0u32) )
+			},
+		values::VFS_MOUNTS_UNMOUNT => {
+			let path: Freeze<[u8]> = try!(args.get());
+			let force: bool = try!(args.get());
+			let path = Path::new(&path);
+			log_debug!("VFS_MOUNTS_UNMOUNT({:?}, force={})", path, force);
+			super::from_result( mount::unmount(path, force).map(|_| 0u32).map_err(|e| ::values::VFSMountError::from(e)) )
+			},
+		values::VFS_MOUNTS_SYNC => {
+			let path: Freeze<[u8]> = try!(args.get());
+			log_debug!("VFS_MOUNTS_SYNC({:?})", Path::new(&path));
+			let rv = if path.len() == 0 { mount::sync_all() } else { mount::sync(Path::new(&path)) };
+			super::from_result( rv.map(|_| 0u32).map_err(|e| ::values::VFSMountError::from(e)) )
+			},
+		values::VFS_MOUNTS_REMOUNT => {
+			let path: Freeze<[u8]> = try!(args.get());
+			let options: Freeze<str> = try!(args.get());
+			let path = Path::new(&path);
+			log_debug!("VFS_MOUNTS_REMOUNT({:?}, {:?})", path, &*options);
+			let opts: Vec<&str> = options.split(',').collect();
+			super::from_result( mount::remount(path, &opts).map(|_| 0u32).map_err(|e| ::values::VFSMountError::from(e)) )
+			},
+		_ => return ::objects::object_has_no_such_method_ref("vfs::Mounts", call),
+		})
+	}
+	fn bind_wait(&self, _flags: u32, _obj: &mut ::kernel::threads::SleepObject) -> u32 { 0 }
+	fn clear_wait(&self, _flags: u32, _obj: &mut ::kernel::threads::SleepObject) -> u32 { 0 }
+}
+
+
 // --------------------------------------------------------------------
 //
 // --------------------------------------------------------------------
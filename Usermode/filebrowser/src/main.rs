@@ -22,7 +22,7 @@ fn main()
 {
 	::wtk::initialise();
 
-	let root_handle: ::syscalls::vfs::Dir = ::syscalls::threads::S_THIS_PROCESS.receive_object("ro:/").expect("No FS root handle passed");
+	let root_handle = ::syscalls::vfs::root().expect("No FS root handle passed");
 	//let root_handle = ::syscalls::vfs::Dir::open("/").unwrap();
 
 	let mut fl = ::filelist::FileList::new(&root_handle);
@@ -43,7 +43,7 @@ fn main()
 fn get_app_exe(name: &[u8]) -> Result<::syscalls::vfs::File, ()> {
 	match name
 	{
-	b"fileviewer" => Ok( ::syscalls::vfs::ROOT.open_child_path("/sysroot/bin/fileviewer").unwrap().into_file(::syscalls::vfs::FileOpenMode::Execute).unwrap() ),
+	b"fileviewer" => Ok( ::syscalls::vfs::root().unwrap().open_child_path("/sysroot/bin/fileviewer").unwrap().into_file(::syscalls::vfs::FileOpenMode::Execute).unwrap() ),
 	_ => Err( () ),
 	}
 }
@@ -51,7 +51,7 @@ fn get_app_exe(name: &[u8]) -> Result<::syscalls::vfs::File, ()> {
 fn view_file(p: &::std::fs::Path, nh: ::syscalls::vfs::Node) {
 	kernel_log!("view_file(p={:?})", p);
 	let byte_args: &[&[u8]] = &[ p.as_ref(), ];
-	match ::loader::new_process(get_app_exe(b"fileviewer").unwrap(), b"/sysroot/bin/fileviewer", byte_args)
+	match ::loader::new_process(get_app_exe(b"fileviewer").unwrap(), b"/sysroot/bin/fileviewer", byte_args, None)
 	{
 	Ok(app) => {
 		kernel_log!("- Sending WGH");
@@ -13,13 +13,14 @@ fn main()
 {
 	kernel_log!("Tifflin (rust_os) userland started");
 
+	let root = ::syscalls::vfs::root().expect("No read-only VFS root");
 	let rw_root: ::syscalls::vfs::Dir = get_handle("RW VFS Root", "RwRoot");
 	
 	//let daemons = Vec::new();
 	//let shells = Vec::new();
 
 	let session_root = {
-		let pp = loader::new_process(open_exec("/sysroot/bin/login"), b"/sysroot/bin/login", &[]).expect("Could not start login");
+		let pp = loader::new_process(open_exec(&root, "/sysroot/bin/login"), b"/sysroot/bin/login", &[], Some(root.clone())).expect("Could not start login");
 
 		pp.send_obj("guigrp", {
 			let wingrp = syscalls::gui::Group::new("Session 1").unwrap();
@@ -51,9 +52,9 @@ fn get_handle<T: ::syscalls::Object>(desc: &str, tag: &str) -> T
 	}
 }
 
-fn open_exec(path: &str) -> ::syscalls::vfs::File
+fn open_exec(root: &::syscalls::vfs::Dir, path: &str) -> ::syscalls::vfs::File
 {
-	match ::syscalls::vfs::ROOT.open_child_path(path.as_bytes())
+	match root.open_child_path(path.as_bytes())
 	{
 	Ok(v) => match v.into_file(::syscalls::vfs::FileOpenMode::Execute)
 		{
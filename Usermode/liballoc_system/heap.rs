@@ -214,8 +214,11 @@ impl AllocState
 		assert!(self.past_end != HEAP_LIMITS.1 as *mut Block);
 		assert!(self.past_end as usize + (npages * PAGE_SIZE) <= HEAP_LIMITS.1);	// TODO: This isn't an assert conditon, it's an OOM
 		if self.start.is_null() {
-			self.start = HEAP_LIMITS.0 as *mut Block;
-			self.past_end = HEAP_LIMITS.0 as *mut Block;
+			// Randomise the heap base within the first quarter of the heap region
+			let slots = (HEAP_LIMITS.1 - HEAP_LIMITS.0) / 4 / PAGE_SIZE;
+			let base = HEAP_LIMITS.0 + (::syscalls::random_usize() % slots) * PAGE_SIZE;
+			self.start = base as *mut Block;
+			self.past_end = base as *mut Block;
 		}
 
 		// SAFE: Allocates only in controlled region.
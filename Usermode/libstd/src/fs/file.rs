@@ -12,6 +12,27 @@ impl File
 		let p = path.as_ref();
 		Ok( File(super::Node::open(p)?.into_file()?) )
 	}
+
+	/// Query the file's metadata
+	pub fn metadata(&self) -> ::io::Result<super::Metadata> {
+		Ok( super::Metadata::from(self.0.metadata()) )
+	}
+
+	/// Create a file (replacing any existing file) and open it for writing
+	pub fn create<P: AsRef<Path>>(path: P) -> ::io::Result<File> {
+		use syscalls::vfs::{Error,FileOpenMode};
+		let (dir, name) = super::open_parent(path.as_ref())?;
+		match dir.create_file(name, FileOpenMode::ExclRW)
+		{
+		Ok(f) => Ok( File(f) ),
+		Err(Error::AlreadyExists) => {
+			// TODO: Truncate the existing file instead of replacing it
+			dir.unlink(name)?;
+			Ok( File(dir.create_file(name, FileOpenMode::ExclRW)?) )
+			},
+		Err(e) => Err( From::from(e) ),
+		}
+	}
 }
 
 impl ::io::Read for File
@@ -20,4 +41,13 @@ impl ::io::Read for File
 		::io::Read::read( &mut self.0, buf )
 	}
 }
+impl ::io::Write for File
+{
+	fn write(&mut self, buf: &[u8]) -> ::io::Result<usize> {
+		::io::Write::write( &mut self.0, buf )
+	}
+	fn flush(&mut self) -> ::io::Result<()> {
+		::io::Write::flush( &mut self.0 )
+	}
+}
 
@@ -0,0 +1,84 @@
+//
+//
+//
+use syscalls::vfs::NodeType;
+
+/// Information about a filesystem node
+#[derive(Debug,Clone)]
+pub struct Metadata(::syscalls::vfs::Metadata);
+
+impl From<::syscalls::vfs::Metadata> for Metadata {
+	fn from(v: ::syscalls::vfs::Metadata) -> Metadata {
+		Metadata(v)
+	}
+}
+
+impl Metadata
+{
+	pub fn is_dir(&self) -> bool {
+		match NodeType::try_from(self.0.node_type as u32)
+		{
+		Ok(NodeType::Dir) => true,
+		_ => false,
+		}
+	}
+	pub fn is_file(&self) -> bool {
+		match NodeType::try_from(self.0.node_type as u32)
+		{
+		Ok(NodeType::File) => true,
+		_ => false,
+		}
+	}
+	pub fn is_symlink(&self) -> bool {
+		match NodeType::try_from(self.0.node_type as u32)
+		{
+		Ok(NodeType::Symlink) => true,
+		_ => false,
+		}
+	}
+
+	/// Size of the node's data in bytes
+	pub fn len(&self) -> u64 {
+		self.0.size
+	}
+	/// Volume inode number
+	pub fn ino(&self) -> u64 {
+		self.0.inode
+	}
+	/// Unix-style permission bits
+	pub fn mode(&self) -> u32 {
+		self.0.mode as u32
+	}
+	pub fn uid(&self) -> u32 {
+		self.0.uid
+	}
+	pub fn gid(&self) -> u32 {
+		self.0.gid
+	}
+	/// Number of directory entries referring to the node
+	pub fn nlink(&self) -> u64 {
+		self.0.link_count as u64
+	}
+
+	/// Last modification time (milliseconds since 1970-01-01 UTC), if recorded by the filesystem
+	pub fn modified(&self) -> Option<i64> {
+		timestamp(self.0.modified)
+	}
+	/// Last access time (milliseconds since 1970-01-01 UTC), if recorded by the filesystem
+	pub fn accessed(&self) -> Option<i64> {
+		timestamp(self.0.accessed)
+	}
+	/// Creation time (milliseconds since 1970-01-01 UTC), if recorded by the filesystem
+	pub fn created(&self) -> Option<i64> {
+		timestamp(self.0.created)
+	}
+}
+
+fn timestamp(v: i64) -> Option<i64> {
+	if v == ::syscalls::vfs::TIMESTAMP_NONE {
+		None
+	}
+	else {
+		Some(v)
+	}
+}
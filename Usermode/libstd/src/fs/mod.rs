@@ -4,6 +4,7 @@
 
 pub use self::path::Path;
 pub use self::file::File;
+pub use self::metadata::Metadata;
 
 //static ROOT_HANDLE: Dir = 
 //static DIR_HANDLES: [::syscalls::vfs::Dir; 4] = [
@@ -14,30 +15,41 @@ struct Node(::syscalls::vfs::Node);
 impl Node
 {
 	fn open(path: &Path) -> ::io::Result<Node> {
+		Node::open_ext(path, true)
+	}
+	/// Open without following a symbolic link in the final component
+	fn open_nofollow(path: &Path) -> ::io::Result<Node> {
+		Node::open_ext(path, false)
+	}
+	fn open_ext(path: &Path, follow_links: bool) -> ::io::Result<Node> {
 		// NOTES:
-		// - Absolute paths refer to a fake root? (or use Windows-style // paths?)
+		// - Absolute paths are relative to the program's root (`vfs::root`), if it was given one
+		// - Paths starting with `/:Name/` are relative to a named root given to this process (e.g. `/:CommonData/`)
 		if path.is_absolute()
 		{
 			// - First node is empty, before leadig /
 			let (_, path) = path.split_off_first();
 			let pb: &[u8] = path.as_ref();
 			// If the path DOESN't start with /: - hand off to root
-			if pb.len() > 0 && pb[0] != b':' {
+			if pb.len() == 0 || pb[0] != b':' {
 				// - Fully absolute path (open readonly relative to the program's root)
-				let n = try!( ::syscalls::vfs::ROOT.open_child_path(path) );
-				Ok(Node( n ))
+				Node::open_in(&try!(root()), path, follow_links)
 			}
 			else {
 				// - Prefixed path, relative to the handle-set
 				let (firstnode, path) = path.split_off_first();
-				match &firstnode.as_bytes()[1..]
-				{
-				//b"AppBin" => 
-				//b"AppData" => 
-				b"CommonData" => todo!("Load from CommonData - {:?}", path),
-				//b"Output" => 
-				_ => Err( ::syscalls::vfs::Error::FileNotFound.into() ),
-				}
+				let root = match ::std::str::from_utf8(&firstnode.as_bytes()[1..])
+					{
+					Ok(name) => match ::syscalls::vfs::named_root(name)
+						{
+						Some(v) => v,
+						// Known root, but this process wasn't given it
+						None if ::syscalls::vfs::named_root_tag(name).is_some() => return Err( ::syscalls::vfs::Error::PermissionDenied.into() ),
+						None => return Err( ::syscalls::vfs::Error::FileNotFound.into() ),
+						},
+					Err(_) => return Err( ::syscalls::vfs::Error::FileNotFound.into() ),
+					};
+				Node::open_in(&root, path, follow_links)
 			}
 		}
 		else {
@@ -45,7 +57,23 @@ impl Node
 			unimplemented!();
 		}
 	}
+	fn open_in(dir: &::syscalls::vfs::Dir, path: &Path, follow_links: bool) -> ::io::Result<Node> {
+		let n = if follow_links {
+				try!( dir.open_child_path(path) )
+			}
+			else {
+				try!( dir.open_child_path_nofollow(path) )
+			};
+		Ok(Node( n ))
+	}
 	
+	fn into_dir(self) -> ::io::Result<::syscalls::vfs::Dir> {
+		match self.0.into_dir()
+		{
+		Ok(v) => Ok(v),
+		Err(e) => Err( From::from(e) ),
+		}
+	}
 	fn into_file(self) -> ::io::Result<::syscalls::vfs::File> {
 		match self.0.into_file(::syscalls::vfs::FileOpenMode::ReadOnly)
 		{
@@ -55,6 +83,57 @@ impl Node
 	}
 }
 
+/// Obtain the program's full filesystem root, if it was given one
+fn root() -> ::io::Result<::syscalls::vfs::Dir> {
+	match ::syscalls::vfs::root()
+	{
+	Some(v) => Ok(v),
+	None => Err( ::syscalls::vfs::Error::PermissionDenied.into() ),
+	}
+}
+
+/// Open the directory containing `path`, returning it along with the final component
+fn open_parent(path: &Path) -> ::io::Result<(::syscalls::vfs::Dir, &::std::ffi::OsStr)> {
+	let (parent, name) = path.split_off_last();
+	if name.as_bytes().len() == 0 {
+		return Err( ::syscalls::vfs::Error::MalformedPath.into() );
+	}
+	let pb: &[u8] = parent.as_ref();
+	if pb.len() == 0 && path.is_absolute() {
+		Ok( (try!(root()), name) )
+	}
+	else {
+		Ok( (Node::open(parent)?.into_dir()?, name) )
+	}
+}
+
+/// Query the metadata of a filesystem node
+pub fn metadata<P: AsRef<Path>>(path: P) -> ::io::Result<Metadata> {
+	let node = Node::open(path.as_ref())?;
+	Ok( Metadata::from(node.0.metadata()) )
+}
+/// Obtain metadata for a path, without following a symbolic link in the final component
+pub fn symlink_metadata<P: AsRef<Path>>(path: P) -> ::io::Result<Metadata> {
+	let node = Node::open_nofollow(path.as_ref())?;
+	Ok( Metadata::from(node.0.metadata()) )
+}
+
+/// Remove a file
+pub fn remove_file<P: AsRef<Path>>(path: P) -> ::io::Result<()> {
+	let (dir, name) = open_parent(path.as_ref())?;
+	Ok( dir.unlink(name)? )
+}
+
+/// Rename a file or directory (both paths must be on the same filesystem)
+///
+/// Fails if `to` already exists.
+pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> ::io::Result<()> {
+	let (src_dir, src_name) = open_parent(from.as_ref())?;
+	let (dst_dir, dst_name) = open_parent(to.as_ref())?;
+	Ok( src_dir.rename(src_name, &dst_dir, dst_name)? )
+}
+
 mod file;
+mod metadata;
 mod path;
 
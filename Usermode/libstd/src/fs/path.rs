@@ -56,6 +56,14 @@ impl Path
 
 		(a.as_ref(), Path::new(b))
 	}
+	pub fn split_off_last(&self) -> (&Path, &::std::ffi::OsStr) {
+		let b = self.0.as_bytes();
+		match b.iter().rposition(|&x| x == b'/')
+		{
+		Some(pos) => (Path::new(&b[..pos]), ::std::ffi::OsStr::new(&b[pos+1..])),
+		None => (Path::new(&b[..0]), ::std::ffi::OsStr::new(b)),
+		}
+	}
 }
 
 pub struct Display<'a>(&'a Path);
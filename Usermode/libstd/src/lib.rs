@@ -113,6 +113,8 @@ pub extern crate std_sync as sync;
 
 pub mod fs;
 
+pub mod thread;
+
 pub mod error;
 
 pub use alloc::{vec, string, borrow};
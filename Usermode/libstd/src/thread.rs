@@ -0,0 +1,199 @@
+// Tifflin OS - Standard Library (clone)
+// - By John Hodge (thePowersGang)
+//
+//! Native threads
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use sync::Mutex;
+
+/// Result of joining a thread
+///
+/// NOTE: A panic terminates the entire process, so this is always `Ok`
+pub type Result<T> = ::core::result::Result<T, Box<Any+Send+'static>>;
+
+/// Maximum size of the stack for each spawned thread (the kernel grows it as it's used)
+const STACK_SIZE: usize = 0x10_0000;
+/// Address space used for spawned threads' stacks
+#[cfg(arch="amd64")]
+const STACK_REGION: (usize, usize) = (0x7000_0000_0000, 0x7100_0000_0000);
+#[cfg(not(arch="amd64"))]
+const STACK_REGION: (usize, usize) = (0x7000_0000, 0x7800_0000);
+/// Spacing of stacks within the region (leaving room for the kernel's guard page)
+const STACK_SLOT_SIZE: usize = STACK_SIZE + 0x1_0000;
+
+/// Threads that were detached while still running, with the stacks to free once they terminate
+///
+/// Reaped when a thread is spawned, joined, or exits.
+static S_DETACHED: Mutex<Option<Vec<(::syscalls::threads::Thread, Stack)>>> = Mutex::new(None);
+/// Next never-used stack slot, and released slots
+static S_NEXT_STACK: AtomicUsize = AtomicUsize::new(0);
+static S_FREE_STACKS: Mutex<Option<Vec<usize>>> = Mutex::new(None);
+/// Randomised base of the stack slots (zero until first used)
+static S_STACK_BASE: AtomicUsize = AtomicUsize::new(0);
+
+/// Obtain the (randomised) base address for stack slots, choosing it on first call
+///
+/// The base is placed within the first half of the region, leaving the remainder for slots.
+fn stack_base() -> usize {
+	let v = S_STACK_BASE.load(Ordering::Relaxed);
+	if v != 0 {
+		return v;
+	}
+	let max_ofs = (STACK_REGION.1 - STACK_REGION.0) / STACK_SLOT_SIZE / 2;
+	let new_base = STACK_REGION.0 + (::syscalls::random_usize() % max_ofs) * STACK_SLOT_SIZE;
+	match S_STACK_BASE.compare_exchange(0, new_base, Ordering::Relaxed, Ordering::Relaxed)
+	{
+	Ok(_) => new_base,
+	Err(existing) => existing,
+	}
+}
+
+/// A stack for a spawned thread (released when dropped)
+struct Stack(usize);
+impl Stack
+{
+	fn new() -> Stack {
+		let slot = match S_FREE_STACKS.lock().as_mut().and_then(|l| l.pop())
+			{
+			Some(v) => v,
+			None => S_NEXT_STACK.fetch_add(1, Ordering::Relaxed),
+			};
+		let rv = Stack(slot);
+		assert!(rv.top() <= STACK_REGION.1, "spawn - Out of stack slots");
+		// SAFE: Address is within the region reserved for stacks, and the slot is unused
+		match unsafe { ::syscalls::memory::alloc_stack(rv.top(), STACK_SIZE) }
+		{
+		Ok(_) => rv,
+		Err(e) => panic!("spawn - Error allocating stack: {:?}", e),
+		}
+	}
+	fn top(&self) -> usize {
+		stack_base() + (self.0 + 1) * STACK_SLOT_SIZE
+	}
+}
+impl Drop for Stack
+{
+	fn drop(&mut self) {
+		// SAFE: Thread using the stack has terminated
+		if let Err(e) = unsafe { ::syscalls::memory::free_stack(self.top()) } {
+			panic!("Error releasing stack: {:?}", e);
+		}
+		let mut lh = S_FREE_STACKS.lock();
+		if lh.is_none() {
+			*lh = Some(Vec::new());
+		}
+		lh.as_mut().unwrap().push(self.0);
+	}
+}
+
+/// Storage for the spawned closure's return value
+struct Packet<T>(UnsafeCell<Option<T>>);
+// SAFE: Only written by the spawned thread, and only read after it has terminated
+unsafe impl<T: Send> Sync for Packet<T> {}
+
+/// An owned permission to join on a thread
+///
+/// Dropping the handle detaches the thread.
+pub struct JoinHandle<T>
+{
+	inner: Option<(::syscalls::threads::Thread, Stack)>,
+	packet: Arc<Packet<T>>,
+}
+
+/// Spawn a new thread, returning a handle that can be used to join it
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+	F: FnOnce() -> T + Send + 'static,
+	T: Send + 'static
+{
+	reap_detached();
+
+	let packet = Arc::new(Packet(UnsafeCell::new(None)));
+
+	let stack = Stack::new();
+	let stack_top = stack.top();
+	// x86-64 functions expect to be entered with a return address on the stack
+	#[cfg(arch="amd64")]
+	let stack_top = stack_top - ::core::mem::size_of::<usize>();
+
+	let arg = Box::into_raw(Box::new( (f, packet.clone()) ));
+	// SAFE: Entrypoint has the correct signature, and the stack is kept alive until the thread terminates
+	match unsafe { ::syscalls::threads::start_thread(thread_root::<F,T> as usize, stack_top, arg as usize) }
+	{
+	Ok(thread) => JoinHandle {
+		inner: Some( (thread, stack) ),
+		packet: packet,
+		},
+	Err(e) => {
+		// SAFE: The thread wasn't started, so this is the only reference
+		::core::mem::drop(unsafe { Box::from_raw(arg) });
+		panic!("spawn - Error starting thread: {}", e);
+		},
+	}
+}
+
+extern "C" fn thread_root<F, T>(arg: usize) -> !
+where
+	F: FnOnce() -> T + Send + 'static,
+	T: Send + 'static
+{
+	// SAFE: Pointer was created by `Box::into_raw` in `spawn`, and is only used once
+	let (f, packet) = *unsafe { Box::from_raw(arg as *mut (F, Arc<Packet<T>>)) };
+	let rv = f();
+	// SAFE: The joining thread only reads the packet after this thread has terminated
+	unsafe {
+		*packet.0.get() = Some(rv);
+	}
+	::core::mem::drop(packet);
+	// - Release the stacks of detached threads that have finished (this thread's own is released by a later reap)
+	reap_detached();
+	::syscalls::threads::exit_thread(0);
+}
+
+/// Release the stacks of any detached threads that have since terminated
+fn reap_detached()
+{
+	let mut lh = S_DETACHED.lock();
+	if let Some(ref mut list) = *lh {
+		list.retain(|e| e.0.get_exit_status().is_none());
+	}
+}
+
+impl<T> JoinHandle<T>
+{
+	/// Wait for the thread to finish, and obtain its result
+	pub fn join(mut self) -> Result<T> {
+		let (thread, stack) = self.inner.take().expect("JoinHandle::join - No thread");
+		thread.join();
+		::core::mem::drop(stack);
+		reap_detached();
+		// SAFE: The thread has terminated, so nothing else accesses the packet
+		match unsafe { (*self.packet.0.get()).take() }
+		{
+		Some(v) => Ok(v),
+		None => panic!("JoinHandle::join - Thread terminated without a result"),
+		}
+	}
+}
+impl<T> Drop for JoinHandle<T>
+{
+	fn drop(&mut self) {
+		if let Some(v) = self.inner.take() {
+			if v.0.get_exit_status().is_some() {
+				// Already terminated, release the stack now
+				::core::mem::drop(v);
+				return ;
+			}
+			// The stack can only be released once the thread has terminated, defer that to the next reap
+			let mut lh = S_DETACHED.lock();
+			if lh.is_none() {
+				*lh = Some(Vec::new());
+			}
+			lh.as_mut().unwrap().push(v);
+		}
+	}
+}
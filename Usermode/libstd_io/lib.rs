@@ -138,6 +138,15 @@ impl Read for ::syscalls::vfs::File {
 		Ok(try!( self.read(buf) ))
 	}
 }
+impl Write for ::syscalls::vfs::File {
+	fn write(&mut self, buf: &[u8]) -> Result<usize> {
+		Ok(try!( self.write(buf) ))
+	}
+	fn flush(&mut self) -> Result<()> {
+		// Writes go directly to the kernel
+		Ok( () )
+	}
+}
 impl Seek for ::syscalls::vfs::File {
 	fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
 		match pos
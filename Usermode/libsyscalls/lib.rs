@@ -334,5 +334,24 @@ pub fn get_text_info(unit: u32, id: u32, buf: &mut [u8]) -> &str {
 	::core::str::from_utf8(&buf[..len]).expect("TODO: get_text_info handle error")
 }
 
+#[inline]
+/// Fill a buffer with kernel-provided random bytes
+///
+/// NOTE: Not suitable for cryptographic use
+pub fn get_random(buf: &mut [u8]) {
+	// SAFE: Syscall
+	unsafe { syscall!(CORE_GETRANDOM, buf.as_mut_ptr() as usize, buf.len()); }
+}
+/// Obtain a random `usize` (see `get_random`)
+pub fn random_usize() -> usize {
+	let mut buf = [0u8; 8];
+	get_random(&mut buf);
+	let mut rv = 0;
+	for &b in buf[.. ::core::mem::size_of::<usize>()].iter() {
+		rv = (rv << 8) | b as usize;
+	}
+	rv
+}
+
 
 
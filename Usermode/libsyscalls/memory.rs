@@ -27,10 +27,77 @@ pub unsafe fn reprotect(addr: usize, protection: ProtectionMode) -> Result<(), E
 		.map(|_| ())
 		.map_err(|_| Error)
 }
+/// Write back modified pages of writeback file mappings in the range
+#[inline]
+pub fn sync(addr: usize, count: usize) -> Result<(), Error> {
+	// SAFE: Syscall only reads the address space
+	super::to_result( unsafe { syscall!(MEM_SYNC, addr, count) } as usize )
+		.map(|_| ())
+		.map_err(|_| Error)
+}
 #[inline]
 pub unsafe fn deallocate(addr: usize) -> Result<(), Error> {
 	super::to_result( syscall!(MEM_DEALLOCATE, addr) as usize )
 		.map(|_| ())
 		.map_err(|_| Error)
 }
+/// Reserve a stack ending at `top`, which grows on use up to `max_size` bytes (there's a guard page below that)
+#[inline]
+pub unsafe fn alloc_stack(top: usize, max_size: usize) -> Result<(), Error> {
+	super::to_result( syscall!(MEM_STACK_ALLOC, top, max_size) as usize )
+		.map(|_| ())
+		.map_err(|_| Error)
+}
+/// Release a stack reserved with `alloc_stack`
+#[inline]
+pub unsafe fn free_stack(top: usize) -> Result<(), Error> {
+	super::to_result( syscall!(MEM_STACK_FREE, top) as usize )
+		.map(|_| ())
+		.map_err(|_| Error)
+}
+
+
+/// Anonymous shared memory object
+///
+/// Can be mapped into any number of processes (send it using `::ipc::RpcChannel::send_obj`)
+pub struct SharedMemory(::ObjectHandle);
+
+impl ::Object for SharedMemory
+{
+	const CLASS: u16 = ::values::CLASS_MEM_SHM;
+	fn class() -> u16 { Self::CLASS }
+	fn from_handle(handle: ::ObjectHandle) -> Self {
+		SharedMemory(handle)
+	}
+	fn into_handle(self) -> ::ObjectHandle {
+		self.0
+	}
+	fn handle(&self) -> &::ObjectHandle {
+		&self.0
+	}
 
+	type Waits = ();
+}
+impl SharedMemory
+{
+	/// Create a new zero-filled object of at least `size` bytes
+	pub fn new(size: usize) -> Result<SharedMemory, Error> {
+		// SAFE: Syscall
+		::ObjectHandle::new( unsafe { syscall!(MEM_SHM_CREATE, size) } as usize )
+			.map(|v| SharedMemory(v))
+			.map_err(|_| Error)
+	}
+
+	/// Size of the object in bytes (a multiple of the page size)
+	pub fn size(&self) -> usize {
+		// SAFE: Syscall with no side-effects
+		unsafe { self.0.call_0(::values::MEM_SHM_GETSIZE) as usize }
+	}
+
+	/// Map the entire object at `addr` (which must be page aligned, and not already in use)
+	pub unsafe fn map(&self, addr: usize, protection: ProtectionMode) -> Result<(), Error> {
+		super::to_result( self.0.call_2(::values::MEM_SHM_MAP, addr, protection as u8 as usize) as usize )
+			.map(|_| ())
+			.map_err(|_| Error)
+	}
+}
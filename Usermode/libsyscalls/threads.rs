@@ -19,19 +19,88 @@ impl ::core::fmt::Display for RecvObjectError {
 	}
 }
 
+/// Start a new thread in this process
+///
+/// `ip` is called with `arg` as its first argument, and must not return (call `exit_thread` instead)
 #[inline]
-pub unsafe fn start_thread(ip: usize, sp: usize, tlsbase: usize) -> Result<u32, u32> {
-	::to_result( syscall!(CORE_STARTTHREAD, ip, sp, tlsbase) as usize )
+pub unsafe fn start_thread(ip: usize, sp: usize, arg: usize) -> Result<Thread, u32> {
+	::ObjectHandle::new( syscall!(CORE_STARTTHREAD, ip, sp, arg) as usize ).map(|v| Thread(v))
 }
+/// Terminate the current thread, passing `status` to any joiners
 #[inline]
-pub fn exit_thread() -> ! {
+pub fn exit_thread(status: u32) -> ! {
 	// SAFE: Syscall
 	unsafe {
-		syscall!(CORE_EXITTHREAD);
+		syscall!(CORE_EXITTHREAD, status as usize);
 		::core::intrinsics::unreachable();
 	}
 }
 
+pub use values::ThreadPriority;
+
+/// Set the scheduling priority of the current thread
+#[inline]
+pub fn set_priority(priority: ThreadPriority) -> Result<(),()> {
+	let p: u8 = priority.into();
+	// SAFE: Syscall
+	::to_result( unsafe { syscall!(CORE_SETPRIORITY, p as usize) } as usize )
+		.map(|_| ())
+		.map_err(|_| ())
+}
+
+define_waits!{ ThreadWaits => (
+	terminate:get_terminate = ::values::EV_THREAD_TERMINATED,
+)}
+/// Handle to a thread in this process (dropping the handle detaches the thread)
+pub struct Thread(::ObjectHandle);
+impl Thread
+{
+	/// Obtain the exit value of the thread (`None` if it is still running)
+	#[inline]
+	pub fn get_exit_status(&self) -> Option<u32> {
+		// SAFE: Syscall
+		match unsafe { self.0.call_0(::values::CORE_THREAD_GETEXIT) }
+		{
+		0xFFFF_FFFF_FFFF_FFFF => None,
+		v => Some(v as u32),
+		}
+	}
+
+	#[inline]
+	pub fn wait_terminate(&self) -> ::values::WaitItem {
+		self.0.get_wait(::values::EV_THREAD_TERMINATED)
+	}
+
+	/// Block until the thread terminates, and return its exit value
+	pub fn join(self) -> u32 {
+		loop
+		{
+			if let Some(v) = self.get_exit_status() {
+				return v;
+			}
+			wait(&mut [self.wait_terminate()], !0);
+		}
+	}
+
+	/// Release the handle, leaving the thread running
+	#[inline]
+	pub fn detach(self) {
+		// SAFE: Syscall
+		unsafe { self.0.call_0_v(::values::CORE_THREAD_DETACH); }
+	}
+}
+impl ::Object for Thread {
+	const CLASS: u16 = ::values::CLASS_CORE_THREAD;
+	fn class() -> u16 { Self::CLASS }
+	fn from_handle(handle: ::ObjectHandle) -> Self {
+		Thread(handle)
+	}
+	fn into_handle(self) -> ::ObjectHandle { self.0 }
+	fn handle(&self) -> &::ObjectHandle { &self.0 }
+	
+	type Waits = ThreadWaits;
+}
+
 // Object 0 : This process
 /// Current process handle
 pub static S_THIS_PROCESS: ThisProcess = ThisProcess;//( ::ObjectHandle(0) );
@@ -119,6 +188,14 @@ impl ProtoProcess
 		// SAFE: Syscall
 		unsafe { self.0.call_2l(::values::CORE_PROTOPROCESS_SENDOBJ, ::values::FixedStr8::from(tag).into(), oh as usize); }
 	}
+	/// Give the child one of the well-known named root directories (see `vfs::NAMED_ROOTS`)
+	///
+	/// Unless the spawner also passes the full root (`ro:/`), these are the only parts of the filesystem the child
+	/// can reach.
+	pub fn send_named_root(&self, name: &str, dir: ::vfs::Dir) {
+		let tag = ::vfs::named_root_tag(name).expect("send_named_root - Unknown root name");
+		self.send_obj(tag, dir);
+	}
  
  	#[inline]
 	pub fn start(self, entry: usize, stack: usize) -> Process {
@@ -165,6 +242,17 @@ pub fn exit(code: u32) -> ! {
 	}
 }
 
+pub use values::{ProcessStats,ProcessState};
+
+/// Obtain information on all processes in the system
+///
+/// Fills as much of `buf` as possible, and returns the total number of processes
+#[inline]
+pub fn list_processes(buf: &mut [ProcessStats]) -> usize {
+	// SAFE: Syscall
+	unsafe { syscall!(CORE_PROCLIST, buf.as_mut_ptr() as usize, buf.len()) as usize }
+}
+
 pub use values::WaitItem;
 
 /// Blocks the current thread on the passed set of objects.
@@ -13,13 +13,84 @@ pub struct Dir(super::ObjectHandle);
 pub struct DirIter(::ObjectHandle);
 /// Symbolic link
 pub struct Symlink(super::ObjectHandle);
+/// Mount management capability (received by init as "MountMgr")
+pub struct Mounts(super::ObjectHandle);
+
+use core::sync::atomic::{AtomicUsize,Ordering};
 
 pub use ::values::VFSError as Error;
 pub use ::values::VFSNodeType as NodeType;
 pub use ::values::VFSFileOpenMode as FileOpenMode;
 pub use ::values::VFSMemoryMapMode as MemoryMapMode;
+pub use ::values::VFSMountError as MountError;
+pub use ::values::VFSMetadata as Metadata;
+pub use ::values::VFS_TIMESTAMP_NONE as TIMESTAMP_NONE;
+
+/// Object tag used to pass the full filesystem root to a new process
+pub const ROOT_TAG: &'static str = "ro:/";
+/// Handle for the full filesystem root (0 = not yet received)
+static ROOT_HANDLE: AtomicUsize = AtomicUsize::new(0);
+
+/// Well-known named root directories (see `Notes/VFS-MultiRoot.md`)
+///
+/// Each entry is the name used in `/:Name/...` paths, and the object tag used to pass the handle to a new process.
+pub const NAMED_ROOTS: [(&'static str, &'static str); 4] = [
+	("CommonData", "rCmDat"),
+	("AppBin",     "rABin"),
+	("AppData",    "rADat"),
+	("AppStorage", "rAStor"),
+	];
+/// Handles for the named roots received by this process (0 = not yet received)
+static NAMED_ROOT_HANDLES: [AtomicUsize; 4] = [AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0)];
 
-pub static ROOT: Dir = Dir( ::ObjectHandle(2) );
+/// Object tag used to pass the named root `name` to a new process
+pub fn named_root_tag(name: &str) -> Option<&'static str> {
+	NAMED_ROOTS.iter().find(|e| e.0 == name).map(|e| e.1)
+}
+
+/// Obtain a handle to the full filesystem root
+///
+/// Returns `None` if the spawner didn't pass one (see `loader::new_process`)
+pub fn root() -> Option<Dir> {
+	receive_root(&ROOT_HANDLE, ROOT_TAG)
+}
+
+/// Obtain a handle to one of the named root directories given to this process (e.g. "CommonData")
+///
+/// The handle is received from the spawning process on first use. Returns `None` if the root was not given.
+pub fn named_root(name: &str) -> Option<Dir> {
+	match NAMED_ROOTS.iter().position(|e| e.0 == name)
+	{
+	Some(idx) => receive_root(&NAMED_ROOT_HANDLES[idx], NAMED_ROOTS[idx].1),
+	None => None,
+	}
+}
+
+/// Receive the root directory with the given tag (on first use), and return a new handle to it
+fn receive_root(slot: &AtomicUsize, tag: &str) -> Option<Dir> {
+	let mut h = slot.load(Ordering::Acquire);
+	if h == 0 {
+		// Only one thread can receive the object, so whoever succeeds stores it
+		match ::threads::S_THIS_PROCESS.receive_object::<Dir>(tag)
+		{
+		Ok(d) => {
+			h = d.0.into_raw() as usize;
+			slot.store(h, Ordering::Release);
+			},
+		Err(_) => {
+			h = slot.load(Ordering::Acquire);
+			if h == 0 {
+				return None;
+			}
+			},
+		}
+	}
+	// Clone the stored handle (the stored copy is never released)
+	let d = Dir( ::ObjectHandle(h as u32) );
+	let rv = d.clone();
+	::core::mem::forget(d);
+	Some(rv)
+}
 
 
 #[inline]
@@ -40,6 +111,15 @@ impl Node
 		NodeType::try_from( unsafe { self.0.call_0(::values::VFS_NODE_GETTYPE) } as u32 ).expect("Bad VFS Node Type")
 	}
 
+	/// Query the node's metadata (size, timestamps, permissions)
+	#[inline]
+	pub fn metadata(&self) -> Metadata {
+		let mut rv = Metadata::default();
+		// SAFE: Syscall that writes to a valid structure
+		unsafe { self.0.call_1(::values::VFS_NODE_GETMETADATA, &mut rv as *mut _ as usize) };
+		rv
+	}
+
 	/// Convert handle to a directory handle
 	#[inline]
 	pub fn into_dir(self) -> Result<Dir,Error> {
@@ -83,6 +163,15 @@ impl File
 		unsafe { self.0.call_0(::values::VFS_FILE_GETSIZE) }
 	}
 
+	/// Query the file's metadata (size, timestamps, permissions)
+	#[inline]
+	pub fn metadata(&self) -> Metadata {
+		let mut rv = Metadata::default();
+		// SAFE: Syscall that writes to a valid structure
+		unsafe { self.0.call_1(::values::VFS_FILE_GETMETADATA, &mut rv as *mut _ as usize) };
+		rv
+	}
+
 	/// Query the current cursor position
 	#[inline]
 	pub fn get_cursor(&self) -> u64 { self.1 }
@@ -97,6 +186,13 @@ impl File
 		self.1 += count as u64;
 		Ok(count)
 	}
+	/// Write bytes at the cursor (incrementing)
+	#[inline]
+	pub fn write(&mut self, data: &[u8]) -> Result<usize,Error> {
+		let count = try!( self.write_at(self.1, data) );
+		self.1 += count as u64;
+		Ok(count)
+	}
 	/// Read from an arbitary location in the file
 	#[inline]
 	pub fn read_at(&self, ofs: u64, data: &mut [u8]) -> Result<usize,Error> {
@@ -170,6 +266,63 @@ impl Dir
 		Err(code) => Err( Error::try_from(code).expect("Bad VFS Error") ),
 		}
 	}
+	/// Open a path relative to this directory, without following a symbolic link in the final component
+	#[inline]
+	pub fn open_child_path_nofollow<P: ?Sized+AsRef<[u8]>>(&self, path: &P) -> Result<Node, Error> {
+		let name = path.as_ref();
+		// SAFE: Syscall
+		match super::ObjectHandle::new( unsafe { self.0.call_2(::values::VFS_DIR_OPENPATH_NOFOLLOW, name.as_ptr() as usize, name.len()) } as usize )
+		{
+		Ok(rv) => Ok( Node(rv) ),
+		Err(code) => Err( Error::try_from(code).expect("Bad VFS Error") ),
+		}
+	}
+
+	/// Create a new file in this directory, and open it with the provided mode
+	#[inline]
+	pub fn create_file<P: ?Sized+AsRef<[u8]>>(&self, name: &P, mode: FileOpenMode) -> Result<File, Error> {
+		let name = name.as_ref();
+		// SAFE: Syscall
+		to_obj( unsafe { self.0.call_3(::values::VFS_DIR_CREATEFILE, name.as_ptr() as usize, name.len(), mode as u8 as usize) } as usize )
+			.map(|h| File(h, 0))
+	}
+	/// Create a new sub-directory
+	#[inline]
+	pub fn mkdir<P: ?Sized+AsRef<[u8]>>(&self, name: &P) -> Result<Dir, Error> {
+		let name = name.as_ref();
+		// SAFE: Syscall
+		to_obj( unsafe { self.0.call_2(::values::VFS_DIR_MKDIR, name.as_ptr() as usize, name.len()) } as usize )
+			.map(|h| Dir(h))
+	}
+	/// Remove a (non-directory) child
+	#[inline]
+	pub fn unlink<P: ?Sized+AsRef<[u8]>>(&self, name: &P) -> Result<(), Error> {
+		let name = name.as_ref();
+		// SAFE: Syscall
+		to_result( unsafe { self.0.call_2(::values::VFS_DIR_UNLINK, name.as_ptr() as usize, name.len()) } as usize )
+			.map(|_| ())
+	}
+	/// Remove an empty child directory
+	#[inline]
+	pub fn rmdir<P: ?Sized+AsRef<[u8]>>(&self, name: &P) -> Result<(), Error> {
+		let name = name.as_ref();
+		// SAFE: Syscall
+		to_result( unsafe { self.0.call_2(::values::VFS_DIR_RMDIR, name.as_ptr() as usize, name.len()) } as usize )
+			.map(|_| ())
+	}
+	/// Atomically move a child to `new_name` in `new_dir` (which must be on the same filesystem)
+	#[inline]
+	pub fn rename<P: ?Sized+AsRef<[u8]>, Q: ?Sized+AsRef<[u8]>>(&self, old_name: &P, new_dir: &Dir, new_name: &Q) -> Result<(), Error> {
+		let old_name = old_name.as_ref();
+		let new_name = new_name.as_ref();
+		// SAFE: Syscall
+		to_result( unsafe { self.0.call_5(::values::VFS_DIR_RENAME,
+			old_name.as_ptr() as usize, old_name.len(),
+			(new_dir.0).0 as usize,
+			new_name.as_ptr() as usize, new_name.len()
+			) } as usize )
+			.map(|_| ())
+	}
 }
 impl ::Object for Dir {
 	const CLASS: u16 = ::values::CLASS_VFS_DIR;
@@ -240,3 +393,62 @@ impl ::Object for Symlink {
 
 	type Waits = ();
 }
+
+
+#[inline]
+fn to_mount_result(val: usize) -> Result<(), MountError> {
+	super::to_result(val).map(|_| ()).map_err(|code| MountError::try_from(code).expect("Bad VFS MountError"))
+}
+
+impl Mounts
+{
+	/// Mount the named volume at `mountpoint`
+	///
+	/// `options` is a comma-separated list (`ro`, `noexec`, `sync`, ...), `fs=NAME` selects the filesystem driver
+	/// (otherwise it's detected). With `fs=ramfs` an in-memory filesystem is mounted (`volume` is ignored), and
+	/// `size=BYTES` (with an optional `k`/`m`/`g` suffix) limits its size.
+	#[inline]
+	pub fn mount<P: ?Sized+AsRef<[u8]>>(&self, mountpoint: &P, volume: &str, options: &str) -> Result<(), MountError> {
+		let mountpoint = mountpoint.as_ref();
+		// SAFE: Syscall
+		to_mount_result( unsafe { self.0.call_6(::values::VFS_MOUNTS_MOUNT,
+			mountpoint.as_ptr() as usize, mountpoint.len(),
+			volume.as_ptr() as usize, volume.len(),
+			options.as_ptr() as usize, options.len()
+			) } as usize )
+	}
+	/// Unmount the volume mounted at `mountpoint`
+	///
+	/// Fails with `Busy` if nodes on the volume are open, unless `force` is set (which releases the volume once they're closed)
+	#[inline]
+	pub fn unmount<P: ?Sized+AsRef<[u8]>>(&self, mountpoint: &P, force: bool) -> Result<(), MountError> {
+		let mountpoint = mountpoint.as_ref();
+		// SAFE: Syscall
+		to_mount_result( unsafe { self.0.call_3(::values::VFS_MOUNTS_UNMOUNT, mountpoint.as_ptr() as usize, mountpoint.len(), force as usize) } as usize )
+	}
+	/// Write modified data on the volume containing `path` back to disk (an empty path syncs all volumes)
+	#[inline]
+	pub fn sync<P: ?Sized+AsRef<[u8]>>(&self, path: &P) -> Result<(), MountError> {
+		let path = path.as_ref();
+		// SAFE: Syscall
+		to_mount_result( unsafe { self.0.call_2(::values::VFS_MOUNTS_SYNC, path.as_ptr() as usize, path.len()) } as usize )
+	}
+	/// Change the options of a mounted volume (e.g. `"ro"` to make it read-only)
+	#[inline]
+	pub fn remount<P: ?Sized+AsRef<[u8]>>(&self, mountpoint: &P, options: &str) -> Result<(), MountError> {
+		let mountpoint = mountpoint.as_ref();
+		// SAFE: Syscall
+		to_mount_result( unsafe { self.0.call_4(::values::VFS_MOUNTS_REMOUNT, mountpoint.as_ptr() as usize, mountpoint.len(), options.as_ptr() as usize, options.len()) } as usize )
+	}
+}
+impl ::Object for Mounts {
+	const CLASS: u16 = ::values::CLASS_VFS_MOUNTS;
+	fn class() -> u16 { Self::CLASS }
+	fn from_handle(handle: ::ObjectHandle) -> Self {
+		Mounts(handle)
+	}
+	fn into_handle(self) -> ::ObjectHandle { self.0 }
+	fn handle(&self) -> &::ObjectHandle { &self.0 }
+
+	type Waits = ();
+}
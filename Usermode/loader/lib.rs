@@ -36,7 +36,7 @@ mod int {
 		//  > Arguments
 		//  > ? Environment (could this be transferred using IPC during init?)
 		//  > ? Handles (same thing really, send them over an IPC channel)
-		pub fn new_process(executable_handle: ::syscalls::vfs::File, process_name: &[u8], args: &[&[u8]]) -> Result<::syscalls::threads::ProtoProcess,super::Error>;
+		pub fn new_process(executable_handle: ::syscalls::vfs::File, process_name: &[u8], args: &[&[u8]], root: Option<::syscalls::vfs::Dir>) -> Result<::syscalls::threads::ProtoProcess,super::Error>;
 
 		pub fn start_process(handle: ::syscalls::threads::ProtoProcess) -> ::syscalls::threads::Process;
 	}
@@ -51,6 +51,10 @@ impl ProtoProcess
 	pub fn send_obj<T: ::syscalls::Object>(&self, tag: &str, obj: T) {
 		self.0.send_obj( tag, obj );
 	}
+	/// Give the new process a named root directory (e.g. "CommonData")
+	pub fn send_named_root(&self, name: &str, dir: ::syscalls::vfs::Dir) {
+		self.0.send_named_root( name, dir );
+	}
 
 	pub fn start(self) -> ::syscalls::threads::Process {
 		// SAFE: FFI into rust code
@@ -60,10 +64,14 @@ impl ProtoProcess
 	}
 }
 
-pub fn new_process(binary_file: ::syscalls::vfs::File, binary: &[u8], args: &[&[u8]]) -> Result<ProtoProcess,Error> {
+/// Create a new process running `binary_file`
+///
+/// The child only gets the full filesystem root if `root` is passed, otherwise it can only reach the directories it's
+/// explicitly sent (e.g. using `ProtoProcess::send_named_root`).
+pub fn new_process(binary_file: ::syscalls::vfs::File, binary: &[u8], args: &[&[u8]], root: Option<::syscalls::vfs::Dir>) -> Result<ProtoProcess,Error> {
 	// SAFE: Call is actually to rust
 	unsafe {
-		int::new_process(binary_file, binary, args).map( |v| ProtoProcess(v) )
+		int::new_process(binary_file, binary, args, root).map( |v| ProtoProcess(v) )
 	}
 }
 
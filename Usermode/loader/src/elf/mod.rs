@@ -38,6 +38,8 @@ pub struct ElfModuleHandle<R: Read+Seek>
 {
 	file: R,
 	header: Header,
+	/// Load base (zero for fixed-address executables)
+	base: usize,
 }
 
 pub fn load_executable(mut fh: File) -> Result<ElfModuleHandle<File>,Error>
@@ -64,6 +66,7 @@ pub fn load_executable(mut fh: File) -> Result<ElfModuleHandle<File>,Error>
 	Ok(ElfModuleHandle{
 		file: fh,
 		header: hdr,
+		base: 0,
 		})
 }
 	
@@ -95,10 +98,32 @@ impl<R: Read+Seek> ElfModuleHandle<R>
 impl<R: Read+Seek> ElfModuleHandle<R>
 {
 	pub fn get_entrypoint(&self) -> usize {
-		self.header.e_entry
+		self.base + self.header.e_entry
+	}
+	/// Returns true if the image can be loaded at any base (i.e. is a position-independent executable)
+	pub fn is_position_independent(&self) -> bool {
+		match self.header.object_type
+		{
+		ObjectType::Dyn => true,
+		_ => false,
+		}
+	}
+	/// Set the address the image is loaded at (only valid for position-independent images)
+	pub fn set_load_base(&mut self, base: usize) {
+		assert!(self.is_position_independent(), "set_load_base on a fixed-address executable");
+		self.base = base;
+	}
+	/// Size of the image's address space (end of the highest loaded segment, relative to the load base)
+	pub fn get_image_size(&mut self) -> usize {
+		let mut end = 0;
+		for seg in LoadSegments(self.phents(), 0) {
+			end = ::std::cmp::max(end, seg.load_addr + seg.mem_size);
+		}
+		end
 	}
 	pub fn load_segments(&mut self) -> LoadSegments<R> {
-		LoadSegments( self.phents() )
+		let base = self.base;
+		LoadSegments( self.phents(), base )
 	}
 	
 	pub fn do_relocation(&mut self) -> Result<(),Error> {
@@ -119,20 +144,20 @@ impl<R: Read+Seek> ElfModuleHandle<R>
 		{
 			match ent
 			{
-			DtEnt::SymTab(addr) => symtab_addr = Some(addr as *const _),
+			DtEnt::SymTab(addr) => symtab_addr = Some((self.base + addr as usize) as *const _),
 			DtEnt::SymEntSz(count) => symtab_esz = Some(count),
-			DtEnt::StrTab(addr) => strtab_addr = Some(addr as *const _),
+			DtEnt::StrTab(addr) => strtab_addr = Some((self.base + addr as usize) as *const _),
 			DtEnt::StrSz(count) => strtab_len = Some(count),
 			
-			DtEnt::RelA(addr) => rela_addr = Some(addr),
+			DtEnt::RelA(addr) => rela_addr = Some((self.base + addr as usize) as *const _),
 			DtEnt::RelASz(size) => rela_sz = Some(size),
 			DtEnt::RelAEnt(size) => rela_esz = Some(size),
 			
-			DtEnt::Rel(addr) => rel_addr = Some(addr),
+			DtEnt::Rel(addr) => rel_addr = Some((self.base + addr as usize) as *const _),
 			DtEnt::RelSz(size) => rel_sz = Some(size),
 			DtEnt::RelEnt(size) => rel_esz = Some(size),
 			
-			DtEnt::Plt(addr) => plt_addr = Some(addr),
+			DtEnt::Plt(addr) => plt_addr = Some((self.base + addr as usize) as *const _),
 			DtEnt::PltRel(ty) => plt_type = match ty {
 				 7 => RelocType::RelA,	// DT_RELA
 				17 => RelocType::Rel,	// DT_REL
@@ -198,7 +223,7 @@ impl<R: Read+Seek> ElfModuleHandle<R>
 		kernel_log!("Applying relocations:");
 		{
 			let rs = RelocationState {
-				base: 0,
+				base: self.base,
 				machine: self.header.machine,
 				strtab: strtab,
 				symtab: symtab,
@@ -224,12 +249,16 @@ impl<'a> RelocationState<'a>
 	{
 		match self.machine
 		{
-		Machine::X8664 => for r in iter { try!(self.apply_reloc_x86_64(r)); },
-		Machine::ARM => for r in iter { try!(self.apply_reloc_arm(r)); },
+		Machine::X8664 => for r in iter { try!(self.apply_reloc_x86_64(self.rebase(r))); },
+		Machine::ARM => for r in iter { try!(self.apply_reloc_arm(self.rebase(r))); },
 		_ => todo!("apply_reloc - Machine {:?}", self.machine),
 		}
 		Ok( () )
 	}
+	/// Convert a relocation's target from an image offset to an address
+	fn rebase(&self, r: Reloc) -> Reloc {
+		Reloc { addr: self.base + r.addr, .. r }
+	}
 	fn apply_reloc_arm(&self, r: Reloc) -> Result<(), Error> {
 		const R_ARM_NONE: u16 = 0;
 		//const R_ARM_PC24: u16 = 1;	// ((S + A) | T) - P
@@ -564,7 +593,7 @@ impl<'a, R: 'a+Read> Iterator for DtEntIterator<'a, R>
 	}
 }
 
-pub struct LoadSegments<'a, R: 'a + Read>(PhEntIterator<'a,R>);
+pub struct LoadSegments<'a, R: 'a + Read>(PhEntIterator<'a,R>, usize);
 impl<'a, R: 'a + Read> ::load::SegmentIterator<R> for LoadSegments<'a, R>
 {
 	fn get_file(&self) -> &R { self.0.file }
@@ -578,7 +607,7 @@ impl<'a, R: 'a+Read> ::std::iter::Iterator for LoadSegments<'a, R> {
 			if e.p_type == PT_LOAD
 			{
 				return Some(Segment {
-					load_addr: e.p_paddr,
+					load_addr: self.1 + e.p_paddr,
 					file_addr: e.p_offset,
 					file_size: e.p_filesz,
 					mem_size: e.p_memsz,
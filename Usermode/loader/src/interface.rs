@@ -23,7 +23,9 @@ impl_from! {
 
 #[no_mangle]
 /// Spawn a new process using the provided binary and arguments
-pub extern "C" fn new_process(executable_handle: ::syscalls::vfs::File, process_name: &[u8], args: &[&[u8]]) -> Result<::syscalls::threads::ProtoProcess,loader::Error>
+///
+/// `root` is passed as the child's full filesystem root (`vfs::root`), if provided.
+pub extern "C" fn new_process(executable_handle: ::syscalls::vfs::File, process_name: &[u8], args: &[&[u8]], root: Option<::syscalls::vfs::Dir>) -> Result<::syscalls::threads::ProtoProcess,loader::Error>
 {
 	extern "C" {
 		static BASE: [u8; 0];
@@ -73,7 +75,10 @@ pub extern "C" fn new_process(executable_handle: ::syscalls::vfs::File, process_
 	// Send the executable handle
 	kernel_log!("- Sending executable handle");
 	proto_proc.send_obj( "exec", executable_handle );
-	proto_proc.send_obj( "ro:/", ::syscalls::vfs::ROOT.clone() );
+	// - The child picks the root up by tag on first use (`vfs::root`)
+	if let Some(root) = root {
+		proto_proc.send_obj( ::syscalls::vfs::ROOT_TAG, root );
+	}
 
 	kernel_log!("- Returning ProtoProcess");
 	Ok(proto_proc)
@@ -118,8 +123,6 @@ fn new_process_entry() -> !
 	
 	
 	let fh: ::syscalls::vfs::File = ::syscalls::threads::S_THIS_PROCESS.receive_object("exec").expect("Could not receive the executable vfs::File object");
-	let root: ::syscalls::vfs::Dir = ::syscalls::threads::S_THIS_PROCESS.receive_object("ro:/").expect("Could not receive the root");
-	::std::mem::forget(root);
 	let entrypoint = ::load_binary(process_name, fh);
 	
 	// TODO: Coordinate with the parent process and receive an initial set of objects (e.g. WM root)?
@@ -132,11 +135,8 @@ fn new_process_entry() -> !
 	}
 	kernel_log!("args = {:?}", &*args);
 	
-	// TODO: Switch stacks into a larger dynamically-allocated stack
-	// SAFE: Entrypoint assumed to have this signature
-	let ep: fn(&[&::std::ffi::OsStr]) -> ! = unsafe { ::std::mem::transmute(entrypoint) };
-	kernel_log!("Calling entry {:p} for {:?}", ep as *const (), process_name);
-	ep(&args);
+	kernel_log!("Calling entry {:#x} for {:?}", entrypoint, process_name);
+	::start_main(entrypoint, &args);
 }
 
 
@@ -27,6 +27,29 @@ const PAGE_SIZE: usize = 0x2000;
 #[cfg(not(arch="armv7"))]
 const PAGE_SIZE: usize = 0x1000;
 
+/// Maximum size of the program's main stack (the kernel grows it as it's used)
+const MAIN_STACK_SIZE: usize = 0x10_0000;
+/// Region containing the program's main stack (above the region libstd uses for thread stacks)
+///
+/// The stack top is randomly placed within this region.
+#[cfg(arch="amd64")]
+const MAIN_STACK_REGION: (usize, usize) = (0x7110_0000_0000, 0x7200_0000_0000);
+#[cfg(not(arch="amd64"))]
+const MAIN_STACK_REGION: (usize, usize) = (0x7810_0000, 0x7900_0000);
+/// Region used for position-independent executables (below the heap)
+#[cfg(arch="amd64")]
+const PIE_REGION: (usize, usize) = (0x0100_0000_0000, 0x0F00_0000_0000);
+#[cfg(not(arch="amd64"))]
+const PIE_REGION: (usize, usize) = (0x0100_0000, 0x0F00_0000);
+
+/// Select a random page-aligned address in `[region.0, region.1 - size]`
+fn random_base(region: (usize, usize), size: usize) -> usize
+{
+	assert!(region.0 + size <= region.1, "random_base - {:#x} doesn't fit in {:#x}--{:#x}", size, region.0, region.1);
+	let slots = (region.1 - region.0 - size) / PAGE_SIZE + 1;
+	region.0 + (::syscalls::random_usize() % slots) * PAGE_SIZE
+}
+
 // Main: This is the initial boot entrypoint
 // NOTE: If you're looking for the new process entrypoint, see interface.rs
 #[no_mangle]
@@ -67,13 +90,46 @@ pub extern "C" fn loader_main(cmdline: *mut u8, cmdline_len: usize) -> !
 	}
 	kernel_log!("args = {:?}", &*args);
 	
-	// TODO: Switch stacks into a larger dynamically-allocated stack
-	// SAFE: Entrypoint assumed to have this format... will likely crash if it isn't
-	let ep: fn(&[&::std::ffi::OsStr]) = unsafe { ::std::mem::transmute(entrypoint) };
-	kernel_log!("Calling entry {:p} for INIT {:?}", ep as *const (), init_path);
-	ep(&args);
-	kernel_log!("User entrypoint returned");
-	::syscalls::threads::exit(!0);
+	kernel_log!("Calling entry {:#x} for INIT {:?}", entrypoint, init_path);
+	start_main(entrypoint, &args);
+}
+
+/// Run the program's entrypoint on a growable stack
+///
+/// The loader's stack is a small fixed buffer, so the program is started in a new thread (with a stack
+/// that has a guard page below it) and this thread exits.
+fn start_main(entrypoint: usize, args: &[&::std::ffi::OsStr]) -> !
+{
+	extern "C" fn main_root(info: usize) -> ! {
+		// SAFE: Pointer is to `info` in `start_main`, which is never popped
+		let &(entrypoint, args) = unsafe { &*(info as *const (usize, &[&::std::ffi::OsStr])) };
+		// SAFE: Entrypoint assumed to have this format... will likely crash if it isn't
+		let ep: fn(&[&::std::ffi::OsStr]) = unsafe { ::std::mem::transmute(entrypoint) };
+		ep(args);
+		kernel_log!("User entrypoint returned");
+		::syscalls::threads::exit(!0);
+	}
+
+	let main_stack_top = random_base(MAIN_STACK_REGION, MAIN_STACK_SIZE) + MAIN_STACK_SIZE;
+	// SAFE: Address is reserved for the main stack
+	unsafe {
+		::syscalls::memory::alloc_stack(main_stack_top, MAIN_STACK_SIZE).expect("Unable to allocate main stack");
+	}
+	// x86-64 functions expect to be entered with a return address on the stack
+	#[cfg(arch="amd64")]
+	let stack_top = main_stack_top - ::std::mem::size_of::<usize>();
+	#[cfg(not(arch="amd64"))]
+	let stack_top = main_stack_top;
+
+	// NOTE: `info` (and `args`) stay valid, as this function never returns and the stack isn't freed
+	let info = (entrypoint, args);
+	// SAFE: Entrypoint has the correct signature, and the stack was just allocated
+	match unsafe { ::syscalls::threads::start_thread(main_root as usize, stack_top, &info as *const _ as usize) }
+	{
+	Ok(_) => {},
+	Err(e) => panic!("Unable to start main thread: {}", e),
+	}
+	::syscalls::threads::exit_thread(0);
 }
 
 struct FixedVec<T> {
@@ -122,6 +178,14 @@ fn load_binary(path: &::std::ffi::OsStr, fh: ::syscalls::vfs::File) -> usize
 			},
 		};
 	
+	// Position-independent executables are loaded at a random base
+	if handle.is_position_independent() {
+		let size = handle.get_image_size();
+		let base = random_base(PIE_REGION, size);
+		kernel_log!("- PIE, base = {:#x} (size {:#x})", base, size);
+		handle.set_load_base(base);
+	}
+	
 	let entrypoint = handle.get_entrypoint();
 	kernel_log!("- entrypoint = {:#x}", entrypoint);
 	
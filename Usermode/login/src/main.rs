@@ -138,7 +138,7 @@ fn try_login(username: &str, password: &str) -> Result<(), &'static str>
 }
 
 fn open_exe(path: &str) -> Result<::syscalls::vfs::File, ::syscalls::vfs::Error> {
-	match ::syscalls::vfs::ROOT.open_child_path(path.as_bytes())
+	match ::syscalls::vfs::root().expect("No VFS root").open_child_path(path.as_bytes())
 	{
 	Ok(v) => v.into_file(::syscalls::vfs::FileOpenMode::Execute),
 	Err(e) => Err(e),
@@ -153,7 +153,7 @@ fn spawn_console_and_wait(path: &str)
 	let handle_server = {
 		let path = "/sysroot/bin/handle_server";
 		let fh = open_exe(path).unwrap_or_else(|e| panic!("Couldn't open handle server - {:?}", e));
-		let pp = loader::new_process(fh, path.as_bytes(), &[]).expect("Could not spawn handle server");
+		let pp = loader::new_process(fh, path.as_bytes(), &[], None).expect("Could not spawn handle server");
 		pp.send_obj( "RwRoot", VFS_ROOT.clone() );
 		pp.send_obj( "HsChan", hs_svr_chan );
 		pp.start()
@@ -165,7 +165,7 @@ fn spawn_console_and_wait(path: &str)
 			Ok(v) => v,
 			Err(e) => panic!("Couldn't open executable '{}' - {:?}", path, e),
 			};
-		let pp = loader::new_process(fh, path.as_bytes(), &[]).expect("Could not spawn shell");
+		let pp = loader::new_process(fh, path.as_bytes(), &[], Some(::syscalls::vfs::root().expect("No VFS root"))).expect("Could not spawn shell");
 		pp.send_obj( "guigrp", ::syscalls::gui::clone_group_handle() );
 		pp.send_obj( "HsChan", hs_clt_chan );
 		pp.start()
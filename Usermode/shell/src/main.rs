@@ -18,20 +18,21 @@ macro_rules! imgpath {
 }
 
 fn start_app_console() {
-	start_app(&["/sysroot/bin/simple_console", "--windowed"], |_app| {
+	start_app(&["/sysroot/bin/simple_console", "--windowed"], false, |_app| {
 		//app.send_obj( "vfs", ::syscalls::vfs::ROOT.clone() );
 		});
 }
 fn start_app_filebrowser() {
-	start_app(&["/sysroot/bin/filebrowser"], |app| {
-		app.send_obj( "ro:/", ::syscalls::vfs::ROOT.clone() );
-		});
+	start_app(&["/sysroot/bin/filebrowser"], true, |_app| {});
+}
+fn start_app_taskmgr() {
+	start_app(&["/sysroot/bin/taskmgr"], false, |_app| {});
 }
 fn start_app_editor() {
 	let path = "/system/1.txt";
-	let f = ::syscalls::vfs::ROOT.open_child_path(path.as_bytes()).expect("Couldn't open editor executable file")
+	let f = root().open_child_path(path.as_bytes()).expect("Couldn't open editor executable file")
 		.into_file(::syscalls::vfs::FileOpenMode::ReadOnly).expect("Couldn't open file as readonly");
-	start_app(&["/sysroot/bin/fileviewer", path], |app| {
+	start_app(&["/sysroot/bin/fileviewer", path], false, |app| {
 		app.send_obj( "file", f );
 		});
 }
@@ -59,6 +60,7 @@ fn main()
 			Spacer,
 			Entry::new("Filesystem", 0, "Win-E", || start_app_filebrowser()),
 			Entry::new("Text Editor", 5, "", || start_app_editor()),
+			Entry::new("Task Manager", 0, "", || start_app_taskmgr()),
 			))
 		};
 	system_menu.set_pos( ::wtk::geom::Pos::new(0,20) );
@@ -131,7 +133,8 @@ fn main()
 
 }
 
-fn start_app<F>(args: &[&str], cb: F)
+/// Spawn an application, only giving it the full filesystem root if `with_root` is set
+fn start_app<F>(args: &[&str], with_root: bool, cb: F)
 where
 	F: FnOnce(&mut ::loader::ProtoProcess)
 {
@@ -139,10 +142,14 @@ where
 	let fh = open_exec(args[0]);
 	// SAFE: &str and &[u8] have the same representation
 	let byte_args: &[&[u8]] = unsafe { ::std::mem::transmute(&args[1..]) };
-	match ::loader::new_process(fh, args[0].as_bytes(), byte_args)
+	let root = if with_root { Some(root()) } else { None };
+	match ::loader::new_process(fh, args[0].as_bytes(), byte_args, root)
 	{
 	Ok(mut app) => {
 		app.send_obj( "guigrp", ::syscalls::gui::clone_group_handle() );
+		if let Some(d) = open_dir("/system/Tifflin/shared") {
+			app.send_named_root( "CommonData", d );
+		}
 		cb(&mut app);
 		app.start();
 		},
@@ -150,9 +157,27 @@ where
 	}
 }
 
+/// The shell's full filesystem root (always passed by login)
+fn root() -> ::syscalls::vfs::Dir
+{
+	::syscalls::vfs::root().expect("No VFS root passed to the shell")
+}
+
+fn open_dir(path: &str) -> Option<::syscalls::vfs::Dir>
+{
+	match root().open_child_path(path.as_bytes()).and_then(|v| v.into_dir())
+	{
+	Ok(v) => Some(v),
+	Err(e) => {
+		kernel_log!("open_dir({}) - {:?}", path, e);
+		None
+		},
+	}
+}
+
 fn open_exec(path: &str) -> ::syscalls::vfs::File
 {
-	match ::syscalls::vfs::ROOT.open_child_path(path.as_bytes())
+	match root().open_child_path(path.as_bytes())
 	{
 	Ok(v) => match v.into_file(::syscalls::vfs::FileOpenMode::Execute)
 		{
@@ -189,8 +189,10 @@ impl ShellState
 			while let Some(v) = args.next() {
 				print!(term, "{} ", v);
 			},
+		// 'ps' - List running processes
+		Some("ps") => command_ps(term),
 		Some("help") => {
-			print!(term, "Builtins: pwd, cd, ls, cat, help, echo");
+			print!(term, "Builtins: pwd, cd, ls, cat, help, echo, ps");
 			},
 		Some(cmd @_) => {
 			print!(term, "Unkown command '{}'", cmd);
@@ -267,6 +269,34 @@ fn command_ls<T: ::Terminal>(term: &T, root: &::syscalls::vfs::Dir, path: &str)
 	}
 }
 
+/// List all processes
+fn command_ps<T: ::Terminal>(term: &T)
+{
+	use syscalls::threads::{ProcessStats, ProcessState};
+	let mut procs: Vec<ProcessStats> = vec![Default::default(); 16];
+	loop
+	{
+		let count = ::syscalls::threads::list_processes(&mut procs);
+		// If processes were created between the calls, the buffer could still be too small
+		if count <= procs.len() {
+			procs.truncate(count);
+			break ;
+		}
+		procs.resize(count, Default::default());
+	}
+
+	print!(term, "  PID STATE THR   CPU(ms)  MEM(KiB) NAME\n");
+	for p in &procs
+	{
+		let state = match ProcessState::try_from(p.state)
+			{
+			Ok(ProcessState::Running) => "run",
+			Ok(ProcessState::Exited) => "exit",
+			Err(_) => "?",
+			};
+		print!(term, "{:5} {:5} {:3} {:9} {:9} {}\n", p.pid, state, p.num_threads, p.cpu_time_ms, p.resident_pages * 4, p.name());
+	}
+}
 
 /// Trait to provde 'is_combining', used by render code
 pub trait UnicodeCombining
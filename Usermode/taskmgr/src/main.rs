@@ -0,0 +1,113 @@
+// Tifflin OS - taskmgr
+// - By John Hodge (thePowersGang)
+//
+// Graphical task manager - Lists running processes and their resource usage
+
+extern crate wtk;
+extern crate syscalls;
+
+// NOTE: The list view is shared with the file browser
+#[path="../../filebrowser/src/listview.rs"]
+mod listview;
+
+// Used by listview
+mod iterx {
+	pub fn zip<A: IntoIterator, B: IntoIterator>(a: A, b: B) -> ::std::iter::Zip<A::IntoIter, B::IntoIter> {
+		Iterator::zip(a.into_iter(), b.into_iter())
+	}
+}
+
+use listview::ListView;
+use syscalls::threads::{ProcessStats,ProcessState};
+
+fn main()
+{
+	::wtk::initialise();
+
+	let list = ProcessList::new();
+	list.refresh();
+
+	let mut window = ::wtk::Window::new_def("Task Manager", &list).unwrap();
+	window.set_title("Task Manager (F5 to refresh)");
+
+	window.focus(&list);
+	window.show();
+
+	window.idle_loop();
+}
+
+/// Obtain information on all running processes
+fn get_processes() -> Vec<ProcessStats>
+{
+	let mut rv: Vec<ProcessStats> = vec![Default::default(); 16];
+	loop
+	{
+		let count = ::syscalls::threads::list_processes(&mut rv);
+		// If processes were created between the calls, the buffer could still be too small
+		if count <= rv.len() {
+			rv.truncate(count);
+			return rv;
+		}
+		rv.resize(count, Default::default());
+	}
+}
+
+struct ProcessList
+{
+	list: ListView<[&'static str; 6], [String; 6]>,
+}
+impl ProcessList
+{
+	fn new() -> ProcessList {
+		ProcessList {
+			// NOTE: Column widths are derived from the titles
+			list: ListView::new(["  PID", "Name                ", "State", "Threads", "CPU (ms)  ", "Memory (KiB)"]),
+		}
+	}
+
+	/// Re-populate the list from the kernel's process list
+	fn refresh(&self) {
+		self.list.clear();
+		for p in get_processes()
+		{
+			let state = match ProcessState::try_from(p.state)
+				{
+				Ok(ProcessState::Running) => "Running".to_owned(),
+				Ok(ProcessState::Exited) => format!("Exited ({})", p.exit_status),
+				Err(v) => format!("? ({})", v),
+				};
+			self.list.append_item([
+				format!("{:5}", p.pid),
+				p.name().to_owned(),
+				state,
+				format!("{}", p.num_threads),
+				format!("{}", p.cpu_time_ms),
+				format!("{}", p.resident_pages * 4),
+				]);
+		}
+	}
+}
+
+impl ::wtk::Element for ProcessList
+{
+	fn render(&self, surface: ::wtk::surface::SurfaceView, force: bool) {
+		self.list.render(surface, force);
+	}
+	fn resize(&self, _w: u32, _h: u32) {
+		// Sizes itself on render
+	}
+	fn handle_event(&self, ev: ::wtk::InputEvent, _win: &mut ::wtk::WindowTrait) -> bool {
+		match ev
+		{
+		::wtk::InputEvent::KeyUp(::wtk::KeyCode::F5) => {
+			self.refresh();
+			true
+			},
+		// No action when a process is "opened"
+		ev => self.list.handle_event(ev, |_| None::<fn()>),
+		}
+	}
+	fn with_element_at_pos(&self, pos: ::wtk::geom::PxPos, _dims: ::wtk::geom::PxDims, f: ::wtk::WithEleAtPosCb) -> bool {
+		f(self, pos)
+	}
+}
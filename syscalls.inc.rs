@@ -59,11 +59,11 @@ def_groups! {
 		=2: CORE_EXITPROCESS,
 		/// Request a text string from the kernel
 		=3: CORE_TEXTINFO,
-		/// Terminate the current thread
+		/// Terminate the current thread, passing an exit value to any joiners
 		=4: CORE_EXITTHREAD,
 		/// Start a new process (loader only, use loader API instead)
 		=5: CORE_STARTPROCESS,
-		/// Start a new thread in the current process
+		/// Start a new thread in the current process (returns a `CLASS_CORE_THREAD` handle)
 		=6: CORE_STARTTHREAD,
 		/// Wait for any of a set of events
 		=7: CORE_WAIT,
@@ -71,6 +71,12 @@ def_groups! {
 		=8: CORE_FUTEX_SLEEP,
 		/// Wake a number of sleepers on a futex
 		=9: CORE_FUTEX_WAKE,
+		/// Set the scheduling priority of the current thread
+		=10: CORE_SETPRIORITY,
+		/// Enumerate processes (fills a `[ProcessStats]` buffer, returns the total number of processes)
+		=11: CORE_PROCLIST,
+		/// Fill a buffer with random bytes (non-cryptographic, used for address-space randomisation)
+		=12: CORE_GETRANDOM,
 	},
 	/// GUI System calls
 	=1: GROUP_GUI = {
@@ -88,6 +94,14 @@ def_groups! {
 		=0: MEM_ALLOCATE,
 		=1: MEM_REPROTECT,
 		=2: MEM_DEALLOCATE,
+		/// Write modified pages of writeback file mappings back to disk (address, page count)
+		=3: MEM_SYNC,
+		/// Create an anonymous shared memory object (size in bytes), returns a `CLASS_MEM_SHM` handle
+		=4: MEM_SHM_CREATE,
+		/// Reserve a growable stack (top address, maximum size in bytes), the page below the limit is a guard page
+		=5: MEM_STACK_ALLOC,
+		/// Release a stack allocated with MEM_STACK_ALLOC (top address)
+		=6: MEM_STACK_FREE,
 	},
 	/// Process memory management
 	=3: GROUP_IPC = {
@@ -202,6 +216,8 @@ def_classes! {
 	/// Opened node
 	=3: CLASS_VFS_NODE = {
 		=0: VFS_NODE_GETTYPE,
+		/// Read the node's metadata (size, timestamps, permissions) into a VFSMetadata
+		=1: VFS_NODE_GETMETADATA,
 		--
 		=0: VFS_NODE_TOFILE,
 		=1: VFS_NODE_TODIR,
@@ -218,6 +234,8 @@ def_classes! {
 		=2: VFS_FILE_WRITEAT,
 		/// Map part of the file into the current address space
 		=3: VFS_FILE_MEMMAP,
+		/// Read the file's metadata into a VFSMetadata
+		=4: VFS_FILE_GETMETADATA,
 		--
 	}|{
 	},
@@ -229,6 +247,18 @@ def_classes! {
 		=1: VFS_DIR_OPENCHILD,
 		/// Open a sub-path
 		=2: VFS_DIR_OPENPATH,
+		/// Create a new file and open it (name, open mode)
+		=3: VFS_DIR_CREATEFILE,
+		/// Create a new directory and open it
+		=4: VFS_DIR_MKDIR,
+		/// Remove a (non-directory) child
+		=5: VFS_DIR_UNLINK,
+		/// Remove an empty child directory
+		=6: VFS_DIR_RMDIR,
+		/// Atomically rename a child (old name, target directory handle, new name)
+		=7: VFS_DIR_RENAME,
+		/// Open a sub-path, without following a symbolic link in the final component
+		=8: VFS_DIR_OPENPATH_NOFOLLOW,
 		--
 	}|{
 	},
@@ -322,6 +352,39 @@ def_classes! {
 	--
 	}|{
 	},
+	/// Handle to a thread within the current process
+	=14: CLASS_CORE_THREAD = {
+		/// Get the thread's exit value (!0 if the thread is still running)
+		=0: CORE_THREAD_GETEXIT,
+		--
+		/// Release the handle without waiting for the thread to terminate
+		=0: CORE_THREAD_DETACH,
+	}|{
+		/// Wakes when the thread terminates
+		=0: EV_THREAD_TERMINATED,
+	},
+	/// Anonymous shared memory object
+	=15: CLASS_MEM_SHM = {
+		/// Get the size of the object (in bytes, a multiple of the page size)
+		=0: MEM_SHM_GETSIZE,
+		/// Map the whole object into the current address space (address, `ProtectionMode`)
+		=1: MEM_SHM_MAP,
+		--
+	}|{
+	},
+	/// Mount management (privileged, passed to init as "MountMgr")
+	=16: CLASS_VFS_MOUNTS = {
+		/// Mount a volume (mountpoint, volume name, comma-separated options - `fs=NAME` selects the driver)
+		=0: VFS_MOUNTS_MOUNT,
+		/// Unmount a volume (mountpoint, force)
+		=1: VFS_MOUNTS_UNMOUNT,
+		/// Write modified data on the volume containing a path back to disk (path, empty for all volumes)
+		=2: VFS_MOUNTS_SYNC,
+		/// Change the options of a mounted volume (mountpoint, comma-separated options)
+		=3: VFS_MOUNTS_REMOUNT,
+		--
+	}|{
+	},
 /*
 	/// A registered read/write buffer
 	=12: CLASS_BUFFER = {
@@ -374,13 +437,118 @@ macro_rules! enum_to_from {
 	}
 }
 
+/// Thread scheduling priorities avaliable to userland (higher priorities are reserved for kernel workers)
+enum_to_from!{ ThreadPriority => u8:
+	/// Background work, only runs when nothing else is runnable
+	Low = 0,
+	/// Default priority
+	Normal = 1,
+}
+
+enum_to_from!{ ProcessState => u8:
+	/// At least one thread is still running
+	Running = 0,
+	/// The process has exited (but is still referenced)
+	Exited = 1,
+}
+
+/// Process information returned by CORE_PROCLIST
+#[repr(C)]
+#[derive(Copy,Clone,Default,Debug)]
+pub struct ProcessStats
+{
+	pub pid: u32,
+	/// Number of threads (including terminated threads that haven't been released)
+	pub num_threads: u32,
+	/// Total CPU time consumed by the process's threads (in milliseconds)
+	pub cpu_time_ms: u64,
+	/// Number of pages mapped into the process's address space
+	pub resident_pages: u64,
+	/// Exit status (only valid when `state` is `ProcessState::Exited`)
+	pub exit_status: u32,
+	/// `ProcessState` value
+	pub state: u8,
+	pub _rsvd: [u8; 3],
+	/// Process name (NUL padded, truncated)
+	pub name: [u8; 32],
+}
+impl ProcessStats {
+	pub fn name(&self) -> &str {
+		let len = self.name.iter().position(|&b| b == 0).unwrap_or(self.name.len());
+		::core::str::from_utf8(&self.name[..len]).unwrap_or("?")
+	}
+}
+
+enum_to_from!{ MemoryError => u32:
+	/// Part of the requested range is already mapped
+	RangeInUse = 0,
+	/// Not enough memory to complete the operation
+	OutOfMemory = 1,
+}
+
 enum_to_from!{ VFSError => u32:
 	FileNotFound = 0,
 	TypeError = 1,
 	PermissionDenied = 2,
 	FileLocked = 3,
 	MalformedPath = 4,
+	ReadOnlyFilesystem = 5,
+	AlreadyExists = 6,
+	InvalidParameter = 7,
+	OutOfSpace = 8,
+	OutOfMemory = 9,
+	IoError = 10,
+	NotEmpty = 11,
+	CrossFilesystem = 12,
+	FileTooLarge = 13,
+	/// Operation failed for a reason not covered above (details are logged by the kernel)
+	Unknown = 14,
+	/// Too many symbolic links were followed while resolving a path
+	RecursionDepthExceeded = 15,
+	/// A non-final component of a path wasn't a directory
+	NonDirComponent = 16,
+}
+enum_to_from!{ VFSMountError => u32:
+	UnknownFilesystem = 0,
+	NoHandler = 1,
+	InvalidMountpoint = 2,
+	MountpointUsed = 3,
+	CallFailed = 4,
+	InvalidOption = 5,
+	NotMounted = 6,
+	Busy = 7,
+	RootUnmount = 8,
+	/// The named volume couldn't be opened
+	InvalidVolume = 9,
+}
+/// Node metadata returned by VFS_NODE_GETMETADATA and VFS_FILE_GETMETADATA
+#[repr(C)]
+#[derive(Copy,Clone,Default,Debug)]
+pub struct VFSMetadata
+{
+	/// Volume inode number
+	pub inode: u64,
+	/// Size in bytes
+	pub size: u64,
+	/// Creation time (milliseconds since 1970-01-01 UTC, `VFS_TIMESTAMP_NONE` if not recorded)
+	pub created: i64,
+	/// Last modification time
+	pub modified: i64,
+	/// Last access time
+	pub accessed: i64,
+	pub uid: u32,
+	pub gid: u32,
+	/// Number of directory entries referring to the node
+	pub link_count: u32,
+	/// Unix-style permission bits
+	pub mode: u16,
+	/// `VFSNodeType` value
+	pub node_type: u8,
+	pub _rsvd: u8,
 }
+/// Value of a `VFSMetadata` timestamp that the filesystem doesn't record
+pub const VFS_TIMESTAMP_NONE: i64 = !0x7FFF_FFFF_FFFF_FFFF;
+
 enum_to_from!{ VFSNodeType => u32:
 	File = 0,
 	Dir = 1,